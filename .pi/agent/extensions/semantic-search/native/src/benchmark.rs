@@ -0,0 +1,83 @@
+//! Timing helpers for the in-process benchmarks (`run_benchmark`).
+//!
+//! The standalone `bench` binary prints; these return numbers so the
+//! extension can show them.
+
+use std::time::Instant;
+
+/// Representative embedding texts in the default template's format, cycled
+/// to fill batches.
+pub const SAMPLE_TEXTS: &[&str] = &[
+    "go | packages/tcp-proxy/controller/limits.go | (c *Controller) GetHTTPRequestRateLimiterForDomain(domain string) (*rate.Limiter, error)",
+    "go | packages/tcp-proxy/middleware/rate_limit.go | func RateLimitMiddleware(next http.Handler) http.Handler",
+    "typescript | .pi/agent/extensions/modal-editor.ts | handleInput(data: string): void",
+    "go | packages/tcp-proxy/handlers/tcp_listener/main.go | func (h *Handler) HandleConnection(conn net.Conn)",
+    "typescript | .pi/agent/extensions/semantic-search/db.ts | insertSymbol(embedding: Float32Array, language: string, kind: string)",
+    "rust | src/main.rs | fn main() -> Result<()>",
+    "python | scripts/deploy.py | def deploy_to_production(env: str, version: str) -> bool",
+    "go | packages/orchestrator/workflows/dataplane/buildandpublish.go | func (w *Workflow) Execute(ctx context.Context) error",
+];
+
+/// `n` sample texts, repeating the list as needed.
+pub fn sample_texts(n: usize) -> Vec<String> {
+    SAMPLE_TEXTS
+        .iter()
+        .cycle()
+        .take(n)
+        .map(|t| t.to_string())
+        .collect()
+}
+
+/// Latency distribution over a run, in milliseconds.
+#[derive(Debug, Clone, Default)]
+pub struct Timing {
+    pub iterations: usize,
+    pub mean_ms: f64,
+    pub p50_ms: f64,
+    pub p95_ms: f64,
+    pub min_ms: f64,
+    pub max_ms: f64,
+}
+
+impl Timing {
+    /// Summarize per-iteration durations (ms).
+    pub fn from_samples(mut samples: Vec<f64>) -> Self {
+        if samples.is_empty() {
+            return Timing::default();
+        }
+        samples.sort_by(f64::total_cmp);
+        let n = samples.len();
+        Timing {
+            iterations: n,
+            mean_ms: samples.iter().sum::<f64>() / n as f64,
+            p50_ms: percentile(&samples, 0.50),
+            p95_ms: percentile(&samples, 0.95),
+            min_ms: samples[0],
+            max_ms: samples[n - 1],
+        }
+    }
+}
+
+/// Nearest-rank percentile of sorted `samples`.
+fn percentile(samples: &[f64], p: f64) -> f64 {
+    let rank = (p * samples.len() as f64).ceil() as usize;
+    samples[rank.clamp(1, samples.len()) - 1]
+}
+
+/// Run `f` `warmup` times untimed, then `iterations` times timed.
+pub fn measure<E>(
+    warmup: usize,
+    iterations: usize,
+    mut f: impl FnMut() -> Result<(), E>,
+) -> Result<Timing, E> {
+    for _ in 0..warmup {
+        f()?;
+    }
+    let mut samples = Vec::with_capacity(iterations);
+    for _ in 0..iterations {
+        let start = Instant::now();
+        f()?;
+        samples.push(start.elapsed().as_secs_f64() * 1000.0);
+    }
+    Ok(Timing::from_samples(samples))
+}
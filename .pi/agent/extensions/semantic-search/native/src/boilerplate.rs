@@ -0,0 +1,130 @@
+//! Boilerplate stripping for embedding texts.
+//!
+//! License headers, generated-code banners, and import blocks say little
+//! about what code does, yet at the top of a file they can fill most of the
+//! model's 128 tokens. With stripping on, document texts lose them just
+//! before embedding; stored texts and queries are left alone.
+
+use std::borrow::Cow;
+
+/// Words that mark a leading comment block as a license header.
+const LICENSE_MARKERS: &[&str] = &["license", "licence", "copyright", "spdx-license-identifier"];
+
+/// Phrases that mark a comment line as a generated-code banner.
+const GENERATED_MARKERS: &[&str] = &[
+    "@generated",
+    "do not edit",
+    "code generated by",
+    "auto-generated",
+    "autogenerated",
+];
+
+/// `text` without its leading license header, generated-code banner lines,
+/// and import statements. Borrowed when nothing is stripped, and the
+/// original when stripping would leave nothing.
+pub fn strip(text: &str) -> Cow<'_, str> {
+    let lines: Vec<&str> = text.lines().collect();
+    let mut keep = vec![true; lines.len()];
+
+    let header = leading_comment_len(&lines);
+    let header_text = lines[..header].join("\n").to_lowercase();
+    if LICENSE_MARKERS.iter().any(|m| header_text.contains(m)) {
+        keep[..header].iter_mut().for_each(|k| *k = false);
+    }
+
+    let mut i = 0;
+    while i < lines.len() {
+        let line = lines[i].trim();
+        if is_comment(line) {
+            let lower = line.to_lowercase();
+            if GENERATED_MARKERS.iter().any(|m| lower.contains(m)) {
+                keep[i] = false;
+            }
+            i += 1;
+            continue;
+        }
+        if !is_import(line) {
+            i += 1;
+            continue;
+        }
+        // Multi-line imports (`import (`, `use a::{`, `import {`) run until
+        // their brackets close
+        let mut depth = 0i32;
+        loop {
+            depth += bracket_balance(lines[i]);
+            keep[i] = false;
+            i += 1;
+            if depth <= 0 || i == lines.len() {
+                break;
+            }
+        }
+    }
+
+    if keep.iter().all(|&k| k) {
+        return Cow::Borrowed(text);
+    }
+    let stripped: Vec<&str> = lines
+        .iter()
+        .zip(&keep)
+        .filter(|(_, &k)| k)
+        .map(|(l, _)| *l)
+        .collect();
+    let stripped = stripped.join("\n");
+    if stripped.trim().is_empty() {
+        Cow::Borrowed(text)
+    } else {
+        Cow::Owned(stripped.trim_start_matches('\n').to_string())
+    }
+}
+
+/// Lines in the comment block (with blank lines around it) that `lines`
+/// starts with.
+fn leading_comment_len(lines: &[&str]) -> usize {
+    let mut in_block = false;
+    let mut n = 0;
+    for line in lines {
+        let line = line.trim();
+        if in_block {
+            in_block = !line.contains("*/");
+        } else if let Some(rest) = line.strip_prefix("/*") {
+            in_block = !rest.contains("*/");
+        } else if !line.is_empty() && !is_comment(line) {
+            break;
+        }
+        n += 1;
+    }
+    n
+}
+
+fn is_comment(line: &str) -> bool {
+    ["//", "/*", "*", "#", "--", ";;"]
+        .iter()
+        .any(|p| line.starts_with(p))
+        && !line.starts_with("#include")
+        && !line.starts_with("#[")
+        && !line.starts_with("#!")
+}
+
+/// Whether `line` (trimmed) starts an import, include, or package statement.
+fn is_import(line: &str) -> bool {
+    let codelike = |l: &str| l.ends_with(';') || l.contains("::") || l.contains('{');
+    line.starts_with("import ")
+        || line.starts_with("import(")
+        || (line.starts_with("from ") && line.contains(" import "))
+        || (line.starts_with("use ") && codelike(line))
+        || line.starts_with("#include")
+        || line.starts_with("extern crate ")
+        || (line.starts_with("using ") && line.ends_with(';'))
+        || (line.starts_with("package ") && !line.contains('{'))
+}
+
+/// Opening minus closing brackets on `line`.
+fn bracket_balance(line: &str) -> i32 {
+    line.chars()
+        .map(|c| match c {
+            '(' | '{' | '[' => 1,
+            ')' | '}' | ']' => -1,
+            _ => 0,
+        })
+        .sum()
+}
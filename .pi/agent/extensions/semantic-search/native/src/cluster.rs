@@ -0,0 +1,113 @@
+//! Topical clusters over stored symbol embeddings.
+//!
+//! `cluster_index` runs k-means on a sample of a workspace's embeddings and
+//! assigns every embedded symbol its nearest centroid. Each cluster is
+//! described by the identifier words most specific to it: frequent among
+//! its symbols' names, rare in the other clusters.
+
+use crate::identifiers::Splitting;
+use crate::pq;
+use std::collections::HashMap;
+
+/// Terms kept per cluster, best first.
+pub const TOP_TERMS: usize = 5;
+
+/// Top terms joined into a cluster's label.
+const LABEL_TERMS: usize = 3;
+
+/// Trained k-means centroids, `k * dims` floats.
+pub struct Centroids {
+    dims: usize,
+    data: Vec<f32>,
+}
+
+impl Centroids {
+    /// Train `k` centroids on `samples` (all the same length).
+    pub fn train(samples: &[Vec<f32>], k: usize) -> Result<Self, String> {
+        if k == 0 {
+            return Err("k must be at least 1".to_string());
+        }
+        if samples.len() < k {
+            return Err(format!(
+                "Need at least {} embedded symbols to form {} clusters, got {}",
+                k,
+                k,
+                samples.len()
+            ));
+        }
+        let dims = samples[0].len();
+        let points: Vec<&[f32]> = samples.iter().map(|s| s.as_slice()).collect();
+        Ok(Centroids { dims, data: pq::kmeans(&points, k, dims) })
+    }
+
+    /// Index of the centroid nearest `v`.
+    pub fn nearest(&self, v: &[f32]) -> usize {
+        pq::nearest(v, self.data.chunks_exact(self.dims))
+    }
+
+    pub fn k(&self) -> usize {
+        self.data.len() / self.dims
+    }
+}
+
+/// Distinct lowercase words of an identifier: `parseHttpRequest` and
+/// `parse_http_request` both give `parse`, `http`, `request`. Words under
+/// three characters and numbers are dropped.
+pub fn name_terms(name: &str) -> Vec<String> {
+    let splitting = Splitting { camel_case: true, snake_case: true, paths: true };
+    let mut terms: Vec<String> = splitting
+        .apply(name)
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|w| w.chars().count() >= 3 && !w.chars().all(|c| c.is_ascii_digit()))
+        .map(str::to_lowercase)
+        .collect();
+    terms.sort_unstable();
+    terms.dedup();
+    terms
+}
+
+/// Up to `TOP_TERMS` terms per cluster, ranked by tf-idf across clusters:
+/// the share of the cluster's symbols using the term, weighted by how few
+/// clusters use it at all. Terms every cluster uses are never picked.
+///
+/// `counts[c]` maps each term to the number of cluster `c`'s symbols
+/// whose names contain it; `sizes[c]` is the cluster's symbol count.
+pub fn top_terms(counts: &[HashMap<String, u64>], sizes: &[u64]) -> Vec<Vec<String>> {
+    let mut df: HashMap<&str, u64> = HashMap::new();
+    for cluster in counts {
+        for term in cluster.keys() {
+            *df.entry(term).or_default() += 1;
+        }
+    }
+    let clusters = counts.iter().filter(|c| !c.is_empty()).count() as f64;
+    counts
+        .iter()
+        .zip(sizes)
+        .map(|(cluster, &size)| {
+            let mut scored: Vec<(f64, &String)> = cluster
+                .iter()
+                .map(|(term, &n)| {
+                    let idf = (clusters / df[term.as_str()] as f64).ln();
+                    (n as f64 / size.max(1) as f64 * idf, term)
+                })
+                .filter(|(score, _)| *score > 0.0)
+                .collect();
+            scored.sort_by(|a, b| b.0.total_cmp(&a.0).then_with(|| a.1.cmp(b.1)));
+            scored
+                .into_iter()
+                .take(TOP_TERMS)
+                .map(|(_, term)| term.clone())
+                .collect()
+        })
+        .collect()
+}
+
+/// A cluster's label: its leading top terms, or `cluster <id>` when it has
+/// none.
+pub fn label(id: usize, terms: &[String]) -> String {
+    if terms.is_empty() {
+        format!("cluster {}", id)
+    } else {
+        terms[..terms.len().min(LABEL_TERMS)].join(" ")
+    }
+}
@@ -0,0 +1,99 @@
+//! Joint batching of work submitted by concurrent calls.
+//!
+//! A caller submits its items, then takes whatever exclusive lock guards the
+//! shared resource (the model) and calls `run`. The first caller through the
+//! lock runs every pending submission in one pass and hands each submitter
+//! its share of the output; callers whose items were already run just
+//! collect them. So when the index queue's worker and a search are both
+//! waiting on the model, their texts go through the same forward passes.
+
+use std::sync::{Arc, Mutex};
+
+pub struct Coalescer<T, R> {
+    pending: Mutex<Vec<Arc<Request<T, R>>>>,
+}
+
+struct Request<T, R> {
+    items: Mutex<Vec<T>>,
+    result: Mutex<Option<Result<Vec<R>, String>>>,
+}
+
+/// A submission's claim on its output; see `Coalescer::run`.
+pub struct Ticket<T, R>(Arc<Request<T, R>>);
+
+impl<T, R> Default for Coalescer<T, R> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T, R> Coalescer<T, R> {
+    pub const fn new() -> Self {
+        Coalescer {
+            pending: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub fn submit(&self, items: Vec<T>) -> Ticket<T, R> {
+        let request = Arc::new(Request {
+            items: Mutex::new(items),
+            result: Mutex::new(None),
+        });
+        lock(&self.pending).push(request.clone());
+        Ticket(request)
+    }
+
+    /// Outputs for `ticket`'s items. Must be called while holding the lock
+    /// that serializes `f`: unless another caller already ran this ticket,
+    /// every pending submission is run through one call of `f`, which must
+    /// return one output per item, in order.
+    pub fn run(
+        &self,
+        ticket: Ticket<T, R>,
+        f: impl FnOnce(&[T]) -> Result<Vec<R>, String>,
+    ) -> Result<Vec<R>, String> {
+        if let Some(result) = lock(&ticket.0.result).take() {
+            return result;
+        }
+
+        let batch = std::mem::take(&mut *lock(&self.pending));
+        let mut items = Vec::new();
+        let mut counts = Vec::with_capacity(batch.len());
+        for request in &batch {
+            let mut own = std::mem::take(&mut *lock(&request.items));
+            counts.push(own.len());
+            items.append(&mut own);
+        }
+
+        match f(&items) {
+            Ok(outputs) if outputs.len() == items.len() => {
+                let mut outputs = outputs.into_iter();
+                for (request, count) in batch.iter().zip(counts) {
+                    let share = outputs.by_ref().take(count).collect();
+                    *lock(&request.result) = Some(Ok(share));
+                }
+            }
+            Ok(outputs) => {
+                let e = format!("expected {} outputs, got {}", items.len(), outputs.len());
+                for request in &batch {
+                    *lock(&request.result) = Some(Err(e.clone()));
+                }
+            }
+            Err(e) => {
+                for request in &batch {
+                    *lock(&request.result) = Some(Err(e.clone()));
+                }
+            }
+        }
+
+        lock(&ticket.0.result)
+            .take()
+            .unwrap_or_else(|| Err("submission was not run".to_string()))
+    }
+}
+
+/// Lock ignoring poisoning: every critical section here leaves the data
+/// consistent.
+fn lock<T>(m: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    m.lock().unwrap_or_else(|e| e.into_inner())
+}
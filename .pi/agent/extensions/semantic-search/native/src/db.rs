@@ -1,14 +1,84 @@
 //! SQLite database layer with simsimd NEON brute-force vector search.
 //!
 //! Embeddings stored as BLOBs in a regular table. Search uses mmap'd SQLite
-//! streaming + simsimd L2² distance with a top-K heap. No sqlite-vec dependency.
+//! streaming + simsimd L2² distance with a top-K heap. An index can instead
+//! mirror its vectors into a sqlite-vec `vec0` table and search that (see
+//! `VectorStorage`).
 
-use rusqlite::{params, Connection, Result as SqlResult};
-use simsimd::SpatialSimilarity;
-use std::collections::BinaryHeap;
+use crate::cluster::{self, Centroids};
+use crate::pq::Codebook;
+use rusqlite::functions::FunctionFlags;
+use rusqlite::types::{ToSqlOutput, Value, ValueRef};
+use rusqlite::{
+    params, Connection, DatabaseName, OpenFlags, OptionalExtension, Result as SqlResult,
+    DropBehavior, Transaction, TransactionBehavior,
+};
+use simsimd::{BinarySimilarity, SpatialSimilarity};
+use std::cell::{Cell, RefCell};
+use std::collections::{BinaryHeap, HashMap, HashSet};
 use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-const SCHEMA_VERSION: i32 = 4;
+const SCHEMA_VERSION: i32 = 23;
+
+/// Indexes added since `SCHEMA_VERSION` last changed. They're created on
+/// every writable open, so adding one doesn't need a version bump (which
+/// drops the index's contents).
+const ADDED_INDEXES: &str = "
+    -- one cluster's members at a time (see near_duplicates)
+    CREATE INDEX IF NOT EXISTS idx_symbols_cluster ON symbols(workspace, cluster);";
+
+/// Hamming shortlist size per requested result for `fast_prefilter`
+/// searches, with a floor for small `top_k`.
+const PREFILTER_OVERSAMPLE: usize = 10;
+const PREFILTER_MIN_CANDIDATES: usize = 100;
+
+/// Filtered searches matching more rows than this scan the table
+/// sequentially instead of going through an index.
+pub const DEFAULT_PREFILTER_CAP: u64 = 50_000;
+
+/// Meta key holding the index's `VectorStorage`; absent means `Blob`.
+const VECTOR_STORAGE_META: &str = "vector_storage";
+
+/// Meta key set to "zstd" while new `embedding_text` and `signature`
+/// values are stored compressed; see `set_text_compression`.
+const TEXT_COMPRESSION_META: &str = "text_compression";
+/// zstd's default level: most of the ratio of higher levels on short code
+/// snippets, at a fraction of the time.
+const ZSTD_LEVEL: i32 = 3;
+
+/// `vec_symbols` keeps each (workspace, language) in its own chunks, so a
+/// language filter reads only that language's vectors.
+const VEC0_PARTITIONS: &str = "workspace TEXT PARTITION KEY, language TEXT PARTITION KEY";
+/// Symbol columns whose updates re-key or replace a row's `vec_symbols`
+/// entry.
+const VEC0_UPDATE_COLUMNS: &str = "embedding, workspace";
+/// Vectors per `vec_symbols` chunk. Every partition allocates at least one
+/// chunk, so this is kept well under vec0's default of 1024 for small
+/// languages' sake.
+const VEC0_CHUNK_SIZE: usize = 256;
+
+/// How long a statement waits on another connection's lock before failing
+/// with SQLITE_BUSY.
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Attempts to begin a write transaction that still comes back busy after
+/// `BUSY_TIMEOUT`, doubling the pause from `WRITE_RETRY_BASE` each time.
+const WRITE_RETRIES: u32 = 4;
+const WRITE_RETRY_BASE: Duration = Duration::from_millis(100);
+
+/// An index session (`begin_ingest`) holds the writer lease from just
+/// before its transaction begins until it ends. Other writes that get the
+/// write lock in between yield to the lease for up to `LEASE_GRACE`; a
+/// lease still there after that was left by a session that died, since a
+/// live holder has the write lock for the whole ingestion.
+const LEASE_GRACE: Duration = Duration::from_secs(1);
+const LEASE_POLL: Duration = Duration::from_millis(50);
+
+/// How long `touch_files` batches search hits before writing them, so
+/// searches don't each take the write lock.
+const HIT_FLUSH_INTERVAL: Duration = Duration::from_secs(30);
 
 #[derive(Debug, Clone)]
 pub struct FileRow {
@@ -28,35 +98,540 @@ pub struct SearchResult {
     pub line: i32,
     pub end_line: Option<i32>,
     pub signature: Option<String>,
+    pub doc_comment: Option<String>,
+    /// See [`symbol_id`]
+    pub symbol_id: String,
+    /// JSON object attached by the extractor
+    pub metadata: Option<String>,
+    pub score: f64,
+}
+
+/// A symbol embedded by a model other than the loaded one; see
+/// `SearchDB::stale_embeddings`.
+#[derive(Debug, Clone)]
+pub struct StaleEmbedding {
+    pub file_path: String,
+    pub line: i32,
+    pub name: String,
+    pub kind: String,
+    /// None for rows whose model wasn't recorded
+    pub model: Option<String>,
+}
+
+/// A topical group of symbols; see `SearchDB::set_clusters`.
+#[derive(Debug, Clone)]
+pub struct Cluster {
+    pub id: u32,
+    pub label: String,
+    /// Most specific name terms, best first
+    pub top_terms: Vec<String>,
+    pub size: u64,
+}
+
+/// Two symbols with near-identical embeddings; see
+/// `SearchDB::near_duplicates`.
+#[derive(Debug, Clone)]
+pub struct DuplicatePair {
+    pub a: SearchResult,
+    pub b: SearchResult,
+    pub similarity: f64,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct DuplicateScan {
+    /// Most similar first
+    pub pairs: Vec<DuplicatePair>,
+    /// Pairs at or above the threshold, including those past the limit
+    pub total: u64,
+    /// Clusters compared; none means `set_clusters` never ran
+    pub clusters: u64,
+    /// Embedded symbols matching the filters but in no cluster, so never
+    /// compared
+    pub unclustered: u64,
+}
+
+/// A result as recorded in the query log: identity key plus the score it had.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct LoggedResult {
+    pub key: String,
     pub score: f64,
 }
 
+#[derive(Debug, Clone)]
+pub struct QueryLogEntry {
+    pub id: i64,
+    pub query: String,
+    pub results: Vec<LoggedResult>,
+    pub clicks: Vec<String>,
+    pub created_at: i64,
+}
+
+/// Primary key of a symbol or chunk: `(workspace, file_path, line)`, the
+/// line being a chunk's start line.
+pub type RowKey = (String, String, i32);
+
+/// A stored symbol's metadata and embedding text, enough to embed it again.
+#[derive(Debug, Clone)]
+pub struct StoredSymbol {
+    pub file_path: String,
+    pub line: i32,
+    pub name: String,
+    pub kind: String,
+    pub language: String,
+    pub end_line: Option<i32>,
+    pub signature: Option<String>,
+    pub embedding_text: String,
+    pub doc_comment: Option<String>,
+    pub metadata: Option<String>,
+}
+
+/// What one `evict_lru` pass dropped.
+#[derive(Debug, Clone, Default)]
+pub struct EvictionStats {
+    pub files: u64,
+    pub symbols: u64,
+    pub chunks: u64,
+    /// Vector bytes dropped (embeddings, doc embeddings, and their codes)
+    pub bytes: u64,
+}
+
+/// A stored vector that failed `verify_embeddings`.
+#[derive(Debug, Clone)]
+pub struct CorruptRow {
+    /// "symbols" or "chunks"
+    pub table: &'static str,
+    pub workspace: String,
+    pub file_path: String,
+    /// Symbol line, or chunk start line
+    pub line: i32,
+    /// "embedding" or "doc_embedding"
+    pub column: &'static str,
+    pub problem: String,
+}
+
+/// Output of `verify_embeddings`.
+#[derive(Debug, Clone, Default)]
+pub struct IntegrityReport {
+    pub rows_checked: u64,
+    pub corrupt: Vec<CorruptRow>,
+    /// Rows deleted when repairing
+    pub deleted: u64,
+}
+
+/// A file's rows flagged by `check_consistency`.
+#[derive(Debug, Clone)]
+pub struct FileDrift {
+    pub workspace: String,
+    pub path: String,
+    /// Orphaned rows, or the file record's expected symbol count
+    pub rows: u64,
+}
+
+/// Output of `check_consistency`.
+#[derive(Debug, Clone, Default)]
+pub struct ConsistencyReport {
+    /// Files with symbols but no file record
+    pub orphan_symbols: Vec<FileDrift>,
+    /// Files with chunks but no file record
+    pub orphan_chunks: Vec<FileDrift>,
+    /// File records claiming symbols that aren't stored
+    pub missing_symbols: Vec<FileDrift>,
+    /// Orphaned rows deleted when repairing
+    pub deleted: u64,
+}
+
 #[derive(Debug, Clone)]
 pub struct Stats {
     pub symbol_count: i64,
     pub file_count: i64,
 }
 
+/// Row filters, score bound, and scan options for a search.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Filters<'a> {
+    /// Workspace to search instead of the connection's (see `set_workspace`)
+    pub workspace: Option<&'a str>,
+    pub language: Option<&'a str>,
+    pub kind: Option<&'a str>,
+    pub path_prefix: Option<&'a str>,
+    /// Regular expressions (`regex` crate syntax, unanchored) that file paths
+    /// and names must match, through the `REGEXP` function; see
+    /// `register_regexp`. Chunks have no names, so a name pattern skips them.
+    pub path_regex: Option<&'a str>,
+    pub name_regex: Option<&'a str>,
+    /// Boolean combination of language, kind and path conditions, ANDed
+    /// with the other filters
+    pub expr: Option<&'a FilterExpr>,
+    /// `(key, value)` pairs a symbol's metadata object must all have as
+    /// top-level members. Chunks have no metadata, so they never match.
+    pub metadata: &'a [(String, rusqlite::types::Value)],
+    /// Only rows of files indexed at or after this time (Unix ms)
+    pub min_indexed_at: Option<i64>,
+    /// Only rows of files indexed at or before this time (Unix ms)
+    pub max_indexed_at: Option<i64>,
+    /// Rows scoring below this are skipped during the scan. Pushed down as a
+    /// distance bound (score = 1 - L2²/2), so hopeless rows are never
+    /// materialized or pushed through the heap.
+    pub min_score: Option<f64>,
+    /// Shortlist symbols by Hamming distance between 1-bit embeddings, then
+    /// score only the shortlist exactly. Much less data read per search, at
+    /// some cost in recall. Ignored for doc comment searches.
+    pub fast_prefilter: bool,
+    /// Like `fast_prefilter`, but shortlist by product-quantized distance
+    /// (see `set_pq_codebook`). Falls back to a full scan without a codebook.
+    pub quantized: bool,
+}
+
+impl Filters<'_> {
+    /// Largest L2² distance that can still reach `min_score`.
+    fn max_distance(&self) -> f64 {
+        self.min_score.map_or(f64::INFINITY, |s| 2.0 * (1.0 - s))
+    }
+
+    /// Whether the filters only touch columns `vec_symbols` has, so vec0's
+    /// KNN query can apply them.
+    fn vec0_filterable(&self) -> bool {
+        self.metadata.is_empty()
+            && self.min_indexed_at.is_none()
+            && self.max_indexed_at.is_none()
+            && self.path_regex.is_none()
+            && self.name_regex.is_none()
+            && self.expr.is_none()
+    }
+}
+
+/// A filter condition tree, deserialized from JSON such as
+/// `{"and": [{"or": [{"language": "go"}, {"language": "rust"}]},
+/// {"not": {"path": "tests"}}]}`. An empty `and` matches everything, an
+/// empty `or` nothing.
+#[derive(Debug, Clone, PartialEq, serde::Deserialize)]
+#[serde(rename_all = "lowercase", deny_unknown_fields)]
+pub enum FilterExpr {
+    And(Vec<FilterExpr>),
+    Or(Vec<FilterExpr>),
+    Not(Box<FilterExpr>),
+    Language(String),
+    Kind(String),
+    /// Paths under this directory, as `Filters::path_prefix`
+    Path(String),
+}
+
+impl FilterExpr {
+    /// The leaves' values mapped through `language` and `kind`, e.g. to
+    /// normalize them.
+    pub fn map_values(self, language: &impl Fn(&str) -> String, kind: &impl Fn(&str) -> String) -> Self {
+        match self {
+            FilterExpr::And(es) => {
+                FilterExpr::And(es.into_iter().map(|e| e.map_values(language, kind)).collect())
+            }
+            FilterExpr::Or(es) => {
+                FilterExpr::Or(es.into_iter().map(|e| e.map_values(language, kind)).collect())
+            }
+            FilterExpr::Not(e) => FilterExpr::Not(Box::new(e.map_values(language, kind))),
+            FilterExpr::Language(l) => FilterExpr::Language(language(&l)),
+            FilterExpr::Kind(k) => FilterExpr::Kind(kind(&k)),
+            FilterExpr::Path(p) => FilterExpr::Path(p),
+        }
+    }
+
+    /// A SQL condition, pushing its parameters in order. `chunks` compiles
+    /// for the chunks table, which has no kind column: chunks are kind
+    /// `"chunk"`.
+    fn to_sql(&self, chunks: bool, params: &mut Vec<Box<dyn rusqlite::types::ToSql>>) -> String {
+        let join = |es: &[FilterExpr], op: &str, empty: &str, params: &mut Vec<_>| {
+            if es.is_empty() {
+                return empty.to_string();
+            }
+            let parts: Vec<String> = es.iter().map(|e| e.to_sql(chunks, params)).collect();
+            format!("({})", parts.join(op))
+        };
+        match self {
+            FilterExpr::And(es) => join(es, " AND ", "1", params),
+            FilterExpr::Or(es) => join(es, " OR ", "0", params),
+            FilterExpr::Not(e) => format!("NOT {}", e.to_sql(chunks, params)),
+            FilterExpr::Language(l) => {
+                params.push(Box::new(l.clone()));
+                "language = ?".to_string()
+            }
+            FilterExpr::Kind(k) if chunks => {
+                if k == "chunk" { "1" } else { "0" }.to_string()
+            }
+            FilterExpr::Kind(k) => {
+                params.push(Box::new(k.clone()));
+                "kind = ?".to_string()
+            }
+            FilterExpr::Path(p) => {
+                let prefix = p.trim_end_matches('/');
+                params.push(Box::new(format!("{}/", prefix)));
+                params.push(Box::new(format!("{}0", prefix)));
+                "(file_path >= ? AND file_path < ?)".to_string()
+            }
+        }
+    }
+}
+
+/// `where_str` (a `WHERE ...` clause) with `expr` ANDed on.
+fn push_expr(
+    where_str: String,
+    param_values: &mut Vec<Box<dyn rusqlite::types::ToSql>>,
+    expr: Option<&FilterExpr>,
+    chunks: bool,
+) -> String {
+    match expr {
+        Some(expr) => format!("{} AND {}", where_str, expr.to_sql(chunks, param_values)),
+        None => where_str,
+    }
+}
+
+/// Connection settings for `SearchDB::open_with`; None keeps the default.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct OpenOptions {
+    /// Bytes per page, a power of two from 512 to 65536. Fixed when the
+    /// index is created.
+    pub page_size: Option<u32>,
+    /// Bytes of the file to memory-map (default 3 GB)
+    pub mmap_size: Option<i64>,
+    /// Page cache: pages if positive, KiB if negative (default 64 MB), as
+    /// `PRAGMA cache_size`
+    pub cache_size: Option<i64>,
+    /// `PRAGMA synchronous`: "off", "normal", "full", or "extra"
+    pub synchronous: Option<&'static str>,
+}
+
+/// Settings read back by `SearchDB::pragmas`.
+#[derive(Debug, Clone, Copy)]
+pub struct Pragmas {
+    pub page_size: u32,
+    pub mmap_size: i64,
+    pub cache_size: i64,
+    pub synchronous: &'static str,
+}
+
+/// Order of `list_symbols` pages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SymbolOrder {
+    /// By file path, then line
+    Path,
+    /// Most recently indexed files first, then by path and line
+    Recent,
+}
+
+/// Where exact symbol searches find their vectors.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VectorStorage {
+    /// Stream `symbols.embedding` BLOBs and score them with simsimd
+    Blob,
+    /// Also keep every embedding in the sqlite-vec `vec0` table
+    /// `vec_symbols`, maintained by triggers on `symbols` and partitioned by
+    /// workspace and language, and search it with vec0's KNN query. Doc
+    /// comment, prefiltered, and quantized searches still read the BLOBs.
+    Vec0,
+}
+
+impl VectorStorage {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "blob" => Some(VectorStorage::Blob),
+            "vec0" => Some(VectorStorage::Vec0),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            VectorStorage::Blob => "blob",
+            VectorStorage::Vec0 => "vec0",
+        }
+    }
+}
+
+/// How a filtered symbol search reads rows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScanPlan {
+    /// Seek through the (language, kind)/kind index or a primary key range.
+    /// Best when the filter is selective.
+    Indexed,
+    /// Read the table in clustered order (narrowed only by a path prefix
+    /// range), filtering in the same pass. Avoids a per-row index lookup when
+    /// most rows match. Index use is disabled with unary `+` on the filtered
+    /// columns; `NOT INDEXED` doesn't stop it on WITHOUT ROWID tables.
+    FullScan,
+}
+
+impl ScanPlan {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ScanPlan::Indexed => "indexed",
+            ScanPlan::FullScan => "full_scan",
+        }
+    }
+}
+
+/// Output of `explain_search`: the plan a filtered search would use.
+#[derive(Debug, Clone)]
+pub struct SearchExplain {
+    pub plan: ScanPlan,
+    /// Rows matching the filters, counted up to `cap + 1`
+    pub matching_rows: u64,
+    pub cap: u64,
+    pub sql: String,
+}
+
+/// Work done by searches since the last `take_scan_stats`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ScanStats {
+    /// Rows whose vector or codes were scored
+    pub rows: u64,
+    /// Time spent in `search`, `search_docs`, and `search_chunks`
+    pub elapsed: Duration,
+}
+
 pub struct SearchDB {
     conn: Connection,
+    /// See `DEFAULT_PREFILTER_CAP`.
+    prefilter_cap: u64,
+    /// Every read and write is scoped to this workspace; "" is the default.
+    workspace: String,
+    /// PQ codebook from `pq_codebook`, if one has been trained.
+    pq: Option<Arc<Codebook>>,
+    /// See `set_vector_storage`.
+    storage: VectorStorage,
+    /// See `set_text_compression`.
+    compress_text: bool,
+    /// Identifies this connection as the holder of the writer lease.
+    session: String,
+    /// Set while `begin_ingest`'s transaction is open.
+    ingesting: bool,
+    /// See `generation`.
+    generation: Cell<u64>,
+    /// SQLite's `data_version` when `generation` last checked it.
+    data_version: Cell<i64>,
+    /// See `take_scan_stats`.
+    scan_stats: Cell<ScanStats>,
+    /// Files hit by searches since `hits_flushed`, by workspace, not yet
+    /// written (see `touch_files`).
+    pending_hits: RefCell<HashMap<String, HashSet<String>>>,
+    hits_flushed: Cell<Instant>,
+    /// How the connection was opened, for reopening the same way.
+    options: OpenOptions,
+    readonly: bool,
 }
 
 impl SearchDB {
+    /// Open (or create) an index. `":memory:"` keeps the whole index in RAM;
+    /// persist it with `backup_to`.
     pub fn open(db_path: &Path) -> SqlResult<Self> {
-        if let Some(parent) = db_path.parent() {
-            std::fs::create_dir_all(parent).ok();
+        Self::open_with(db_path, &OpenOptions::default())
+    }
+
+    /// `open` with connection settings. `options.page_size` only applies to
+    /// a new index; compare `pragmas().page_size` to see if it took.
+    pub fn open_with(db_path: &Path, options: &OpenOptions) -> SqlResult<Self> {
+        if db_path != Path::new(":memory:") {
+            if let Some(parent) = db_path.parent() {
+                std::fs::create_dir_all(parent).ok();
+            }
         }
 
+        register_sqlite_vec();
         let conn = Connection::open(db_path)?;
+        conn.busy_timeout(BUSY_TIMEOUT)?;
+        register_regexp(&conn)?;
+
+        // Must precede the first table (and WAL mode) to take effect
+        if let Some(page_size) = options.page_size {
+            conn.pragma_update(None, "page_size", page_size)?;
+        }
 
         // Performance pragmas
         conn.pragma_update(None, "journal_mode", "WAL")?;
-        conn.pragma_update(None, "mmap_size", 3_000_000_000i64)?;
-        conn.pragma_update(None, "temp_store", 2)?; // memory
-        conn.pragma_update(None, "cache_size", -64000)?; // 64MB
+        apply_pragmas(&conn, options)?;
 
-        let mut db = Self { conn };
+        let mut db = Self {
+            conn,
+            prefilter_cap: DEFAULT_PREFILTER_CAP,
+            workspace: String::new(),
+            pq: None,
+            storage: VectorStorage::Blob,
+            compress_text: false,
+            session: format!("pid {} at {}", std::process::id(), now_millis()),
+            ingesting: false,
+            generation: Cell::new(0),
+            data_version: Cell::new(0),
+            scan_stats: Cell::new(ScanStats::default()),
+            pending_hits: RefCell::new(HashMap::new()),
+            hits_flushed: Cell::new(Instant::now()),
+            options: *options,
+            readonly: false,
+        };
         db.init_schema()?;
+        db.load_pq()?;
+        db.load_storage()?;
+        db.upgrade_vec0()?;
+        Ok(db)
+    }
+
+    /// Open an existing index without writing to it: no schema creation,
+    /// no meta rows, no journal mode change. For indexes on read-only media.
+    ///
+    /// Fails if the index was built with a different schema version, since
+    /// it can't be migrated in place.
+    pub fn open_readonly(db_path: &Path) -> SqlResult<Self> {
+        Self::open_readonly_with(db_path, &OpenOptions::default())
+    }
+
+    /// `open_readonly` with connection settings; `options.page_size` is
+    /// ignored.
+    pub fn open_readonly_with(db_path: &Path, options: &OpenOptions) -> SqlResult<Self> {
+        register_sqlite_vec();
+        let conn = Connection::open_with_flags(
+            db_path,
+            OpenFlags::SQLITE_OPEN_READ_ONLY
+                | OpenFlags::SQLITE_OPEN_URI
+                | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+        )?;
+        conn.busy_timeout(BUSY_TIMEOUT)?;
+        register_regexp(&conn)?;
+
+        apply_pragmas(&conn, options)?;
+
+        let version: Option<String> = conn
+            .query_row(
+                "SELECT value FROM meta WHERE key = 'schema_version'",
+                [],
+                |r| r.get(0),
+            )
+            .optional()?;
+        let version = version.and_then(|v| v.parse::<i32>().ok());
+        if version != Some(SCHEMA_VERSION) {
+            return Err(rusqlite::Error::SqliteFailure(
+                rusqlite::ffi::Error::new(rusqlite::ffi::SQLITE_MISMATCH),
+                Some(format!(
+                    "index schema version {:?} does not match expected {}",
+                    version, SCHEMA_VERSION
+                )),
+            ));
+        }
+
+        let mut db = Self {
+            conn,
+            prefilter_cap: DEFAULT_PREFILTER_CAP,
+            workspace: String::new(),
+            pq: None,
+            storage: VectorStorage::Blob,
+            compress_text: false,
+            session: format!("pid {} at {}", std::process::id(), now_millis()),
+            ingesting: false,
+            generation: Cell::new(0),
+            data_version: Cell::new(0),
+            scan_stats: Cell::new(ScanStats::default()),
+            pending_hits: RefCell::new(HashMap::new()),
+            hits_flushed: Cell::new(Instant::now()),
+            options: *options,
+            readonly: true,
+        };
+        db.load_pq()?;
+        db.load_storage()?;
         Ok(db)
     }
 
@@ -82,7 +657,7 @@ impl SearchDB {
 
             if let Some(v) = version {
                 if v.parse::<i32>().unwrap_or(0) == SCHEMA_VERSION {
-                    return Ok(());
+                    return self.conn.execute_batch(ADDED_INDEXES);
                 }
             }
 
@@ -90,7 +665,17 @@ impl SearchDB {
             self.conn.execute_batch(
                 "DROP TABLE IF EXISTS files;
                  DROP TABLE IF EXISTS symbols;
+                 DROP TABLE IF EXISTS symbol_windows;
+                 DROP TABLE IF EXISTS symbol_tokens;
+                 DROP TABLE IF EXISTS sparse_terms;
+                 DROP TABLE IF EXISTS edges;
+                 DROP TABLE IF EXISTS chunks;
                  DROP TABLE IF EXISTS vec_symbols;
+                 DROP TABLE IF EXISTS query_clicks;
+                 DROP TABLE IF EXISTS query_log;
+                 DROP TABLE IF EXISTS symbol_stats;
+                 DROP TABLE IF EXISTS pq_codebook;
+                 DROP TABLE IF EXISTS clusters;
                  DROP TABLE IF EXISTS meta;",
             )?;
         }
@@ -101,15 +686,26 @@ impl SearchDB {
                 value TEXT NOT NULL
             );
 
+            -- workspace partitions one DB into isolated indexes ('' = default)
             CREATE TABLE IF NOT EXISTS files (
-                path TEXT PRIMARY KEY,
+                workspace TEXT NOT NULL DEFAULT '',
+                path TEXT NOT NULL,
                 hash TEXT NOT NULL,
                 language TEXT,
                 symbol_count INTEGER,
-                indexed_at INTEGER NOT NULL
+                indexed_at INTEGER NOT NULL,
+                -- last time one of the file's rows came back from a search
+                last_hit_at INTEGER,
+                -- set while the file's vectors are evicted (see evict_lru)
+                evicted_at INTEGER,
+                -- set while the file is soft-deleted; its rows then live in
+                -- the workspace's tombstone partition (see soft_delete_files)
+                deleted_at INTEGER,
+                PRIMARY KEY (workspace, path)
             );
 
             CREATE TABLE IF NOT EXISTS symbols (
+                workspace TEXT NOT NULL DEFAULT '',
                 file_path TEXT NOT NULL,
                 line INTEGER NOT NULL,
                 name TEXT NOT NULL,
@@ -118,12 +714,159 @@ impl SearchDB {
                 end_line INTEGER,
                 signature TEXT,
                 embedding_text TEXT NOT NULL,
+                doc_comment TEXT,
+                symbol_id TEXT NOT NULL,
+                -- JSON object of extractor-defined attributes (see
+                -- Filters::metadata)
+                metadata TEXT,
+                -- name of the model that computed embedding (see
+                -- stale_embeddings)
+                embedding_model TEXT,
+                -- id in `clusters` from the last cluster_index run; NULL for
+                -- symbols indexed since
+                cluster INTEGER,
+                -- sign bits of embedding (see binarize), ahead of the full
+                -- vector so prefilter scans don't read its overflow pages
+                embedding_bits BLOB,
+                -- product-quantized embedding, once a codebook is trained
+                pq_codes BLOB,
+                -- NULL while evicted; embedding_text is kept to restore it
+                embedding BLOB,
+                doc_embedding BLOB,
+                PRIMARY KEY (workspace, file_path, line)
+            ) WITHOUT ROWID;
+
+            -- (workspace, language, kind) also serves language-only filters
+            DROP INDEX IF EXISTS idx_symbols_language;
+            CREATE INDEX IF NOT EXISTS idx_symbols_language_kind ON symbols(workspace, language, kind);
+            CREATE INDEX IF NOT EXISTS idx_symbols_kind ON symbols(workspace, kind);
+            CREATE INDEX IF NOT EXISTS idx_symbols_symbol_id ON symbols(symbol_id);
+            -- exact and prefix name lookups, case-insensitive (see symbols_by_name)
+            CREATE INDEX IF NOT EXISTS idx_symbols_name ON symbols(workspace, name COLLATE NOCASE);
+
+            -- embeddings of a long symbol's windows after the first (its
+            -- symbols.embedding); searches score the symbol by its best window
+            CREATE TABLE IF NOT EXISTS symbol_windows (
+                workspace TEXT NOT NULL,
+                file_path TEXT NOT NULL,
+                line INTEGER NOT NULL,
+                seq INTEGER NOT NULL,
                 embedding BLOB NOT NULL,
-                PRIMARY KEY (file_path, line)
+                PRIMARY KEY (workspace, file_path, line, seq)
+            ) WITHOUT ROWID;
+
+            CREATE TRIGGER IF NOT EXISTS symbols_delete_windows AFTER DELETE ON symbols
+            BEGIN
+                DELETE FROM symbol_windows
+                WHERE workspace = old.workspace AND file_path = old.file_path AND line = old.line;
+            END;
+
+            -- a symbol's token-group vectors for late interaction scoring
+            -- (see max_sim), `vectors` packed back to back
+            CREATE TABLE IF NOT EXISTS symbol_tokens (
+                workspace TEXT NOT NULL,
+                file_path TEXT NOT NULL,
+                line INTEGER NOT NULL,
+                vectors BLOB NOT NULL,
+                PRIMARY KEY (workspace, file_path, line)
+            ) WITHOUT ROWID;
+
+            CREATE TRIGGER IF NOT EXISTS symbols_delete_tokens AFTER DELETE ON symbols
+            BEGIN
+                DELETE FROM symbol_tokens
+                WHERE workspace = old.workspace AND file_path = old.file_path AND line = old.line;
+            END;
+
+            -- postings of symbols' sparse lexical vectors (see tf_weights),
+            -- by tokenizer term id
+            CREATE TABLE IF NOT EXISTS sparse_terms (
+                workspace TEXT NOT NULL,
+                term INTEGER NOT NULL,
+                file_path TEXT NOT NULL,
+                line INTEGER NOT NULL,
+                weight REAL NOT NULL,
+                PRIMARY KEY (workspace, term, file_path, line)
+            ) WITHOUT ROWID;
+
+            CREATE INDEX IF NOT EXISTS idx_sparse_terms_symbol ON sparse_terms(workspace, file_path, line);
+
+            CREATE TRIGGER IF NOT EXISTS symbols_delete_sparse AFTER DELETE ON symbols
+            BEGIN
+                DELETE FROM sparse_terms
+                WHERE workspace = old.workspace AND file_path = old.file_path AND line = old.line;
+            END;
+
+            -- lowercase trigrams of every symbol name ever indexed, across
+            -- workspaces, for fuzzy name lookup (see symbols_by_name). Names
+            -- whose symbols are gone linger; lookups skip them
+            CREATE TABLE IF NOT EXISTS name_trigrams (
+                trigram TEXT NOT NULL,
+                name TEXT NOT NULL,
+                PRIMARY KEY (trigram, name)
+            ) WITHOUT ROWID;
+
+            -- call/reference graph between symbol ids (see insert_edges)
+            CREATE TABLE IF NOT EXISTS edges (
+                workspace TEXT NOT NULL,
+                caller TEXT NOT NULL,
+                callee TEXT NOT NULL,
+                PRIMARY KEY (workspace, caller, callee)
+            ) WITHOUT ROWID;
+
+            CREATE INDEX IF NOT EXISTS idx_edges_callee ON edges(workspace, callee);
+
+            CREATE TABLE IF NOT EXISTS chunks (
+                workspace TEXT NOT NULL DEFAULT '',
+                file_path TEXT NOT NULL,
+                start_line INTEGER NOT NULL,
+                end_line INTEGER NOT NULL,
+                language TEXT NOT NULL,
+                text TEXT NOT NULL,
+                -- NULL while evicted
+                embedding BLOB,
+                PRIMARY KEY (workspace, file_path, start_line)
+            ) WITHOUT ROWID;
+
+            CREATE TABLE IF NOT EXISTS query_log (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                query TEXT NOT NULL,
+                results TEXT NOT NULL,
+                created_at INTEGER NOT NULL
+            );
+
+            CREATE TABLE IF NOT EXISTS query_clicks (
+                query_id INTEGER NOT NULL REFERENCES query_log(id),
+                result_key TEXT NOT NULL,
+                clicked_at INTEGER NOT NULL
+            );
+
+            CREATE INDEX IF NOT EXISTS idx_query_clicks_query ON query_clicks(query_id);
+
+            -- times each result (by its query log key) was opened
+            CREATE TABLE IF NOT EXISTS symbol_stats (
+                workspace TEXT NOT NULL,
+                key TEXT NOT NULL,
+                opens INTEGER NOT NULL,
+                last_opened_at INTEGER NOT NULL,
+                PRIMARY KEY (workspace, key)
             ) WITHOUT ROWID;
 
-            CREATE INDEX IF NOT EXISTS idx_symbols_language ON symbols(language);
-            CREATE INDEX IF NOT EXISTS idx_symbols_kind ON symbols(kind);",
+            -- single row: the serialized pq::Codebook
+            CREATE TABLE IF NOT EXISTS pq_codebook (
+                id INTEGER PRIMARY KEY CHECK (id = 0),
+                codebook BLOB NOT NULL
+            );
+
+            -- topical clusters from the last cluster_index run (see
+            -- set_clusters); top_terms is a JSON array
+            CREATE TABLE IF NOT EXISTS clusters (
+                workspace TEXT NOT NULL,
+                id INTEGER NOT NULL,
+                label TEXT NOT NULL,
+                top_terms TEXT NOT NULL,
+                size INTEGER NOT NULL,
+                PRIMARY KEY (workspace, id)
+            ) WITHOUT ROWID;",
         )?;
 
         self.conn.execute(
@@ -139,14 +882,75 @@ impl SearchDB {
             params!["dimensions", "768"],
         )?;
 
+        self.conn.execute_batch(ADDED_INDEXES)
+    }
+
+    /// Read a value from the `meta` table.
+    pub fn get_meta(&self, key: &str) -> SqlResult<Option<String>> {
+        self.conn
+            .query_row("SELECT value FROM meta WHERE key = ?", params![key], |r| {
+                r.get(0)
+            })
+            .optional()
+    }
+
+    /// Remove a key from the `meta` table.
+    pub fn delete_meta(&self, key: &str) -> SqlResult<()> {
+        self.write(|conn| conn.execute("DELETE FROM meta WHERE key = ?", params![key]))?;
+        Ok(())
+    }
+
+    /// Write a value to the `meta` table.
+    pub fn set_meta(&self, key: &str, value: &str) -> SqlResult<()> {
+        self.write(|conn| {
+            conn.execute(
+                "INSERT OR REPLACE INTO meta (key, value) VALUES (?, ?)",
+                params![key, value],
+            )
+        })?;
         Ok(())
     }
 
+    /// Scope later reads and writes to `workspace` ("" is the default).
+    pub fn set_workspace(&mut self, workspace: &str) {
+        self.workspace = workspace.to_string();
+    }
+
+    pub fn workspace(&self) -> &str {
+        &self.workspace
+    }
+
+    /// File the index lives in; None for `":memory:"`.
+    pub fn path(&self) -> Option<&str> {
+        self.conn.path().filter(|p| !p.is_empty())
+    }
+
+    /// The settings the index was opened with.
+    pub fn open_options(&self) -> OpenOptions {
+        self.options
+    }
+
+    /// Whether the index was opened with `open_readonly_with`.
+    pub fn is_readonly(&self) -> bool {
+        self.readonly
+    }
+
+    /// Workspaces with at least one indexed file, sorted.
+    pub fn list_workspaces(&self) -> SqlResult<Vec<String>> {
+        let mut stmt = self
+            .conn
+            .prepare_cached(
+                "SELECT DISTINCT workspace FROM files WHERE deleted_at IS NULL ORDER BY workspace",
+            )?;
+        let rows = stmt.query_map([], |r| r.get(0))?;
+        rows.collect()
+    }
+
     pub fn get_all_files(&self) -> SqlResult<Vec<FileRow>> {
         let mut stmt = self.conn.prepare_cached(
-            "SELECT path, hash, language, symbol_count, indexed_at FROM files",
+            "SELECT path, hash, language, symbol_count, indexed_at FROM files WHERE workspace = ?",
         )?;
-        let rows = stmt.query_map([], |r| {
+        let rows = stmt.query_map(params![self.workspace], |r| {
             Ok(FileRow {
                 path: r.get(0)?,
                 hash: r.get(1)?,
@@ -158,92 +962,629 @@ impl SearchDB {
         rows.collect()
     }
 
+    /// Look up files by path, in input order; None for paths not indexed.
+    pub fn get_files(&self, paths: &[&str]) -> SqlResult<Vec<Option<FileRow>>> {
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT path, hash, language, symbol_count, indexed_at FROM files
+             WHERE workspace = ? AND path = ?",
+        )?;
+        paths
+            .iter()
+            .map(|path| {
+                stmt.query_row(params![self.workspace, path], |r| {
+                    Ok(FileRow {
+                        path: r.get(0)?,
+                        hash: r.get(1)?,
+                        language: r.get(2)?,
+                        symbol_count: r.get(3)?,
+                        indexed_at: r.get(4)?,
+                    })
+                })
+                .optional()
+            })
+            .collect()
+    }
+
+    /// Whether each path is indexed, in input order.
+    pub fn has_files(&self, paths: &[&str]) -> SqlResult<Vec<bool>> {
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT EXISTS (SELECT 1 FROM files WHERE workspace = ? AND path = ?)",
+        )?;
+        paths
+            .iter()
+            .map(|path| stmt.query_row(params![self.workspace, path], |r| r.get(0)))
+            .collect()
+    }
+
+    /// Soft-delete files: move their file record, symbols, and chunks into
+    /// the workspace's tombstone partition, which no search or listing
+    /// reads, until `restore_files` brings them back or `purge_tombstones`
+    /// drops them. An older tombstone for the same path is replaced. Returns
+    /// the number of files tombstoned.
+    pub fn soft_delete_files(&mut self, paths: &[&str]) -> SqlResult<u64> {
+        let tombstone = tombstone_workspace(&self.workspace);
+        let now = now_millis();
+        let tx = self.write_tx()?;
+        let mut deleted = 0;
+        for path in paths {
+            let exists: bool = tx.query_row(
+                "SELECT EXISTS (SELECT 1 FROM files WHERE workspace = ? AND path = ?)",
+                params![self.workspace, path],
+                |r| r.get(0),
+            )?;
+            if !exists {
+                continue;
+            }
+            delete_file_rows(&tx, &tombstone, path)?;
+            move_file_rows(&tx, &self.workspace, &tombstone, path)?;
+            tx.execute(
+                "UPDATE files SET deleted_at = ? WHERE workspace = ? AND path = ?",
+                params![now, tombstone, path],
+            )?;
+            deleted += 1;
+        }
+        tx.commit()?;
+        Ok(deleted)
+    }
+
+    /// Soft-deleted files in the current workspace as `(path, deleted_at)`,
+    /// most recent first.
+    pub fn tombstones(&self) -> SqlResult<Vec<(String, i64)>> {
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT path, deleted_at FROM files WHERE workspace = ? ORDER BY deleted_at DESC, path",
+        )?;
+        let rows = stmt.query_map(params![tombstone_workspace(&self.workspace)], |r| {
+            Ok((r.get(0)?, r.get(1)?))
+        })?;
+        rows.collect()
+    }
+
+    /// Undo `soft_delete_files` for `paths`, or for every tombstone in the
+    /// current workspace when None. Paths indexed again since their
+    /// deletion keep the new rows and their tombstones stay until purged.
+    /// Returns the number of files restored.
+    pub fn restore_files(&mut self, paths: Option<&[&str]>) -> SqlResult<u64> {
+        let tombstone = tombstone_workspace(&self.workspace);
+        let paths: Vec<String> = match paths {
+            Some(paths) => paths.iter().map(|p| p.to_string()).collect(),
+            None => self.tombstones()?.into_iter().map(|(path, _)| path).collect(),
+        };
+        let tx = self.write_tx()?;
+        let mut restored = 0;
+        for path in &paths {
+            let (tombstoned, live): (bool, bool) = tx.query_row(
+                "SELECT EXISTS (SELECT 1 FROM files WHERE workspace = ?1 AND path = ?3),
+                        EXISTS (SELECT 1 FROM files WHERE workspace = ?2 AND path = ?3)",
+                params![tombstone, self.workspace, path],
+                |r| Ok((r.get(0)?, r.get(1)?)),
+            )?;
+            if !tombstoned || live {
+                continue;
+            }
+            move_file_rows(&tx, &tombstone, &self.workspace, path)?;
+            tx.execute(
+                "UPDATE files SET deleted_at = NULL WHERE workspace = ? AND path = ?",
+                params![self.workspace, path],
+            )?;
+            restored += 1;
+        }
+        tx.commit()?;
+        Ok(restored)
+    }
+
+    /// Permanently delete files soft-deleted at or before `cutoff` (ms since the
+    /// epoch), across all workspaces. Returns the number of files purged.
+    pub fn purge_tombstones(&mut self, cutoff: i64) -> SqlResult<u64> {
+        let tx = self.write_tx()?;
+        let victims: Vec<(String, String)> = tx
+            .prepare("SELECT workspace, path FROM files WHERE deleted_at <= ?")?
+            .query_map(params![cutoff], |r| Ok((r.get(0)?, r.get(1)?)))?
+            .collect::<SqlResult<_>>()?;
+        for (workspace, path) in &victims {
+            delete_file_rows(&tx, workspace, path)?;
+        }
+        tx.commit()?;
+        Ok(victims.len() as u64)
+    }
+
     /// Search using mmap'd streaming + simsimd NEON L2².
     ///
     /// Streams rows from SQLite, applies optional filters, computes L2² distance
-    /// via simsimd ARM NEON, and maintains a top-K max-heap.
-    /// Score = 1 - (L2² / 2), mapping back to cosine similarity for L2-normalized vectors.
+    /// via simsimd ARM NEON, and maintains a top-K max-heap (see `scan_top_k`).
     pub fn search(
         &self,
         query_embedding: &[f32],
         top_k: i32,
-        language: Option<&str>,
-        kind: Option<&str>,
-        path_prefix: Option<&str>,
+        filters: &Filters,
+    ) -> SqlResult<Vec<SearchResult>> {
+        self.timed(|| self.search_symbols(false, query_embedding, top_k, filters))
+    }
+
+    /// Like `search`, but scores each symbol by its doc comment embedding.
+    /// Symbols without a doc comment are skipped.
+    pub fn search_docs(
+        &self,
+        query_embedding: &[f32],
+        top_k: i32,
+        filters: &Filters,
     ) -> SqlResult<Vec<SearchResult>> {
-        // Build query with optional WHERE filters
-        let mut where_clauses = Vec::new();
-        let mut param_values: Vec<Box<dyn rusqlite::types::ToSql>> = Vec::new();
+        self.timed(|| self.search_symbols(true, query_embedding, top_k, filters))
+    }
+
+    /// Rows scored and time spent searching since the last call, for
+    /// reporting per-search work.
+    pub fn take_scan_stats(&self) -> ScanStats {
+        self.scan_stats.take()
+    }
+
+    fn timed<T>(&self, f: impl FnOnce() -> T) -> T {
+        let start = Instant::now();
+        let out = f();
+        let mut stats = self.scan_stats.get();
+        stats.elapsed += start.elapsed();
+        self.scan_stats.set(stats);
+        out
+    }
+
+    fn count_rows(&self, n: u64) {
+        let mut stats = self.scan_stats.get();
+        stats.rows += n;
+        self.scan_stats.set(stats);
+    }
+
+    /// Set the row count above which filtered searches switch to a full scan.
+    pub fn set_prefilter_cap(&mut self, cap: u64) {
+        self.prefilter_cap = cap;
+    }
+
+    /// WHERE clause and parameters for a symbol search.
+    fn symbol_where(
+        &self,
+        by_doc: bool,
+        plan: ScanPlan,
+        filters: &Filters,
+    ) -> (String, Vec<Box<dyn rusqlite::types::ToSql>>) {
+        let mut where_clauses = vec!["workspace = ?"];
+        let mut param_values: Vec<Box<dyn rusqlite::types::ToSql>> =
+            vec![Box::new(filters.workspace.unwrap_or(&self.workspace).to_string())];
 
-        if let Some(prefix) = path_prefix {
-            where_clauses.push("file_path LIKE ?");
-            param_values.push(Box::new(format!("{}/%", prefix)));
+        if by_doc {
+            where_clauses.push("doc_embedding IS NOT NULL");
         }
-        if let Some(lang) = language {
-            where_clauses.push("language = ?");
+        if let Some(prefix) = filters.path_prefix {
+            push_path_prefix(&mut where_clauses, &mut param_values, prefix);
+        }
+        let indexed = plan == ScanPlan::Indexed;
+        if let Some(lang) = filters.language {
+            where_clauses.push(if indexed { "language = ?" } else { "+language = ?" });
             param_values.push(Box::new(lang.to_string()));
         }
-        if let Some(k) = kind {
-            where_clauses.push("kind = ?");
+        if let Some(k) = filters.kind {
+            where_clauses.push(if indexed { "kind = ?" } else { "+kind = ?" });
             param_values.push(Box::new(k.to_string()));
         }
+        push_regex(&mut where_clauses, &mut param_values, "file_path REGEXP ?", filters.path_regex);
+        push_regex(&mut where_clauses, &mut param_values, "name REGEXP ?", filters.name_regex);
+        push_metadata(&mut where_clauses, &mut param_values, filters.metadata);
+        self.push_indexed_at(&mut where_clauses, &mut param_values, filters);
+
+        let where_str = format!("WHERE {}", where_clauses.join(" AND "));
+        (push_expr(where_str, &mut param_values, filters.expr, false), param_values)
+    }
+
+    /// Filter to rows of files indexed within `filters`' `indexed_at` bounds.
+    /// Not correlated with the outer table, so it serves symbols and chunks
+    /// alike.
+    fn push_indexed_at(
+        &self,
+        where_clauses: &mut Vec<&'static str>,
+        param_values: &mut Vec<Box<dyn rusqlite::types::ToSql>>,
+        filters: &Filters,
+    ) {
+        if filters.min_indexed_at.is_none() && filters.max_indexed_at.is_none() {
+            return;
+        }
+        where_clauses.push(
+            "file_path IN (SELECT path FROM files
+                           WHERE workspace = ? AND indexed_at BETWEEN ? AND ?)",
+        );
+        param_values.push(Box::new(filters.workspace.unwrap_or(&self.workspace).to_string()));
+        param_values.push(Box::new(filters.min_indexed_at.unwrap_or(i64::MIN)));
+        param_values.push(Box::new(filters.max_indexed_at.unwrap_or(i64::MAX)));
+    }
 
-        let where_str = if where_clauses.is_empty() {
-            String::new()
+    /// Count rows matching `where_str`, stopping at `cap + 1`, and pick a plan.
+    /// Searches without a language or kind filter always scan the (workspace
+    /// and path prefix) primary key range; their rows are only counted when
+    /// `count_unfiltered` is set (0 otherwise).
+    fn plan_scan(
+        &self,
+        filtered: bool,
+        where_str: &str,
+        params: &[&dyn rusqlite::types::ToSql],
+        count_unfiltered: bool,
+    ) -> SqlResult<(ScanPlan, u64)> {
+        if !filtered {
+            let total: i64 = if count_unfiltered {
+                self.conn.query_row(
+                    &format!("SELECT count(*) FROM symbols {}", where_str),
+                    params,
+                    |r| r.get(0),
+                )?
+            } else {
+                0
+            };
+            return Ok((ScanPlan::FullScan, total as u64));
+        }
+        let sql = format!(
+            "SELECT count(*) FROM (SELECT 1 FROM symbols {} LIMIT {})",
+            where_str,
+            self.prefilter_cap.saturating_add(1)
+        );
+        let matching: i64 = self.conn.query_row(&sql, params, |r| r.get(0))?;
+        let plan = if matching as u64 > self.prefilter_cap {
+            ScanPlan::FullScan
         } else {
-            format!("WHERE {}", where_clauses.join(" AND "))
+            ScanPlan::Indexed
         };
+        Ok((plan, matching as u64))
+    }
 
-        // WITHOUT ROWID table is clustered by (file_path, line) — natural scan
+    fn symbol_sql(by_doc: bool, where_str: &str) -> String {
+        let embedding_col = if by_doc { "doc_embedding" } else { "embedding" };
+        // WITHOUT ROWID table is clustered by (workspace, file_path, line) — natural scan
         // order groups symbols by file. No ORDER BY needed.
-        let sql = format!(
-            "SELECT file_path, line, name, kind, language, end_line, signature, embedding
+        format!(
+            "SELECT file_path, line, name, kind, language, end_line, signature, doc_comment, symbol_id,
+                    metadata, {}
              FROM symbols {}",
+            embedding_col, where_str
+        )
+    }
+
+    /// Pick a plan for these filters and build the search WHERE clause and
+    /// parameters.
+    #[allow(clippy::type_complexity)]
+    fn plan_symbol_search(
+        &self,
+        explain: bool,
+        by_doc: bool,
+        filters: &Filters,
+    ) -> SqlResult<(ScanPlan, u64, String, Vec<Box<dyn rusqlite::types::ToSql>>)> {
+        let (where_str, param_values) = self.symbol_where(by_doc, ScanPlan::Indexed, filters);
+        let params_ref: Vec<&dyn rusqlite::types::ToSql> =
+            param_values.iter().map(|p| p.as_ref()).collect();
+        let filtered = filters.language.is_some() || filters.kind.is_some();
+        let (plan, matching_rows) = self.plan_scan(filtered, &where_str, &params_ref, explain)?;
+        drop(params_ref);
+
+        let (where_str, param_values) = match plan {
+            ScanPlan::Indexed => (where_str, param_values),
+            ScanPlan::FullScan => self.symbol_where(by_doc, plan, filters),
+        };
+        tracing::debug!(
+            "symbol scan: {} ({} matching rows, cap {}) {}",
+            plan.as_str(),
+            matching_rows,
+            self.prefilter_cap,
+            where_str
+        );
+        Ok((plan, matching_rows, where_str, param_values))
+    }
+
+    /// Report how a symbol search with these filters would run, without
+    /// running it.
+    pub fn explain_search(
+        &self,
+        by_doc: bool,
+        filters: &Filters,
+    ) -> SqlResult<SearchExplain> {
+        let (plan, matching_rows, where_str, _) =
+            self.plan_symbol_search(true, by_doc, filters)?;
+        Ok(SearchExplain {
+            plan,
+            matching_rows,
+            cap: self.prefilter_cap,
+            sql: Self::symbol_sql(by_doc, &where_str),
+        })
+    }
+
+    fn search_symbols(
+        &self,
+        by_doc: bool,
+        query_embedding: &[f32],
+        top_k: i32,
+        filters: &Filters,
+    ) -> SqlResult<Vec<SearchResult>> {
+        let (_, _, where_str, param_values) =
+            self.plan_symbol_search(false, by_doc, filters)?;
+        let params_ref: Vec<&dyn rusqlite::types::ToSql> =
+            param_values.iter().map(|p| p.as_ref()).collect();
+
+        if filters.quantized && !by_doc {
+            if let Some(pq) = &self.pq {
+                let table = pq.distance_table(query_embedding);
+                // ADC distances are non-negative, so their bit patterns sort
+                // like the floats
+                let dist = |codes: &[u8]| pq.adc_distance(&table, codes).to_bits();
+                let results = self.search_shortlisted(
+                    "pq_codes", dist, &where_str, &params_ref, query_embedding, top_k, filters,
+                )?;
+                return self
+                    .merge_windows(results, &where_str, &params_ref, query_embedding, top_k, filters);
+            }
+        }
+        if filters.fast_prefilter && !by_doc {
+            let query_bits = binarize(query_embedding);
+            let dist = |bits: &[u8]| u8::hamming(&query_bits, bits).unwrap_or(f64::MAX) as u32;
+            let results = self.search_shortlisted(
+                "embedding_bits", dist, &where_str, &params_ref, query_embedding, top_k, filters,
+            )?;
+            return self
+                .merge_windows(results, &where_str, &params_ref, query_embedding, top_k, filters);
+        }
+
+        if by_doc {
+            // Embedding BLOB is last — metadata columns read from page first.
+            let sql = Self::symbol_sql(by_doc, &where_str);
+            let max_dist = filters.max_distance();
+            return self.scan_top_k(
+                &sql, &params_ref, query_embedding, top_k as usize, max_dist, 10, symbol_from_row,
+            );
+        }
+        let results = match self.storage {
+            VectorStorage::Vec0 if filters.vec0_filterable() => {
+                self.search_vec0(query_embedding, top_k, filters)?
+            }
+            _ => {
+                let sql = Self::symbol_sql(false, &where_str);
+                let max_dist = filters.max_distance();
+                self.scan_top_k(
+                    &sql, &params_ref, query_embedding, top_k as usize, max_dist, 10, symbol_from_row,
+                )?
+            }
+        };
+        self.merge_windows(results, &where_str, &params_ref, query_embedding, top_k, filters)
+    }
+
+    /// Score long symbols by their best window: fold the extra windows in
+    /// `symbol_windows` of symbols matching `where_str` into `results`
+    /// (scored by each symbol's first window), keeping one result per symbol.
+    /// Windows have no compact codes, so shortlisted searches score them
+    /// exactly too.
+    fn merge_windows(
+        &self,
+        mut results: Vec<SearchResult>,
+        where_str: &str,
+        params: &[&dyn rusqlite::types::ToSql],
+        query_embedding: &[f32],
+        top_k: i32,
+        filters: &Filters,
+    ) -> SqlResult<Vec<SearchResult>> {
+        let top_k = top_k.max(0) as usize;
+        let has_windows: bool =
+            self.conn
+                .query_row("SELECT EXISTS (SELECT 1 FROM symbol_windows)", [], |r| r.get(0))?;
+        if !has_windows || top_k == 0 {
+            return Ok(results);
+        }
+
+        let sql = format!(
+            "SELECT file_path, line, embedding FROM symbol_windows
+             WHERE (workspace, file_path, line) IN
+                   (SELECT workspace, file_path, line FROM symbols {} AND embedding IS NOT NULL)",
             where_str
         );
+        let max_dist = filters.max_distance();
+        let mut best: HashMap<(String, i32), f64> = HashMap::new();
+        let mut stmt = self.conn.prepare(&sql)?;
+        let mut rows = stmt.query(params)?;
+        let mut scored = 0;
+        while let Some(row) = rows.next()? {
+            scored += 1;
+            let emb: &[f32] = bytemuck::cast_slice(row.get_ref(2)?.as_blob()?);
+            let dist = f32::l2sq(query_embedding, emb).unwrap_or(f64::MAX);
+            if dist > max_dist {
+                continue;
+            }
+            best.entry((row.get(0)?, row.get(1)?))
+                .and_modify(|d| *d = d.min(dist))
+                .or_insert(dist);
+        }
+        drop(rows);
+        self.count_rows(scored);
 
+        let mut hits: Vec<((String, i32), f64)> = best.into_iter().collect();
+        hits.sort_by(|a, b| a.1.total_cmp(&b.1));
+        hits.truncate(top_k);
+        let workspace = filters.workspace.unwrap_or(&self.workspace);
+        let mut lookup = self.conn.prepare_cached(
+            "SELECT file_path, line, name, kind, language, end_line, signature, doc_comment, symbol_id,
+                    metadata
+             FROM symbols WHERE workspace = ? AND file_path = ? AND line = ?",
+        )?;
+        for ((path, line), dist) in hits {
+            let score = 1.0 - dist / 2.0; // L2² to cosine similarity
+            match results.iter_mut().find(|r| r.file_path == path && r.line == line) {
+                Some(r) => r.score = r.score.max(score),
+                None => {
+                    let mut r = lookup.query_row(params![workspace, path, line], symbol_from_row)?;
+                    r.score = score;
+                    results.push(r);
+                }
+            }
+        }
+        results.sort_by(|a, b| b.score.total_cmp(&a.score));
+        results.truncate(top_k);
+        Ok(results)
+    }
+
+    /// Two-phase search: stream only the compact codes in `code_col` of rows
+    /// matching `where_str` and keep the nearest by `code_dist`, then score
+    /// that shortlist against the full embeddings.
+    #[allow(clippy::too_many_arguments)]
+    fn search_shortlisted(
+        &self,
+        code_col: &str,
+        code_dist: impl Fn(&[u8]) -> u32,
+        where_str: &str,
+        params: &[&dyn rusqlite::types::ToSql],
+        query_embedding: &[f32],
+        top_k: i32,
+        filters: &Filters,
+    ) -> SqlResult<Vec<SearchResult>> {
+        let top_k = top_k.max(0) as usize;
+        let shortlist_len = (top_k * PREFILTER_OVERSAMPLE).max(PREFILTER_MIN_CANDIDATES);
+
+        let sql = format!(
+            "SELECT file_path, line, {col} FROM symbols {} AND {col} IS NOT NULL",
+            where_str,
+            col = code_col
+        );
         let mut stmt = self.conn.prepare(&sql)?;
+        let mut rows = stmt.query(params)?;
+        let mut heap: BinaryHeap<(u32, String, i32)> = BinaryHeap::with_capacity(shortlist_len + 1);
+        let mut scored = 0;
+        while let Some(row) = rows.next()? {
+            scored += 1;
+            let dist = code_dist(row.get_ref(2)?.as_blob()?);
+            if heap.len() < shortlist_len {
+                heap.push((dist, row.get(0)?, row.get(1)?));
+            } else if dist < heap.peek().unwrap().0 {
+                heap.pop();
+                heap.push((dist, row.get(0)?, row.get(1)?));
+            }
+        }
+        drop(rows);
+        self.count_rows(scored);
+        if heap.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        // Join against the shortlist (CROSS JOIN keeps it the outer loop) so
+        // each row is a primary key lookup. It goes in as one JSON parameter:
+        // a bound pair per key would pass SQLite's variable limit for large
+        // `top_k`
+        let keys: Vec<(String, i32)> = heap.into_iter().map(|(_, path, line)| (path, line)).collect();
+        let keys_json = serde_json::to_string(&keys)
+            .map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))?;
+        let sql = "SELECT s.file_path, s.line, s.name, s.kind, s.language, s.end_line, s.signature,
+                    s.doc_comment, s.symbol_id, s.metadata, s.embedding
+             FROM (SELECT value ->> 0 AS file_path, value ->> 1 AS line FROM json_each(?)) k
+             CROSS JOIN symbols s
+                 ON s.workspace = ? AND s.file_path = k.file_path AND s.line = k.line";
+        let workspace = filters.workspace.unwrap_or(&self.workspace);
+        let key_params: [&dyn rusqlite::types::ToSql; 2] = [&keys_json, &workspace];
+        let max_dist = filters.max_distance();
+        self.scan_top_k(sql, &key_params, query_embedding, top_k, max_dist, 10, symbol_from_row)
+    }
+
+    /// Search document chunks. Results come back as `kind = "chunk"`, named
+    /// by the chunk's first non-empty line.
+    pub fn search_chunks(
+        &self,
+        query_embedding: &[f32],
+        top_k: i32,
+        filters: &Filters,
+    ) -> SqlResult<Vec<SearchResult>> {
+        if !filters.metadata.is_empty() || filters.name_regex.is_some() {
+            return Ok(Vec::new());
+        }
+        let mut where_clauses = vec!["workspace = ?"];
+        let mut param_values: Vec<Box<dyn rusqlite::types::ToSql>> =
+            vec![Box::new(filters.workspace.unwrap_or(&self.workspace).to_string())];
+
+        if let Some(prefix) = filters.path_prefix {
+            push_path_prefix(&mut where_clauses, &mut param_values, prefix);
+        }
+        if let Some(lang) = filters.language {
+            where_clauses.push("language = ?");
+            param_values.push(Box::new(lang.to_string()));
+        }
+        push_regex(&mut where_clauses, &mut param_values, "file_path REGEXP ?", filters.path_regex);
+        self.push_indexed_at(&mut where_clauses, &mut param_values, filters);
+
+        let where_str = format!("WHERE {}", where_clauses.join(" AND "));
+        let where_str = push_expr(where_str, &mut param_values, filters.expr, true);
+
+        let sql = format!(
+            "SELECT file_path, start_line, end_line, language, text, embedding
+             FROM chunks {}",
+            where_str
+        );
         let params_ref: Vec<&dyn rusqlite::types::ToSql> =
             param_values.iter().map(|p| p.as_ref()).collect();
-        let mut rows = stmt.query(params_ref.as_slice())?;
 
-        let top_k = top_k as usize;
+        let max_dist = filters.max_distance();
+        let read = |row: &rusqlite::Row| {
+            let text = row.get_ref(4)?.as_str()?;
+            let file_path: String = row.get(0)?;
+            let name = chunk_title(text);
+            Ok(SearchResult {
+                symbol_id: symbol_id(&file_path, &name, "chunk", None),
+                file_path,
+                line: row.get(1)?,
+                end_line: row.get(2)?,
+                language: row.get(3)?,
+                name,
+                kind: "chunk".to_string(),
+                signature: None,
+                doc_comment: None,
+                metadata: None,
+                score: 0.0,
+            })
+        };
+        self.timed(|| {
+            self.scan_top_k(&sql, &params_ref, query_embedding, top_k as usize, max_dist, 5, read)
+        })
+    }
+
+    /// Stream rows from `sql`, compute L2² against the BLOB in `embedding_col`
+    /// via simsimd, and keep the `top_k` nearest in a max-heap. Rows farther
+    /// than `max_dist` are dropped before touching the heap.
+    ///
+    /// `read` builds the result from the row's other columns; it only runs for
+    /// rows that enter the heap. Score = 1 - (L2² / 2), mapping back to cosine
+    /// similarity for L2-normalized vectors.
+    #[allow(clippy::too_many_arguments)]
+    fn scan_top_k(
+        &self,
+        sql: &str,
+        params: &[&dyn rusqlite::types::ToSql],
+        query_embedding: &[f32],
+        top_k: usize,
+        max_dist: f64,
+        embedding_col: usize,
+        read: impl Fn(&rusqlite::Row) -> SqlResult<SearchResult>,
+    ) -> SqlResult<Vec<SearchResult>> {
+        let mut stmt = self.conn.prepare(sql)?;
+        let mut rows = stmt.query(params)?;
+
         let mut heap: BinaryHeap<HeapItem> = BinaryHeap::with_capacity(top_k + 1);
 
+        let mut scored = 0;
         while let Some(row) = rows.next()? {
-            // Columns: file_path(0), line(1), name(2), kind(3), language(4),
-            //          end_line(5), signature(6), embedding(7)
-            // Embedding BLOB is last — metadata columns read from page first.
-            let blob = row.get_ref(7)?.as_blob()?;
+            // Evicted rows have no embedding
+            let Some(blob) = row.get_ref(embedding_col)?.as_blob_or_null()? else {
+                continue;
+            };
+            scored += 1;
             let emb: &[f32] = bytemuck::cast_slice(blob);
             let dist = f32::l2sq(query_embedding, emb).unwrap_or(f64::MAX);
+            if dist > max_dist {
+                continue;
+            }
 
             if heap.len() < top_k {
-                heap.push(HeapItem {
-                    dist,
-                    file_path: row.get(0)?,
-                    line: row.get(1)?,
-                    name: row.get(2)?,
-                    kind: row.get(3)?,
-                    language: row.get(4)?,
-                    end_line: row.get(5)?,
-                    signature: row.get(6)?,
-                });
+                heap.push(HeapItem { dist, result: read(row)? });
             } else if dist < heap.peek().unwrap().dist {
                 heap.pop();
-                heap.push(HeapItem {
-                    dist,
-                    file_path: row.get(0)?,
-                    line: row.get(1)?,
-                    name: row.get(2)?,
-                    kind: row.get(3)?,
-                    language: row.get(4)?,
-                    end_line: row.get(5)?,
-                    signature: row.get(6)?,
-                });
+                heap.push(HeapItem { dist, result: read(row)? });
             }
         }
 
+        self.count_rows(scored);
+
         // Convert heap to sorted results
         let mut results: Vec<_> = heap.into_vec();
         results.sort_by(|a, b| a.dist.partial_cmp(&b.dist).unwrap());
@@ -251,62 +1592,2372 @@ impl SearchDB {
         Ok(results
             .into_iter()
             .map(|item| SearchResult {
-                file_path: item.file_path,
-                name: item.name,
-                kind: item.kind,
-                language: item.language,
-                line: item.line,
-                end_line: item.end_line,
-                signature: item.signature,
                 score: 1.0 - (item.dist / 2.0), // L2² to cosine similarity
+                ..item.result
             })
             .collect())
     }
 
-    pub fn get_stats(&self) -> SqlResult<Stats> {
-        let symbol_count: i64 = self
+    /// Look up symbols by [`symbol_id`], in input order, with score 0.
+    /// Missing ids yield `None`; an id shared by several rows (identical
+    /// declarations in one file) returns the first by line.
+    pub fn get_symbols_by_id(&self, ids: &[&str]) -> SqlResult<Vec<Option<SearchResult>>> {
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT file_path, line, name, kind, language, end_line, signature, doc_comment, symbol_id,
+                    metadata
+             FROM symbols WHERE symbol_id = ? AND workspace = ? ORDER BY line LIMIT 1",
+        )?;
+        ids.iter()
+            .map(|id| {
+                stmt.query_row(params![id, self.workspace], symbol_from_row)
+                    .optional()
+            })
+            .collect()
+    }
+
+    /// Symbols of `file_path` whose lines overlap `start_line..=end_line`,
+    /// by start line, with score 0. A symbol without an end line covers only
+    /// its first line. For a single line, enclosing symbols nest in result
+    /// order, so the last one is the innermost.
+    pub fn symbols_in_range(
+        &self,
+        file_path: &str,
+        start_line: i32,
+        end_line: i32,
+    ) -> SqlResult<Vec<SearchResult>> {
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT file_path, line, name, kind, language, end_line, signature, doc_comment, symbol_id,
+                    metadata
+             FROM symbols
+             WHERE workspace = ?1 AND file_path = ?2 AND line <= ?4
+               AND coalesce(end_line, line) >= ?3
+             ORDER BY line",
+        )?;
+        let rows = stmt.query_map(
+            params![self.workspace, file_path, start_line, end_line],
+            symbol_from_row,
+        )?;
+        rows.collect()
+    }
+
+    /// Up to `limit` symbols matching `filters` whose name matches `query`,
+    /// best first, scored by `name_match_score`: exact (ignoring case), then
+    /// prefix, substring, and shared trigrams for misspellings. Symbols of
+    /// the same name are in path order.
+    pub fn symbols_by_name(
+        &self,
+        query: &str,
+        limit: usize,
+        filters: &Filters,
+    ) -> SqlResult<Vec<SearchResult>> {
+        let query = query.trim().to_lowercase();
+        if query.is_empty() || limit == 0 {
+            return Ok(Vec::new());
+        }
+        let ws = filters.workspace.unwrap_or(&self.workspace);
+        let mut names: Vec<String> = Vec::new();
+
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT DISTINCT name FROM symbols WHERE workspace = ? AND name = ? COLLATE NOCASE",
+        )?;
+        for name in stmt.query_map(params![ws, query], |r| r.get(0))? {
+            names.push(name?);
+        }
+        let escaped = query.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_");
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT DISTINCT name FROM symbols
+             WHERE workspace = ? AND name LIKE ? ESCAPE '\\'
+             LIMIT ?",
+        )?;
+        let prefixed = stmt.query_map(
+            params![ws, format!("{}%", escaped), NAME_CANDIDATES as i64],
+            |r| r.get(0),
+        )?;
+        for name in prefixed {
+            names.push(name?);
+        }
+        let trigrams = name_trigrams(&query);
+        if !trigrams.is_empty() {
+            let trigrams_json = serde_json::to_string(&trigrams)
+                .map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))?;
+            // At least half the query's trigrams: one typo costs up to three
+            let min_shared = trigrams.len().div_ceil(2) as i64;
+            let mut stmt = self.conn.prepare_cached(
+                "SELECT name FROM name_trigrams
+                 WHERE trigram IN (SELECT value FROM json_each(?))
+                 GROUP BY name HAVING count(*) >= ?
+                 ORDER BY count(*) DESC
+                 LIMIT ?",
+            )?;
+            let similar = stmt.query_map(
+                params![trigrams_json, min_shared, NAME_CANDIDATES as i64],
+                |r| r.get(0),
+            )?;
+            for name in similar {
+                names.push(name?);
+            }
+        }
+
+        let mut scored: Vec<(f64, String)> = names
+            .into_iter()
+            .map(|name| (name_match_score(&query, &name), name))
+            .collect();
+        scored.sort_by(|a, b| {
+            b.0.total_cmp(&a.0)
+                .then(a.1.len().cmp(&b.1.len()))
+                .then(a.1.cmp(&b.1))
+        });
+        scored.dedup_by(|a, b| a.1 == b.1);
+
+        let (where_str, param_values) = self.symbol_where(false, ScanPlan::Indexed, filters);
+        let sql = format!(
+            "SELECT file_path, line, name, kind, language, end_line, signature, doc_comment, symbol_id,
+                    metadata
+             FROM symbols {} AND name = ? COLLATE NOCASE AND name = ?
+             ORDER BY file_path, line
+             LIMIT ?",
+            where_str
+        );
+        let mut stmt = self.conn.prepare(&sql)?;
+        let mut results = Vec::new();
+        for (score, name) in scored {
+            let remaining = (limit - results.len()) as i64;
+            let mut params: Vec<&dyn rusqlite::types::ToSql> =
+                param_values.iter().map(|p| p.as_ref()).collect();
+            params.extend([&name as &dyn rusqlite::types::ToSql, &name, &remaining]);
+            for row in stmt.query_map(params.as_slice(), symbol_from_row)? {
+                results.push(SearchResult { score, ..row? });
+            }
+            if results.len() >= limit {
+                break;
+            }
+        }
+        Ok(results)
+    }
+
+    /// Page through symbols matching `filters` (score bound and scan options
+    /// are ignored), with score 0. `Path` pages walk the primary key;
+    /// `Recent` pages put the most recently indexed files first.
+    pub fn list_symbols(
+        &self,
+        filters: &Filters,
+        order: SymbolOrder,
+        offset: u64,
+        limit: u64,
+    ) -> SqlResult<Vec<SearchResult>> {
+        let (where_str, mut param_values) = self.symbol_where(false, ScanPlan::Indexed, filters);
+        let select = format!(
+            "SELECT file_path, line, name, kind, language, end_line, signature, doc_comment, symbol_id,
+                    metadata, workspace
+             FROM symbols {}",
+            where_str
+        );
+        let sql = match order {
+            SymbolOrder::Path => format!("{} ORDER BY file_path, line LIMIT ? OFFSET ?", select),
+            // Both tables have workspace and language columns, so filter first
+            SymbolOrder::Recent => format!(
+                "SELECT s.* FROM ({}) s
+                 LEFT JOIN files f ON f.workspace = s.workspace AND f.path = s.file_path
+                 ORDER BY f.indexed_at DESC, s.file_path, s.line LIMIT ? OFFSET ?",
+                select
+            ),
+        };
+        param_values.push(Box::new(limit.min(i64::MAX as u64) as i64));
+        param_values.push(Box::new(offset.min(i64::MAX as u64) as i64));
+        let params: Vec<&dyn rusqlite::types::ToSql> =
+            param_values.iter().map(|p| p.as_ref()).collect();
+        let mut stmt = self.conn.prepare(&sql)?;
+        let rows = stmt.query_map(params.as_slice(), symbol_from_row)?;
+        rows.collect()
+    }
+
+    /// Delete the symbols and/or chunks matching `filters` (score bound and
+    /// scan options are ignored) in one transaction, and recount
+    /// `files.symbol_count` to match. File rows are kept, so their files are
+    /// not re-indexed until they change. Returns how many rows were removed.
+    pub fn delete_where(
+        &mut self,
+        filters: &Filters,
+        symbols: bool,
+        chunks: bool,
+    ) -> SqlResult<u64> {
+        let tx = self.write_tx()?;
+        let mut deleted = 0;
+        let mut tables = Vec::new();
+        if symbols {
+            tables.push(("symbols", *filters));
+        }
+        if chunks && filters.metadata.is_empty() && filters.name_regex.is_none() {
+            // Chunks have no kind column
+            tables.push(("chunks", Filters { kind: None, expr: None, ..*filters }));
+        }
+        for (table, table_filters) in tables {
+            let (where_str, mut param_values) =
+                self.symbol_where(false, ScanPlan::Indexed, &table_filters);
+            let where_str = match table {
+                "chunks" => push_expr(where_str, &mut param_values, filters.expr, true),
+                _ => where_str,
+            };
+            let params: Vec<&dyn rusqlite::types::ToSql> =
+                param_values.iter().map(|p| p.as_ref()).collect();
+            deleted += tx.execute(
+                &format!("DELETE FROM {} {}", table, where_str),
+                params.as_slice(),
+            )? as u64;
+        }
+        if symbols && deleted > 0 {
+            tx.execute(
+                "UPDATE files SET symbol_count = (
+                     SELECT count(*) FROM symbols s
+                     WHERE s.workspace = files.workspace AND s.file_path = files.path
+                 )
+                 WHERE workspace = ?",
+                params![filters.workspace.unwrap_or(&self.workspace)],
+            )?;
+        }
+        tx.commit()?;
+        Ok(deleted)
+    }
+
+    /// Fetch stored embeddings for `(file_path, line)` keys, in input order.
+    /// Keys are looked up as symbols first, then as chunk start lines.
+    /// Missing rows yield `None`. `workspace` overrides the connection's.
+    pub fn get_embeddings(
+        &self,
+        workspace: Option<&str>,
+        keys: &[(&str, i32)],
+    ) -> SqlResult<Vec<Option<Vec<f32>>>> {
+        let mut symbol_stmt = self
             .conn
-            .query_row("SELECT count(*) FROM symbols", [], |r| r.get(0))?;
-        let file_count: i64 = self
+            .prepare_cached(
+                "SELECT embedding FROM symbols WHERE workspace = ? AND file_path = ? AND line = ?",
+            )?;
+        let mut chunk_stmt = self.conn.prepare_cached(
+            "SELECT embedding FROM chunks WHERE workspace = ? AND file_path = ? AND start_line = ?",
+        )?;
+        let read = |r: &rusqlite::Row| Ok(r.get_ref(0)?.as_blob_or_null()?.map(blob_to_vec));
+        keys.iter()
+            .map(|(path, line)| {
+                let ws = workspace.unwrap_or(&self.workspace);
+                match symbol_stmt.query_row(params![ws, path, line], read).optional()? {
+                    Some(emb) => Ok(emb),
+                    None => Ok(chunk_stmt.query_row(params![ws, path, line], read).optional()?.flatten()),
+                }
+            })
+            .collect()
+    }
+
+    /// Stored token-group vectors of symbols by `(file_path, line)`, packed
+    /// back to back; None for symbols (and chunks) without any.
+    pub fn get_token_vectors(
+        &self,
+        workspace: Option<&str>,
+        keys: &[(&str, i32)],
+    ) -> SqlResult<Vec<Option<Vec<f32>>>> {
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT vectors FROM symbol_tokens WHERE workspace = ? AND file_path = ? AND line = ?",
+        )?;
+        let ws = workspace.unwrap_or(&self.workspace);
+        keys.iter()
+            .map(|(path, line)| {
+                stmt.query_row(params![ws, path, line], |r| {
+                    Ok(blob_to_vec(r.get_ref(0)?.as_blob()?))
+                })
+                .optional()
+            })
+            .collect()
+    }
+
+    /// Sparse query vector over `terms`: each distinct term weighted by its
+    /// BM25 inverse document frequency among the workspace's symbols, then
+    /// normalized, so rare identifiers dominate.
+    pub fn sparse_query(&self, workspace: Option<&str>, terms: &[u32]) -> SqlResult<Vec<(u32, f64)>> {
+        let ws = workspace.unwrap_or(&self.workspace);
+        let symbols: i64 =
+            self.conn
+                .query_row("SELECT count(*) FROM symbols WHERE workspace = ?", params![ws], |r| {
+                    r.get(0)
+                })?;
+        let mut df_stmt = self
             .conn
-            .query_row("SELECT count(*) FROM files", [], |r| r.get(0))?;
+            .prepare_cached("SELECT count(*) FROM sparse_terms WHERE workspace = ? AND term = ?")?;
+        let mut distinct = terms.to_vec();
+        distinct.sort_unstable();
+        distinct.dedup();
+        let mut query = Vec::with_capacity(distinct.len());
+        for term in distinct {
+            let df: i64 = df_stmt.query_row(params![ws, term], |r| r.get(0))?;
+            if df == 0 {
+                continue;
+            }
+            let (n, df) = (symbols as f64, df as f64);
+            query.push((term, (1.0 + (n - df + 0.5) / (df + 0.5)).ln()));
+        }
+        let norm = query.iter().map(|(_, w)| w * w).sum::<f64>().sqrt();
+        if norm > 0.0 {
+            query.iter_mut().for_each(|(_, w)| *w /= norm);
+        }
+        Ok(query)
+    }
+
+    /// The `top_k` symbols matching `filters` (score bound and scan options
+    /// are ignored) with the highest sparse score against `query` (from
+    /// `sparse_query`), as their score.
+    pub fn sparse_search(
+        &self,
+        query: &[(u32, f64)],
+        top_k: usize,
+        filters: &Filters,
+    ) -> SqlResult<Vec<SearchResult>> {
+        if query.is_empty() {
+            return Ok(Vec::new());
+        }
+        let (where_str, mut param_values) = self.symbol_where(false, ScanPlan::Indexed, filters);
+        // Both tables have file_path and line columns, so filter first
+        let sql = format!(
+            "SELECT s.*, h.score FROM (
+                 SELECT file_path, line, name, kind, language, end_line, signature, doc_comment,
+                        symbol_id, metadata
+                 FROM symbols {}
+             ) s
+             JOIN (
+                 SELECT p.file_path, p.line, sum(p.weight * q.weight) AS score
+                 FROM (SELECT value ->> 0 AS term, value ->> 1 AS weight FROM json_each(?)) q
+                 JOIN sparse_terms p ON p.workspace = ? AND p.term = q.term
+                 GROUP BY p.file_path, p.line
+             ) h ON h.file_path = s.file_path AND h.line = s.line
+             ORDER BY h.score DESC LIMIT ?",
+            where_str
+        );
+        param_values.push(Box::new(serde_json::to_string(query).unwrap_or_default()));
+        param_values.push(Box::new(filters.workspace.unwrap_or(&self.workspace).to_string()));
+        param_values.push(Box::new(top_k.min(i64::MAX as usize) as i64));
+        let params: Vec<&dyn rusqlite::types::ToSql> =
+            param_values.iter().map(|p| p.as_ref()).collect();
+        let mut stmt = self.conn.prepare(&sql)?;
+        let rows = stmt.query_map(params.as_slice(), |r| {
+            Ok(SearchResult {
+                score: r.get(10)?,
+                ..symbol_from_row(r)?
+            })
+        })?;
+        rows.collect()
+    }
+
+    /// Sparse scores against `query` of symbols by `(file_path, line)`; 0
+    /// for symbols (and chunks) without sparse terms.
+    pub fn sparse_scores(
+        &self,
+        workspace: Option<&str>,
+        query: &[(u32, f64)],
+        keys: &[(&str, i32)],
+    ) -> SqlResult<Vec<f64>> {
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT weight FROM sparse_terms
+             WHERE workspace = ? AND term = ? AND file_path = ? AND line = ?",
+        )?;
+        let ws = workspace.unwrap_or(&self.workspace);
+        keys.iter()
+            .map(|(path, line)| {
+                let mut score = 0.0;
+                for (term, weight) in query {
+                    let w: Option<f64> = stmt
+                        .query_row(params![ws, term, path, line], |r| r.get(0))
+                        .optional()?;
+                    score += weight * w.unwrap_or(0.0);
+                }
+                Ok(score)
+            })
+            .collect()
+    }
+
+    /// Record `(caller, callee)` symbol id edges in the current workspace.
+    /// Returns how many were new.
+    pub fn insert_edges(&mut self, edges: &[(&str, &str)]) -> SqlResult<u64> {
+        let tx = self.write_tx()?;
+        let mut inserted = 0;
+        {
+            let mut stmt = tx.prepare_cached(
+                "INSERT OR IGNORE INTO edges (workspace, caller, callee) VALUES (?, ?, ?)",
+            )?;
+            for (caller, callee) in edges {
+                inserted += stmt.execute(params![self.workspace, caller, callee])? as u64;
+            }
+        }
+        tx.commit()?;
+        Ok(inserted)
+    }
+
+    /// Drop the outgoing edges of `callers`, e.g. before re-recording a
+    /// changed file's calls. Returns how many were removed.
+    pub fn delete_edges(&mut self, callers: &[&str]) -> SqlResult<u64> {
+        let tx = self.write_tx()?;
+        let mut deleted = 0;
+        {
+            let mut stmt =
+                tx.prepare_cached("DELETE FROM edges WHERE workspace = ? AND caller = ?")?;
+            for caller in callers {
+                deleted += stmt.execute(params![self.workspace, caller])? as u64;
+            }
+        }
+        tx.commit()?;
+        Ok(deleted)
+    }
+
+    /// Symbols directly connected to symbol `id` by an edge in either
+    /// direction, with their embeddings (None while evicted), score 0.
+    /// Edges whose other end is no longer indexed are skipped.
+    /// `workspace` overrides the connection's.
+    pub fn neighbors(
+        &self,
+        workspace: Option<&str>,
+        id: &str,
+    ) -> SqlResult<Vec<(SearchResult, Option<Vec<f32>>)>> {
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT s.file_path, s.line, s.name, s.kind, s.language, s.end_line, s.signature,
+                    s.doc_comment, s.symbol_id, s.metadata, s.embedding
+             FROM (SELECT callee AS id FROM edges WHERE workspace = ?1 AND caller = ?2
+                   UNION
+                   SELECT caller FROM edges WHERE workspace = ?1 AND callee = ?2) e
+             JOIN symbols s ON s.symbol_id = e.id AND s.workspace = ?1
+             ORDER BY s.file_path, s.line",
+        )?;
+        let rows = stmt.query_map(params![workspace.unwrap_or(&self.workspace), id], |r| {
+            Ok((symbol_from_row(r)?, r.get_ref(10)?.as_blob_or_null()?.map(blob_to_vec)))
+        })?;
+        rows.collect()
+    }
+
+    /// Replace all chunks of `file_path` with `chunks`
+    /// (`(text, start_line, end_line)` tuples) and their embeddings.
+    pub fn replace_chunks(
+        &mut self,
+        file_path: &str,
+        language: &str,
+        chunks: &[(&str, i32, i32)],
+        embeddings: &[Vec<f32>],
+    ) -> SqlResult<()> {
+        let tx = self.write_tx()?;
+        tx.execute(
+            "DELETE FROM chunks WHERE workspace = ? AND file_path = ?",
+            params![self.workspace, file_path],
+        )?;
+        {
+            let mut stmt = tx.prepare_cached(
+                "INSERT OR REPLACE INTO chunks (workspace, file_path, start_line, end_line, language, text, embedding)
+                 VALUES (?, ?, ?, ?, ?, ?, ?)",
+            )?;
+            for ((text, start_line, end_line), emb) in chunks.iter().zip(embeddings) {
+                let embedding_bytes: &[u8] = bytemuck::cast_slice(emb.as_slice());
+                stmt.execute(params![
+                    self.workspace,
+                    file_path,
+                    start_line,
+                    end_line,
+                    language,
+                    text,
+                    embedding_bytes
+                ])?;
+            }
+        }
+        tx.commit()
+    }
+
+    pub fn get_stats(&self) -> SqlResult<Stats> {
+        let symbol_count: i64 = self.conn.query_row(
+            "SELECT count(*) FROM symbols WHERE workspace = ?",
+            params![self.workspace],
+            |r| r.get(0),
+        )?;
+        let file_count: i64 = self.conn.query_row(
+            "SELECT count(*) FROM files WHERE workspace = ?",
+            params![self.workspace],
+            |r| r.get(0),
+        )?;
         Ok(Stats {
             symbol_count,
             file_count,
         })
     }
 
-    /// Begin a transaction on the underlying connection.
-    pub fn transaction(&mut self) -> SqlResult<rusqlite::Transaction<'_>> {
-        self.conn.transaction()
+    /// `(kind, symbol count)` in the current workspace, most common first.
+    pub fn kind_counts(&self) -> SqlResult<Vec<(String, u64)>> {
+        self.counts_by("kind")
+    }
+
+    /// `(language, symbol count)` in the current workspace, most common first.
+    pub fn language_counts(&self) -> SqlResult<Vec<(String, u64)>> {
+        self.counts_by("language")
+    }
+
+    fn counts_by(&self, column: &str) -> SqlResult<Vec<(String, u64)>> {
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT {0}, count(*) AS n FROM symbols WHERE workspace = ?
+             GROUP BY {0} ORDER BY n DESC, {0}",
+            column
+        ))?;
+        let rows = stmt.query_map(params![self.workspace], |r| {
+            Ok((r.get(0)?, r.get::<_, i64>(1)? as u64))
+        })?;
+        rows.collect()
+    }
+
+    /// Symbol count of every file in the current workspace, in no order.
+    pub fn symbols_per_file(&self) -> SqlResult<Vec<u64>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT count(s.line) FROM files f
+             LEFT JOIN symbols s ON s.workspace = f.workspace AND s.file_path = f.path
+             WHERE f.workspace = ?
+             GROUP BY f.path",
+        )?;
+        let rows = stmt.query_map(params![self.workspace], |r| Ok(r.get::<_, i64>(0)? as u64))?;
+        rows.collect()
+    }
+
+    /// Embedding texts of up to `n` symbols of the current workspace, chosen
+    /// at random.
+    pub fn sample_embedding_texts(&self, n: usize) -> SqlResult<Vec<String>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT embedding_text FROM symbols WHERE workspace = ? ORDER BY random() LIMIT ?",
+        )?;
+        let rows = stmt.query_map(params![self.workspace, n as i64], |r| {
+            Ok(unpack_text(r, 0)?.unwrap_or_default())
+        })?;
+        rows.collect()
+    }
+
+    /// Bytes of pages used by each table of the whole file (all workspaces),
+    /// its indexes included, largest first.
+    pub fn table_sizes(&self) -> SqlResult<Vec<(String, u64)>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT coalesce(m.tbl_name, d.name) AS t, sum(d.pgsize) AS bytes
+             FROM dbstat d LEFT JOIN sqlite_master m ON m.name = d.name
+             GROUP BY t ORDER BY bytes DESC, t",
+        )?;
+        let rows = stmt.query_map([], |r| Ok((r.get(0)?, r.get::<_, i64>(1)? as u64)))?;
+        rows.collect()
+    }
+
+    /// Record a search and the results it returned. Returns the query id.
+    pub fn log_search(&self, query: &str, results: &[LoggedResult]) -> SqlResult<i64> {
+        let results_json = serde_json::to_string(results)
+            .map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))?;
+        self.record(|conn| {
+            conn.execute(
+                "INSERT INTO query_log (query, results, created_at) VALUES (?, ?, ?)",
+                params![query, results_json, now_millis()],
+            )?;
+            Ok(conn.last_insert_rowid())
+        })
+    }
+
+    /// Record that the user opened `result_key` from the results of `query_id`,
+    /// and count the open toward the result's popularity (see `open_counts`).
+    pub fn record_click(&self, query_id: i64, result_key: &str) -> SqlResult<()> {
+        let now = now_millis();
+        self.record(|conn| {
+            conn.execute(
+                "INSERT INTO query_clicks (query_id, result_key, clicked_at) VALUES (?, ?, ?)",
+                params![query_id, result_key, now],
+            )?;
+            conn.execute(
+                "INSERT INTO symbol_stats (workspace, key, opens, last_opened_at) VALUES (?, ?, 1, ?)
+                 ON CONFLICT (workspace, key)
+                 DO UPDATE SET opens = opens + 1, last_opened_at = excluded.last_opened_at",
+                params![self.workspace, result_key, now],
+            )?;
+            Ok(())
+        })
+    }
+
+    /// How many times each of `keys` (query log result keys) was opened, in
+    /// input order. `workspace` overrides the connection's.
+    pub fn open_counts(&self, workspace: Option<&str>, keys: &[&str]) -> SqlResult<Vec<u64>> {
+        let mut stmt = self
+            .conn
+            .prepare_cached("SELECT opens FROM symbol_stats WHERE workspace = ? AND key = ?")?;
+        let ws = workspace.unwrap_or(&self.workspace);
+        keys.iter()
+            .map(|key| {
+                let opens: Option<i64> =
+                    stmt.query_row(params![ws, key], |r| r.get(0)).optional()?;
+                Ok(opens.unwrap_or(0) as u64)
+            })
+            .collect()
     }
+
+    /// Most recent `limit` logged queries, newest first, with their clicks.
+    pub fn get_query_history(&self, limit: i64) -> SqlResult<Vec<QueryLogEntry>> {
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT id, query, results, created_at FROM query_log ORDER BY id DESC LIMIT ?",
+        )?;
+        let mut entries = stmt
+            .query_map(params![limit], |r| {
+                let results_json: String = r.get(2)?;
+                Ok(QueryLogEntry {
+                    id: r.get(0)?,
+                    query: r.get(1)?,
+                    results: serde_json::from_str(&results_json).unwrap_or_default(),
+                    clicks: Vec::new(),
+                    created_at: r.get(3)?,
+                })
+            })?
+            .collect::<SqlResult<Vec<_>>>()?;
+
+        let mut clicks_stmt = self.conn.prepare_cached(
+            "SELECT result_key FROM query_clicks WHERE query_id = ? ORDER BY clicked_at",
+        )?;
+        for entry in &mut entries {
+            entry.clicks = clicks_stmt
+                .query_map(params![entry.id], |r| r.get(0))?
+                .collect::<SqlResult<Vec<String>>>()?;
+        }
+        Ok(entries)
+    }
+
+    /// The connection settings in effect.
+    pub fn pragmas(&self) -> SqlResult<Pragmas> {
+        let get = |name: &str| self.conn.pragma_query_value(None, name, |r| r.get::<_, i64>(0));
+        let synchronous = match get("synchronous")? {
+            0 => "off",
+            1 => "normal",
+            2 => "full",
+            _ => "extra",
+        };
+        Ok(Pragmas {
+            page_size: get("page_size")? as u32,
+            mmap_size: get("mmap_size")?,
+            cache_size: get("cache_size")?,
+            synchronous,
+        })
+    }
+
+    /// Handle that aborts whatever statement this connection is running,
+    /// from any thread; the interrupted call fails with `SQLITE_INTERRUPT`.
+    pub fn interrupt_handle(&self) -> rusqlite::InterruptHandle {
+        self.conn.get_interrupt_handle()
+    }
+
+    /// Copy the whole index to `path` with SQLite's online backup API.
+    pub fn backup_to(&self, path: &Path) -> SqlResult<()> {
+        self.conn.backup(DatabaseName::Main, path, None)
+    }
+
+    /// Replace the whole index with the contents of `path`, then bring the
+    /// schema up to date (an outdated backup is reset like any stale index).
+    pub fn restore_from(&mut self, path: &Path) -> SqlResult<()> {
+        self.conn
+            .restore(DatabaseName::Main, path, None::<fn(rusqlite::backup::Progress)>)?;
+        self.bump_generation();
+        self.init_schema()?;
+        self.load_pq()?;
+        self.load_storage()
+    }
+
+    /// Fill this (new, empty) index with every row of the index at
+    /// `source`, which must have the same schema version, then ANALYZE.
+    /// The vector storage mode carries over, with its `vec0` table rebuilt
+    /// in the current layout. Returns the number of rows copied.
+    pub fn copy_from(&mut self, source: &Path) -> SqlResult<u64> {
+        self.conn.execute(
+            "ATTACH DATABASE ? AS src",
+            params![source.to_string_lossy()],
+        )?;
+        let copied = self.copy_attached();
+        self.conn.execute_batch("DETACH DATABASE src")?;
+        let (copied, storage) = copied?;
+
+        self.load_pq()?;
+        if storage != VectorStorage::Blob {
+            self.set_vector_storage(storage)?;
+        }
+        self.analyze()?;
+        Ok(copied)
+    }
+
+    /// `copy_from`'s row copy from the attached `src`. Returns the rows
+    /// copied and the source's vector storage mode, which is left unset.
+    fn copy_attached(&self) -> SqlResult<(u64, VectorStorage)> {
+        let version: Option<String> = self
+            .conn
+            .query_row(
+                "SELECT value FROM src.meta WHERE key = 'schema_version'",
+                [],
+                |r| r.get(0),
+            )
+            .optional()?;
+        if version.as_deref() != Some(SCHEMA_VERSION.to_string().as_str()) {
+            return Err(rusqlite::Error::SqliteFailure(
+                rusqlite::ffi::Error::new(rusqlite::ffi::SQLITE_MISMATCH),
+                Some(format!(
+                    "source schema version {:?} does not match expected {}",
+                    version, SCHEMA_VERSION
+                )),
+            ));
+        }
+
+        let tx = self.write_tx()?;
+        // The vec0 table and its shadow tables are rebuilt, not copied
+        let tables: Vec<String> = tx
+            .prepare(
+                "SELECT name FROM main.sqlite_master
+                 WHERE type = 'table' AND name NOT LIKE 'sqlite_%' AND name NOT LIKE 'vec_symbols%'",
+            )?
+            .query_map([], |r| r.get(0))?
+            .collect::<SqlResult<_>>()?;
+        let mut copied = 0;
+        for table in &tables {
+            let columns: Vec<String> = tx
+                .prepare(&format!("PRAGMA main.table_info(\"{}\")", table))?
+                .query_map([], |r| r.get(1))?
+                .collect::<SqlResult<_>>()?;
+            let columns = columns.join(", ");
+            tx.execute(&format!("DELETE FROM main.\"{}\"", table), [])?;
+            copied += tx.execute(
+                &format!(
+                    "INSERT INTO main.\"{0}\" ({1}) SELECT {1} FROM src.\"{0}\"",
+                    table, columns
+                ),
+                [],
+            )? as u64;
+        }
+        let storage: Option<String> = tx
+            .query_row(
+                "SELECT value FROM meta WHERE key = ?",
+                params![VECTOR_STORAGE_META],
+                |r| r.get(0),
+            )
+            .optional()?;
+        // The lease belonged to the source's writer; the vec0 table doesn't
+        // exist here yet
+        tx.execute(
+            "DELETE FROM meta WHERE key = 'writer_lease' OR key = ?",
+            params![VECTOR_STORAGE_META],
+        )?;
+        tx.commit()?;
+        let storage = storage
+            .and_then(|s| VectorStorage::parse(&s))
+            .unwrap_or(VectorStorage::Blob);
+        Ok((copied, storage))
+    }
+
+    fn load_pq(&mut self) -> SqlResult<()> {
+        let bytes: Option<Vec<u8>> = self
+            .conn
+            .query_row("SELECT codebook FROM pq_codebook WHERE id = 0", [], |r| r.get(0))
+            .optional()?;
+        self.pq = match bytes {
+            Some(b) => Some(Arc::new(Codebook::from_bytes(&b).map_err(|e| {
+                rusqlite::Error::FromSqlConversionFailure(0, rusqlite::types::Type::Blob, e.into())
+            })?)),
+            None => None,
+        };
+        Ok(())
+    }
+
+    /// The trained PQ codebook, if any. New symbols should be stored with
+    /// `pq_codes` from it.
+    pub fn pq_codebook(&self) -> Option<Arc<Codebook>> {
+        self.pq.clone()
+    }
+
+    /// Up to `n` symbol embeddings chosen at random across all workspaces,
+    /// as PQ training data.
+    pub fn sample_embeddings(&self, n: usize) -> SqlResult<Vec<Vec<f32>>> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT embedding FROM symbols WHERE embedding IS NOT NULL ORDER BY random() LIMIT ?")?;
+        let rows = stmt.query_map(params![n as i64], |r| Ok(blob_to_vec(r.get_ref(0)?.as_blob()?)))?;
+        rows.collect()
+    }
+
+    /// Up to `n` of this workspace's symbol embeddings chosen at random, as
+    /// clustering data.
+    pub fn sample_workspace_embeddings(&self, n: usize) -> SqlResult<Vec<Vec<f32>>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT embedding FROM symbols
+             WHERE workspace = ? AND embedding IS NOT NULL ORDER BY random() LIMIT ?",
+        )?;
+        let rows = stmt.query_map(params![self.workspace, n as i64], |r| {
+            Ok(blob_to_vec(r.get_ref(0)?.as_blob()?))
+        })?;
+        rows.collect()
+    }
+
+    /// Assign every embedded symbol in the workspace to its nearest of
+    /// `centroids` and replace the workspace's clusters, described by the
+    /// terms of their members' names, in one transaction. Empty clusters
+    /// are left out. Returns the rows assigned.
+    pub fn set_clusters(&self, centroids: &Centroids) -> SqlResult<u64> {
+        let k = centroids.k();
+        let mut counts: Vec<HashMap<String, u64>> = vec![HashMap::new(); k];
+        let mut sizes = vec![0u64; k];
+        let tx = self.write_tx()?;
+        tx.execute(
+            "UPDATE symbols SET cluster = NULL WHERE workspace = ?",
+            params![self.workspace],
+        )?;
+        tx.execute("DELETE FROM clusters WHERE workspace = ?", params![self.workspace])?;
+        let mut assigned = 0u64;
+        {
+            let mut read = tx.prepare(
+                "SELECT file_path, line, name, embedding FROM symbols
+                 WHERE workspace = ? AND embedding IS NOT NULL",
+            )?;
+            let mut write = tx.prepare(
+                "UPDATE symbols SET cluster = ? WHERE workspace = ? AND file_path = ? AND line = ?",
+            )?;
+            let mut rows = read.query(params![self.workspace])?;
+            let mut updates: Vec<(usize, String, i32)> = Vec::new();
+            while let Some(row) = rows.next()? {
+                let c = centroids.nearest(&blob_to_vec(row.get_ref(3)?.as_blob()?));
+                sizes[c] += 1;
+                for term in cluster::name_terms(row.get_ref(2)?.as_str()?) {
+                    *counts[c].entry(term).or_default() += 1;
+                }
+                updates.push((c, row.get(0)?, row.get(1)?));
+            }
+            drop(rows);
+            for (c, path, line) in updates {
+                write.execute(params![c as i64, self.workspace, path, line])?;
+                assigned += 1;
+            }
+        }
+        let terms = cluster::top_terms(&counts, &sizes);
+        {
+            let mut insert = tx.prepare(
+                "INSERT INTO clusters (workspace, id, label, top_terms, size) VALUES (?, ?, ?, ?, ?)",
+            )?;
+            for (id, (terms, &size)) in terms.iter().zip(&sizes).enumerate() {
+                if size == 0 {
+                    continue;
+                }
+                let terms_json = serde_json::to_string(terms)
+                    .map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))?;
+                insert.execute(params![
+                    self.workspace,
+                    id as i64,
+                    cluster::label(id, terms),
+                    terms_json,
+                    size as i64
+                ])?;
+            }
+        }
+        tx.commit()?;
+        Ok(assigned)
+    }
+
+    /// The workspace's clusters from the last `set_clusters`, largest
+    /// first.
+    pub fn clusters(&self) -> SqlResult<Vec<Cluster>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT id, label, top_terms, size FROM clusters WHERE workspace = ? ORDER BY size DESC, id",
+        )?;
+        let rows = stmt.query_map(params![self.workspace], |r| {
+            let terms: String = r.get(2)?;
+            Ok(Cluster {
+                id: r.get(0)?,
+                label: r.get(1)?,
+                top_terms: serde_json::from_str(&terms).unwrap_or_default(),
+                size: r.get::<_, i64>(3)? as u64,
+            })
+        })?;
+        rows.collect()
+    }
+
+    /// Pairs of symbols matching `filters` whose embeddings have a dot
+    /// product of at least `threshold`, at most `limit` of them, most
+    /// similar first. Only symbols in the same cluster (see
+    /// `set_clusters`) are compared, so the scan is quadratic in cluster
+    /// size rather than index size; duplicates split across clusters are
+    /// missed. Pairs where one symbol encloses the other (a method and its
+    /// class) are skipped.
+    pub fn near_duplicates(
+        &self,
+        threshold: f32,
+        limit: usize,
+        filters: &Filters,
+    ) -> SqlResult<DuplicateScan> {
+        let workspace = filters.workspace.unwrap_or(&self.workspace);
+        let ids: Vec<i64> = self
+            .conn
+            .prepare("SELECT id FROM clusters WHERE workspace = ? ORDER BY id")?
+            .query_map(params![workspace], |r| r.get(0))?
+            .collect::<SqlResult<_>>()?;
+        let mut scan = DuplicateScan { clusters: ids.len() as u64, ..Default::default() };
+
+        let (where_str, param_values) = self.symbol_where(false, ScanPlan::Indexed, filters);
+        let params_ref: Vec<&dyn rusqlite::types::ToSql> =
+            param_values.iter().map(|p| p.as_ref()).collect();
+        scan.unclustered = self.conn.query_row(
+            &format!(
+                "SELECT count(*) FROM symbols {} AND cluster IS NULL AND embedding IS NOT NULL",
+                where_str
+            ),
+            params_ref.as_slice(),
+            |r| r.get::<_, i64>(0),
+        )? as u64;
+
+        let sql = Self::symbol_sql(
+            false,
+            &format!("{} AND cluster = ? AND embedding IS NOT NULL", where_str),
+        );
+        let mut stmt = self.conn.prepare(&sql)?;
+        // Most similar `limit` pairs so far, least similar on top
+        let mut heap: BinaryHeap<HeapItem<DuplicatePair>> = BinaryHeap::with_capacity(limit + 1);
+        for id in &ids {
+            let mut params = params_ref.clone();
+            params.push(id);
+            let members: Vec<(SearchResult, Vec<f32>)> = stmt
+                .query_map(params.as_slice(), |r| {
+                    Ok((symbol_from_row(r)?, blob_to_vec(r.get_ref(10)?.as_blob()?)))
+                })?
+                .collect::<SqlResult<_>>()?;
+            for (i, (a, ea)) in members.iter().enumerate() {
+                for (b, eb) in &members[i + 1..] {
+                    let similarity = f32::dot(ea, eb).unwrap_or(0.0);
+                    if similarity < threshold as f64 || encloses(a, b) || encloses(b, a) {
+                        continue;
+                    }
+                    scan.total += 1;
+                    let dist = -similarity;
+                    if heap.len() == limit && heap.peek().is_none_or(|top| dist >= top.dist) {
+                        continue;
+                    }
+                    if heap.len() == limit {
+                        heap.pop();
+                    }
+                    let pair = DuplicatePair { a: a.clone(), b: b.clone(), similarity };
+                    heap.push(HeapItem { dist, result: pair });
+                }
+            }
+        }
+        scan.pairs = heap.into_iter().map(|item| item.result).collect();
+        sort_pairs(&mut scan.pairs);
+        Ok(scan)
+    }
+
+    /// Store `codebook` and re-encode every symbol with it, in one
+    /// transaction. Returns the number of rows encoded.
+    pub fn set_pq_codebook(&mut self, codebook: Codebook) -> SqlResult<u64> {
+        let tx = self.write_tx()?;
+        tx.execute(
+            "INSERT OR REPLACE INTO pq_codebook (id, codebook) VALUES (0, ?)",
+            params![codebook.to_bytes()],
+        )?;
+        let mut encoded = 0u64;
+        {
+            let mut read = tx.prepare(
+                "SELECT workspace, file_path, line, embedding FROM symbols WHERE embedding IS NOT NULL",
+            )?;
+            let mut write = tx.prepare(
+                "UPDATE symbols SET pq_codes = ? WHERE workspace = ? AND file_path = ? AND line = ?",
+            )?;
+            let mut rows = read.query([])?;
+            let mut updates: Vec<(Vec<u8>, String, String, i32)> = Vec::new();
+            while let Some(row) = rows.next()? {
+                let emb = blob_to_vec(row.get_ref(3)?.as_blob()?);
+                updates.push((codebook.encode(&emb), row.get(0)?, row.get(1)?, row.get(2)?));
+            }
+            drop(rows);
+            for (codes, workspace, path, line) in updates {
+                write.execute(params![codes, workspace, path, line])?;
+                encoded += 1;
+            }
+        }
+        tx.commit()?;
+        self.pq = Some(Arc::new(codebook));
+        Ok(encoded)
+    }
+
+    /// Bytes of the database file in use, excluding free pages. Evicting
+    /// frees pages for reuse but doesn't shrink the file.
+    pub fn index_size(&self) -> SqlResult<u64> {
+        let pragma = |name: &str| -> SqlResult<i64> {
+            self.conn.pragma_query_value(None, name, |r| r.get(0))
+        };
+        let used = (pragma("page_count")? - pragma("freelist_count")?) * pragma("page_size")?;
+        Ok(used.max(0) as u64)
+    }
+
+    /// Record that rows from `paths` just came back from a search, for
+    /// `evict_lru`. `workspace` overrides the connection's. Hits are
+    /// batched and written at most every `HIT_FLUSH_INTERVAL`; see
+    /// `flush_hits`.
+    pub fn touch_files(&self, workspace: Option<&str>, paths: &[&str]) -> SqlResult<()> {
+        self.pending_hits
+            .borrow_mut()
+            .entry(workspace.unwrap_or(&self.workspace).to_string())
+            .or_default()
+            .extend(paths.iter().map(|p| p.to_string()));
+        if self.hits_flushed.get().elapsed() < HIT_FLUSH_INTERVAL {
+            return Ok(());
+        }
+        self.flush_hits()
+    }
+
+    /// Write the hits `touch_files` has batched, if the write lock is free
+    /// right now. Searches call this, so it never waits for the lock or the
+    /// writer lease: while another connection writes, or an index session
+    /// is open on this one (its rollback would drop them), hits stay batched
+    /// for the next try. `evict_lru` writes them in its own transaction.
+    pub fn flush_hits(&self) -> SqlResult<()> {
+        if self.readonly || !self.conn.is_autocommit() || self.pending_hits.borrow().is_empty() {
+            return Ok(());
+        }
+        self.hits_flushed.set(Instant::now());
+        self.conn.busy_timeout(Duration::ZERO)?;
+        let written = Transaction::new_unchecked(&self.conn, TransactionBehavior::Immediate)
+            .and_then(|tx| {
+                self.write_hits(&tx)?;
+                tx.commit()
+            });
+        self.conn.busy_timeout(BUSY_TIMEOUT)?;
+        match written {
+            Err(e) if is_busy(&e) => Ok(()),
+            result => result,
+        }
+    }
+
+    /// Write and clear the batched hits inside `conn`'s open transaction.
+    fn write_hits(&self, conn: &Connection) -> SqlResult<()> {
+        let now = now_millis();
+        for (workspace, paths) in self.pending_hits.borrow().iter() {
+            let paths_json = serde_json::to_string(paths)
+                .map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))?;
+            conn.execute(
+                "UPDATE files SET last_hit_at = ?
+                 WHERE workspace = ? AND path IN (SELECT value FROM json_each(?))",
+                params![now, workspace, paths_json],
+            )?;
+        }
+        self.pending_hits.borrow_mut().clear();
+        Ok(())
+    }
+
+    /// Drop the vectors of the least recently searched files (by last search
+    /// hit, else index time), across all workspaces, until the index fits in
+    /// `budget` bytes. File records, metadata, and embedding text stay, so
+    /// evicted files can be restored without re-extracting them. Vector
+    /// searches can't score their rows until then, so searches restore the
+    /// files in their scope first (see `evicted_paths`).
+    pub fn evict_lru(&mut self, budget: u64) -> SqlResult<EvictionStats> {
+        let size = self.index_size()?;
+        let mut stats = EvictionStats::default();
+        if size <= budget {
+            return Ok(stats);
+        }
+        let excess = size - budget;
+
+        let tx = self.write_tx()?;
+        self.write_hits(&tx)?;
+        {
+            // length() reads BLOB sizes from record headers, not the content
+            let mut candidates = tx.prepare(
+                "SELECT f.workspace, f.path,
+                    (SELECT coalesce(sum(length(embedding) + coalesce(length(doc_embedding), 0)
+                                         + coalesce(length(embedding_bits), 0)
+                                         + coalesce(length(pq_codes), 0)), 0)
+                     FROM symbols s
+                     WHERE s.workspace = f.workspace AND s.file_path = f.path
+                       AND s.embedding IS NOT NULL)
+                  + (SELECT coalesce(sum(length(embedding)), 0)
+                     FROM symbol_windows w
+                     WHERE w.workspace = f.workspace AND w.file_path = f.path)
+                  + (SELECT coalesce(sum(length(vectors)), 0)
+                     FROM symbol_tokens t
+                     WHERE t.workspace = f.workspace AND t.file_path = f.path)
+                  + (SELECT coalesce(sum(length(embedding)), 0)
+                     FROM chunks c
+                     WHERE c.workspace = f.workspace AND c.file_path = f.path
+                       AND c.embedding IS NOT NULL)
+                 FROM files f
+                 WHERE f.evicted_at IS NULL
+                 ORDER BY coalesce(f.last_hit_at, f.indexed_at)",
+            )?;
+            let mut victims: Vec<(String, String)> = Vec::new();
+            let mut rows = candidates.query([])?;
+            while stats.bytes < excess {
+                let Some(row) = rows.next()? else { break };
+                let bytes: i64 = row.get(2)?;
+                stats.bytes += bytes as u64;
+                victims.push((row.get(0)?, row.get(1)?));
+            }
+            drop(rows);
+
+            let now = now_millis();
+            for (workspace, path) in &victims {
+                stats.symbols += tx.execute(
+                    "UPDATE symbols
+                     SET embedding = NULL, doc_embedding = NULL, embedding_bits = NULL, pq_codes = NULL
+                     WHERE workspace = ? AND file_path = ? AND embedding IS NOT NULL",
+                    params![workspace, path],
+                )? as u64;
+                for sql in [
+                    "DELETE FROM symbol_windows WHERE workspace = ? AND file_path = ?",
+                    "DELETE FROM symbol_tokens WHERE workspace = ? AND file_path = ?",
+                ] {
+                    tx.execute(sql, params![workspace, path])?;
+                }
+                stats.chunks += tx.execute(
+                    "UPDATE chunks SET embedding = NULL
+                     WHERE workspace = ? AND file_path = ? AND embedding IS NOT NULL",
+                    params![workspace, path],
+                )? as u64;
+                tx.execute(
+                    "UPDATE files SET evicted_at = ? WHERE workspace = ? AND path = ?",
+                    params![now, workspace, path],
+                )?;
+            }
+            stats.files = victims.len() as u64;
+        }
+        tx.commit()?;
+        Ok(stats)
+    }
+
+    /// `(files, symbols, chunks)` currently evicted, across all workspaces.
+    pub fn eviction_counts(&self) -> SqlResult<(u64, u64, u64)> {
+        let count = |sql: &str| -> SqlResult<u64> {
+            Ok(self.conn.query_row(sql, [], |r| r.get::<_, i64>(0))? as u64)
+        };
+        Ok((
+            count("SELECT count(*) FROM files WHERE evicted_at IS NOT NULL")?,
+            count("SELECT count(*) FROM symbols WHERE embedding IS NULL")?,
+            count("SELECT count(*) FROM chunks WHERE embedding IS NULL")?,
+        ))
+    }
+
+    /// Up to `limit` symbols in the current workspace whose embedding was
+    /// computed by a model other than `model`, in key order, with the total
+    /// count. Evicted symbols aren't stale: restoring them uses the loaded
+    /// model.
+    pub fn stale_embeddings(
+        &self,
+        model: &str,
+        limit: usize,
+    ) -> SqlResult<(u64, Vec<StaleEmbedding>)> {
+        let total: i64 = self.conn.query_row(
+            "SELECT count(*) FROM symbols
+             WHERE workspace = ? AND embedding IS NOT NULL AND embedding_model IS NOT ?",
+            params![self.workspace, model],
+            |r| r.get(0),
+        )?;
+        let mut stmt = self.conn.prepare(
+            "SELECT file_path, line, name, kind, embedding_model
+             FROM symbols
+             WHERE workspace = ? AND embedding IS NOT NULL AND embedding_model IS NOT ?
+             ORDER BY file_path, line
+             LIMIT ?",
+        )?;
+        let rows = stmt
+            .query_map(params![self.workspace, model, limit as i64], |r| {
+                Ok(StaleEmbedding {
+                    file_path: r.get(0)?,
+                    line: r.get(1)?,
+                    name: r.get(2)?,
+                    kind: r.get(3)?,
+                    model: r.get(4)?,
+                })
+            })?
+            .collect::<SqlResult<Vec<_>>>()?;
+        Ok((total as u64, rows))
+    }
+
+    /// `(file_path, line)` of the `results` whose symbol embedding was
+    /// computed by a model other than `model`. Chunks are never stale.
+    pub fn stale_results(
+        &self,
+        workspace: Option<&str>,
+        model: &str,
+        results: &[SearchResult],
+    ) -> SqlResult<HashSet<(String, i32)>> {
+        let ws = workspace.unwrap_or(&self.workspace);
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT 1 FROM symbols
+             WHERE workspace = ? AND file_path = ? AND line = ? AND embedding_model IS NOT ?",
+        )?;
+        let mut stale = HashSet::new();
+        for r in results {
+            if stmt.exists(params![ws, r.file_path, r.line, model])? {
+                stale.insert((r.file_path.clone(), r.line));
+            }
+        }
+        Ok(stale)
+    }
+
+    /// Up to `limit` evicted files in the current workspace, under
+    /// `path_prefix` if given, most recently searched first.
+    pub fn evicted_paths(&self, path_prefix: Option<&str>, limit: usize) -> SqlResult<Vec<String>> {
+        let (from, to) = match path_prefix {
+            Some(prefix) => {
+                let prefix = prefix.trim_end_matches('/');
+                (Some(format!("{}/", prefix)), Some(format!("{}0", prefix)))
+            }
+            None => (None, None),
+        };
+        let mut stmt = self.conn.prepare(
+            "SELECT path FROM files
+             WHERE workspace = ?1 AND evicted_at IS NOT NULL
+               AND (?2 IS NULL OR (path >= ?2 AND path < ?3))
+             ORDER BY coalesce(last_hit_at, indexed_at) DESC
+             LIMIT ?4",
+        )?;
+        let paths = stmt
+            .query_map(params![self.workspace, from, to, limit as i64], |r| r.get(0))?
+            .collect::<SqlResult<Vec<_>>>()?;
+        Ok(paths)
+    }
+
+    /// Evicted symbols and chunks (`(file_path, start_line, text)`) in the
+    /// current workspace, limited to `paths` when given.
+    #[allow(clippy::type_complexity)]
+    pub fn evicted_rows(
+        &self,
+        paths: Option<&[String]>,
+    ) -> SqlResult<(Vec<StoredSymbol>, Vec<(String, i32, String)>)> {
+        let paths_json = paths
+            .map(serde_json::to_string)
+            .transpose()
+            .map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))?;
+        let mut stmt = self.conn.prepare(
+            "SELECT file_path, line, name, kind, language, end_line, signature, embedding_text, doc_comment,
+                    metadata
+             FROM symbols
+             WHERE workspace = ?1 AND embedding IS NULL
+               AND (?2 IS NULL OR file_path IN (SELECT value FROM json_each(?2)))",
+        )?;
+        let symbols = stmt
+            .query_map(params![self.workspace, paths_json], |r| {
+                Ok(StoredSymbol {
+                    file_path: r.get(0)?,
+                    line: r.get(1)?,
+                    name: r.get(2)?,
+                    kind: r.get(3)?,
+                    language: r.get(4)?,
+                    end_line: r.get(5)?,
+                    signature: unpack_text(r, 6)?,
+                    embedding_text: unpack_text(r, 7)?.unwrap_or_default(),
+                    doc_comment: r.get(8)?,
+                    metadata: r.get(9)?,
+                })
+            })?
+            .collect::<SqlResult<Vec<_>>>()?;
+        let mut stmt = self.conn.prepare(
+            "SELECT file_path, start_line, text
+             FROM chunks
+             WHERE workspace = ?1 AND embedding IS NULL
+               AND (?2 IS NULL OR file_path IN (SELECT value FROM json_each(?2)))",
+        )?;
+        let chunks = stmt
+            .query_map(params![self.workspace, paths_json], |r| {
+                Ok((r.get(0)?, r.get(1)?, r.get(2)?))
+            })?
+            .collect::<SqlResult<Vec<_>>>()?;
+        Ok((symbols, chunks))
+    }
+
+    /// `(symbols, chunks)` with stored embeddings, across all workspaces.
+    pub fn embedded_counts(&self) -> SqlResult<(u64, u64)> {
+        let count = |sql: &str| -> SqlResult<u64> {
+            Ok(self.conn.query_row(sql, [], |r| r.get::<_, i64>(0))? as u64)
+        };
+        Ok((
+            count("SELECT count(*) FROM symbols WHERE embedding IS NOT NULL")?,
+            count("SELECT count(*) FROM chunks WHERE embedding IS NOT NULL")?,
+        ))
+    }
+
+    /// Up to `limit` symbols with stored embeddings, across all workspaces,
+    /// in key order after `after` (`(workspace, file_path, line)`): pages for
+    /// walking the whole index. Returns each symbol with its workspace.
+    pub fn embedded_symbols_page(
+        &self,
+        after: Option<&RowKey>,
+        limit: usize,
+    ) -> SqlResult<Vec<(String, StoredSymbol)>> {
+        let (workspace, path, line) = split_key(after);
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT workspace, file_path, line, name, kind, language, end_line, signature, embedding_text,
+                    doc_comment, metadata
+             FROM symbols
+             WHERE embedding IS NOT NULL
+               AND (?1 IS NULL OR (workspace, file_path, line) > (?1, ?2, ?3))
+             ORDER BY workspace, file_path, line
+             LIMIT ?4",
+        )?;
+        let rows = stmt.query_map(params![workspace, path, line, limit as i64], |r| {
+            Ok((
+                r.get(0)?,
+                StoredSymbol {
+                    file_path: r.get(1)?,
+                    line: r.get(2)?,
+                    name: r.get(3)?,
+                    kind: r.get(4)?,
+                    language: r.get(5)?,
+                    end_line: r.get(6)?,
+                    signature: unpack_text(r, 7)?,
+                    embedding_text: unpack_text(r, 8)?.unwrap_or_default(),
+                    doc_comment: r.get(9)?,
+                    metadata: r.get(10)?,
+                },
+            ))
+        })?;
+        rows.collect()
+    }
+
+    /// Like `embedded_symbols_page`, for chunks: `((workspace, file_path,
+    /// start_line), text)`.
+    pub fn embedded_chunks_page(
+        &self,
+        after: Option<&RowKey>,
+        limit: usize,
+    ) -> SqlResult<Vec<(RowKey, String)>> {
+        let (workspace, path, line) = split_key(after);
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT workspace, file_path, start_line, text
+             FROM chunks
+             WHERE embedding IS NOT NULL
+               AND (?1 IS NULL OR (workspace, file_path, start_line) > (?1, ?2, ?3))
+             ORDER BY workspace, file_path, start_line
+             LIMIT ?4",
+        )?;
+        let rows = stmt.query_map(params![workspace, path, line, limit as i64], |r| {
+            Ok(((r.get(0)?, r.get(1)?, r.get(2)?), r.get(3)?))
+        })?;
+        rows.collect()
+    }
+
+    /// Delete the PQ codebook and every row's codes, for when stored
+    /// embeddings are replaced by ones it wasn't trained on.
+    pub fn clear_pq_codebook(&mut self) -> SqlResult<()> {
+        let tx = self.write_tx()?;
+        tx.execute("DELETE FROM pq_codebook", [])?;
+        tx.execute("UPDATE symbols SET pq_codes = NULL WHERE pq_codes IS NOT NULL", [])?;
+        tx.commit()?;
+        self.pq = None;
+        Ok(())
+    }
+
+    fn load_storage(&mut self) -> SqlResult<()> {
+        self.storage = self
+            .get_meta(VECTOR_STORAGE_META)?
+            .and_then(|s| VectorStorage::parse(&s))
+            .unwrap_or(VectorStorage::Blob);
+        self.compress_text = self.get_meta(TEXT_COMPRESSION_META)?.as_deref() == Some("zstd");
+        Ok(())
+    }
+
+    /// Whether new `embedding_text` and `signature` values should be
+    /// stored with `pack_text(.., true)`.
+    pub fn text_compression(&self) -> bool {
+        self.compress_text
+    }
+
+    /// Store `embedding_text` and `signature` zstd-compressed (or not), and
+    /// rewrite every existing row, across all workspaces, to match. Reads
+    /// decompress transparently, so rows of either form can coexist; values
+    /// that don't shrink stay uncompressed. Returns the stored size of both
+    /// columns before and after, in bytes.
+    pub fn set_text_compression(&mut self, enabled: bool) -> SqlResult<(u64, u64)> {
+        let tx = self.write_tx()?;
+        let (mut before, mut after) = (0, 0);
+        {
+            let mut select = tx.prepare(
+                "SELECT workspace, file_path, line, embedding_text, signature FROM symbols",
+            )?;
+            let mut update = tx.prepare(
+                "UPDATE symbols SET embedding_text = ?, signature = ?
+                 WHERE workspace = ? AND file_path = ? AND line = ?",
+            )?;
+            let mut rows = select.query([])?;
+            while let Some(row) = rows.next()? {
+                let stored = |i: usize| -> SqlResult<u64> {
+                    Ok(row.get_ref(i)?.as_bytes_or_null()?.map_or(0, |b| b.len() as u64))
+                };
+                before += stored(3)? + stored(4)?;
+                let text = unpack_text(row, 3)?.unwrap_or_default();
+                let signature = unpack_text(row, 4)?;
+                let text = pack_text(&text, enabled);
+                let signature = signature.as_deref().map(|s| pack_text(s, enabled));
+                after += packed_len(&text) + signature.as_ref().map_or(0, packed_len);
+                let workspace: String = row.get(0)?;
+                let path: String = row.get(1)?;
+                let line: i64 = row.get(2)?;
+                update.execute(params![text, signature, workspace, path, line])?;
+            }
+        }
+        if enabled {
+            tx.execute(
+                "INSERT OR REPLACE INTO meta (key, value) VALUES (?, 'zstd')",
+                params![TEXT_COMPRESSION_META],
+            )?;
+        } else {
+            tx.execute("DELETE FROM meta WHERE key = ?", params![TEXT_COMPRESSION_META])?;
+        }
+        tx.commit()?;
+        self.compress_text = enabled;
+        Ok((before, after))
+    }
+
+    /// Rebuild a `vec_symbols` created before it was partitioned by
+    /// language, or before its update trigger followed workspace moves
+    /// (which left vectors of soft-deleted symbols behind under their live
+    /// keys).
+    fn upgrade_vec0(&mut self) -> SqlResult<()> {
+        if self.storage != VectorStorage::Vec0 {
+            return Ok(());
+        }
+        let schema_sql = |kind: &str, name: &str| -> SqlResult<Option<String>> {
+            self.conn
+                .query_row(
+                    "SELECT sql FROM sqlite_master WHERE type = ? AND name = ?",
+                    params![kind, name],
+                    |r| r.get(0),
+                )
+                .optional()
+        };
+        let table = schema_sql("table", "vec_symbols")?;
+        let trigger = schema_sql("trigger", "symbols_vec_update")?;
+        if !table.is_some_and(|sql| sql.contains(VEC0_PARTITIONS))
+            || !trigger.is_some_and(|sql| sql.contains(VEC0_UPDATE_COLUMNS))
+        {
+            self.set_vector_storage(VectorStorage::Vec0)?;
+        }
+        Ok(())
+    }
+
+    pub fn vector_storage(&self) -> VectorStorage {
+        self.storage
+    }
+
+    /// Switch how exact symbol searches find vectors. Moving to `Vec0`
+    /// creates `vec_symbols` and its triggers and copies every stored
+    /// embedding, across all workspaces, into it; moving back drops them.
+    /// Best chosen when an index is created, since the copy touches every
+    /// row. Returns the vectors copied.
+    pub fn set_vector_storage(&mut self, storage: VectorStorage) -> SqlResult<u64> {
+        let dims = self
+            .get_meta("dimensions")?
+            .and_then(|d| d.parse::<usize>().ok())
+            .unwrap_or(768);
+        let tx = self.write_tx()?;
+        tx.execute_batch(
+            "DROP TRIGGER IF EXISTS symbols_vec_insert;
+             DROP TRIGGER IF EXISTS symbols_vec_update;
+             DROP TRIGGER IF EXISTS symbols_vec_delete;
+             DROP TABLE IF EXISTS vec_symbols;",
+        )?;
+        let mut copied = 0;
+        if storage == VectorStorage::Vec0 {
+            // `INSERT OR REPLACE INTO symbols` doesn't fire delete triggers,
+            // so the insert trigger clears a replaced row's vector itself.
+            // Evicting (embedding = NULL) removes the vector; restoring
+            // puts it back. Moving a row to another workspace re-keys it.
+            tx.execute_batch(&format!(
+                "CREATE VIRTUAL TABLE vec_symbols USING vec0(
+                     chunk_size={chunk},
+                     {partitions},
+                     key TEXT PRIMARY KEY,
+                     embedding float[{dims}],
+                     file_path TEXT,
+                     line INTEGER,
+                     kind TEXT
+                 );
+
+                 CREATE TRIGGER symbols_vec_insert AFTER INSERT ON symbols BEGIN
+                     DELETE FROM vec_symbols WHERE key = {new_key};
+                     INSERT INTO vec_symbols
+                         (workspace, key, embedding, file_path, line, language, kind)
+                     SELECT NEW.workspace, {new_key}, NEW.embedding, NEW.file_path,
+                            NEW.line, NEW.language, NEW.kind
+                     WHERE NEW.embedding IS NOT NULL;
+                 END;
+
+                 CREATE TRIGGER symbols_vec_update AFTER UPDATE OF {update_columns} ON symbols BEGIN
+                     DELETE FROM vec_symbols WHERE key = {old_key};
+                     INSERT INTO vec_symbols
+                         (workspace, key, embedding, file_path, line, language, kind)
+                     SELECT NEW.workspace, {new_key}, NEW.embedding, NEW.file_path,
+                            NEW.line, NEW.language, NEW.kind
+                     WHERE NEW.embedding IS NOT NULL;
+                 END;
+
+                 CREATE TRIGGER symbols_vec_delete AFTER DELETE ON symbols BEGIN
+                     DELETE FROM vec_symbols WHERE key = {old_key};
+                 END;",
+                chunk = VEC0_CHUNK_SIZE,
+                partitions = VEC0_PARTITIONS,
+                update_columns = VEC0_UPDATE_COLUMNS,
+                dims = dims,
+                new_key = vec_key_sql("NEW"),
+                old_key = vec_key_sql("OLD"),
+            ))?;
+            copied = tx.execute(
+                &format!(
+                    "INSERT INTO vec_symbols
+                         (workspace, key, embedding, file_path, line, language, kind)
+                     SELECT workspace, {}, embedding, file_path, line, language, kind
+                     FROM symbols WHERE embedding IS NOT NULL",
+                    vec_key_sql("symbols")
+                ),
+                [],
+            )? as u64;
+        }
+        tx.execute(
+            "INSERT OR REPLACE INTO meta (key, value) VALUES (?, ?)",
+            params![VECTOR_STORAGE_META, storage.as_str()],
+        )?;
+        tx.commit()?;
+        self.storage = storage;
+        Ok(copied)
+    }
+
+    /// `search_symbols`' exact scan through vec0's KNN query. Filters map to
+    /// vec0 metadata columns, so they're applied inside the KNN search
+    /// rather than after it.
+    fn search_vec0(
+        &self,
+        query_embedding: &[f32],
+        top_k: i32,
+        filters: &Filters,
+    ) -> SqlResult<Vec<SearchResult>> {
+        let mut where_clauses = vec!["embedding MATCH ?", "k = ?", "workspace = ?"];
+        let mut param_values: Vec<Box<dyn rusqlite::types::ToSql>> = vec![
+            Box::new(bytemuck::cast_slice::<f32, u8>(query_embedding).to_vec()),
+            Box::new(top_k.max(0)),
+            Box::new(filters.workspace.unwrap_or(&self.workspace).to_string()),
+        ];
+        if let Some(prefix) = filters.path_prefix {
+            push_path_prefix(&mut where_clauses, &mut param_values, prefix);
+        }
+        if let Some(lang) = filters.language {
+            where_clauses.push("language = ?");
+            param_values.push(Box::new(lang.to_string()));
+        }
+        if let Some(k) = filters.kind {
+            where_clauses.push("kind = ?");
+            param_values.push(Box::new(k.to_string()));
+        }
+        let params_ref: Vec<&dyn rusqlite::types::ToSql> =
+            param_values.iter().map(|p| p.as_ref()).collect();
+
+        let sql = format!(
+            "SELECT s.file_path, s.line, s.name, s.kind, s.language, s.end_line, s.signature,
+                    s.doc_comment, s.symbol_id, s.metadata, v.distance
+             FROM (SELECT workspace, file_path, line, distance FROM vec_symbols
+                   WHERE {}) v
+             JOIN symbols s
+               ON s.workspace = v.workspace AND s.file_path = v.file_path AND s.line = v.line
+             ORDER BY v.distance",
+            where_clauses.join(" AND ")
+        );
+        let max_dist = filters.max_distance();
+        let mut stmt = self.conn.prepare(&sql)?;
+        let mut rows = stmt.query(params_ref.as_slice())?;
+        let mut results = Vec::new();
+        while let Some(row) = rows.next()? {
+            // vec0 reports L2; scores are defined on L2²
+            let dist: f64 = row.get(10)?;
+            let dist = dist * dist;
+            if dist > max_dist {
+                break;
+            }
+            results.push(SearchResult {
+                score: 1.0 - (dist / 2.0),
+                ..symbol_from_row(row)?
+            });
+        }
+        self.count_rows(results.len() as u64);
+        Ok(results)
+    }
+
+    /// Check every stored vector, in all workspaces, for a length that
+    /// doesn't match the `dimensions` meta value and for NaN or infinite
+    /// components. Evicted rows (NULL embeddings) are skipped.
+    ///
+    /// With `repair`, corrupt rows are deleted and their files' hashes
+    /// cleared, so the next incremental reindex re-extracts and re-embeds
+    /// them.
+    pub fn verify_embeddings(&mut self, repair: bool) -> SqlResult<IntegrityReport> {
+        const TABLES: [(&str, &str, &[&str]); 2] = [
+            ("symbols", "line", &["embedding", "doc_embedding"]),
+            ("chunks", "start_line", &["embedding"]),
+        ];
+        let dims = self
+            .get_meta("dimensions")?
+            .and_then(|d| d.parse::<usize>().ok());
+        let mut report = IntegrityReport::default();
+
+        for (table, line_col, columns) in TABLES {
+            let mut stmt = self.conn.prepare(&format!(
+                "SELECT workspace, file_path, {}, {} FROM {}",
+                line_col,
+                columns.join(", "),
+                table
+            ))?;
+            let mut rows = stmt.query([])?;
+            while let Some(row) = rows.next()? {
+                report.rows_checked += 1;
+                for (i, &column) in columns.iter().enumerate() {
+                    let Some(blob) = row.get_ref(3 + i)?.as_blob_or_null()? else {
+                        continue;
+                    };
+                    if let Some(problem) = vector_problem(blob, dims) {
+                        report.corrupt.push(CorruptRow {
+                            table,
+                            workspace: row.get(0)?,
+                            file_path: row.get(1)?,
+                            line: row.get(2)?,
+                            column,
+                            problem,
+                        });
+                        break;
+                    }
+                }
+            }
+        }
+
+        if repair && !report.corrupt.is_empty() {
+            let tx = self.write_tx()?;
+            for row in &report.corrupt {
+                let line_col = if row.table == "symbols" { "line" } else { "start_line" };
+                report.deleted += tx.execute(
+                    &format!(
+                        "DELETE FROM {} WHERE workspace = ? AND file_path = ? AND {} = ?",
+                        row.table, line_col
+                    ),
+                    params![row.workspace, row.file_path, row.line],
+                )? as u64;
+                tx.execute(
+                    "UPDATE files SET hash = '' WHERE workspace = ? AND path = ?",
+                    params![row.workspace, row.file_path],
+                )?;
+            }
+            tx.commit()?;
+        }
+        Ok(report)
+    }
+
+    /// Find drift between `files` and the `symbols`/`chunks` tables, in all
+    /// workspaces, e.g. left by an interrupted deletion: rows whose file has
+    /// no record, and records whose symbols are gone.
+    ///
+    /// With `repair`, orphaned rows are deleted and records missing their
+    /// symbols get their hashes cleared, so the next incremental reindex
+    /// re-extracts those files.
+    pub fn check_consistency(&mut self, repair: bool) -> SqlResult<ConsistencyReport> {
+        let orphans_from = |table: &str| {
+            format!(
+                "FROM {} t WHERE NOT EXISTS (
+                     SELECT 1 FROM files f WHERE f.workspace = t.workspace AND f.path = t.file_path)",
+                table
+            )
+        };
+        const MISSING: &str = "FROM files f WHERE f.symbol_count > 0 AND NOT EXISTS (
+             SELECT 1 FROM symbols s WHERE s.workspace = f.workspace AND s.file_path = f.path)";
+        let drift = |sql: &str| -> SqlResult<Vec<FileDrift>> {
+            let mut stmt = self.conn.prepare(sql)?;
+            let rows = stmt.query_map([], |r| {
+                Ok(FileDrift {
+                    workspace: r.get(0)?,
+                    path: r.get(1)?,
+                    rows: r.get::<_, i64>(2)? as u64,
+                })
+            })?;
+            rows.collect()
+        };
+        let orphans = |table: &str| {
+            drift(&format!(
+                "SELECT t.workspace, t.file_path, count(*) {} GROUP BY t.workspace, t.file_path",
+                orphans_from(table)
+            ))
+        };
+        let mut report = ConsistencyReport {
+            orphan_symbols: orphans("symbols")?,
+            orphan_chunks: orphans("chunks")?,
+            missing_symbols: drift(&format!("SELECT f.workspace, f.path, f.symbol_count {}", MISSING))?,
+            deleted: 0,
+        };
+
+        let drifted = !report.orphan_symbols.is_empty()
+            || !report.orphan_chunks.is_empty()
+            || !report.missing_symbols.is_empty();
+        if repair && drifted {
+            let tx = self.write_tx()?;
+            for table in ["symbols", "chunks"] {
+                report.deleted += tx.execute(
+                    &format!(
+                        "DELETE FROM {} WHERE (workspace, file_path) IN (SELECT t.workspace, t.file_path {})",
+                        table,
+                        orphans_from(table)
+                    ),
+                    [],
+                )? as u64;
+            }
+            tx.execute(
+                &format!(
+                    "UPDATE files SET hash = '' WHERE (workspace, path) IN (SELECT f.workspace, f.path {})",
+                    MISSING
+                ),
+                [],
+            )?;
+            tx.commit()?;
+        }
+        Ok(report)
+    }
+
+    /// Refresh the query planner's statistics. Run after bulk writes so
+    /// filtered searches pick the (language, kind) index or a PK range scan
+    /// over a full scan.
+    pub fn analyze(&self) -> SqlResult<()> {
+        self.write(|conn| conn.execute_batch("ANALYZE"))
+    }
+
+    /// Begin a write transaction. See `begin_write`.
+    pub fn transaction(&mut self) -> SqlResult<Transaction<'_>> {
+        self.write_tx()
+    }
+
+    /// Run `f` as one write: in its own write transaction, or inside the one
+    /// already open on the connection (an ingestion), which holds the lease.
+    fn write<T>(&self, f: impl FnOnce(&Connection) -> SqlResult<T>) -> SqlResult<T> {
+        if !self.conn.is_autocommit() {
+            self.bump_generation();
+            return f(&self.conn);
+        }
+        let tx = self.write_tx()?;
+        let out = f(&tx)?;
+        tx.commit()?;
+        Ok(out)
+    }
+
+    /// Like `write`, for bookkeeping (query log, clicks) that doesn't touch
+    /// indexed rows: it only waits for the write lock, not for another
+    /// session's lease.
+    fn record<T>(&self, f: impl FnOnce(&Connection) -> SqlResult<T>) -> SqlResult<T> {
+        if !self.conn.is_autocommit() {
+            self.bump_generation();
+            return f(&self.conn);
+        }
+        self.bump_generation();
+        let tx = begin_immediate(&self.conn)?;
+        let out = f(&tx)?;
+        tx.commit()?;
+        Ok(out)
+    }
+
+    fn write_tx(&self) -> SqlResult<Transaction<'_>> {
+        if self.ingesting {
+            return Err(busy_error(
+                "an index session is open; commit or abort it first".to_string(),
+            ));
+        }
+        self.bump_generation();
+        begin_write(&self.conn, &self.session)
+    }
+
+    /// Begin a write transaction that stays open across calls until
+    /// `end_ingest`, so a long ingestion commits or rolls back as a whole.
+    /// Meanwhile other write transactions fail, and reads through this
+    /// connection see the uncommitted rows. The writer lease is claimed in
+    /// its own committed write first, so other sessions' errors can name
+    /// this one.
+    pub fn begin_ingest(&mut self) -> SqlResult<()> {
+        let tx = self.write_tx()?;
+        tx.execute(
+            "INSERT OR REPLACE INTO meta (key, value) VALUES ('writer_lease', ?)",
+            params![self.session],
+        )?;
+        tx.commit()?;
+        let mut tx = match begin_immediate(&self.conn) {
+            Ok(tx) => tx,
+            Err(e) => {
+                self.release_lease();
+                return Err(e);
+            }
+        };
+        tx.set_drop_behavior(DropBehavior::Ignore);
+        drop(tx);
+        self.ingesting = true;
+        Ok(())
+    }
+
+    /// The connection, for writes into the open `begin_ingest` transaction.
+    pub fn ingest_conn(&self) -> Option<&Connection> {
+        if !self.ingesting {
+            return None;
+        }
+        self.bump_generation();
+        Some(&self.conn)
+    }
+
+    /// Commit or roll back `begin_ingest`'s transaction, then release the
+    /// writer lease. A failed commit rolls back.
+    pub fn end_ingest(&mut self, commit: bool) -> SqlResult<()> {
+        if !std::mem::take(&mut self.ingesting) {
+            return Ok(());
+        }
+        self.bump_generation();
+        let result = if commit {
+            self.conn.execute_batch("COMMIT").inspect_err(|_| {
+                let _ = self.conn.execute_batch("ROLLBACK");
+            })
+        } else {
+            self.conn.execute_batch("ROLLBACK")
+        };
+        self.release_lease();
+        result
+    }
+
+    /// Drop this session's writer lease. Best effort: a lease left behind
+    /// only delays other writers by `LEASE_GRACE`.
+    fn release_lease(&self) {
+        let _ = self.conn.execute(
+            "DELETE FROM meta WHERE key = 'writer_lease' AND value = ?",
+            params![self.session],
+        );
+    }
+
+    fn bump_generation(&self) {
+        self.generation.set(self.generation.get() + 1);
+    }
+
+    /// A counter that changes whenever the index may have changed: on every
+    /// write transaction or meta update through this connection, and on
+    /// commits by other connections. Searches are stale across a change.
+    pub fn generation(&self) -> SqlResult<u64> {
+        let data_version: i64 = self
+            .conn
+            .pragma_query_value(None, "data_version", |r| r.get(0))?;
+        if data_version != self.data_version.get() {
+            self.data_version.set(data_version);
+            self.bump_generation();
+        }
+        Ok(self.generation.get())
+    }
+}
+
+impl Drop for SearchDB {
+    /// Roll back an unfinished ingestion, which hands the writer lease back.
+    fn drop(&mut self) {
+        let _ = self.end_ingest(false);
+    }
+}
+
+/// Per-connection pragmas shared by `open_with` and `open_readonly_with`.
+fn apply_pragmas(conn: &Connection, options: &OpenOptions) -> SqlResult<()> {
+    conn.pragma_update(None, "mmap_size", options.mmap_size.unwrap_or(3_000_000_000))?;
+    conn.pragma_update(None, "temp_store", 2)?; // memory
+    conn.pragma_update(None, "cache_size", options.cache_size.unwrap_or(-64000))?; // 64MB
+    if let Some(synchronous) = options.synchronous {
+        conn.pragma_update(None, "synchronous", synchronous)?;
+    }
+    Ok(())
+}
+
+/// Begin a write transaction for `session`. The write lock is taken up
+/// front (`BEGIN IMMEDIATE`) so two writers can't deadlock upgrading read
+/// locks (see `begin_immediate`). With the lock, check the writer lease:
+/// while another session holds it, roll back (so the holder can begin its
+/// ingestion) and poll for up to `LEASE_GRACE`, then clear it as left
+/// behind.
+fn begin_write<'c>(conn: &'c Connection, session: &str) -> SqlResult<Transaction<'c>> {
+    let deadline = Instant::now() + LEASE_GRACE;
+    loop {
+        let tx = begin_immediate(conn)?;
+        match lease_holder(&tx) {
+            Some(holder) if holder != session && Instant::now() < deadline => {
+                drop(tx);
+                std::thread::sleep(LEASE_POLL);
+            }
+            Some(holder) if holder != session => {
+                tracing::warn!("clearing writer lease left by {}", holder);
+                tx.execute("DELETE FROM meta WHERE key = 'writer_lease'", [])?;
+                return Ok(tx);
+            }
+            _ => return Ok(tx),
+        }
+    }
+}
+
+/// The session holding the writer lease, if any.
+fn lease_holder(conn: &Connection) -> Option<String> {
+    conn.query_row("SELECT value FROM meta WHERE key = 'writer_lease'", [], |r| r.get(0))
+        .optional()
+        .unwrap_or(None)
+}
+
+/// `BEGIN IMMEDIATE`, retrying with backoff while another process holds
+/// the write lock past the busy timeout. If that process is an index
+/// session (it holds the writer lease), fail right away naming it: an
+/// ingestion can keep the lock for minutes.
+fn begin_immediate(conn: &Connection) -> SqlResult<Transaction<'_>> {
+    let mut pause = WRITE_RETRY_BASE;
+    let mut attempt = 0;
+    loop {
+        match Transaction::new_unchecked(conn, TransactionBehavior::Immediate) {
+            Ok(tx) => return Ok(tx),
+            Err(e) if is_busy(&e) => {
+                if let Some(holder) = lease_holder(conn) {
+                    return Err(busy_error(format!(
+                        "index is being written by another session ({}); retry when it finishes",
+                        holder
+                    )));
+                }
+                if attempt == WRITE_RETRIES {
+                    return Err(busy_error(format!(
+                        "index is locked by another process; gave up after {} attempts",
+                        attempt + 1
+                    )));
+                }
+                std::thread::sleep(pause);
+                pause *= 2;
+                attempt += 1;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+fn is_busy(e: &rusqlite::Error) -> bool {
+    matches!(
+        e.sqlite_error_code(),
+        Some(rusqlite::ErrorCode::DatabaseBusy | rusqlite::ErrorCode::DatabaseLocked)
+    )
+}
+
+fn busy_error(message: String) -> rusqlite::Error {
+    rusqlite::Error::SqliteFailure(
+        rusqlite::ffi::Error::new(rusqlite::ffi::SQLITE_BUSY),
+        Some(message),
+    )
+}
+
+/// Candidate names each `symbols_by_name` source (prefix, trigrams) adds.
+const NAME_CANDIDATES: usize = 256;
+
+/// Distinct trigrams of `name`, lowercased; none for names under three
+/// characters.
+pub fn name_trigrams(name: &str) -> Vec<String> {
+    let chars: Vec<char> = name.to_lowercase().chars().collect();
+    let mut trigrams: Vec<String> = chars.windows(3).map(|w| w.iter().collect()).collect();
+    trigrams.sort_unstable();
+    trigrams.dedup();
+    trigrams
+}
+
+/// Record `name` in `name_trigrams`. Callers own the transaction.
+pub fn insert_name_trigrams(conn: &Connection, name: &str) -> SqlResult<()> {
+    let trigrams = name_trigrams(name);
+    let Some(first) = trigrams.first() else {
+        return Ok(());
+    };
+    // Names are inserted whole, so one trigram present means all are
+    let mut exists = conn
+        .prepare_cached("SELECT 1 FROM name_trigrams WHERE trigram = ? AND name = ?")?;
+    if exists.exists(params![first, name])? {
+        return Ok(());
+    }
+    let mut stmt =
+        conn.prepare_cached("INSERT OR IGNORE INTO name_trigrams (trigram, name) VALUES (?, ?)")?;
+    for trigram in &trigrams {
+        stmt.execute(params![trigram, name])?;
+    }
+    Ok(())
+}
+
+/// How well `name` matches `query` (lowercase), 0 to 1: 1 for the same
+/// name ignoring case, above 0.8 for a prefix, above 0.6 for a substring
+/// (more for a larger share of the name), else up to 0.6 by trigram
+/// overlap.
+pub fn name_match_score(query: &str, name: &str) -> f64 {
+    let lower = name.to_lowercase();
+    let coverage = query.chars().count() as f64 / lower.chars().count().max(1) as f64;
+    if lower == query {
+        1.0
+    } else if lower.starts_with(query) {
+        0.8 + 0.1 * coverage
+    } else if lower.contains(query) {
+        0.6 + 0.1 * coverage
+    } else {
+        let q = name_trigrams(query);
+        let n = name_trigrams(&lower);
+        let shared = q.iter().filter(|t| n.binary_search(t).is_ok()).count();
+        let union = q.len() + n.len() - shared;
+        if union == 0 {
+            0.0
+        } else {
+            0.6 * shared as f64 / union as f64
+        }
+    }
+}
+
+/// Whether `outer`'s line range contains `inner`'s, in the same file.
+fn encloses(outer: &SearchResult, inner: &SearchResult) -> bool {
+    outer.file_path == inner.file_path
+        && outer.line <= inner.line
+        && inner.end_line.unwrap_or(inner.line) <= outer.end_line.unwrap_or(outer.line)
+}
+
+/// Most similar first, then by position for a stable order.
+fn sort_pairs(pairs: &mut [DuplicatePair]) {
+    pairs.sort_by(|x, y| {
+        y.similarity
+            .total_cmp(&x.similarity)
+            .then_with(|| x.a.file_path.cmp(&y.a.file_path))
+            .then_with(|| x.a.line.cmp(&y.a.line))
+            .then_with(|| x.b.file_path.cmp(&y.b.file_path))
+            .then_with(|| x.b.line.cmp(&y.b.line))
+    });
+}
+
+/// Read a symbol selected with the columns of `symbol_sql`: file_path(0),
+/// line(1), name(2), kind(3), language(4), end_line(5), signature(6),
+/// doc_comment(7), symbol_id(8), metadata(9). Score is left at 0.
+fn symbol_from_row(row: &rusqlite::Row) -> SqlResult<SearchResult> {
+    Ok(SearchResult {
+        file_path: row.get(0)?,
+        line: row.get(1)?,
+        name: row.get(2)?,
+        kind: row.get(3)?,
+        language: row.get(4)?,
+        end_line: row.get(5)?,
+        signature: unpack_text(row, 6)?,
+        doc_comment: row.get(7)?,
+        symbol_id: row.get(8)?,
+        metadata: row.get(9)?,
+        score: 0.0,
+    })
+}
+
+/// A text value to store in `embedding_text` or `signature`: zstd bytes
+/// (a BLOB) when `compress` is set and that is smaller, else the text.
+pub fn pack_text(text: &str, compress: bool) -> ToSqlOutput<'_> {
+    if compress {
+        if let Ok(packed) = zstd::encode_all(text.as_bytes(), ZSTD_LEVEL) {
+            if packed.len() < text.len() {
+                return ToSqlOutput::Owned(Value::Blob(packed));
+            }
+        }
+    }
+    ToSqlOutput::Borrowed(ValueRef::Text(text.as_bytes()))
+}
+
+fn packed_len(value: &ToSqlOutput) -> u64 {
+    match value {
+        ToSqlOutput::Owned(Value::Blob(b)) => b.len() as u64,
+        ToSqlOutput::Borrowed(ValueRef::Text(t)) => t.len() as u64,
+        _ => 0,
+    }
+}
+
+/// Read a column written with `pack_text`, decompressing BLOBs.
+fn unpack_text(row: &rusqlite::Row, idx: usize) -> SqlResult<Option<String>> {
+    let invalid = |e: Box<dyn std::error::Error + Send + Sync>| {
+        rusqlite::Error::FromSqlConversionFailure(idx, rusqlite::types::Type::Blob, e)
+    };
+    match row.get_ref(idx)? {
+        ValueRef::Blob(packed) => {
+            let bytes = zstd::decode_all(packed).map_err(|e| invalid(e.into()))?;
+            String::from_utf8(bytes).map(Some).map_err(|e| invalid(e.into()))
+        }
+        _ => row.get(idx),
+    }
+}
+
+/// Pack an embedding's sign bits, most significant bit first: 96 bytes for
+/// 768 dimensions. Hamming distance between these approximates angular
+/// distance between the full vectors.
+pub fn binarize(embedding: &[f32]) -> Vec<u8> {
+    embedding
+        .chunks(8)
+        .map(|c| {
+            c.iter()
+                .enumerate()
+                .fold(0u8, |b, (i, &x)| if x > 0.0 { b | (0x80 >> i) } else { b })
+        })
+        .collect()
+}
+
+/// Sparse lexical vector of a text's token ids: log-scaled term frequency
+/// (`1 + ln(tf)`) per distinct id, normalized. Plain term weighting, not a
+/// learned expansion like SPLADE: a text only gets weight on ids it
+/// contains. Code identifiers split into several word pieces, so a rare
+/// identifier still matches its own pieces exactly.
+pub fn tf_weights(ids: &[u32]) -> Vec<(u32, f32)> {
+    let mut counts: std::collections::BTreeMap<u32, u32> = std::collections::BTreeMap::new();
+    for &id in ids {
+        *counts.entry(id).or_default() += 1;
+    }
+    let mut weights: Vec<(u32, f32)> =
+        counts.into_iter().map(|(id, tf)| (id, 1.0 + (tf as f32).ln())).collect();
+    let norm = weights.iter().map(|(_, w)| w * w).sum::<f32>().sqrt();
+    if norm > 0.0 {
+        weights.iter_mut().for_each(|(_, w)| *w /= norm);
+    }
+    weights
+}
+
+/// Display name for a chunk: its first non-empty line, capped at 80 chars.
+fn chunk_title(text: &str) -> String {
+    let line = text
+        .lines()
+        .map(str::trim)
+        .find(|l| !l.is_empty())
+        .unwrap_or("");
+    match line.char_indices().nth(80) {
+        Some((i, _)) => format!("{}…", &line[..i]),
+        None => line.to_string(),
+    }
+}
+
+/// The hidden workspace holding `workspace`'s soft-deleted files. Every
+/// read is scoped to one workspace, so its rows drop out of searches,
+/// listings, and stats without a per-row check.
+fn tombstone_workspace(workspace: &str) -> String {
+    format!("\u{1}deleted\u{1}{}", workspace)
+}
+
+/// Delete a file's record, symbols, and chunks. Callers own the
+/// transaction.
+pub fn delete_file_rows(conn: &Connection, workspace: &str, path: &str) -> SqlResult<()> {
+    for sql in [
+        "DELETE FROM symbols WHERE workspace = ? AND file_path = ?",
+        "DELETE FROM chunks WHERE workspace = ? AND file_path = ?",
+        "DELETE FROM files WHERE workspace = ? AND path = ?",
+    ] {
+        conn.execute(sql, params![workspace, path])?;
+    }
+    Ok(())
+}
+
+/// Take an exclusive lock on the index file at `path`, held until the
+/// returned connection closes, e.g. while replacing the file. Fails with
+/// SQLITE_BUSY if another connection still has it open after
+/// `BUSY_TIMEOUT`. Leaving WAL mode, which needs that exclusive access,
+/// also checkpoints and removes the WAL, so it can't be replayed onto a
+/// replacement file.
+pub fn lock_index_file(path: &Path) -> SqlResult<Connection> {
+    let conn = Connection::open(path)?;
+    conn.busy_timeout(BUSY_TIMEOUT)?;
+    conn.pragma_update(None, "locking_mode", "EXCLUSIVE")?;
+    let mode: String =
+        conn.pragma_update_and_check(None, "journal_mode", "MEMORY", |r| r.get(0))?;
+    if !mode.eq_ignore_ascii_case("memory") {
+        return Err(busy_error(format!("index is still in {} mode", mode)));
+    }
+    conn.execute_batch("BEGIN EXCLUSIVE; COMMIT;")?;
+    Ok(conn)
+}
+
+/// Move a file's record, symbols (with their windows, token vectors, and
+/// sparse terms), and chunks to another workspace; `symbols_vec_update`
+/// re-keys their `vec_symbols` rows. Callers own the transaction.
+fn move_file_rows(conn: &Connection, from: &str, to: &str, path: &str) -> SqlResult<()> {
+    for sql in [
+        "UPDATE symbols SET workspace = ?2 WHERE workspace = ?1 AND file_path = ?3",
+        "UPDATE symbol_windows SET workspace = ?2 WHERE workspace = ?1 AND file_path = ?3",
+        "UPDATE symbol_tokens SET workspace = ?2 WHERE workspace = ?1 AND file_path = ?3",
+        "UPDATE sparse_terms SET workspace = ?2 WHERE workspace = ?1 AND file_path = ?3",
+        "UPDATE chunks SET workspace = ?2 WHERE workspace = ?1 AND file_path = ?3",
+        "UPDATE files SET workspace = ?2 WHERE workspace = ?1 AND path = ?3",
+    ] {
+        conn.execute(sql, params![from, to, path])?;
+    }
+    Ok(())
+}
+
+/// SQL for a symbol row's `vec_symbols.key`, given the row's alias
+/// (`NEW`, `OLD`, or a table name). Unit separators can't occur in paths.
+fn vec_key_sql(row: &str) -> String {
+    format!("{r}.workspace || char(31) || {r}.file_path || char(31) || {r}.line", r = row)
+}
+
+/// Make sqlite-vec's functions and `vec0` module available to every
+/// connection opened after this.
+fn register_sqlite_vec() {
+    type EntryPoint = unsafe extern "C" fn(
+        *mut rusqlite::ffi::sqlite3,
+        *mut *mut std::os::raw::c_char,
+        *const rusqlite::ffi::sqlite3_api_routines,
+    ) -> std::os::raw::c_int;
+    static REGISTER: std::sync::Once = std::sync::Once::new();
+    REGISTER.call_once(|| {
+        // Safety: sqlite3_vec_init is an SQLite extension entry point, the
+        // signature sqlite3_auto_extension expects; the crate declares it
+        // without arguments only to avoid depending on SQLite's headers.
+        unsafe {
+            let init = std::mem::transmute::<*const (), EntryPoint>(
+                sqlite_vec::sqlite3_vec_init as *const (),
+            );
+            rusqlite::ffi::sqlite3_auto_extension(Some(init));
+        }
+    });
+}
+
+/// Define `X REGEXP Y` (SQLite calls `regexp(Y, X)`) on `conn`: whether
+/// text `X` matches pattern `Y`, compiled once per statement. NULL never
+/// matches.
+fn register_regexp(conn: &Connection) -> SqlResult<()> {
+    conn.create_scalar_function(
+        "regexp",
+        2,
+        FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC,
+        |ctx| {
+            let re: Arc<regex::Regex> = ctx.get_or_create_aux(
+                0,
+                |pattern| -> Result<_, Box<dyn std::error::Error + Send + Sync>> {
+                    Ok(regex::Regex::new(pattern.as_str()?)?)
+                },
+            )?;
+            let text = ctx
+                .get_raw(1)
+                .as_str_or_null()
+                .map_err(|e| rusqlite::Error::UserFunctionError(e.into()))?;
+            Ok(text.is_some_and(|t| re.is_match(t)))
+        },
+    )
+}
+
+/// Filter with `clause` (one `?`) when `pattern` is set.
+fn push_regex(
+    where_clauses: &mut Vec<&'static str>,
+    param_values: &mut Vec<Box<dyn rusqlite::types::ToSql>>,
+    clause: &'static str,
+    pattern: Option<&str>,
+) {
+    if let Some(pattern) = pattern {
+        where_clauses.push(clause);
+        param_values.push(Box::new(pattern.to_string()));
+    }
+}
+
+/// Filter to paths under `prefix/` as a range on the clustered primary key
+/// (`prefix/` <= file_path < `prefix0`, since '0' follows '/'), so SQLite
+/// seeks instead of scanning. Unlike LIKE, `%`/`_` in the prefix match
+/// literally and matching is case-sensitive, like paths.
+fn push_path_prefix(
+    where_clauses: &mut Vec<&'static str>,
+    param_values: &mut Vec<Box<dyn rusqlite::types::ToSql>>,
+    prefix: &str,
+) {
+    let prefix = prefix.trim_end_matches('/');
+    where_clauses.push("file_path >= ? AND file_path < ?");
+    param_values.push(Box::new(format!("{}/", prefix)));
+    param_values.push(Box::new(format!("{}0", prefix)));
+}
+
+/// Filter to symbols whose metadata has each `(key, value)`. Keys name
+/// top-level members and are bound, not spliced into a JSON path, so dots
+/// and quotes in them are literal.
+fn push_metadata(
+    where_clauses: &mut Vec<&'static str>,
+    param_values: &mut Vec<Box<dyn rusqlite::types::ToSql>>,
+    metadata: &[(String, rusqlite::types::Value)],
+) {
+    for (key, value) in metadata {
+        where_clauses.push("EXISTS (SELECT 1 FROM json_each(metadata) WHERE key = ? AND value = ?)");
+        param_values.push(Box::new(key.clone()));
+        param_values.push(Box::new(value.clone()));
+    }
+}
+
+/// Stable identity for a symbol: the first 16 hex digits of
+/// SHA-256(path, name, kind, signature). Unlike `(file_path, line)` it
+/// survives the symbol moving within its file.
+pub fn symbol_id(file_path: &str, name: &str, kind: &str, signature: Option<&str>) -> String {
+    use sha2::{Digest, Sha256};
+    let mut hasher = Sha256::new();
+    for part in [file_path, name, kind, signature.unwrap_or("")] {
+        hasher.update(part.as_bytes());
+        hasher.update([0u8]);
+    }
+    hasher.finalize()[..8]
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// Milliseconds since the Unix epoch, the timestamp unit used in every table.
+pub fn now_millis() -> i64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as i64
+}
+
+/// Split a keyset pagination cursor (the last `RowKey` of the previous
+/// page) into nullable query parameters, all NULL for the first page.
+fn split_key(after: Option<&RowKey>) -> (Option<&str>, Option<&str>, Option<i32>) {
+    match after {
+        Some((workspace, path, line)) => (Some(workspace), Some(path), Some(*line)),
+        None => (None, None, None),
+    }
+}
+
+/// Why an embedding blob is unusable, if it is: a length that isn't
+/// `dims` f32s, or a component that isn't finite.
+fn vector_problem(blob: &[u8], dims: Option<usize>) -> Option<String> {
+    if !blob.len().is_multiple_of(4) {
+        return Some(format!("{} bytes is not a whole number of floats", blob.len()));
+    }
+    if let Some(dims) = dims {
+        if blob.len() != dims * 4 {
+            return Some(format!("{} dimensions, expected {}", blob.len() / 4, dims));
+        }
+    }
+    let non_finite = blob_to_vec(blob).iter().filter(|x| !x.is_finite()).count();
+    if non_finite > 0 {
+        return Some(format!("{} NaN or infinite values", non_finite));
+    }
+    None
+}
+
+/// Late interaction (MaxSim) score of a document's packed unit vectors
+/// against a query's: each query vector's best cosine over the document's,
+/// averaged over the query so it stays on the cosine scale.
+pub fn max_sim(query: &[Vec<f32>], doc: &[f32], dims: usize) -> f64 {
+    if query.is_empty() || dims == 0 || doc.len() < dims {
+        return 0.0;
+    }
+    let total: f64 = query
+        .iter()
+        .map(|q| {
+            doc.chunks_exact(dims)
+                .map(|d| f32::dot(q, d).unwrap_or(0.0))
+                .fold(f64::NEG_INFINITY, f64::max)
+        })
+        .sum();
+    total / query.len() as f64
+}
+
+/// Copy an embedding BLOB into an owned vector. BLOBs carry no alignment
+/// guarantee, so this avoids `bytemuck::cast_slice` when the data must outlive the row.
+pub fn blob_to_vec(blob: &[u8]) -> Vec<f32> {
+    blob.chunks_exact(4)
+        .map(|b| f32::from_ne_bytes([b[0], b[1], b[2], b[3]]))
+        .collect()
 }
 
 // ── Top-K heap item ────────────────────────────────────────────────────
 
-struct HeapItem {
+struct HeapItem<T = SearchResult> {
     dist: f64,
-    file_path: String,
-    name: String,
-    kind: String,
-    language: String,
-    line: i32,
-    end_line: Option<i32>,
-    signature: Option<String>,
+    result: T,
 }
 
-impl PartialEq for HeapItem {
+impl<T> PartialEq for HeapItem<T> {
     fn eq(&self, other: &Self) -> bool {
         self.dist == other.dist
     }
 }
-impl Eq for HeapItem {}
-impl PartialOrd for HeapItem {
+impl<T> Eq for HeapItem<T> {}
+impl<T> PartialOrd for HeapItem<T> {
     fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
         self.dist.partial_cmp(&other.dist)
     }
 }
-impl Ord for HeapItem {
+impl<T> Ord for HeapItem<T> {
     fn cmp(&self, other: &Self) -> std::cmp::Ordering {
         self.partial_cmp(other).unwrap_or(std::cmp::Ordering::Equal)
     }
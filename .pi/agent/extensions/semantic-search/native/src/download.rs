@@ -0,0 +1,135 @@
+//! Hugging Face Hub model downloader.
+//!
+//! Fetches model files over plain HTTPS (`/resolve/<revision>/<file>`), writing
+//! to `<file>.part` and renaming on completion. An interrupted download resumes
+//! from the partial file with a Range request.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+const HF_ENDPOINT: &str = "https://huggingface.co";
+
+/// Files needed by `init()`: weights + config for the model, and the tokenizer.
+pub const DEFAULT_FILES: &[&str] = &["config.json", "model.safetensors", "tokenizer.json"];
+
+/// Progress report for one file. `total` is None when the server doesn't
+/// send a Content-Length.
+pub struct Progress<'a> {
+    pub file: &'a str,
+    pub downloaded: u64,
+    pub total: Option<u64>,
+}
+
+/// Download `files` from `repo_id` at `revision` into `dest_dir`.
+///
+/// Files already present in `dest_dir` are skipped. Returns the local paths
+/// in the same order as `files`. Uses `HF_TOKEN` from the environment for
+/// gated/private repos.
+pub fn download_repo(
+    repo_id: &str,
+    revision: &str,
+    dest_dir: &Path,
+    files: &[String],
+    mut on_progress: impl FnMut(Progress),
+) -> Result<Vec<PathBuf>, String> {
+    fs::create_dir_all(dest_dir)
+        .map_err(|e| format!("Failed to create {}: {}", dest_dir.display(), e))?;
+
+    let agent = ureq::AgentBuilder::new().build();
+    let token = std::env::var("HF_TOKEN").ok();
+
+    let mut paths = Vec::with_capacity(files.len());
+    for file in files {
+        let dest = dest_dir.join(file);
+        if dest.exists() {
+            paths.push(dest);
+            continue;
+        }
+        if let Some(parent) = dest.parent() {
+            fs::create_dir_all(parent)
+                .map_err(|e| format!("Failed to create {}: {}", parent.display(), e))?;
+        }
+
+        let url = format!("{}/{}/resolve/{}/{}", HF_ENDPOINT, repo_id, revision, file);
+        download_file(&agent, &url, token.as_deref(), &dest, file, &mut on_progress)?;
+        paths.push(dest);
+    }
+    Ok(paths)
+}
+
+fn download_file(
+    agent: &ureq::Agent,
+    url: &str,
+    token: Option<&str>,
+    dest: &Path,
+    file: &str,
+    on_progress: &mut impl FnMut(Progress),
+) -> Result<(), String> {
+    let part = dest.with_file_name(format!(
+        "{}.part",
+        dest.file_name().unwrap_or_default().to_string_lossy()
+    ));
+    let resume_from = fs::metadata(&part).map(|m| m.len()).unwrap_or(0);
+
+    let mut req = agent.get(url);
+    if let Some(t) = token {
+        req = req.set("Authorization", &format!("Bearer {}", t));
+    }
+    if resume_from > 0 {
+        req = req.set("Range", &format!("bytes={}-", resume_from));
+    }
+
+    let resp = match req.call() {
+        Ok(r) => r,
+        // Partial file already complete (or stale beyond EOF): start over
+        Err(ureq::Error::Status(416, _)) => {
+            fs::remove_file(&part).ok();
+            return download_file(agent, url, token, dest, file, on_progress);
+        }
+        Err(e) => return Err(format!("Failed to fetch {}: {}", url, e)),
+    };
+
+    // 206 = server honored the Range header; anything else restarts from 0
+    let resumed = resp.status() == 206;
+    let mut downloaded = if resumed { resume_from } else { 0 };
+    let total = resp
+        .header("Content-Length")
+        .and_then(|v| v.parse::<u64>().ok())
+        .map(|len| len + downloaded);
+
+    let mut out = if resumed {
+        OpenOptions::new().append(true).open(&part)
+    } else {
+        File::create(&part)
+    }
+    .map_err(|e| format!("Failed to open {}: {}", part.display(), e))?;
+
+    let mut reader = resp.into_reader();
+    let mut buf = vec![0u8; 1 << 20];
+    on_progress(Progress { file, downloaded, total });
+    loop {
+        let n = reader
+            .read(&mut buf)
+            .map_err(|e| format!("Download of {} interrupted: {}", file, e))?;
+        if n == 0 {
+            break;
+        }
+        out.write_all(&buf[..n])
+            .map_err(|e| format!("Failed to write {}: {}", part.display(), e))?;
+        downloaded += n as u64;
+        on_progress(Progress { file, downloaded, total });
+    }
+    out.sync_all()
+        .map_err(|e| format!("Failed to flush {}: {}", part.display(), e))?;
+
+    if let Some(t) = total {
+        if downloaded != t {
+            return Err(format!(
+                "Download of {} incomplete: {} of {} bytes (rerun to resume)",
+                file, downloaded, t
+            ));
+        }
+    }
+    fs::rename(&part, dest).map_err(|e| format!("Failed to finalize {}: {}", dest.display(), e))
+}
@@ -0,0 +1,513 @@
+//! Tree-sitter symbol extraction.
+//!
+//! Port of `tree-sitter-nav/symbols.ts` + `chunker.ts` for the grammars compiled
+//! into the addon. Walks the AST, picks out functions, types, classes, etc. with
+//! names, line ranges, and compact signatures, and flattens nested symbols
+//! (methods inside classes, items inside impls) into one list.
+
+use std::collections::HashMap;
+use tree_sitter::{Language, Node, Parser};
+
+/// Symbol kinds worth indexing. Mirrors INDEXABLE_KINDS in chunker.ts.
+const INDEXABLE_KINDS: &[&str] = &[
+    "function", "method", "type", "struct", "interface", "class", "enum", "constant", "trait",
+    "impl", "module", "property", "block", "resource", "data",
+];
+
+const MAX_NAME_LEN: usize = 80;
+const MAX_SIGNATURE_LEN: usize = 120;
+const MAX_DOC_LEN: usize = 500;
+
+#[derive(Debug, Clone)]
+pub struct ExtractedSymbol {
+    pub name: String,
+    pub kind: &'static str,
+    /// 1-based
+    pub line: i32,
+    /// 1-based
+    pub end_line: i32,
+    pub signature: Option<String>,
+    /// Comment block directly above the symbol (or Python docstring), with
+    /// comment markers stripped.
+    pub doc_comment: Option<String>,
+}
+
+/// Per-grammar extraction rules. Mirrors `LanguageSpec` in symbols.ts.
+struct LanguageSpec {
+    /// AST node type → symbol kind
+    node_types: HashMap<&'static str, &'static str>,
+    get_name: fn(Node, &'static str, &[u8]) -> Option<String>,
+    get_signature: fn(Node, &[u8]) -> Option<String>,
+    resolve_kind: Option<fn(Node, &'static str) -> &'static str>,
+    /// Node types that can contain nested symbols
+    container_types: &'static [&'static str],
+}
+
+/// Extract indexable symbols from `source`. `language` is the lowercase
+/// language name stored with symbols ("rust", "typescript", "tsx", ...).
+///
+/// Returns None for languages without a compiled-in grammar.
+pub fn extract_symbols(source: &str, language: &str) -> Option<Vec<ExtractedSymbol>> {
+    let (grammar, spec) = spec_for(language)?;
+
+    let mut parser = Parser::new();
+    parser.set_language(&grammar).ok()?;
+    let tree = parser.parse(source, None)?;
+
+    let mut out = Vec::new();
+    walk_node(tree.root_node(), &spec, source.as_bytes(), &mut out);
+    Some(out)
+}
+
+/// Languages with a compiled-in grammar.
+pub fn supported_languages() -> &'static [&'static str] {
+    &["go", "java", "javascript", "python", "rust", "tsx", "typescript"]
+}
+
+fn spec_for(language: &str) -> Option<(Language, LanguageSpec)> {
+    Some(match language {
+        "typescript" => (tree_sitter_typescript::LANGUAGE_TYPESCRIPT.into(), typescript_spec()),
+        "tsx" => (tree_sitter_typescript::LANGUAGE_TSX.into(), typescript_spec()),
+        "javascript" => (tree_sitter_javascript::LANGUAGE.into(), javascript_spec()),
+        "python" => (tree_sitter_python::LANGUAGE.into(), python_spec()),
+        "rust" => (tree_sitter_rust::LANGUAGE.into(), rust_spec()),
+        "go" => (tree_sitter_go::LANGUAGE.into(), go_spec()),
+        "java" => (tree_sitter_java::LANGUAGE.into(), java_spec()),
+        _ => return None,
+    })
+}
+
+// ── Walker ─────────────────────────────────────────────────────────────
+
+fn push_symbol(
+    node: Node,
+    span: Node,
+    kind: &'static str,
+    name: String,
+    spec: &LanguageSpec,
+    source: &[u8],
+    out: &mut Vec<ExtractedSymbol>,
+) {
+    if INDEXABLE_KINDS.contains(&kind) {
+        out.push(ExtractedSymbol {
+            name: truncate(name, MAX_NAME_LEN),
+            kind,
+            line: span.start_position().row as i32 + 1,
+            end_line: span.end_position().row as i32 + 1,
+            signature: (spec.get_signature)(node, source).map(|s| truncate(s, MAX_SIGNATURE_LEN)),
+            doc_comment: doc_comment(span, source).map(|s| truncate(s, MAX_DOC_LEN)),
+        });
+    }
+}
+
+fn walk_node(node: Node, spec: &LanguageSpec, source: &[u8], out: &mut Vec<ExtractedSymbol>) {
+    let mut cursor = node.walk();
+    for child in node.named_children(&mut cursor) {
+        if let Some(&raw_kind) = spec.node_types.get(child.kind()) {
+            let kind = spec.resolve_kind.map_or(raw_kind, |f| f(child, raw_kind));
+            if let Some(name) = (spec.get_name)(child, kind, source) {
+                push_symbol(child, child, kind, name, spec, source, out);
+
+                if spec.container_types.contains(&child.kind()) {
+                    walk_node(child, spec, source, out);
+                } else if child.kind() == "decorated_definition" {
+                    // Decorated containers (e.g. @dataclass class): recurse into the definition
+                    if let Some(def) = child.child_by_field_name("definition") {
+                        if spec.container_types.contains(&def.kind()) {
+                            walk_node(def, spec, source, out);
+                        }
+                    }
+                }
+                continue;
+            }
+        }
+
+        // Export statements: look at the exported declaration
+        if child.kind() == "export_statement" || child.kind() == "export_declaration" {
+            if let Some(decl) = child.child_by_field_name("declaration") {
+                if let Some(&kind) = spec.node_types.get(decl.kind()) {
+                    if let Some(name) = (spec.get_name)(decl, kind, source) {
+                        push_symbol(decl, child, kind, name, spec, source, out);
+                        if spec.container_types.contains(&decl.kind()) {
+                            walk_node(decl, spec, source, out);
+                        }
+                        continue;
+                    }
+                }
+            }
+        }
+
+        // Recurse into non-symbol nodes to find nested definitions
+        // (e.g. program > expression_statement > assignment > function)
+        if !spec.node_types.contains_key(child.kind()) {
+            walk_node(child, spec, source, out);
+        }
+    }
+}
+
+// ── Helpers ────────────────────────────────────────────────────────────
+
+fn truncate(s: String, max: usize) -> String {
+    if s.chars().count() <= max {
+        return s;
+    }
+    let mut t: String = s.chars().take(max - 3).collect();
+    t.push_str("...");
+    t
+}
+
+/// Doc comment for a definition: the run of comments immediately above it
+/// (attributes/decorators in between are skipped), or for Python the
+/// docstring opening the body.
+fn doc_comment(node: Node, source: &[u8]) -> Option<String> {
+    let mut lines: Vec<String> = Vec::new();
+    let mut start_row = node.start_position().row;
+    let mut prev = node.prev_named_sibling();
+    while let Some(sib) = prev {
+        if sib.end_position().row + 1 < start_row {
+            break;
+        }
+        if sib.kind().contains("comment") {
+            let raw = sib.utf8_text(source).ok()?;
+            for line in raw.lines().rev() {
+                lines.push(strip_comment_markers(line));
+            }
+        } else if !matches!(sib.kind(), "attribute_item" | "decorator" | "annotation") {
+            break;
+        }
+        start_row = sib.start_position().row;
+        prev = sib.prev_named_sibling();
+    }
+    lines.reverse();
+
+    let doc = if lines.is_empty() {
+        python_docstring(node, source)?
+    } else {
+        lines.join("\n")
+    };
+    let doc = doc.trim();
+    (!doc.is_empty()).then(|| doc.to_string())
+}
+
+fn strip_comment_markers(line: &str) -> String {
+    let mut l = line.trim();
+    for prefix in ["///", "//!", "//", "/**", "/*", "#", "*"] {
+        if let Some(rest) = l.strip_prefix(prefix) {
+            l = rest;
+            break;
+        }
+    }
+    l.trim_end_matches("*/").trim().to_string()
+}
+
+fn python_docstring(node: Node, source: &[u8]) -> Option<String> {
+    let def = if node.kind() == "decorated_definition" {
+        node.child_by_field_name("definition")?
+    } else {
+        node
+    };
+    let body = def.child_by_field_name("body")?;
+    let first = body.named_child(0)?;
+    if first.kind() != "expression_statement" {
+        return None;
+    }
+    let string = first.named_child(0).filter(|n| n.kind() == "string")?;
+    let raw = string.utf8_text(source).ok()?;
+    let inner = raw
+        .trim_start_matches(|c: char| c.is_ascii_alphabetic())
+        .trim_matches('"')
+        .trim_matches('\'');
+    Some(inner.lines().map(str::trim).collect::<Vec<_>>().join("\n"))
+}
+
+fn text(node: Node, source: &[u8]) -> Option<String> {
+    node.utf8_text(source).ok().map(str::to_string)
+}
+
+fn field_text(node: Node, field: &str, source: &[u8]) -> Option<String> {
+    node.child_by_field_name(field).and_then(|n| text(n, source))
+}
+
+fn first_child_of_type<'a>(node: Node<'a>, kind: &str) -> Option<Node<'a>> {
+    let mut cursor = node.walk();
+    let found = node.children(&mut cursor).find(|c| c.kind() == kind);
+    found
+}
+
+fn is_inside_class(node: Node) -> bool {
+    let mut current = node.parent();
+    while let Some(n) = current {
+        if matches!(n.kind(), "class_definition" | "class_declaration" | "class_body") {
+            return true;
+        }
+        current = n.parent();
+    }
+    false
+}
+
+fn extract_params(node: Node, source: &[u8]) -> Option<String> {
+    let params = node
+        .child_by_field_name("parameters")
+        .or_else(|| node.child_by_field_name("params"))
+        .or_else(|| first_child_of_type(node, "formal_parameters"))
+        .or_else(|| first_child_of_type(node, "parameters"))
+        .or_else(|| first_child_of_type(node, "parameter_list"))?;
+    text(params, source)
+}
+
+fn extract_return_type(node: Node, source: &[u8]) -> Option<String> {
+    field_text(node, "return_type", source).or_else(|| field_text(node, "result", source))
+}
+
+/// Compact signature: `name(params): ret`.
+fn build_signature(name: &str, node: Node, source: &[u8]) -> Option<String> {
+    let params = extract_params(node, source)?;
+    match extract_return_type(node, source) {
+        Some(ret) => {
+            let ret = ret.trim_start_matches(':').trim();
+            Some(format!("{}{}: {}", name, params, ret))
+        }
+        None => Some(format!("{}{}", name, params)),
+    }
+}
+
+fn named_signature(node: Node, source: &[u8]) -> Option<String> {
+    let name = field_text(node, "name", source)?;
+    build_signature(&name, node, source)
+}
+
+fn name_field(node: Node, _kind: &'static str, source: &[u8]) -> Option<String> {
+    field_text(node, "name", source)
+}
+
+// ── Language specs ─────────────────────────────────────────────────────
+
+fn ts_get_name(node: Node, _kind: &'static str, source: &[u8]) -> Option<String> {
+    match node.kind() {
+        // const x = () => {} — only variables holding functions count
+        "lexical_declaration" | "variable_declaration" => {
+            let declarator = first_child_of_type(node, "variable_declarator")?;
+            let init = declarator.child_by_field_name("value")?;
+            if matches!(init.kind(), "arrow_function" | "function_expression" | "function") {
+                field_text(declarator, "name", source)
+            } else {
+                None
+            }
+        }
+        "export_statement" => ts_get_name(node.child_by_field_name("declaration")?, _kind, source),
+        _ => field_text(node, "name", source),
+    }
+}
+
+fn ts_get_signature(node: Node, source: &[u8]) -> Option<String> {
+    let name = ts_get_name(node, "function", source)?;
+    if matches!(node.kind(), "lexical_declaration" | "variable_declaration") {
+        let declarator = first_child_of_type(node, "variable_declarator")?;
+        let init = declarator.child_by_field_name("value")?;
+        return build_signature(&name, init, source);
+    }
+    build_signature(&name, node, source)
+}
+
+fn typescript_spec() -> LanguageSpec {
+    LanguageSpec {
+        node_types: HashMap::from([
+            ("function_declaration", "function"),
+            ("generator_function_declaration", "function"),
+            ("class_declaration", "class"),
+            ("abstract_class_declaration", "class"),
+            ("method_definition", "method"),
+            ("interface_declaration", "interface"),
+            ("type_alias_declaration", "type"),
+            ("enum_declaration", "enum"),
+            ("lexical_declaration", "function"),
+            ("variable_declaration", "function"),
+            ("module", "module"),
+        ]),
+        get_name: ts_get_name,
+        get_signature: ts_get_signature,
+        resolve_kind: None,
+        container_types: &[
+            "class_declaration",
+            "abstract_class_declaration",
+            "interface_declaration",
+            "enum_declaration",
+            "module",
+        ],
+    }
+}
+
+fn javascript_spec() -> LanguageSpec {
+    LanguageSpec {
+        node_types: HashMap::from([
+            ("function_declaration", "function"),
+            ("generator_function_declaration", "function"),
+            ("class_declaration", "class"),
+            ("method_definition", "method"),
+            ("lexical_declaration", "function"),
+            ("variable_declaration", "function"),
+        ]),
+        get_name: ts_get_name,
+        get_signature: ts_get_signature,
+        resolve_kind: None,
+        container_types: &["class_declaration"],
+    }
+}
+
+fn python_spec() -> LanguageSpec {
+    fn get_name(node: Node, _kind: &'static str, source: &[u8]) -> Option<String> {
+        if node.kind() == "decorated_definition" {
+            return field_text(node.child_by_field_name("definition")?, "name", source);
+        }
+        field_text(node, "name", source)
+    }
+
+    fn resolve_kind(node: Node, kind: &'static str) -> &'static str {
+        if node.kind() == "decorated_definition" {
+            match node.child_by_field_name("definition").map(|d| d.kind()) {
+                Some("class_definition") => return "class",
+                Some("function_definition") => {
+                    return if is_inside_class(node) { "method" } else { "function" }
+                }
+                _ => {}
+            }
+        }
+        if node.kind() == "function_definition" && is_inside_class(node) {
+            return "method";
+        }
+        kind
+    }
+
+    fn get_signature(node: Node, source: &[u8]) -> Option<String> {
+        let target = if node.kind() == "decorated_definition" {
+            node.child_by_field_name("definition").unwrap_or(node)
+        } else {
+            node
+        };
+        let name = field_text(target, "name", source)?;
+        let params = field_text(target, "parameters", source)?;
+        Some(match field_text(target, "return_type", source) {
+            Some(ret) => format!("{}{} -> {}", name, params, ret),
+            None => format!("{}{}", name, params),
+        })
+    }
+
+    LanguageSpec {
+        node_types: HashMap::from([
+            ("function_definition", "function"),
+            ("class_definition", "class"),
+            ("decorated_definition", "function"),
+        ]),
+        get_name,
+        get_signature,
+        resolve_kind: Some(resolve_kind),
+        container_types: &["class_definition"],
+    }
+}
+
+fn rust_spec() -> LanguageSpec {
+    fn get_name(node: Node, kind: &'static str, source: &[u8]) -> Option<String> {
+        if kind == "impl" {
+            // impl Type or impl Trait for Type
+            let ty = field_text(node, "type", source);
+            return match (field_text(node, "trait", source), ty) {
+                (Some(tr), Some(ty)) => Some(format!("{} for {}", tr, ty)),
+                (_, ty) => ty,
+            };
+        }
+        field_text(node, "name", source)
+    }
+
+    LanguageSpec {
+        node_types: HashMap::from([
+            ("function_item", "function"),
+            ("struct_item", "struct"),
+            ("enum_item", "enum"),
+            ("impl_item", "impl"),
+            ("trait_item", "trait"),
+            ("mod_item", "module"),
+            ("type_item", "type"),
+            ("const_item", "constant"),
+            ("static_item", "constant"),
+            ("macro_definition", "function"),
+        ]),
+        get_name,
+        get_signature: named_signature,
+        resolve_kind: None,
+        container_types: &["impl_item", "trait_item", "mod_item", "struct_item", "enum_item"],
+    }
+}
+
+fn go_spec() -> LanguageSpec {
+    fn get_name(node: Node, _kind: &'static str, source: &[u8]) -> Option<String> {
+        if node.kind() == "type_declaration" {
+            return field_text(first_child_of_type(node, "type_spec")?, "name", source);
+        }
+        field_text(node, "name", source)
+    }
+
+    fn resolve_kind(node: Node, kind: &'static str) -> &'static str {
+        // type_declaration → struct/interface based on the inner type_spec
+        if node.kind() == "type_declaration" {
+            let value = first_child_of_type(node, "type_spec")
+                .and_then(|spec| spec.child_by_field_name("type"));
+            match value.map(|v| v.kind()) {
+                Some("struct_type") => return "struct",
+                Some("interface_type") => return "interface",
+                _ => {}
+            }
+        }
+        kind
+    }
+
+    fn get_signature(node: Node, source: &[u8]) -> Option<String> {
+        let name = field_text(node, "name", source)?;
+        if node.kind() == "method_declaration" {
+            let recv = field_text(node, "receiver", source)
+                .map(|r| format!("{} ", r))
+                .unwrap_or_default();
+            let params =
+                field_text(node, "parameters", source).unwrap_or_else(|| "()".to_string());
+            return Some(match field_text(node, "result", source) {
+                Some(result) => format!("{}{}{} {}", recv, name, params, result),
+                None => format!("{}{}{}", recv, name, params),
+            });
+        }
+        build_signature(&name, node, source)
+    }
+
+    LanguageSpec {
+        node_types: HashMap::from([
+            ("function_declaration", "function"),
+            ("method_declaration", "method"),
+            ("type_declaration", "type"),
+        ]),
+        get_name,
+        get_signature,
+        resolve_kind: Some(resolve_kind),
+        container_types: &[],
+    }
+}
+
+fn java_spec() -> LanguageSpec {
+    LanguageSpec {
+        node_types: HashMap::from([
+            ("class_declaration", "class"),
+            ("interface_declaration", "interface"),
+            ("enum_declaration", "enum"),
+            ("method_declaration", "method"),
+            ("constructor_declaration", "method"),
+            ("annotation_type_declaration", "interface"),
+            ("record_declaration", "class"),
+        ]),
+        get_name: name_field,
+        get_signature: named_signature,
+        resolve_kind: None,
+        container_types: &[
+            "class_declaration",
+            "interface_declaration",
+            "enum_declaration",
+            "record_declaration",
+        ],
+    }
+}
@@ -0,0 +1,128 @@
+//! Git-backed change detection.
+//!
+//! Uses git blob ids as file hashes: tracked, unmodified files take their id
+//! straight from the index (`git ls-files --stage`), so only files that are
+//! dirty or untracked need to be read and hashed (`git hash-object`).
+
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+/// Gitlink entries (submodules) have no content to index.
+const GITLINK_MODE: &str = "160000";
+
+fn git(repo_root: &Path, args: &[&str]) -> Result<Vec<u8>, String> {
+    let out = Command::new("git")
+        .arg("-C")
+        .arg(repo_root)
+        .args(args)
+        .output()
+        .map_err(|e| format!("Failed to run git: {}", e))?;
+    if !out.status.success() {
+        return Err(format!(
+            "git {} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&out.stderr).trim()
+        ));
+    }
+    Ok(out.stdout)
+}
+
+fn split_nul(bytes: &[u8]) -> impl Iterator<Item = String> + '_ {
+    bytes
+        .split(|&b| b == 0)
+        .filter(|s| !s.is_empty())
+        .map(|s| String::from_utf8_lossy(s).into_owned())
+}
+
+/// Hash worktree files with `git hash-object --stdin-paths`, one id per path.
+fn hash_objects(repo_root: &Path, paths: &[String]) -> Result<Vec<String>, String> {
+    if paths.is_empty() {
+        return Ok(Vec::new());
+    }
+    let mut child = Command::new("git")
+        .arg("-C")
+        .arg(repo_root)
+        .args(["hash-object", "--stdin-paths"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("Failed to run git: {}", e))?;
+
+    let mut input = String::new();
+    for p in paths {
+        input.push_str(p);
+        input.push('\n');
+    }
+    // Write from a separate thread so a full stdout pipe can't deadlock us
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+    let writer = std::thread::spawn(move || stdin.write_all(input.as_bytes()));
+
+    let out = child
+        .wait_with_output()
+        .map_err(|e| format!("Failed to run git: {}", e))?;
+    writer
+        .join()
+        .map_err(|_| "git hash-object writer panicked".to_string())?
+        .map_err(|e| format!("Failed to write to git: {}", e))?;
+    if !out.status.success() {
+        return Err(format!(
+            "git hash-object failed: {}",
+            String::from_utf8_lossy(&out.stderr).trim()
+        ));
+    }
+
+    let ids: Vec<String> = String::from_utf8_lossy(&out.stdout)
+        .lines()
+        .map(str::to_string)
+        .collect();
+    if ids.len() != paths.len() {
+        return Err(format!(
+            "git hash-object returned {} ids for {} paths",
+            ids.len(),
+            paths.len()
+        ));
+    }
+    Ok(ids)
+}
+
+/// Current blob id of every non-ignored file under `repo_root`, keyed by
+/// path relative to `repo_root`. Covers tracked files (with worktree edits)
+/// and untracked files not excluded by .gitignore.
+pub fn worktree_hashes(repo_root: &Path) -> Result<HashMap<String, String>, String> {
+    let mut hashes = HashMap::new();
+
+    // Index entries: "<mode> <oid> <stage>\t<path>"
+    for entry in split_nul(&git(repo_root, &["ls-files", "-z", "--stage"])?) {
+        let Some((meta, path)) = entry.split_once('\t') else { continue };
+        let mut fields = meta.split(' ');
+        let (Some(mode), Some(oid)) = (fields.next(), fields.next()) else { continue };
+        if mode != GITLINK_MODE {
+            hashes.insert(path.to_string(), oid.to_string());
+        }
+    }
+
+    // Modified/deleted tracked files and untracked files: the index id is stale
+    let dirty: Vec<String> = split_nul(&git(
+        repo_root,
+        &["ls-files", "-z", "--modified", "--others", "--exclude-standard"],
+    )?)
+    .collect();
+
+    let mut to_hash = Vec::with_capacity(dirty.len());
+    for path in dirty {
+        if repo_root.join(&path).is_file() {
+            to_hash.push(path);
+        } else {
+            hashes.remove(&path);
+        }
+    }
+    to_hash.sort();
+    to_hash.dedup();
+
+    let ids = hash_objects(repo_root, &to_hash)?;
+    hashes.extend(to_hash.into_iter().zip(ids));
+    Ok(hashes)
+}
@@ -0,0 +1,63 @@
+//! Query-term match offsets for result highlighting.
+//!
+//! Results are ranked by embedding similarity, so nothing guarantees a query
+//! word appears in the result. These offsets mark the words that do, letting
+//! the UI explain a hit without re-tokenizing in JS.
+
+/// Terms shorter than this (e.g. "a", "of") are too noisy to highlight.
+const MIN_TERM_LEN: usize = 2;
+
+#[derive(Debug, Clone)]
+pub struct Highlight {
+    /// Which result field matched: "name", "signature", or "doc_comment"
+    pub field: &'static str,
+    /// The query term, lowercased
+    pub term: String,
+    /// Byte range within the field
+    pub start: usize,
+    pub end: usize,
+}
+
+/// Lowercased, deduplicated query terms: runs of alphanumerics/underscores.
+pub fn query_terms<'a>(queries: impl IntoIterator<Item = &'a str>) -> Vec<String> {
+    let mut terms: Vec<String> = Vec::new();
+    for q in queries {
+        for word in q.split(|c: char| !(c.is_alphanumeric() || c == '_')) {
+            if word.chars().count() < MIN_TERM_LEN {
+                continue;
+            }
+            let word = word.to_lowercase();
+            if !terms.contains(&word) {
+                terms.push(word);
+            }
+        }
+    }
+    terms
+}
+
+/// Case-insensitive (ASCII) occurrences of `terms` in each field, sorted by
+/// field order then position. Overlapping matches are kept; the UI merges.
+pub fn find(terms: &[String], fields: &[(&'static str, &str)]) -> Vec<Highlight> {
+    let mut out = Vec::new();
+    for &(field, text) in fields {
+        // ASCII lowercasing keeps byte offsets aligned with `text`
+        let haystack = text.to_ascii_lowercase();
+        let mut field_hits = Vec::new();
+        for term in terms {
+            let mut from = 0;
+            while let Some(pos) = haystack[from..].find(term.as_str()) {
+                let start = from + pos;
+                field_hits.push(Highlight {
+                    field,
+                    term: term.clone(),
+                    start,
+                    end: start + term.len(),
+                });
+                from = start + term.len();
+            }
+        }
+        field_hits.sort_by_key(|h| (h.start, h.end));
+        out.extend(field_hits);
+    }
+    out
+}
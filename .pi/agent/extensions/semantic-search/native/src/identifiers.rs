@@ -0,0 +1,131 @@
+//! Identifier splitting ahead of the model's tokenizer.
+//!
+//! WordPiece cuts `getHTTPRequestRateLimiter` into pieces that don't line up
+//! with its words, so the embedding barely reflects them. With splitting on,
+//! embedding texts and queries reach the tokenizer as `get HTTP Request Rate
+//! Limiter`. Stored texts are left as extracted.
+
+use std::borrow::Cow;
+
+/// Which splits apply; all off is no preprocessing.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Splitting {
+    /// `getHTTPRequest` → `get HTTP Request`
+    pub camel_case: bool,
+    /// `rate_limiter` → `rate limiter`
+    pub snake_case: bool,
+    /// `src/net/client.rs`, `std::io::Read` → `src net client rs`, `std io Read`
+    pub paths: bool,
+}
+
+impl Splitting {
+    pub fn is_off(&self) -> bool {
+        *self == Splitting::default()
+    }
+
+    /// Parse the comma-separated form of `as_str` ("camel,snake,path"; ""
+    /// for none).
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let mut splitting = Splitting::default();
+        for part in s.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+            match part {
+                "camel" => splitting.camel_case = true,
+                "snake" => splitting.snake_case = true,
+                "path" => splitting.paths = true,
+                other => {
+                    return Err(format!(
+                        "Unknown identifier split '{}'. Expected \"camel\", \"snake\" or \"path\".",
+                        other
+                    ))
+                }
+            }
+        }
+        Ok(splitting)
+    }
+
+    pub fn as_str(&self) -> String {
+        let parts = [
+            (self.camel_case, "camel"),
+            (self.snake_case, "snake"),
+            (self.paths, "path"),
+        ];
+        parts
+            .iter()
+            .filter(|(on, _)| *on)
+            .map(|(_, name)| *name)
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+
+    /// `text` with its identifiers split; borrowed when nothing changes.
+    pub fn apply<'a>(&self, text: &'a str) -> Cow<'a, str> {
+        if self.is_off() {
+            return Cow::Borrowed(text);
+        }
+        let chars: Vec<char> = text.chars().collect();
+        let word = |i: Option<usize>| {
+            i.and_then(|i| chars.get(i))
+                .is_some_and(|c| c.is_alphanumeric())
+        };
+        let mut out = String::with_capacity(text.len() + text.len() / 4);
+        let mut changed = false;
+        let mut i = 0;
+        while i < chars.len() {
+            let c = chars[i];
+            let prev = i.checked_sub(1);
+            if self.snake_case && c == '_' && word(prev) && word(Some(i + 1)) {
+                out.push(' ');
+                changed = true;
+                i += 1;
+                continue;
+            }
+            if self.paths {
+                if let Some(len) = separator_len(&chars, i) {
+                    if word(prev) && word(Some(i + len)) {
+                        out.push(' ');
+                        changed = true;
+                        i += len;
+                        continue;
+                    }
+                }
+            }
+            if self.camel_case && c.is_uppercase() {
+                if let Some(p) = prev.map(|p| chars[p]) {
+                    // fooBar, sha256Hash, or the last capital of an acronym:
+                    // HTTPRequest
+                    let boundary = p.is_lowercase()
+                        || p.is_ascii_digit()
+                        || (p.is_uppercase() && chars.get(i + 1).is_some_and(|n| n.is_lowercase()));
+                    if boundary {
+                        out.push(' ');
+                        changed = true;
+                    }
+                }
+            }
+            out.push(c);
+            i += 1;
+        }
+        if changed {
+            Cow::Owned(out)
+        } else {
+            Cow::Borrowed(text)
+        }
+    }
+}
+
+/// Length of the path or module separator at `chars[i]`, if any: `/`,
+/// `\`, `::`, or a `.` that isn't a decimal point.
+fn separator_len(chars: &[char], i: usize) -> Option<usize> {
+    match chars[i] {
+        '/' | '\\' => Some(1),
+        ':' if chars.get(i + 1) == Some(&':') => Some(2),
+        '.' => {
+            let digit = |j: Option<usize>| {
+                j.and_then(|j| chars.get(j))
+                    .is_some_and(char::is_ascii_digit)
+            };
+            (!(digit(i.checked_sub(1)) && digit(Some(i + 1)))).then_some(1)
+        }
+        _ => None,
+    }
+}
@@ -0,0 +1,118 @@
+//! Canonical symbol kinds.
+//!
+//! `kind` arrives as free-form text from extractors and callers, so the same
+//! thing shows up as "func", "fn", and "function". Kinds are normalized when
+//! symbols are stored and when filters name them, so one filter matches all
+//! spellings. Unknown kinds pass through lowercased.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Function,
+    Method,
+    Type,
+    Struct,
+    Interface,
+    Class,
+    Enum,
+    Constant,
+    Trait,
+    Impl,
+    Module,
+    Property,
+    Block,
+    Resource,
+    Data,
+    /// Content chunks from `index_chunks`
+    Chunk,
+}
+
+/// Alias (lowercase) → kind. Canonical names are matched separately.
+const ALIASES: &[(&str, Kind)] = &[
+    ("func", Kind::Function),
+    ("fn", Kind::Function),
+    ("def", Kind::Function),
+    ("proc", Kind::Function),
+    ("procedure", Kind::Function),
+    ("arrow_function", Kind::Function),
+    ("constructor", Kind::Method),
+    ("ctor", Kind::Method),
+    ("member_function", Kind::Method),
+    ("typedef", Kind::Type),
+    ("type_alias", Kind::Type),
+    ("alias", Kind::Type),
+    ("record", Kind::Struct),
+    ("protocol", Kind::Interface),
+    ("enumeration", Kind::Enum),
+    ("const", Kind::Constant),
+    ("static", Kind::Constant),
+    ("implementation", Kind::Impl),
+    ("extension", Kind::Impl),
+    ("mod", Kind::Module),
+    ("namespace", Kind::Module),
+    ("package", Kind::Module),
+    ("field", Kind::Property),
+    ("attribute", Kind::Property),
+    ("prop", Kind::Property),
+    ("getter", Kind::Property),
+    ("setter", Kind::Property),
+];
+
+impl Kind {
+    pub const ALL: [Kind; 16] = [
+        Kind::Function,
+        Kind::Method,
+        Kind::Type,
+        Kind::Struct,
+        Kind::Interface,
+        Kind::Class,
+        Kind::Enum,
+        Kind::Constant,
+        Kind::Trait,
+        Kind::Impl,
+        Kind::Module,
+        Kind::Property,
+        Kind::Block,
+        Kind::Resource,
+        Kind::Data,
+        Kind::Chunk,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Kind::Function => "function",
+            Kind::Method => "method",
+            Kind::Type => "type",
+            Kind::Struct => "struct",
+            Kind::Interface => "interface",
+            Kind::Class => "class",
+            Kind::Enum => "enum",
+            Kind::Constant => "constant",
+            Kind::Trait => "trait",
+            Kind::Impl => "impl",
+            Kind::Module => "module",
+            Kind::Property => "property",
+            Kind::Block => "block",
+            Kind::Resource => "resource",
+            Kind::Data => "data",
+            Kind::Chunk => "chunk",
+        }
+    }
+
+    /// The kind `name` or one of its aliases refers to, case-insensitively.
+    pub fn parse(name: &str) -> Option<Kind> {
+        let name = name.trim().to_ascii_lowercase();
+        Kind::ALL
+            .into_iter()
+            .find(|k| k.as_str() == name)
+            .or_else(|| ALIASES.iter().find(|(a, _)| *a == name).map(|&(_, k)| k))
+    }
+}
+
+/// The canonical name for `kind`, or `kind` trimmed and lowercased when it
+/// isn't a known kind or alias.
+pub fn normalize(kind: &str) -> String {
+    match Kind::parse(kind) {
+        Some(k) => k.as_str().to_string(),
+        None => kind.trim().to_ascii_lowercase(),
+    }
+}
@@ -0,0 +1,176 @@
+//! Language detection for files the extractor couldn't classify.
+//!
+//! Names match the lowercase language names the TS chunker stores
+//! (`tree-sitter-nav/languages.ts`), so language filters see one vocabulary.
+//! Stored languages and filters go through `normalize_language`, which also
+//! folds dialects and abbreviations ("ts", "tsx") into one name.
+
+use std::path::Path;
+
+/// Extension (lowercase, without the dot) → language name.
+const EXTENSIONS: &[(&str, &str)] = &[
+    ("ts", "typescript"),
+    ("mts", "typescript"),
+    ("cts", "typescript"),
+    ("tsx", "tsx"),
+    ("js", "javascript"),
+    ("jsx", "javascript"),
+    ("mjs", "javascript"),
+    ("cjs", "javascript"),
+    ("py", "python"),
+    ("pyi", "python"),
+    ("rs", "rust"),
+    ("go", "go"),
+    ("java", "java"),
+    ("kt", "kotlin"),
+    ("kts", "kotlin"),
+    ("swift", "swift"),
+    ("rb", "ruby"),
+    ("php", "php"),
+    ("cs", "c#"),
+    ("scala", "scala"),
+    ("lua", "lua"),
+    ("sh", "bash"),
+    ("bash", "bash"),
+    ("zsh", "bash"),
+    ("zig", "zig"),
+    ("ex", "elixir"),
+    ("exs", "elixir"),
+    ("dart", "dart"),
+    ("ml", "ocaml"),
+    ("mli", "ocaml"),
+    ("yml", "yaml"),
+    ("yaml", "yaml"),
+    ("toml", "toml"),
+    ("hcl", "hcl"),
+    ("tf", "terraform"),
+    ("tfvars", "terraform"),
+    ("md", "markdown"),
+    ("markdown", "markdown"),
+];
+
+/// Extensionless file names with a conventional language.
+const FILE_NAMES: &[(&str, &str)] = &[
+    ("Rakefile", "ruby"),
+    ("Gemfile", "ruby"),
+    (".bashrc", "bash"),
+    (".bash_profile", "bash"),
+    (".zshrc", "bash"),
+    (".zprofile", "bash"),
+    (".profile", "bash"),
+];
+
+/// Names that aren't file extensions, or whose extension maps elsewhere
+/// (`tsx` files use their own grammar but are stored as TypeScript).
+const ALIASES: &[(&str, &str)] = &[
+    ("tsx", "typescript"),
+    ("golang", "go"),
+    ("python3", "python"),
+    ("node", "javascript"),
+    ("csharp", "c#"),
+    ("shell", "bash"),
+    ("tf", "terraform"),
+];
+
+/// Shebang interpreter → language name.
+const INTERPRETERS: &[(&str, &str)] = &[
+    ("python", "python"),
+    ("node", "javascript"),
+    ("deno", "typescript"),
+    ("ts-node", "typescript"),
+    ("tsx", "typescript"),
+    ("bun", "typescript"),
+    ("bash", "bash"),
+    ("sh", "bash"),
+    ("zsh", "bash"),
+    ("ruby", "ruby"),
+    ("php", "php"),
+    ("lua", "lua"),
+    ("elixir", "elixir"),
+];
+
+/// Guess the language of `path` from its extension, then its file name, then
+/// a shebang or `vim: ft=` modeline in `content_sample` (the first few lines
+/// are enough). Returns None when nothing matches.
+pub fn detect_language(path: &str, content_sample: &str) -> Option<&'static str> {
+    let p = Path::new(path);
+    if let Some(ext) = p.extension().and_then(|e| e.to_str()) {
+        let ext = ext.to_ascii_lowercase();
+        if let Some(&(_, lang)) = EXTENSIONS.iter().find(|(e, _)| *e == ext) {
+            return Some(lang);
+        }
+    }
+    if let Some(name) = p.file_name().and_then(|n| n.to_str()) {
+        if let Some(&(_, lang)) = FILE_NAMES.iter().find(|(n, _)| *n == name) {
+            return Some(lang);
+        }
+    }
+    from_shebang(content_sample).or_else(|| from_modeline(content_sample))
+}
+
+/// The canonical language name for `name`: an alias or file extension
+/// ("ts", "py", "golang") maps to its language, anything else is lowercased.
+pub fn normalize_language(name: &str) -> String {
+    let name = name.trim().to_ascii_lowercase();
+    ALIASES
+        .iter()
+        .chain(EXTENSIONS)
+        .find(|(alias, _)| *alias == name)
+        .map_or(name, |&(_, lang)| lang.to_string())
+}
+
+/// The language to pick an extraction grammar by: `normalize_language`,
+/// except that `tsx` keeps its own grammar.
+pub fn grammar_language(name: &str) -> String {
+    let name = name.trim().to_ascii_lowercase();
+    if name == "tsx" {
+        return name;
+    }
+    normalize_language(&name)
+}
+
+/// `#!/usr/bin/env python3`, `#!/bin/bash -e`, `#!/usr/bin/env -S deno run` ...
+fn from_shebang(content: &str) -> Option<&'static str> {
+    let line = content.lines().next()?.strip_prefix("#!")?;
+    let mut words = line.split_whitespace();
+    let mut program = words.next()?.rsplit('/').next()?;
+    if program == "env" {
+        program = words.find(|w| !w.starts_with('-'))?;
+    }
+    // python3.11 → python
+    let program = program.trim_end_matches(|c: char| c.is_ascii_digit() || c == '.');
+    INTERPRETERS
+        .iter()
+        .find(|(name, _)| *name == program)
+        .map(|&(_, lang)| lang)
+}
+
+/// `vim: set ft=python:` / `vim: filetype=sh` in the first five lines.
+fn from_modeline(content: &str) -> Option<&'static str> {
+    for line in content.lines().take(5) {
+        let Some(idx) = line.find("vim:") else {
+            continue;
+        };
+        for key in ["filetype=", "ft="] {
+            if let Some(pos) = line[idx..].find(key) {
+                let value: String = line[idx + pos + key.len()..]
+                    .chars()
+                    .take_while(|c| c.is_ascii_alphanumeric() || *c == '_')
+                    .collect();
+                let ext = match value.as_str() {
+                    "python" => "py",
+                    "javascript" => "js",
+                    "typescript" => "ts",
+                    "rust" => "rs",
+                    "ruby" => "rb",
+                    "sh" | "zsh" => "bash",
+                    other => other,
+                };
+                if let Some(&(_, lang)) = EXTENSIONS.iter().find(|(e, _)| *e == ext) {
+                    return Some(lang);
+                }
+            }
+        }
+    }
+    None
+}
@@ -5,45 +5,532 @@
 //!
 //! Designed for minimal FFI overhead: batch APIs everywhere, embeddings never cross the boundary.
 
+pub mod benchmark;
+pub mod boilerplate;
+pub mod cache;
+pub mod cluster;
+pub mod coalesce;
 pub mod db;
+pub mod download;
+pub mod extract;
+pub mod git;
+pub mod highlight;
+pub mod identifiers;
+pub mod kind;
+pub mod lang;
 pub mod model;
+pub mod pq;
+pub mod queue;
+pub mod rank;
+pub mod recall;
+pub mod scope;
+pub mod template;
+pub mod watch;
+pub mod wire;
 
 use db::SearchDB;
-use model::{mean_pool_normalize, NomicBertConfig, NomicBertModel};
-use mlx_rs::module::ModuleParametersExt;
+use model::{embed_pooled, mean_pool_normalize, NomicBertConfig, NomicBertModel};
+use mlx_rs::module::{ModuleParameters, ModuleParametersExt};
+use napi::bindgen_prelude::{AsyncTask, Buffer, Either3, External, Float32Array};
+use napi::threadsafe_function::{ErrorStrategy, ThreadsafeFunction, ThreadsafeFunctionCallMode};
+use napi::{Either, Env};
 use napi_derive::napi;
-use std::collections::HashMap;
+use simsimd::SpatialSimilarity;
+use std::collections::{HashMap, HashSet};
+use std::ops::ControlFlow;
 use std::path::PathBuf;
 use std::sync::Mutex;
 use tokenizers::Tokenizer;
+use tracing_subscriber::{filter::LevelFilter, Registry};
 
 const MAX_LENGTH: usize = 128;
-const QUERY_PREFIX: &str = "Represent this query for searching relevant code: ";
+const DEFAULT_BATCH_SIZE: usize = 32;
+/// CodeRankEmbed's query instruction, used when neither `InitOptions` nor
+/// the model card sets one. Documents get no prefix by default.
+const DEFAULT_QUERY_PREFIX: &str = "Represent this query for searching relevant code: ";
+/// Batches at least this large refresh planner statistics after committing.
+const ANALYZE_MIN_ROWS: usize = 1000;
+/// Open search sessions kept for `search_next`; the oldest is dropped first.
+const MAX_SEARCH_SESSIONS: usize = 16;
+/// `search` calls whose results are kept for identical repeats.
+const RESULT_CACHE_SIZE: usize = 64;
+/// Query embeddings kept so repeated query strings skip the model
+/// (768 floats, 3 KB each).
+const QUERY_CACHE_SIZE: usize = 1024;
 
 struct State {
     model: NomicBertModel,
     tokenizer: Tokenizer,
+    /// Embedding dimensionality (hidden size) of the loaded model.
+    dims: usize,
+    /// Texts per forward pass in `embed_internal`.
+    batch_size: usize,
+    /// MLX device the model runs on: "gpu" or "cpu".
+    device: &'static str,
+    /// Device for background indexing; see `State::on_background_device`.
+    background_device: &'static str,
+    /// Run the forward pass through a compiled graph (`InitOptions.compile`).
+    compile: bool,
+    /// Pad every forward pass to `batch_size` rows
+    /// (`InitOptions.deterministic`).
+    deterministic: bool,
+    /// f32 embeddings of `benchmark::SAMPLE_TEXTS`, taken before the weights
+    /// were cast to a reduced-precision dtype. Empty when running in f32.
+    precision_reference: Vec<Vec<f32>>,
+    /// Prepended to query and document texts before tokenizing.
+    query_prefix: String,
+    document_prefix: String,
+    info: ModelInfo,
     db: Option<SearchDB>,
+    /// Cached candidate lists from `search_session`, oldest first.
+    sessions: Vec<SearchSession>,
+    next_session_id: u32,
+    /// Open `begin_index_session` ingestion, if any.
+    index_session: Option<IndexSession>,
+    /// Results of recent `search` calls, valid for `result_cache_generation`
+    /// of the index (see `SearchDB::generation`).
+    result_cache: cache::LruCache<String, Vec<db::SearchResult>>,
+    result_cache_generation: u64,
+    /// Prefixed query text → embedding. Truncation and identifier splitting
+    /// are per index, so cleared when they change or the index is replaced.
+    query_cache: cache::LruCache<String, Vec<f32>>,
+    /// Changes whenever the open index is replaced (see `index_replaced`).
+    /// Calls that release the lock to embed check it before writing or
+    /// caching what they computed.
+    db_epoch: u64,
 }
 
-static STATE: std::sync::OnceLock<Mutex<State>> = std::sync::OnceLock::new();
+impl State {
+    /// Run `f` with `background_device` as MLX's default device. Safe
+    /// because every model call happens under the state lock.
+    fn on_background_device<T>(&mut self, f: impl FnOnce(&mut State) -> T) -> T {
+        if self.background_device == self.device {
+            return f(self);
+        }
+        set_default_device(self.background_device);
+        let out = f(self);
+        set_default_device(self.device);
+        out
+    }
+
+    /// The instruction prefix for queries or documents.
+    fn prefix(&self, is_query: bool) -> &str {
+        if is_query {
+            &self.query_prefix
+        } else {
+            &self.document_prefix
+        }
+    }
+}
+
+/// What was loaded by `init`, for `get_model_info`.
+struct ModelInfo {
+    name: String,
+    config: NomicBertConfig,
+    parameter_count: usize,
+    dtype: String,
+    load_ms: f64,
+}
+
+struct SearchSession {
+    id: u32,
+    queries: Vec<String>,
+    highlight: bool,
+    results: Vec<db::SearchResult>,
+}
+
+/// None before `init` and after `shutdown`.
+static STATE: Mutex<Option<State>> = Mutex::new(None);
+
+/// Interrupt handle of the index a search is reading, while it runs its
+/// statements (see `CancelScope`), for `cancel_search`. Separate from
+/// `STATE` because the search being cancelled holds its lock.
+static SEARCH_INTERRUPT: Mutex<Option<rusqlite::InterruptHandle>> = Mutex::new(None);
+
+/// Source of `State::db_epoch` values, unique across `init`s.
+static NEXT_DB_EPOCH: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(1);
+
+fn next_db_epoch() -> u64 {
+    NEXT_DB_EPOCH.fetch_add(1, std::sync::atomic::Ordering::Relaxed)
+}
+
+/// Lets `cancel_search` interrupt `db` until dropped. Only search reads
+/// run inside one, so a cancel never aborts an index write sharing the
+/// connection. Nested scopes leave it to the outermost.
+struct CancelScope {
+    armed: bool,
+}
+
+impl CancelScope {
+    fn new(db: &SearchDB) -> Self {
+        let mut slot = SEARCH_INTERRUPT.lock().unwrap_or_else(|e| e.into_inner());
+        let armed = slot.is_none();
+        if armed {
+            *slot = Some(db.interrupt_handle());
+        }
+        CancelScope { armed }
+    }
+}
+
+impl Drop for CancelScope {
+    fn drop(&mut self) {
+        if self.armed {
+            *SEARCH_INTERRUPT.lock().unwrap_or_else(|e| e.into_inner()) = None;
+        }
+    }
+}
+
+/// Lock `STATE`. A call that panicked while holding the lock poisons it;
+/// rather than failing every later call until the process restarts, the
+/// state is taken back and whatever that call may have left half done is
+/// dropped (see `recover_state`).
+fn lock_state() -> napi::Result<std::sync::MutexGuard<'static, Option<State>>> {
+    Ok(STATE.lock().unwrap_or_else(|poisoned| {
+        let mut guard = poisoned.into_inner();
+        if let Some(state) = guard.as_mut() {
+            recover_state(state);
+        }
+        STATE.clear_poison();
+        guard
+    }))
+}
+
+/// Drop per-call state a failed call may have left inconsistent: search
+/// sessions, the index session (rolled back), cached results, and a
+/// background device left selected. The model and index stay loaded.
+fn recover_state(state: &mut State) {
+    set_default_device(state.device);
+    state.sessions.clear();
+    state.index_session = None;
+    state.result_cache.clear();
+    if let Some(db) = state.db.as_mut() {
+        // Already rolled back unless the panic hit mid-session
+        let _ = db.end_ingest(false);
+    }
+}
 
+/// Run `f` with the global state. Holds back the background index queue
+/// until it returns, so calls from JS never wait behind more than one
+/// queued batch.
 fn with_state<T>(f: impl FnOnce(&mut State) -> napi::Result<T>) -> napi::Result<T> {
-    let mutex = STATE
-        .get()
+    let _foreground = INDEX_QUEUE.get().map(|q| q.foreground());
+    with_state_background(f)
+}
+
+/// `with_state` for the index queue's worker. A panic in `f` becomes an
+/// error naming the function that called `with_state` (normally the napi
+/// entry point), and the state is recovered as after a poisoned lock.
+fn with_state_background<T, F>(f: F) -> napi::Result<T>
+where
+    F: FnOnce(&mut State) -> napi::Result<T>,
+{
+    let mut guard = lock_state()?;
+    let state = guard
+        .as_mut()
         .ok_or_else(|| napi::Error::from_reason("Not initialized. Call init() first."))?;
-    let mut state = mutex
-        .lock()
-        .map_err(|e| napi::Error::from_reason(format!("Lock poisoned: {}", e)))?;
-    f(&mut state)
+    match std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| f(state))) {
+        Ok(out) => out,
+        Err(payload) => {
+            recover_state(state);
+            Err(panic_error(&caller_name::<F>(), payload))
+        }
+    }
+}
+
+/// Run `f`, turning a panic into an error naming `api`, for entry points
+/// that don't go through `with_state`.
+fn catch_panic<T>(api: &str, f: impl FnOnce() -> napi::Result<T>) -> napi::Result<T> {
+    std::panic::catch_unwind(std::panic::AssertUnwindSafe(f))
+        .unwrap_or_else(|payload| Err(panic_error(api, payload)))
+}
+
+/// A JS-facing error for a panic (e.g. in tokenizers or MLX) in `api`.
+fn panic_error(api: &str, payload: Box<dyn std::any::Any + Send>) -> napi::Error {
+    let message = payload
+        .downcast_ref::<String>()
+        .map(String::as_str)
+        .or_else(|| payload.downcast_ref::<&str>().copied())
+        .unwrap_or("unknown panic payload");
+    napi::Error::from_reason(format!("{} panicked: {}", api, message))
+}
+
+/// The function a closure of type `F` was written in, e.g. `search`.
+fn caller_name<F>() -> String {
+    std::any::type_name::<F>()
+        .trim_end_matches("::{{closure}}")
+        .replace(concat!(env!("CARGO_CRATE_NAME"), "::"), "")
 }
 
 // ── Initialization ─────────────────────────────────────────────────────
 
-#[napi]
-pub fn init(model_dir: String, tokenizer_path: String) -> napi::Result<()> {
+/// Weight and activation dtype, chosen by `InitOptions.dtype`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Precision {
+    F32,
+    /// Half the memory bandwidth of f32; Apple Silicon GPUs compute it natively
+    F16,
+    /// f32's range with f16's size, so no overflow in large activations
+    Bf16,
+}
+
+impl Precision {
+    fn parse(s: &str) -> napi::Result<Self> {
+        match s {
+            "f32" => Ok(Precision::F32),
+            "f16" => Ok(Precision::F16),
+            "bf16" => Ok(Precision::Bf16),
+            other => Err(napi::Error::from_reason(format!(
+                "Unknown dtype '{}'. Expected f32, f16, or bf16.",
+                other
+            ))),
+        }
+    }
+
+    fn dtype(self) -> mlx_rs::Dtype {
+        match self {
+            Precision::F32 => mlx_rs::Dtype::Float32,
+            Precision::F16 => mlx_rs::Dtype::Float16,
+            Precision::Bf16 => mlx_rs::Dtype::Bfloat16,
+        }
+    }
+}
+
+/// Lowest cosine similarity between f32 and reduced-precision embeddings of
+/// the same text that `self_test` accepts.
+const PRECISION_MIN_COSINE: f32 = 0.99;
+
+#[napi(object)]
+#[derive(Default)]
+pub struct InitOptions {
+    /// Hex SHA-256 of model.safetensors. When set, the file is hashed before
+    /// loading and init fails on mismatch (corrupt download, wrong model).
+    pub expected_sha256: Option<String>,
+    /// Texts per forward pass when embedding. Defaults to 32; lower it to
+    /// reduce peak GPU memory.
+    pub batch_size: Option<u32>,
+    /// Cap on MLX GPU memory in MB. Useful on 8GB Macs to avoid swapping
+    /// while indexing large repos.
+    pub memory_limit_mb: Option<f64>,
+    /// `"gpu"` or `"cpu"`. Defaults to the GPU when Metal is available and
+    /// falls back to the CPU otherwise (e.g. Linux CI).
+    pub device: Option<String>,
+    /// Device for background work (`enqueue_index`, `reembed_all`), e.g.
+    /// `"cpu"` to keep the GPU free for interactive queries while a large
+    /// repo indexes. Defaults to `device`.
+    pub background_device: Option<String>,
+    /// Trace the forward pass once per batch shape and replay the compiled
+    /// graph instead of rebuilding it every batch. Defaults to true; turn it
+    /// off to rule compilation out when debugging numerical differences.
+    pub compile: Option<bool>,
+    /// `"f32"`, `"f16"`, or `"bf16"`: weights are cast to it after loading
+    /// and the forward pass runs in it. Defaults to `"f32"`. `self_test`
+    /// checks reduced-precision embeddings against f32 ones.
+    pub dtype: Option<String>,
+    /// Make embeddings bit-identical across runs and batchings: the forward
+    /// pass runs in f32, uncompiled, and every batch is padded to
+    /// `batch_size` rows so each text sees the same kernels whatever it was
+    /// batched with. MLX's random seed is fixed as well. Costs speed on
+    /// small batches; for golden tests and content-hash caches. Can't be
+    /// combined with `compile: true`, a `dtype` other than `"f32"`, or a
+    /// `background_device` other than `device`.
+    pub deterministic: Option<bool>,
+    /// Prepended to search queries. Overrides the model card; defaults to
+    /// CodeRankEmbed's "Represent this query for searching relevant code: ".
+    pub query_prefix: Option<String>,
+    /// Prepended to indexed texts, e.g. "passage: " for E5-style models.
+    /// Overrides the model card; defaults to none.
+    pub document_prefix: Option<String>,
+}
+
+/// Instruction prefixes from `config_sentence_transformers.json` in the
+/// model directory (`"prompts": { "query": ..., "document" | "passage": ... }`),
+/// the sentence-transformers model card convention.
+#[derive(Default, serde::Deserialize)]
+struct ModelCardPrompts {
+    query: Option<String>,
+    #[serde(alias = "passage")]
+    document: Option<String>,
+}
+
+fn read_model_card_prompts(model_dir: &std::path::Path) -> napi::Result<ModelCardPrompts> {
+    #[derive(serde::Deserialize)]
+    struct Card {
+        #[serde(default)]
+        prompts: Option<ModelCardPrompts>,
+    }
+    let path = model_dir.join("config_sentence_transformers.json");
+    let text = match std::fs::read_to_string(&path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            return Ok(ModelCardPrompts::default())
+        }
+        Err(e) => {
+            return Err(napi::Error::from_reason(format!(
+                "Failed to read {}: {}",
+                path.display(),
+                e
+            )))
+        }
+    };
+    let card: Card = serde_json::from_str(&text).map_err(|e| {
+        napi::Error::from_reason(format!("Failed to parse {}: {}", path.display(), e))
+    })?;
+    Ok(card.prompts.unwrap_or_default())
+}
+
+const ALREADY_INITIALIZED: &str = "Already initialized. Call shutdown() first to re-init.";
+
+fn metal_available() -> bool {
+    let mut available = false;
+    // Safety: plain C call writing to a valid out-pointer.
+    let rc = unsafe { mlx_sys::mlx_metal_is_available(&mut available) };
+    rc == 0 && available
+}
+
+/// Resolve a `device` option: "gpu" or "cpu", defaulting to the GPU when
+/// Metal is available.
+fn resolve_device(requested: Option<&str>) -> napi::Result<&'static str> {
+    match requested {
+        Some("gpu") if !metal_available() => Err(napi::Error::from_reason(
+            "device \"gpu\" requested but Metal is unavailable",
+        )),
+        Some("gpu") => Ok("gpu"),
+        Some("cpu") => Ok("cpu"),
+        Some(other) => Err(napi::Error::from_reason(format!(
+            "Unknown device '{}'. Expected \"cpu\" or \"gpu\".",
+            other
+        ))),
+        None if metal_available() => Ok("gpu"),
+        None => Ok("cpu"),
+    }
+}
+
+/// Make `device` ("gpu" or "cpu") MLX's default for all ops.
+fn set_default_device(device: &str) {
+    let mlx_device = if device == "gpu" {
+        mlx_rs::Device::gpu()
+    } else {
+        mlx_rs::Device::cpu()
+    };
+    mlx_rs::Device::set_default(&mlx_device);
+}
+
+/// Set MLX's memory limit. Allocations beyond it wait for buffers to free
+/// instead of growing, trading speed for a bounded footprint.
+fn set_memory_limit(bytes: usize) -> napi::Result<()> {
+    let mut previous = 0usize;
+    // Safety: plain C call writing to a valid out-pointer.
+    let rc = unsafe { mlx_sys::mlx_set_memory_limit(&mut previous, bytes) };
+    if rc != 0 {
+        return Err(napi::Error::from_reason("Failed to set MLX memory limit"));
+    }
+    Ok(())
+}
+
+fn sha256_file(path: &std::path::Path) -> std::io::Result<String> {
+    use sha2::{Digest, Sha256};
+    use std::io::Read;
+
+    let mut file = std::io::BufReader::new(std::fs::File::open(path)?);
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0u8; 1 << 20];
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect())
+}
+
+#[napi(catch_unwind)]
+pub fn init(
+    model_dir: String,
+    tokenizer_path: String,
+    options: Option<InitOptions>,
+) -> napi::Result<()> {
+    if lock_state()?.is_some() {
+        return Err(napi::Error::from_reason(ALREADY_INITIALIZED));
+    }
+    let options = options.unwrap_or_default();
+    let batch_size = match options.batch_size {
+        Some(0) => return Err(napi::Error::from_reason("batch_size must be at least 1")),
+        Some(n) => n as usize,
+        None => DEFAULT_BATCH_SIZE,
+    };
     let model_dir = PathBuf::from(&model_dir);
+    let weights_path = model_dir.join("model.safetensors");
+
+    if let Some(expected) = &options.expected_sha256 {
+        let actual = sha256_file(&weights_path).map_err(|e| {
+            napi::Error::from_reason(format!("Failed to hash model.safetensors: {}", e))
+        })?;
+        if !actual.eq_ignore_ascii_case(expected.trim()) {
+            return Err(napi::Error::from_reason(format!(
+                "model.safetensors checksum mismatch: expected {}, got {}. \
+                 The download may be corrupted or {} holds a different model.",
+                expected.trim(),
+                actual,
+                model_dir.display()
+            )));
+        }
+    }
+
+    let device = resolve_device(options.device.as_deref())?;
+    let background_device = match options.background_device.as_deref() {
+        Some(requested) => resolve_device(Some(requested))?,
+        None => device,
+    };
+    set_default_device(device);
+    let deterministic = options.deterministic.unwrap_or(false);
+    let compile = options.compile.unwrap_or(!deterministic);
+    let precision = Precision::parse(options.dtype.as_deref().unwrap_or("f32"))?;
+    if deterministic {
+        if compile {
+            return Err(napi::Error::from_reason(
+                "deterministic can't be combined with compile: true",
+            ));
+        }
+        if precision != Precision::F32 {
+            return Err(napi::Error::from_reason(format!(
+                "deterministic requires dtype \"f32\", got \"{}\"",
+                options.dtype.as_deref().unwrap_or_default()
+            )));
+        }
+        // CPU and GPU kernels round differently
+        if background_device != device {
+            return Err(napi::Error::from_reason(format!(
+                "deterministic requires background_device to match device \"{}\", got \"{}\"",
+                device, background_device
+            )));
+        }
+        mlx_rs::random::seed(0)
+            .map_err(|e| napi::Error::from_reason(format!("Failed to seed MLX: {}", e)))?;
+    }
+
+    if let Some(mb) = options.memory_limit_mb {
+        if mb <= 0.0 {
+            return Err(napi::Error::from_reason("memory_limit_mb must be positive"));
+        }
+        set_memory_limit((mb * 1024.0 * 1024.0) as usize)?;
+    }
 
+    let prompts = read_model_card_prompts(&model_dir)?;
+    let query_prefix = options
+        .query_prefix
+        .or(prompts.query)
+        .unwrap_or_else(|| DEFAULT_QUERY_PREFIX.to_string());
+    let document_prefix = options
+        .document_prefix
+        .or(prompts.document)
+        .unwrap_or_default();
+
+    let load_start = std::time::Instant::now();
     let config_str = std::fs::read_to_string(model_dir.join("config.json"))
         .map_err(|e| napi::Error::from_reason(format!("Failed to read config.json: {}", e)))?;
     let config: NomicBertConfig = serde_json::from_str(&config_str)
@@ -52,374 +539,6749 @@ pub fn init(model_dir: String, tokenizer_path: String) -> napi::Result<()> {
     let mut model = NomicBertModel::new(&config)
         .map_err(|e| napi::Error::from_reason(format!("Failed to create model: {}", e)))?;
     model
-        .load_safetensors(model_dir.join("model.safetensors"))
+        .load_safetensors(&weights_path)
         .map_err(|e| napi::Error::from_reason(format!("Failed to load weights: {}", e)))?;
 
     let tokenizer = Tokenizer::from_file(&tokenizer_path)
         .map_err(|e| napi::Error::from_reason(format!("Failed to load tokenizer: {}", e)))?;
 
-    STATE
-        .set(Mutex::new(State {
-            model,
-            tokenizer,
-            db: None,
-        }))
-        .map_err(|_| napi::Error::from_reason("Already initialized"))?;
+    let precision_reference = if precision == Precision::F32 {
+        Vec::new()
+    } else {
+        let reference = embed_samples(&mut model, &tokenizer)?;
+        model
+            .cast_parameters(precision.dtype())
+            .map_err(|e| napi::Error::from_reason(format!("Failed to cast weights: {}", e)))?;
+        reference
+    };
+
+    let params = model.parameters();
+    let params = params.flatten();
+    let parameter_count = params.values().map(|a| a.size()).sum();
+    let dtype = params
+        .values()
+        .next()
+        .map(|a| format!("{:?}", a.dtype()).to_lowercase())
+        .unwrap_or_default();
+    let info = ModelInfo {
+        name: config.name_or_path.clone().unwrap_or_else(|| {
+            model_dir
+                .file_name()
+                .map(|n| n.to_string_lossy().into_owned())
+                .unwrap_or_default()
+        }),
+        config: config.clone(),
+        parameter_count,
+        dtype,
+        load_ms: load_start.elapsed().as_secs_f64() * 1000.0,
+    };
+    tracing::info!(
+        "loaded {} ({} parameters, {}) on {} in {:.0}ms",
+        info.name,
+        info.parameter_count,
+        info.dtype,
+        device,
+        info.load_ms
+    );
+
+    let mut slot = lock_state()?;
+    if slot.is_some() {
+        return Err(napi::Error::from_reason(ALREADY_INITIALIZED));
+    }
+    *slot = Some(State {
+        model,
+        tokenizer,
+        dims: config.n_embd as usize,
+        batch_size,
+        device,
+        background_device,
+        compile,
+        deterministic,
+        precision_reference,
+        query_prefix,
+        document_prefix,
+        info,
+        db: None,
+        sessions: Vec::new(),
+        next_session_id: 1,
+        index_session: None,
+        result_cache: cache::LruCache::new(RESULT_CACHE_SIZE),
+        result_cache_generation: 0,
+        query_cache: cache::LruCache::new(QUERY_CACHE_SIZE),
+        db_epoch: next_db_epoch(),
+    });
 
     Ok(())
 }
 
-#[napi]
-pub fn open_db(db_path: String) -> napi::Result<()> {
+/// Unload the model, tokenizer, and index, returning their GPU memory to the
+/// system, so `init` can run again (e.g. with a different model). Queued
+/// background indexing is dropped and the log callback released. Returns
+/// false if not initialized.
+#[napi(catch_unwind)]
+pub fn shutdown() -> napi::Result<bool> {
+    clear_index_queue();
+    let state = lock_state()?.take();
+    let was_initialized = state.is_some();
+    drop(state);
+    *LOG_SINK.lock().unwrap_or_else(|e| e.into_inner()) = None;
+    if LOG_FILTER.get().is_some() {
+        set_log_level(LevelFilter::OFF);
+    }
+    mlx_rs::transforms::compile::clear_cache();
+    // Safety: plain C call; frees MLX's buffer cache now that no arrays
+    // reference it.
+    unsafe { mlx_sys::mlx_clear_cache() };
+    Ok(was_initialized)
+}
+
+/// Recover from a failed call without restarting the agent: drop search
+/// sessions, roll back an open index session, and clear cached results and
+/// queued background indexing. The model and index stay loaded, so unlike
+/// `shutdown` no `init` is needed afterwards. Returns false if not
+/// initialized.
+#[napi(catch_unwind)]
+pub fn reset_state() -> napi::Result<bool> {
+    clear_index_queue();
+    let mut guard = lock_state()?;
+    let Some(state) = guard.as_mut() else {
+        return Ok(false);
+    };
+    recover_state(state);
+    Ok(true)
+}
+
+// ── Model download ─────────────────────────────────────────────────────
+
+#[napi(object)]
+pub struct DownloadOptions {
+    /// Branch, tag, or commit. Defaults to "main".
+    pub revision: Option<String>,
+    /// Files to fetch. Defaults to config.json, model.safetensors, tokenizer.json.
+    pub files: Option<Vec<String>>,
+}
+
+#[napi(object)]
+pub struct JsDownloadProgress {
+    pub file: String,
+    pub downloaded: f64,
+    pub total: Option<f64>,
+}
+
+pub struct DownloadTask {
+    repo_id: String,
+    revision: String,
+    dest_dir: PathBuf,
+    files: Vec<String>,
+    on_progress: Option<ThreadsafeFunction<JsDownloadProgress, ErrorStrategy::Fatal>>,
+}
+
+impl napi::Task for DownloadTask {
+    type Output = Vec<String>;
+    type JsValue = Vec<String>;
+
+    fn compute(&mut self) -> napi::Result<Self::Output> {
+        // Runs on a libuv worker, outside any napi entry point's panic guard
+        catch_panic("download_model", || {
+            let on_progress = self.on_progress.as_ref();
+            let paths = download::download_repo(
+                &self.repo_id,
+                &self.revision,
+                &self.dest_dir,
+                &self.files,
+                |p| {
+                    if let Some(cb) = on_progress {
+                        cb.call(
+                            JsDownloadProgress {
+                                file: p.file.to_string(),
+                                downloaded: p.downloaded as f64,
+                                total: p.total.map(|t| t as f64),
+                            },
+                            ThreadsafeFunctionCallMode::NonBlocking,
+                        );
+                    }
+                },
+            )
+            .map_err(napi::Error::from_reason)?;
+            Ok(paths
+                .into_iter()
+                .map(|p| p.to_string_lossy().into_owned())
+                .collect())
+        })
+    }
+
+    fn resolve(&mut self, _env: napi::Env, output: Self::Output) -> napi::Result<Self::JsValue> {
+        Ok(output)
+    }
+}
+
+/// Download model files from the Hugging Face Hub into `dest_dir`.
+///
+/// Runs off the JS thread and resolves to the local file paths. Interrupted
+/// downloads resume from `<file>.part`; existing files are skipped.
+/// `on_progress` receives `{ file, downloaded, total }` as bytes arrive.
+#[napi(catch_unwind)]
+pub fn download_model(
+    repo_id: String,
+    dest_dir: String,
+    options: Option<DownloadOptions>,
+    on_progress: Option<ThreadsafeFunction<JsDownloadProgress, ErrorStrategy::Fatal>>,
+) -> AsyncTask<DownloadTask> {
+    let (revision, files) = match options {
+        Some(o) => (o.revision, o.files),
+        None => (None, None),
+    };
+    AsyncTask::new(DownloadTask {
+        repo_id,
+        revision: revision.unwrap_or_else(|| "main".to_string()),
+        dest_dir: PathBuf::from(dest_dir),
+        files: files.unwrap_or_else(|| {
+            download::DEFAULT_FILES.iter().map(|f| f.to_string()).collect()
+        }),
+        on_progress,
+    })
+}
+
+#[napi(object)]
+pub struct OpenDbOptions {
+    /// Bytes per page, a power of two from 512 to 65536 (see search_bench.rs).
+    /// Only applies when the index is created
+    pub page_size: Option<u32>,
+    /// Bytes of the file to memory-map (default 3 GB)
+    pub mmap_size: Option<f64>,
+    /// Page cache: pages if positive, KiB if negative (default -64000, 64 MB)
+    pub cache_size: Option<f64>,
+    /// "off", "normal", "full", or "extra" (SQLite's default is "full")
+    pub synchronous: Option<String>,
+}
+
+/// Connection settings of the open index.
+#[napi(object)]
+pub struct JsDbPragmas {
+    pub page_size: u32,
+    pub mmap_size: f64,
+    pub cache_size: f64,
+    pub synchronous: String,
+    /// `options.page_size` was given but the existing index has another page
+    /// size. Rebuild the index to change it.
+    pub page_size_mismatch: bool,
+}
+
+fn open_options(options: Option<OpenDbOptions>) -> napi::Result<db::OpenOptions> {
+    let Some(o) = options else {
+        return Ok(db::OpenOptions::default());
+    };
+    if let Some(size) = o.page_size {
+        if !size.is_power_of_two() || !(512..=65536).contains(&size) {
+            return Err(napi::Error::from_reason(format!(
+                "Invalid page_size {}. Expected a power of two from 512 to 65536.",
+                size
+            )));
+        }
+    }
+    let synchronous = match o.synchronous.as_deref() {
+        None => None,
+        Some("off") => Some("off"),
+        Some("normal") => Some("normal"),
+        Some("full") => Some("full"),
+        Some("extra") => Some("extra"),
+        Some(other) => {
+            return Err(napi::Error::from_reason(format!(
+                "Unknown synchronous '{}'. Expected \"off\", \"normal\", \"full\", or \"extra\".",
+                other
+            )))
+        }
+    };
+    Ok(db::OpenOptions {
+        page_size: o.page_size,
+        mmap_size: o.mmap_size.map(|v| v as i64),
+        cache_size: o.cache_size.map(|v| v as i64),
+        synchronous,
+    })
+}
+
+fn db_pragmas(db: &SearchDB, options: &db::OpenOptions) -> napi::Result<JsDbPragmas> {
+    let p = db
+        .pragmas()
+        .map_err(|e| napi::Error::from_reason(format!("DB error: {}", e)))?;
+    Ok(JsDbPragmas {
+        page_size: p.page_size,
+        mmap_size: p.mmap_size as f64,
+        cache_size: p.cache_size as f64,
+        synchronous: p.synchronous.to_string(),
+        page_size_mismatch: options.page_size.is_some_and(|size| size != p.page_size),
+    })
+}
+
+/// Open (or create) the index. Pass `":memory:"` for a RAM-only index.
+/// Drops batches still waiting in the background index queue. Returns the
+/// connection settings in effect.
+#[napi(catch_unwind)]
+pub fn open_db(db_path: String, options: Option<OpenDbOptions>) -> napi::Result<JsDbPragmas> {
+    let options = open_options(options)?;
     with_state(|state| {
-        let db = SearchDB::open(std::path::Path::new(&db_path))
+        let db = SearchDB::open_with(std::path::Path::new(&db_path), &options)
             .map_err(|e| napi::Error::from_reason(format!("Failed to open DB: {}", e)))?;
-        state.db = Some(db);
-        Ok(())
+        let pragmas = db_pragmas(&db, &options)?;
+        set_db(state, Some(db));
+        Ok(pragmas)
+    })
+}
+
+/// Open an existing index read-only (e.g. on a read-only mount).
+/// Search APIs work as usual; anything that writes will fail. `page_size`
+/// can't be applied here; a mismatch is reported as for `open_db`.
+#[napi(catch_unwind)]
+pub fn open_db_readonly(
+    db_path: String,
+    options: Option<OpenDbOptions>,
+) -> napi::Result<JsDbPragmas> {
+    let options = open_options(options)?;
+    with_state(|state| {
+        let db = SearchDB::open_readonly_with(std::path::Path::new(&db_path), &options)
+            .map_err(|e| napi::Error::from_reason(format!("Failed to open DB: {}", e)))?;
+        let pragmas = db_pragmas(&db, &options)?;
+        set_db(state, Some(db));
+        Ok(pragmas)
     })
 }
 
-#[napi]
+#[napi(catch_unwind)]
 pub fn close_db() -> napi::Result<()> {
     with_state(|state| {
-        state.db = None;
+        set_db(state, None);
+        Ok(())
+    })
+}
+
+/// Replace the open index (None closes it) and drop everything tied to the
+/// old one: search sessions, the index session, queued background batches,
+/// and cached results.
+fn set_db(state: &mut State, db: Option<SearchDB>) {
+    // Best effort: batched hits only order eviction
+    if let Some(old) = &state.db {
+        let _ = old.flush_hits();
+    }
+    state.db = db;
+    state.sessions.clear();
+    state.index_session = None;
+    clear_index_queue();
+    index_replaced(state);
+}
+
+/// Note that the open index was replaced wholesale: drop results and query
+/// embeddings cached for it (`SearchDB::generation` restarts with every
+/// connection, so it can't tell) and start a new `State::db_epoch`.
+fn index_replaced(state: &mut State) {
+    state.result_cache.clear();
+    state.result_cache_generation = 0;
+    state.query_cache.clear();
+    state.db_epoch = next_db_epoch();
+}
+
+/// Fail if the open index was replaced since `epoch` was read, so work
+/// computed for one index isn't written to or cached for another.
+fn check_db_epoch(state: &State, epoch: u64) -> napi::Result<()> {
+    if state.db_epoch != epoch {
+        return Err(napi::Error::from_reason(
+            "The index was replaced or closed while this call was embedding. Retry it.",
+        ));
+    }
+    Ok(())
+}
+
+/// Scope the open index to `workspace` (None for the default), so one DB can
+/// hold several repos' or packages' indexes side by side. Every read and write
+/// after this — file records, symbols, chunks, stats, searches — sees only
+/// that workspace; searches can override it with `filters.workspace`.
+/// Reopening the DB resets to the default workspace.
+#[napi(catch_unwind)]
+pub fn set_workspace(workspace: Option<String>) -> napi::Result<()> {
+    with_state(|state| {
+        get_db(state)?.set_workspace(workspace.as_deref().unwrap_or(""));
         Ok(())
     })
 }
 
-// ── Internal embedding helpers ─────────────────────────────────────────
+/// Workspaces in the open index that have indexed files ("" is the default).
+#[napi(catch_unwind)]
+pub fn list_workspaces() -> napi::Result<Vec<String>> {
+    with_state(|state| {
+        get_db(state)?
+            .list_workspaces()
+            .map_err(|e| napi::Error::from_reason(format!("DB error: {}", e)))
+    })
+}
+
+/// Persist the open index (typically `:memory:`) to a file.
+#[napi(catch_unwind)]
+pub fn backup_to(path: String) -> napi::Result<()> {
+    with_state(|state| {
+        let db = get_db(state)?;
+        db.backup_to(std::path::Path::new(&path))
+            .map_err(|e| napi::Error::from_reason(format!("Backup failed: {}", e)))
+    })
+}
+
+/// Replace the open index's contents with a previously saved file.
+#[napi(catch_unwind)]
+pub fn restore_from(path: String) -> napi::Result<()> {
+    with_state(|state| {
+        state.sessions.clear();
+        index_replaced(state);
+        let db = get_db(state)?;
+        db.restore_from(std::path::Path::new(&path))
+            .map_err(|e| napi::Error::from_reason(format!("Restore failed: {}", e)))
+    })
+}
+
+/// File of the snapshot `name` of the open index: `<index>.snapshots/<name>.db`.
+fn snapshot_path(db: &SearchDB, name: &str) -> napi::Result<std::path::PathBuf> {
+    let valid = !name.is_empty()
+        && !name.starts_with('.')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+    if !valid {
+        return Err(napi::Error::from_reason(format!(
+            "Invalid snapshot name '{}'. Use letters, digits, '-', '_' and '.'",
+            name
+        )));
+    }
+    Ok(snapshot_dir(db)?.join(format!("{}.db", name)))
+}
+
+fn snapshot_dir(db: &SearchDB) -> napi::Result<std::path::PathBuf> {
+    let path = db
+        .path()
+        .ok_or_else(|| napi::Error::from_reason("An in-memory index has no snapshots"))?;
+    Ok(std::path::PathBuf::from(format!("{}.snapshots", path)))
+}
+
+#[napi(object)]
+pub struct JsSnapshot {
+    pub name: String,
+    pub size_bytes: f64,
+    /// When the snapshot was taken, in ms since the epoch
+    pub created_at: f64,
+}
+
+/// Save the open index as snapshot `name` (replacing an older one of that
+/// name), e.g. before a risky bulk re-index. Snapshots live next to the
+/// index file, in `<index>.snapshots/`.
+#[napi(catch_unwind)]
+pub fn create_snapshot(name: String) -> napi::Result<JsSnapshot> {
+    with_state(|state| {
+        let db = get_db(state)?;
+        let path = snapshot_path(db, &name)?;
+        std::fs::create_dir_all(snapshot_dir(db)?)
+            .map_err(|e| napi::Error::from_reason(format!("Snapshot failed: {}", e)))?;
+        db.backup_to(&path)
+            .map_err(|e| napi::Error::from_reason(format!("Snapshot failed: {}", e)))?;
+        Ok(JsSnapshot {
+            name,
+            size_bytes: std::fs::metadata(&path).map_or(0, |m| m.len()) as f64,
+            created_at: db::now_millis() as f64,
+        })
+    })
+}
+
+/// Replace the open index's contents with snapshot `name`.
+#[napi(catch_unwind)]
+pub fn restore_snapshot(name: String) -> napi::Result<()> {
+    with_state(|state| {
+        if state.index_session.is_some() {
+            return Err(napi::Error::from_reason(
+                "An index session is open. Commit or abort it before restoring.",
+            ));
+        }
+        state.sessions.clear();
+        index_replaced(state);
+        let db = get_db(state)?;
+        let path = snapshot_path(db, &name)?;
+        if !path.exists() {
+            return Err(napi::Error::from_reason(format!("No snapshot named '{}'", name)));
+        }
+        db.restore_from(&path)
+            .map_err(|e| napi::Error::from_reason(format!("Restore failed: {}", e)))
+    })
+}
+
+/// Snapshots of the open index, oldest first.
+#[napi(catch_unwind)]
+pub fn list_snapshots() -> napi::Result<Vec<JsSnapshot>> {
+    with_state(|state| {
+        let dir = snapshot_dir(get_db(state)?)?;
+        let Ok(entries) = std::fs::read_dir(&dir) else {
+            return Ok(Vec::new());
+        };
+        let mut snapshots: Vec<JsSnapshot> = entries
+            .filter_map(|entry| {
+                let entry = entry.ok()?;
+                let file_name = entry.file_name().into_string().ok()?;
+                let name = file_name.strip_suffix(".db")?.to_string();
+                let meta = entry.metadata().ok()?;
+                let created_at = meta
+                    .modified()
+                    .ok()?
+                    .duration_since(std::time::UNIX_EPOCH)
+                    .ok()?
+                    .as_millis() as f64;
+                Some(JsSnapshot {
+                    name,
+                    size_bytes: meta.len() as f64,
+                    created_at,
+                })
+            })
+            .collect();
+        snapshots.sort_by(|a, b| a.created_at.total_cmp(&b.created_at));
+        Ok(snapshots)
+    })
+}
+
+/// Delete snapshot `name`. Returns false if there was none.
+#[napi(catch_unwind)]
+pub fn delete_snapshot(name: String) -> napi::Result<bool> {
+    with_state(|state| {
+        let path = snapshot_path(get_db(state)?, &name)?;
+        match std::fs::remove_file(&path) {
+            Ok(()) => Ok(true),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(napi::Error::from_reason(format!("Delete failed: {}", e))),
+        }
+    })
+}
+
+/// Open snapshot `name` of `db` read-only, scoped to `db`'s workspace.
+fn open_snapshot(db: &SearchDB, name: &str) -> napi::Result<SearchDB> {
+    let path = snapshot_path(db, name)?;
+    if !path.exists() {
+        return Err(napi::Error::from_reason(format!("No snapshot named '{}'", name)));
+    }
+    let mut snapshot = SearchDB::open_readonly_with(&path, &db::OpenOptions::default())
+        .map_err(|e| napi::Error::from_reason(format!("DB error: {}", e)))?;
+    snapshot.set_workspace(db.workspace());
+    Ok(snapshot)
+}
+
+// ── Internal embedding helpers ─────────────────────────────────────────
+
+fn tokenize_batch(
+    tokenizer: &Tokenizer,
+    texts: &[String],
+    rows: usize,
+    max_len: usize,
+    truncation: Truncation,
+) -> (mlx_rs::Array, mlx_rs::Array) {
+    let seqs: Vec<Vec<u32>> = texts
+        .iter()
+        .map(|t| {
+            let enc = tokenizer.encode(t.as_str(), true).unwrap();
+            truncation.apply(enc.get_ids(), max_len).collect()
+        })
+        .collect();
+    pack_batch(&seqs, rows, max_len)
+}
+
+/// Rows in a forward pass over `n` sequences: `n`, or the full batch size
+/// in deterministic mode so every pass has the same shape.
+fn batch_rows(state: &State, n: usize) -> usize {
+    if state.deterministic {
+        state.batch_size.max(n)
+    } else {
+        n
+    }
+}
+
+/// Pad token id sequences (each at most `max_len`) into `[rows, max_len]`
+/// input ids and attention mask; rows past `seqs` are all padding.
+fn pack_batch(seqs: &[Vec<u32>], rows: usize, max_len: usize) -> (mlx_rs::Array, mlx_rs::Array) {
+    let batch_size = rows.max(seqs.len());
+    let mut input_ids = vec![0i32; batch_size * max_len];
+    let mut attention_mask = vec![0i32; batch_size * max_len];
+
+    for (i, ids) in seqs.iter().enumerate() {
+        for (j, &id) in ids.iter().take(max_len).enumerate() {
+            input_ids[i * max_len + j] = id as i32;
+            attention_mask[i * max_len + j] = 1;
+        }
+    }
+
+    let ids = mlx_rs::Array::from_slice(&input_ids, &[batch_size as i32, max_len as i32]);
+    let mask = mlx_rs::Array::from_slice(&attention_mask, &[batch_size as i32, max_len as i32]);
+    (ids, mask)
+}
+
+const TRUNCATION_META: &str = "truncation";
+
+/// Which tokens of a text longer than `MAX_LENGTH` are embedded. All but
+/// `Head` keep the leading [CLS] and trailing [SEP] and cut the text between.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Truncation {
+    /// The first tokens — the default, and what indexes built before
+    /// truncation was configurable used
+    Head,
+    /// The last tokens, e.g. the end of a function body
+    Tail,
+    /// Half from the start and half from the end, dropping the middle
+    HeadTail,
+    /// The middle of the text, dropping equally from both ends
+    MiddleOut,
+}
+
+impl Truncation {
+    fn parse(s: &str) -> napi::Result<Self> {
+        match s {
+            "head" => Ok(Truncation::Head),
+            "tail" => Ok(Truncation::Tail),
+            "head_tail" => Ok(Truncation::HeadTail),
+            "middle_out" => Ok(Truncation::MiddleOut),
+            other => Err(napi::Error::from_reason(format!(
+                "Unknown truncation '{}'. Expected \"head\", \"tail\", \"head_tail\", \
+                 or \"middle_out\".",
+                other
+            ))),
+        }
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            Truncation::Head => "head",
+            Truncation::Tail => "tail",
+            Truncation::HeadTail => "head_tail",
+            Truncation::MiddleOut => "middle_out",
+        }
+    }
+
+    /// At most `max_len` of `ids` (special tokens included), in order.
+    fn apply(self, ids: &[u32], max_len: usize) -> impl Iterator<Item = u32> + '_ {
+        let keep_ends = self != Truncation::Head && ids.len() > max_len && max_len >= 2;
+        let (head, body, tail) = if !keep_ends {
+            (&ids[..0], &ids[..ids.len().min(max_len)], &ids[..0])
+        } else {
+            (&ids[..1], &ids[1..ids.len() - 1], &ids[ids.len() - 1..])
+        };
+        let budget = max_len.saturating_sub(head.len() + tail.len());
+        let cut = body.len().saturating_sub(budget);
+        let (first, second): (&[u32], &[u32]) = match self {
+            _ if cut == 0 => (body, &[]),
+            Truncation::Head => (&body[..budget], &[]),
+            Truncation::Tail => (&body[cut..], &[]),
+            Truncation::HeadTail => {
+                let front = budget.div_ceil(2);
+                (&body[..front], &body[body.len() - (budget - front)..])
+            }
+            Truncation::MiddleOut => {
+                let start = cut / 2;
+                (&body[start..start + budget], &[])
+            }
+        };
+        head.iter()
+            .chain(first)
+            .chain(second)
+            .chain(tail)
+            .copied()
+    }
+}
+
+/// The open index's truncation, or `Head` when it was never set or no index
+/// is open.
+fn index_truncation(state: &State) -> napi::Result<Truncation> {
+    let Some(db) = &state.db else {
+        return Ok(Truncation::Head);
+    };
+    match db
+        .get_meta(TRUNCATION_META)
+        .map_err(|e| napi::Error::from_reason(format!("DB error: {}", e)))?
+    {
+        Some(s) => Truncation::parse(&s),
+        None => Ok(Truncation::Head),
+    }
+}
+
+const IDENTIFIER_SPLIT_META: &str = "identifier_split";
+
+/// The open index's identifier splitting; off when never set or no index
+/// is open.
+fn index_splitting(state: &State) -> napi::Result<identifiers::Splitting> {
+    let Some(db) = &state.db else {
+        return Ok(identifiers::Splitting::default());
+    };
+    match db
+        .get_meta(IDENTIFIER_SPLIT_META)
+        .map_err(|e| napi::Error::from_reason(format!("DB error: {}", e)))?
+    {
+        Some(s) => identifiers::Splitting::parse(&s).map_err(napi::Error::from_reason),
+        None => Ok(identifiers::Splitting::default()),
+    }
+}
+
+/// `text` as the tokenizer gets it: identifiers split per `splitting`,
+/// except in a leading query or document prefix (e.g. "search_query: ").
+fn split_identifiers<'a>(
+    state: &State,
+    splitting: identifiers::Splitting,
+    text: &'a str,
+) -> std::borrow::Cow<'a, str> {
+    use std::borrow::Cow;
+    if splitting.is_off() {
+        return Cow::Borrowed(text);
+    }
+    let prefix = [&state.query_prefix, &state.document_prefix]
+        .into_iter()
+        .filter(|p| !p.is_empty() && text.starts_with(p.as_str()))
+        .map(String::len)
+        .max()
+        .unwrap_or(0);
+    match splitting.apply(&text[prefix..]) {
+        Cow::Borrowed(_) => Cow::Borrowed(text),
+        Cow::Owned(body) => Cow::Owned(format!("{}{}", &text[..prefix], body)),
+    }
+}
+
+const STRIP_BOILERPLATE_META: &str = "strip_boilerplate";
+
+/// Whether the open index strips boilerplate from document texts; off when
+/// never set or no index is open.
+fn index_strips_boilerplate(state: &State) -> napi::Result<bool> {
+    let Some(db) = &state.db else {
+        return Ok(false);
+    };
+    Ok(db
+        .get_meta(STRIP_BOILERPLATE_META)
+        .map_err(|e| napi::Error::from_reason(format!("DB error: {}", e)))?
+        .is_some_and(|v| v == "1"))
+}
+
+/// A document text as the model gets it, before any prefix.
+fn document_body(strip: bool, text: &str) -> std::borrow::Cow<'_, str> {
+    if strip {
+        boilerplate::strip(text)
+    } else {
+        std::borrow::Cow::Borrowed(text)
+    }
+}
+
+const MAX_WINDOWS_META: &str = "max_windows";
+/// Upper bound for `set_sliding_windows`.
+const MAX_WINDOWS_LIMIT: u32 = 16;
+/// Tokens shared by consecutive windows of a long symbol, so text cut at a
+/// window edge is whole in one of them.
+const WINDOW_OVERLAP: usize = 32;
+
+/// Embeddings per symbol in the open index; 1 (windows off) when never set
+/// or no index is open.
+fn index_max_windows(state: &State) -> napi::Result<usize> {
+    let Some(db) = &state.db else {
+        return Ok(1);
+    };
+    Ok(db
+        .get_meta(MAX_WINDOWS_META)
+        .map_err(|e| napi::Error::from_reason(format!("DB error: {}", e)))?
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(1))
+}
+
+/// Up to `count` overlapping windows over the tokens of `ids` that the first
+/// `max_len` tokens (the symbol's main embedding) don't cover, each wrapped
+/// in `ids`' leading [CLS] and trailing [SEP]. Empty when `ids` fits.
+fn extra_windows(ids: &[u32], max_len: usize, count: usize) -> Vec<Vec<u32>> {
+    if ids.len() <= max_len || max_len < 3 {
+        return Vec::new();
+    }
+    let (cls, body, sep) = (ids[0], &ids[1..ids.len() - 1], ids[ids.len() - 1]);
+    let budget = max_len - 2;
+    let stride = budget.saturating_sub(WINDOW_OVERLAP).max(1);
+    let mut windows = Vec::new();
+    let mut start = stride;
+    while windows.len() < count && start + WINDOW_OVERLAP < body.len() {
+        let end = (start + budget).min(body.len());
+        let mut window = Vec::with_capacity(end - start + 2);
+        window.push(cls);
+        window.extend_from_slice(&body[start..end]);
+        window.push(sep);
+        windows.push(window);
+        if end == body.len() {
+            break;
+        }
+        start += stride;
+    }
+    windows
+}
+
+const LATE_INTERACTION_META: &str = "late_interaction";
+/// Upper bound for `set_late_interaction`'s tokens per vector.
+const LATE_INTERACTION_GROUP_LIMIT: u32 = 64;
+/// Dense candidates per result that `search` reranks by late interaction.
+const LATE_INTERACTION_POOL: i32 = 4;
+
+const SPARSE_META: &str = "sparse_vectors";
+/// Dense and sparse candidates per result that a `sparse_weight` search fuses.
+const SPARSE_POOL: i32 = 4;
+
+/// Candidates per result that a `per_kind_limits` or `stale_weight` search
+/// picks from.
+const KIND_LIMIT_POOL: i32 = 4;
+
+/// Whether `db` stores sparse lexical vectors of its symbols.
+fn sparse_enabled(db: &SearchDB) -> napi::Result<bool> {
+    Ok(db
+        .get_meta(SPARSE_META)
+        .map_err(|e| napi::Error::from_reason(format!("DB error: {}", e)))?
+        .as_deref()
+        == Some("1"))
+}
+
+/// Tokens pooled into each stored late interaction vector of `db`'s
+/// symbols; 0 when late interaction is off.
+fn late_interaction_group(db: &SearchDB) -> napi::Result<usize> {
+    Ok(db
+        .get_meta(LATE_INTERACTION_META)
+        .map_err(|e| napi::Error::from_reason(format!("DB error: {}", e)))?
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(0))
+}
+
+/// Embed `texts` with the query or document prefix. Queries are
+/// looked up in `State::query_cache` first; only misses run the model.
+fn embed_internal(
+    state: &mut State,
+    texts: &[String],
+    is_query: bool,
+) -> napi::Result<Vec<Vec<f32>>> {
+    if !is_query {
+        return embed_uncached(state, texts, false);
+    }
+    let prefixed = query_texts(state, texts, None);
+    embed_prefixed_queries(state, &prefixed)
+}
+
+/// `queries` with `instruction` prepended, or the model's query prefix when
+/// there is none. A space is added after an instruction that doesn't end in
+/// whitespace.
+fn query_texts(state: &State, queries: &[String], instruction: Option<&str>) -> Vec<String> {
+    let prefix = match instruction {
+        Some(i) if i.is_empty() || i.ends_with(char::is_whitespace) => i.to_string(),
+        Some(i) => format!("{} ", i),
+        None => state.query_prefix.clone(),
+    };
+    queries.iter().map(|q| format!("{}{}", prefix, q)).collect()
+}
+
+/// Embed already-prefixed query texts through `State::query_cache`.
+fn embed_prefixed_queries(state: &mut State, texts: &[String]) -> napi::Result<Vec<Vec<f32>>> {
+    let cached = cached_queries(state, texts);
+    let missing = missing_queries(texts, &cached);
+    let fresh = if missing.is_empty() {
+        Vec::new()
+    } else {
+        embed_texts(state, &missing)?
+    };
+    Ok(fill_queries(state, texts, cached, fresh))
+}
+
+/// `State::query_cache` entries for `texts`, None on a miss.
+fn cached_queries(state: &mut State, texts: &[String]) -> Vec<Option<Vec<f32>>> {
+    texts.iter().map(|t| state.query_cache.get(t)).collect()
+}
+
+/// The texts `cached_queries` missed, in order.
+fn missing_queries(texts: &[String], cached: &[Option<Vec<f32>>]) -> Vec<String> {
+    texts
+        .iter()
+        .zip(cached)
+        .filter(|(_, e)| e.is_none())
+        .map(|(t, _)| t.clone())
+        .collect()
+}
+
+/// Fill the misses in `cached` with `fresh` (embeddings of
+/// `missing_queries`, in order), caching them.
+fn fill_queries(
+    state: &mut State,
+    texts: &[String],
+    cached: Vec<Option<Vec<f32>>>,
+    fresh: Vec<Vec<f32>>,
+) -> Vec<Vec<f32>> {
+    let mut fresh = fresh.into_iter();
+    texts
+        .iter()
+        .zip(cached)
+        .map(|(text, slot)| {
+            slot.unwrap_or_else(|| {
+                let emb = fresh.next().unwrap_or_default();
+                state.query_cache.insert(text.clone(), emb.clone());
+                emb
+            })
+        })
+        .collect()
+}
+
+/// Model inputs, prefixes applied, waiting for a joint forward pass.
+static EMBED_REQUESTS: coalesce::Coalescer<String, Vec<f32>> = coalesce::Coalescer::new();
+
+/// Embed model inputs (prefixes applied) from outside `with_state`, sharing
+/// forward passes with other threads' pending `embed_joint` calls, e.g. the
+/// index queue's worker and a search. A joint batch runs on the device of
+/// whichever call gets the model first.
+fn embed_joint(texts: Vec<String>, background: bool) -> napi::Result<Vec<Vec<f32>>> {
+    if texts.is_empty() {
+        return Ok(Vec::new());
+    }
+    let ticket = EMBED_REQUESTS.submit(texts);
+    let run = |state: &mut State| {
+        EMBED_REQUESTS
+            .run(ticket, |all| embed_texts(state, all).map_err(|e| e.reason))
+            .map_err(napi::Error::from_reason)
+    };
+    if background {
+        with_state_background(|state| state.on_background_device(run))
+    } else {
+        with_state(run)
+    }
+}
+
+/// `texts` with boilerplate stripped (if enabled) and the document prefix.
+fn document_texts(state: &State, texts: Vec<String>) -> napi::Result<Vec<String>> {
+    let strip = index_strips_boilerplate(state)?;
+    if state.document_prefix.is_empty() && !strip {
+        return Ok(texts);
+    }
+    Ok(texts
+        .into_iter()
+        .map(|t| format!("{}{}", state.document_prefix, document_body(strip, &t)))
+        .collect())
+}
+
+fn embed_uncached(
+    state: &mut State,
+    texts: &[String],
+    is_query: bool,
+) -> napi::Result<Vec<Vec<f32>>> {
+    if !is_query {
+        let texts = document_texts(state, texts.to_vec())?;
+        return embed_texts(state, &texts);
+    }
+    let prefix = state.prefix(true);
+    if prefix.is_empty() {
+        return embed_texts(state, texts);
+    }
+    let prefixed: Vec<String> = texts.iter().map(|t| format!("{}{}", prefix, t)).collect();
+    embed_texts(state, &prefixed)
+}
+
+/// Run the model on `texts` as given, in `State::batch_size` batches.
+fn embed_texts(state: &mut State, texts: &[String]) -> napi::Result<Vec<Vec<f32>>> {
+    let truncation = index_truncation(state)?;
+    let splitting = index_splitting(state)?;
+    let mut results = Vec::new();
+
+    for chunk in texts.chunks(state.batch_size) {
+        let chunk_vec: Vec<String> = chunk
+            .iter()
+            .map(|t| split_identifiers(state, splitting, t).into_owned())
+            .collect();
+        let rows = batch_rows(state, chunk_vec.len());
+        let (input_ids, attention_mask) =
+            tokenize_batch(&state.tokenizer, &chunk_vec, rows, MAX_LENGTH, truncation);
+        let pooled = forward_pooled(state, &input_ids, &attention_mask)?;
+        results.extend(pooled.into_iter().take(chunk.len()));
+    }
+
+    Ok(results)
+}
+
+/// Embed pre-tokenized sequences (special tokens included, at most
+/// `MAX_LENGTH` each), in `State::batch_size` batches.
+fn embed_token_ids(state: &mut State, seqs: &[Vec<u32>]) -> napi::Result<Vec<Vec<f32>>> {
+    let mut results = Vec::with_capacity(seqs.len());
+    for chunk in seqs.chunks(state.batch_size) {
+        let rows = batch_rows(state, chunk.len());
+        let (input_ids, attention_mask) = pack_batch(chunk, rows, MAX_LENGTH);
+        let pooled = forward_pooled(state, &input_ids, &attention_mask)?;
+        results.extend(pooled.into_iter().take(chunk.len()));
+    }
+    Ok(results)
+}
+
+/// Per-token hidden states of `seqs` without [CLS] and [SEP], mean-pooled
+/// over runs of `group` tokens and normalized, packed back to back. Empty
+/// for sequences with no other tokens.
+fn embed_token_groups(
+    state: &mut State,
+    seqs: &[Vec<u32>],
+    group: usize,
+) -> napi::Result<Vec<Vec<f32>>> {
+    let mut results = Vec::with_capacity(seqs.len());
+    for chunk in seqs.chunks(state.batch_size) {
+        let rows = batch_rows(state, chunk.len());
+        let (input_ids, attention_mask) = pack_batch(chunk, rows, MAX_LENGTH);
+        let hidden = state
+            .model
+            .forward(&input_ids, Some(&attention_mask))
+            .and_then(|h| h.as_type::<f32>())
+            .map_err(|e| napi::Error::from_reason(format!("Forward pass failed: {}", e)))?;
+        hidden
+            .eval()
+            .map_err(|e| napi::Error::from_reason(format!("Eval failed: {}", e)))?;
+        let data = hidden.as_slice::<f32>();
+        let (len, dims) = (hidden.shape()[1] as usize, hidden.shape()[2] as usize);
+        for (i, ids) in chunk.iter().enumerate() {
+            let n = ids.len().min(len);
+            let body = &data[(i * len + 1) * dims..(i * len + n.saturating_sub(1).max(1)) * dims];
+            results.push(pool_token_groups(body, dims, group));
+        }
+    }
+    Ok(results)
+}
+
+/// Mean of each run of `group` vectors in `tokens`, normalized.
+fn pool_token_groups(tokens: &[f32], dims: usize, group: usize) -> Vec<f32> {
+    let mut out = Vec::with_capacity(tokens.len().div_ceil(group.max(1)));
+    for run in tokens.chunks(dims * group.max(1)) {
+        let mut v = vec![0.0f32; dims];
+        for token in run.chunks_exact(dims) {
+            v.iter_mut().zip(token).for_each(|(a, b)| *a += b);
+        }
+        let norm = v.iter().map(|x| x * x).sum::<f32>().sqrt().max(1e-12);
+        out.extend(v.iter().map(|x| x / norm));
+    }
+    out
+}
+
+/// Per-token vectors of already-prefixed query texts, for late interaction.
+fn embed_query_tokens(state: &mut State, texts: &[String]) -> napi::Result<Vec<Vec<Vec<f32>>>> {
+    let seqs = token_ids(state, texts, true)?;
+    let dims = state.dims;
+    Ok(embed_token_groups(state, &seqs, 1)?
+        .into_iter()
+        .map(|packed| packed.chunks_exact(dims).map(<[f32]>::to_vec).collect())
+        .collect())
+}
+
+/// One forward pass, mean-pooled and normalized, one vector per row.
+fn forward_pooled(
+    state: &mut State,
+    input_ids: &mlx_rs::Array,
+    attention_mask: &mlx_rs::Array,
+) -> napi::Result<Vec<Vec<f32>>> {
+    // Inputs are always padded to `MAX_LENGTH`, so the shapes seen are
+    // (batch_size, MAX_LENGTH) plus the smaller final batch of a run (none
+    // in deterministic mode). MLX keeps one traced graph per input shape and
+    // replays it on repeats.
+    let start = std::time::Instant::now();
+    let inputs = (input_ids, attention_mask);
+    let result = if state.compile {
+        let mut compiled = mlx_rs::transforms::compile::compile_with_state(embed_pooled, false);
+        compiled(&mut state.model, inputs)
+    } else {
+        embed_pooled(&mut state.model, inputs)
+    }
+    .map_err(|e| napi::Error::from_reason(format!("Forward pass failed: {}", e)))?;
+    let rows = pooled_rows(&result)?;
+    tracing::trace!(
+        "forward pass of {} sequences{} in {:.1}ms",
+        rows.len(),
+        if state.compile { " (compiled)" } else { "" },
+        start.elapsed().as_secs_f64() * 1000.0
+    );
+    Ok(rows)
+}
+
+/// Evaluate a pooled `[batch, dims]` array into one vector per row.
+fn pooled_rows(result: &mlx_rs::Array) -> napi::Result<Vec<Vec<f32>>> {
+    result
+        .eval()
+        .map_err(|e| napi::Error::from_reason(format!("Eval failed: {}", e)))?;
+
+    let data = result.as_slice::<f32>();
+    let dims = result.shape();
+    let n = dims[0] as usize;
+    let d = dims[1] as usize;
+    Ok((0..n).map(|i| data[i * d..(i + 1) * d].to_vec()).collect())
+}
+
+/// Embed `benchmark::SAMPLE_TEXTS` with a model not yet in `State`, for
+/// `State::precision_reference`.
+fn embed_samples(
+    model: &mut NomicBertModel,
+    tokenizer: &Tokenizer,
+) -> napi::Result<Vec<Vec<f32>>> {
+    let texts = benchmark::sample_texts(benchmark::SAMPLE_TEXTS.len());
+    let (input_ids, attention_mask) =
+        tokenize_batch(tokenizer, &texts, texts.len(), MAX_LENGTH, Truncation::Head);
+    let result = embed_pooled(model, (&input_ids, &attention_mask))
+        .map_err(|e| napi::Error::from_reason(format!("Forward pass failed: {}", e)))?;
+    pooled_rows(&result)
+}
+
+// ── Batch DB helpers ───────────────────────────────────────────────────
+
+fn get_db(state: &mut State) -> napi::Result<&mut SearchDB> {
+    state
+        .db
+        .as_mut()
+        .ok_or_else(|| napi::Error::from_reason("DB not opened. Call open_db() first."))
+}
+
+// ── napi types ─────────────────────────────────────────────────────────
+
+#[napi(object)]
+pub struct JsFileRow {
+    pub path: String,
+    pub hash: String,
+    pub language: Option<String>,
+    pub symbol_count: Option<i32>,
+    pub indexed_at: f64,
+}
+
+impl From<db::FileRow> for JsFileRow {
+    fn from(r: db::FileRow) -> Self {
+        JsFileRow {
+            path: r.path,
+            hash: r.hash,
+            language: r.language,
+            symbol_count: r.symbol_count,
+            indexed_at: r.indexed_at as f64,
+        }
+    }
+}
+
+#[napi(object)]
+pub struct JsSearchResult {
+    pub file_path: String,
+    pub name: String,
+    pub kind: String,
+    pub language: String,
+    pub line: i32,
+    pub end_line: Option<i32>,
+    pub signature: Option<String>,
+    pub doc_comment: Option<String>,
+    /// Stable across line moves; pass to `get_symbols_by_id`
+    pub symbol_id: String,
+    pub score: f64,
+    /// Query-term matches, set when `options.highlight` is on
+    pub highlights: Option<Vec<JsHighlight>>,
+    /// JSON object the symbol was indexed with (see `SymbolInput.metadata`)
+    pub metadata: Option<String>,
+    /// Index the result came from, as passed to `search_federated`
+    pub repo: Option<String>,
+    /// Set on symbols `search_with_neighbors` pulled in through the edge
+    /// table: the `symbol_id` of the hit they're connected to
+    pub neighbor_of: Option<String>,
+}
+
+#[napi(object)]
+pub struct JsHighlight {
+    /// "name", "signature", or "doc_comment"
+    pub field: String,
+    pub term: String,
+    /// Byte offsets into the field's UTF-8 text
+    pub start: u32,
+    pub end: u32,
+}
+
+#[napi(object)]
+pub struct JsLoggedResult {
+    pub key: String,
+    pub score: f64,
+}
+
+#[napi(object)]
+pub struct JsQueryLogEntry {
+    pub id: f64,
+    pub query: String,
+    pub results: Vec<JsLoggedResult>,
+    /// Result keys the user opened, in click order.
+    pub clicks: Vec<String>,
+    pub created_at: f64,
+}
+
+#[napi(object)]
+pub struct JsStats {
+    pub symbol_count: f64,
+    pub file_count: f64,
+}
+
+#[napi(object)]
+pub struct SymbolInput {
+    pub embedding_text: String,
+    pub file_path: String,
+    pub name: String,
+    pub kind: String,
+    pub language: String,
+    pub line: i32,
+    pub end_line: Option<i32>,
+    pub signature: Option<String>,
+    /// Stored separately, appended to the embedding text (whether supplied
+    /// or rendered from the template) unless it already contains it, and
+    /// embedded on its own for `filters.search_docs_only`
+    pub doc_comment: Option<String>,
+    /// JSON object of extractor-defined attributes (e.g.
+    /// `{"visibility":"public"}`), stored as given, returned with results,
+    /// and matched by `filters.metadata_filter`. Not embedded.
+    pub metadata: Option<String>,
+}
+
+impl From<db::StoredSymbol> for SymbolInput {
+    fn from(s: db::StoredSymbol) -> Self {
+        SymbolInput {
+            embedding_text: s.embedding_text,
+            file_path: s.file_path,
+            name: s.name,
+            kind: s.kind,
+            language: s.language,
+            line: s.line,
+            end_line: s.end_line,
+            signature: s.signature,
+            doc_comment: s.doc_comment,
+            metadata: s.metadata,
+        }
+    }
+}
+
+#[napi(object)]
+pub struct FileInput {
+    pub path: String,
+    pub hash: String,
+    pub language: Option<String>,
+    pub symbol_count: i32,
+}
+
+#[napi(object)]
+#[derive(Default)]
+pub struct SearchFilters {
+    /// Search this workspace instead of the one set with `set_workspace`
+    pub workspace: Option<String>,
+    pub language: Option<String>,
+    pub kind: Option<String>,
+    pub path_prefix: Option<String>,
+    /// Also search document chunks (see `index_chunks`). `kind: "chunk"`
+    /// searches chunks only.
+    pub include_chunks: Option<bool>,
+    /// Match against symbols' doc comments instead of their embedding text.
+    /// Symbols without a doc comment are skipped; chunks are not searched.
+    pub search_docs_only: Option<bool>,
+    /// Shortlist symbols by comparing 1-bit (sign) embeddings, then score
+    /// only the shortlist exactly. Faster on large indexes; may miss a few
+    /// results a full scan would find.
+    pub fast_prefilter: Option<bool>,
+    /// Like `fast_prefilter`, but shortlist with product-quantized codes
+    /// (see `train_pq`). Ignored until a codebook has been trained.
+    pub quantized: Option<bool>,
+    /// Only symbols whose metadata has all of these top-level values, e.g.
+    /// `{ visibility: "public" }`. Chunks have no metadata, so they never
+    /// match.
+    pub metadata_filter: Option<HashMap<String, Either3<String, f64, bool>>>,
+    /// Only results from files indexed at or after this time (Unix ms),
+    /// e.g. to restrict a search to recently indexed code
+    pub min_indexed_at: Option<f64>,
+    /// Only results from files indexed at or before this time (Unix ms),
+    /// e.g. to check whether any results come from stale files
+    pub max_indexed_at: Option<f64>,
+    /// Only results whose file path matches this regular expression (Rust
+    /// `regex` syntax, unanchored), e.g. `^packages/(a|b)/`
+    pub path_regex: Option<String>,
+    /// Only symbols whose name matches this regular expression, e.g.
+    /// `^(get|set)[A-Z]`. Chunks have no names, so they never match.
+    pub name_regex: Option<String>,
+    /// JSON condition tree over `language`, `kind` and `path` (a directory
+    /// prefix) with `and`, `or` and `not`, ANDed with the other filters.
+    /// E.g. Go or Rust outside tests:
+    /// `{"and": [{"or": [{"language": "go"}, {"language": "rust"}]}, {"not": {"path": "tests"}}]}`
+    pub filter_expr: Option<String>,
+}
+
+/// `filters.filter_expr` parsed, with languages and kinds normalized like
+/// the flat filters.
+fn filter_expr(filters: &SearchFilters) -> napi::Result<Option<db::FilterExpr>> {
+    let Some(json) = &filters.filter_expr else {
+        return Ok(None);
+    };
+    let expr: db::FilterExpr = serde_json::from_str(json)
+        .map_err(|e| napi::Error::from_reason(format!("Invalid filter_expr: {}", e)))?;
+    Ok(Some(expr.map_values(&lang::normalize_language, &kind::normalize)))
+}
+
+/// Fail on a `path_regex` or `name_regex` that doesn't compile, before the
+/// scan would.
+fn check_regex_filters(filters: &SearchFilters) -> napi::Result<()> {
+    let patterns = [("path_regex", &filters.path_regex), ("name_regex", &filters.name_regex)];
+    for (field, pattern) in patterns {
+        if let Some(pattern) = pattern {
+            regex::Regex::new(pattern)
+                .map_err(|e| napi::Error::from_reason(format!("Invalid {}: {}", field, e)))?;
+        }
+    }
+    Ok(())
+}
+
+/// `filters.metadata_filter` as SQL values, sorted by key. Booleans compare
+/// as the 1/0 SQLite's JSON functions return for `true`/`false`, and SQLite
+/// compares a REAL equal to the same INTEGER.
+fn metadata_filter(filters: &SearchFilters) -> Vec<(String, rusqlite::types::Value)> {
+    use rusqlite::types::Value;
+    let mut pairs: Vec<(String, Value)> = filters
+        .metadata_filter
+        .iter()
+        .flatten()
+        .map(|(k, v)| {
+            let value = match v {
+                Either3::A(s) => Value::Text(s.clone()),
+                Either3::B(n) => Value::Real(*n),
+                Either3::C(b) => Value::Integer(*b as i64),
+            };
+            (k.clone(), value)
+        })
+        .collect();
+    pairs.sort_by(|a, b| a.0.cmp(&b.0));
+    pairs
+}
+
+/// `SearchFilters` validated and normalized into the values `db::Filters`
+/// borrows: languages and kinds normalized, `filter_expr` parsed, metadata
+/// as SQL values. The row filters only; see `ResolvedFilters::db`.
+struct ResolvedFilters {
+    workspace: Option<String>,
+    language: Option<String>,
+    kind: Option<String>,
+    path_prefix: Option<String>,
+    path_regex: Option<String>,
+    name_regex: Option<String>,
+    expr: Option<db::FilterExpr>,
+    metadata: Vec<(String, rusqlite::types::Value)>,
+    min_indexed_at: Option<i64>,
+    max_indexed_at: Option<i64>,
+}
+
+impl ResolvedFilters {
+    fn new(filters: &SearchFilters) -> napi::Result<Self> {
+        check_regex_filters(filters)?;
+        Ok(ResolvedFilters {
+            workspace: filters.workspace.clone(),
+            language: filters.language.as_deref().map(lang::normalize_language),
+            kind: filters.kind.as_deref().map(kind::normalize),
+            path_prefix: filters.path_prefix.clone(),
+            path_regex: filters.path_regex.clone(),
+            name_regex: filters.name_regex.clone(),
+            expr: filter_expr(filters)?,
+            metadata: metadata_filter(filters),
+            min_indexed_at: filters.min_indexed_at.map(|t| t as i64),
+            max_indexed_at: filters.max_indexed_at.map(|t| t as i64),
+        })
+    }
+
+    /// The row filters, with no score bound or shortlist; set those with
+    /// struct update syntax where a search needs them.
+    fn db(&self) -> db::Filters<'_> {
+        db::Filters {
+            workspace: self.workspace.as_deref(),
+            language: self.language.as_deref(),
+            kind: self.kind.as_deref(),
+            path_prefix: self.path_prefix.as_deref(),
+            path_regex: self.path_regex.as_deref(),
+            name_regex: self.name_regex.as_deref(),
+            expr: self.expr.as_ref(),
+            metadata: &self.metadata,
+            min_indexed_at: self.min_indexed_at,
+            max_indexed_at: self.max_indexed_at,
+            ..Default::default()
+        }
+    }
+
+    /// Whether the kind filter selects chunks only.
+    fn chunks_only(&self) -> bool {
+        self.kind.as_deref() == Some(kind::Kind::Chunk.as_str())
+    }
+}
+
+#[napi(object)]
+pub struct ChunkInput {
+    pub text: String,
+    pub start_line: i32,
+    pub end_line: i32,
+}
+
+/// Per-kind score thresholds. Kinds missing from `by_kind` use `default`.
+#[napi(object)]
+pub struct KindThresholds {
+    pub default: f64,
+    pub by_kind: HashMap<String, f64>,
+}
+
+impl KindThresholds {
+    fn for_kind(&self, kind: &str) -> f64 {
+        self.by_kind.get(kind).copied().unwrap_or(self.default)
+    }
+
+    /// Lowest score any result could pass with: the kind's own threshold when
+    /// the search is restricted to one kind, else the loosest configured one.
+    fn min_score(&self, kind: Option<&str>) -> f64 {
+        match kind {
+            Some(k) => self.for_kind(k),
+            None => self.by_kind.values().copied().fold(self.default, f64::min),
+        }
+    }
+}
+
+/// Normalize a `threshold` argument: a plain number applies to every kind,
+/// and kind names may be aliases.
+fn kind_thresholds(threshold: Either<f64, KindThresholds>) -> KindThresholds {
+    match threshold {
+        Either::A(default) => KindThresholds {
+            default,
+            by_kind: HashMap::new(),
+        },
+        Either::B(t) => KindThresholds {
+            default: t.default,
+            by_kind: t
+                .by_kind
+                .into_iter()
+                .map(|(k, v)| (kind::normalize(&k), v))
+                .collect(),
+        },
+    }
+}
+
+/// Maximal marginal relevance over the candidate pool.
+/// `by` is `"file"` (penalize repeats from the same file) or `"embedding"`
+/// (penalize near-duplicate vectors). `lambda` = 1.0 is pure relevance.
+#[napi(object)]
+#[derive(Clone)]
+pub struct DiversifyOptions {
+    pub lambda: f64,
+    pub by: String,
+}
+
+#[napi(object)]
+pub struct SearchOptions {
+    pub diversify: Option<DiversifyOptions>,
+    /// Return byte offsets of query terms found in each result's name,
+    /// signature, and doc comment
+    pub highlight: Option<bool>,
+    /// Replaces the model's query prefix, for task-specific retrieval with
+    /// instruction-following models (e.g. "Find tests for:")
+    pub task_instruction: Option<String>,
+    /// Search this snapshot (see `create_snapshot`) instead of the live
+    /// index, e.g. to see code as it was before a refactor. Honored by
+    /// `search`, `search_async`, `search_with_stats` and `search_by_vector`
+    pub snapshot: Option<String>,
+    /// Share of the score from sparse term matches (see
+    /// `set_sparse_vectors`), 0 to 1; the rest is dense similarity. Only
+    /// `search` and `search_async` fuse; default 0
+    pub sparse_weight: Option<f64>,
+    /// Most results of each kind, e.g. `{ function: 10, class: 5 }`, so one
+    /// kind can't crowd out the rest; unlisted kinds are unlimited. Only
+    /// `search` and `search_async` apply them
+    pub per_kind_limits: Option<HashMap<String, u32>>,
+    /// Score multiplier, 0 to 1, for symbols embedded by a model other than
+    /// the loaded one (see `find_stale_embeddings`), e.g. midway through a
+    /// `reembed_all`; 0 drops them. Only `search` and `search_async` apply
+    /// it; default 1
+    pub stale_weight: Option<f64>,
+}
+
+/// One scoped search inside a `search_many` batch.
+#[napi(object)]
+pub struct SearchRequest {
+    pub queries: Vec<String>,
+    pub top_k: i32,
+    pub threshold: Either<f64, KindThresholds>,
+    pub filters: SearchFilters,
+    pub options: Option<SearchOptions>,
+}
+
+#[napi(object)]
+pub struct FileSearchOptions {
+    pub aggregate: Option<String>,
+    pub symbols_per_file: Option<i32>,
+}
+
+#[napi(object)]
+pub struct JsFileSearchResult {
+    pub file_path: String,
+    pub score: f64,
+    pub symbols: Vec<JsSearchResult>,
+}
+
+// ── Batch APIs ─────────────────────────────────────────────────────────
+
+/// Get all indexed files. Single FFI call returns everything.
+#[napi(catch_unwind)]
+pub fn db_get_all_files() -> napi::Result<Vec<JsFileRow>> {
+    with_state(|state| {
+        let db = get_db(state)?;
+        let rows = db
+            .get_all_files()
+            .map_err(|e| napi::Error::from_reason(format!("DB error: {}", e)))?;
+        Ok(rows.into_iter().map(JsFileRow::from).collect())
+    })
+}
+
+/// Look up files by path, in input order: null for paths not in the index.
+/// One call instead of `db_get_all_files` plus a map when checking a batch
+/// of hashes.
+#[napi(catch_unwind)]
+pub fn db_get_files(paths: Vec<String>) -> napi::Result<Vec<Option<JsFileRow>>> {
+    with_state(|state| {
+        let refs: Vec<&str> = paths.iter().map(|p| p.as_str()).collect();
+        let rows = get_db(state)?
+            .get_files(&refs)
+            .map_err(|e| napi::Error::from_reason(format!("DB error: {}", e)))?;
+        Ok(rows.into_iter().map(|r| r.map(JsFileRow::from)).collect())
+    })
+}
+
+/// Whether each path is in the index, in input order.
+#[napi(catch_unwind)]
+pub fn db_has_files(paths: Vec<String>) -> napi::Result<Vec<bool>> {
+    with_state(|state| {
+        let refs: Vec<&str> = paths.iter().map(|p| p.as_str()).collect();
+        get_db(state)?
+            .has_files(&refs)
+            .map_err(|e| napi::Error::from_reason(format!("DB error: {}", e)))
+    })
+}
+
+/// Page through indexed symbols without searching, e.g. for an index
+/// browser. Only the workspace, language, kind, path prefix, regex,
+/// metadata and `indexed_at` filters apply; chunks are not listed. `order`
+/// is `"path"` (the default: by file path, then line) or `"recent"` (most
+/// recently indexed files first).
+/// Results carry score 0.
+#[napi(catch_unwind)]
+pub fn db_list_symbols(
+    filters: Option<SearchFilters>,
+    offset: u32,
+    limit: u32,
+    order: Option<String>,
+) -> napi::Result<Vec<JsSearchResult>> {
+    let order = match order.as_deref() {
+        None | Some("path") => db::SymbolOrder::Path,
+        Some("recent") => db::SymbolOrder::Recent,
+        Some(other) => {
+            return Err(napi::Error::from_reason(format!(
+                "Unknown order '{}'. Expected \"path\" or \"recent\".",
+                other
+            )))
+        }
+    };
+    let filters = filters.unwrap_or_default();
+    let resolved = ResolvedFilters::new(&filters)?;
+    let db_filters = resolved.db();
+    with_state(|state| {
+        let rows = get_db(state)?
+            .list_symbols(&db_filters, order, offset as u64, limit as u64)
+            .map_err(|e| napi::Error::from_reason(format!("DB error: {}", e)))?;
+        Ok(rows.into_iter().map(JsSearchResult::from).collect())
+    })
+}
+
+/// Look symbols up by name, like an editor's "go to symbol in workspace":
+/// `query` matches names exactly or by prefix or substring (ignoring case),
+/// or approximately by shared trigrams, so `HtpClient` still finds
+/// `HttpClient`. Complements `search` when the identifier is roughly known.
+/// Returns up to `limit` (default 50) best matches first, scored 0 to 1
+/// (1 for the exact name); the workspace, language, kind, path prefix,
+/// regex, metadata and `indexed_at` filters apply.
+#[napi(catch_unwind)]
+pub fn list_symbols_by_name(
+    query: String,
+    limit: Option<u32>,
+    filters: Option<SearchFilters>,
+) -> napi::Result<Vec<JsSearchResult>> {
+    let filters = filters.unwrap_or_default();
+    let resolved = ResolvedFilters::new(&filters)?;
+    let db_filters = resolved.db();
+    with_state(|state| {
+        let rows = get_db(state)?
+            .symbols_by_name(&query, limit.unwrap_or(50) as usize, &db_filters)
+            .map_err(|e| napi::Error::from_reason(format!("DB error: {}", e)))?;
+        Ok(rows.into_iter().map(JsSearchResult::from).collect())
+    })
+}
+
+#[napi(object)]
+pub struct DeleteOptions {
+    /// Tombstone the files instead of deleting them: searches skip them
+    /// until `restore_deleted_files` undoes the delete or
+    /// `purge_tombstones` makes it permanent
+    pub soft: Option<bool>,
+}
+
+/// Delete multiple files and their symbols in a single transaction.
+#[napi(catch_unwind)]
+pub fn delete_files(paths: Vec<String>, options: Option<DeleteOptions>) -> napi::Result<()> {
+    let soft = options.and_then(|o| o.soft).unwrap_or(false);
+    with_state(|state| {
+        let db = get_db(state)?;
+        if soft {
+            let paths: Vec<&str> = paths.iter().map(String::as_str).collect();
+            db.soft_delete_files(&paths)
+                .map_err(|e| napi::Error::from_reason(format!("DB error: {}", e)))?;
+            return Ok(());
+        }
+        let ws = db.workspace().to_string();
+        let tx = db.transaction()
+            .map_err(|e| napi::Error::from_reason(format!("DB error: {}", e)))?;
+        for path in &paths {
+            delete_file_rows(&tx, &ws, path)?;
+        }
+        tx.commit()
+            .map_err(|e| napi::Error::from_reason(format!("DB error: {}", e)))?;
+        Ok(())
+    })
+}
+
+#[napi(object)]
+pub struct JsDeletedFile {
+    pub path: String,
+    pub deleted_at: f64,
+}
+
+/// Files soft-deleted from the current workspace, most recent first.
+#[napi(catch_unwind)]
+pub fn list_deleted_files() -> napi::Result<Vec<JsDeletedFile>> {
+    with_state(|state| {
+        let rows = get_db(state)?
+            .tombstones()
+            .map_err(|e| napi::Error::from_reason(format!("DB error: {}", e)))?;
+        Ok(rows
+            .into_iter()
+            .map(|(path, deleted_at)| JsDeletedFile {
+                path,
+                deleted_at: deleted_at as f64,
+            })
+            .collect())
+    })
+}
+
+/// Undo soft deletes of `paths`, or of every soft-deleted file in the
+/// current workspace when omitted. Files indexed again since are left
+/// as they are. Returns the number of files restored.
+#[napi(catch_unwind)]
+pub fn restore_deleted_files(paths: Option<Vec<String>>) -> napi::Result<f64> {
+    with_state(|state| {
+        let paths: Option<Vec<&str>> = paths
+            .as_ref()
+            .map(|paths| paths.iter().map(String::as_str).collect());
+        let restored = get_db(state)?
+            .restore_files(paths.as_deref())
+            .map_err(|e| napi::Error::from_reason(format!("DB error: {}", e)))?;
+        Ok(restored as f64)
+    })
+}
+
+/// Permanently delete files soft-deleted more than `older_than_ms` ago, in
+/// every workspace (0 purges them all). Returns the number of files purged.
+#[napi(catch_unwind)]
+pub fn purge_tombstones(older_than_ms: f64) -> napi::Result<f64> {
+    let cutoff = db::now_millis() - older_than_ms.max(0.0) as i64;
+    with_state(|state| {
+        let purged = get_db(state)?
+            .purge_tombstones(cutoff)
+            .map_err(|e| napi::Error::from_reason(format!("DB error: {}", e)))?;
+        Ok(purged as f64)
+    })
+}
+
+/// Delete every symbol matching `filters` in one transaction, e.g. all of
+/// `vendor/` or all of a language. Only the workspace, language, kind,
+/// path prefix, regex, metadata and `indexed_at` filters apply. Chunks are
+/// deleted too unless a kind, name regex or metadata filter is given; kind
+/// `"chunk"` deletes only chunks. File records are kept (with their symbol
+/// counts updated), so unchanged files stay out of the next index pass.
+/// Returns how many rows were removed.
+#[napi(catch_unwind)]
+pub fn delete_symbols_where(filters: SearchFilters) -> napi::Result<f64> {
+    let resolved = ResolvedFilters::new(&filters)?;
+    let chunks_only = resolved.chunks_only();
+    let db_filters = resolved.db();
+    with_state(|state| {
+        let deleted = get_db(state)?
+            .delete_where(&db_filters, !chunks_only, resolved.kind.is_none() || chunks_only)
+            .map_err(|e| napi::Error::from_reason(format!("DB error: {}", e)))?;
+        Ok(deleted as f64)
+    })
+}
+
+/// Upsert multiple file records in a single transaction.
+/// Files without a `language` get one from `detect_language` (by path).
+#[napi(catch_unwind)]
+pub fn upsert_files(files: Vec<FileInput>) -> napi::Result<()> {
+    with_state(|state| {
+        let db = get_db(state)?;
+        check_index_scope(db, files.iter().map(|f| f.path.as_str()))?;
+        let ws = db.workspace().to_string();
+        let tx = db.transaction()
+            .map_err(|e| napi::Error::from_reason(format!("DB error: {}", e)))?;
+        let now = db::now_millis();
+        for f in &files {
+            upsert_file_row(&tx, &ws, f, now)?;
+        }
+        tx.commit()
+            .map_err(|e| napi::Error::from_reason(format!("DB error: {}", e)))?;
+        Ok(())
+    })
+}
+
+/// Delete a file's record, symbols, and chunks. Callers own the transaction.
+fn delete_file_rows(conn: &rusqlite::Connection, workspace: &str, path: &str) -> napi::Result<()> {
+    db::delete_file_rows(conn, workspace, path)
+        .map_err(|e| napi::Error::from_reason(format!("DB error: {}", e)))
+}
+
+/// Insert or replace a file record, detecting its language by path when
+/// not given. Callers own the transaction.
+fn upsert_file_row(
+    conn: &rusqlite::Connection,
+    workspace: &str,
+    f: &FileInput,
+    now: i64,
+) -> napi::Result<()> {
+    let language = f
+        .language
+        .as_deref()
+        .or_else(|| lang::detect_language(&f.path, ""))
+        .map(lang::normalize_language);
+    conn.execute(
+        "INSERT OR REPLACE INTO files (workspace, path, hash, language, symbol_count, indexed_at) VALUES (?, ?, ?, ?, ?, ?)",
+        rusqlite::params![workspace, f.path, f.hash, language, f.symbol_count, now],
+    ).map_err(|e| napi::Error::from_reason(format!("DB error: {}", e)))?;
+    Ok(())
+}
+
+/// Per-symbol doc comment embeddings; None for symbols without a doc comment.
+type DocEmbeddings = Vec<Option<Vec<f32>>>;
+
+/// Per-symbol vectors stored beside the main embedding.
+#[derive(Default)]
+struct ExtraEmbeddings {
+    /// Embeddings of the windows after the first (see
+    /// `set_sliding_windows`); empty for symbols that fit in one
+    windows: Vec<Vec<Vec<f32>>>,
+    /// Packed token-group vectors (see `set_late_interaction`); empty when
+    /// late interaction is off
+    tokens: Vec<Vec<f32>>,
+    /// Sparse lexical vectors (see `set_sparse_vectors`); empty when off
+    sparse: Vec<Vec<(u32, f32)>>,
+}
+
+/// Embed symbols' embedding text and doc comments in one model pass, plus
+/// the extra vectors the index has enabled (see `embed_extra`).
+/// Returns `(embeddings, doc_embeddings, extra)`; `doc_embeddings[i]` is
+/// None when symbol `i` has no doc comment.
+fn embed_symbols(
+    state: &mut State,
+    symbols: &[SymbolInput],
+) -> napi::Result<(Vec<Vec<f32>>, DocEmbeddings, ExtraEmbeddings)> {
+    let texts = symbol_texts(symbols);
+    if texts.is_empty() {
+        return Ok((Vec::new(), Vec::new(), ExtraEmbeddings::default()));
+    }
+
+    let all = embed_internal(state, &texts, false)?;
+    let (embeddings, doc_embeddings) = split_symbol_embeddings(symbols, all);
+    let extra = embed_extra(state, symbols)?;
+    Ok((embeddings, doc_embeddings, extra))
+}
+
+/// What `embed_symbols` runs through the model, before the document
+/// prefix: every embedding text, then every non-empty doc comment.
+fn symbol_texts(symbols: &[SymbolInput]) -> Vec<String> {
+    let mut texts: Vec<String> = symbols.iter().map(|s| s.embedding_text.clone()).collect();
+    let docs = symbols
+        .iter()
+        .filter_map(|s| s.doc_comment.as_ref().filter(|d| !d.is_empty()));
+    texts.extend(docs.cloned());
+    texts
+}
+
+/// Split embeddings of `symbol_texts(symbols)` into `(embeddings,
+/// doc_embeddings)`.
+fn split_symbol_embeddings(
+    symbols: &[SymbolInput],
+    all: Vec<Vec<f32>>,
+) -> (Vec<Vec<f32>>, DocEmbeddings) {
+    let mut all = all.into_iter();
+    let embeddings: Vec<Vec<f32>> = all.by_ref().take(symbols.len()).collect();
+    let doc_embeddings = symbols
+        .iter()
+        .map(|s| match &s.doc_comment {
+            Some(d) if !d.is_empty() => all.next(),
+            _ => None,
+        })
+        .collect();
+    (embeddings, doc_embeddings)
+}
+
+/// Extra windows of long symbols, token-group vectors, and sparse vectors,
+/// as far as the index has them enabled.
+fn embed_extra(state: &mut State, symbols: &[SymbolInput]) -> napi::Result<ExtraEmbeddings> {
+    let windows = embed_windows(state, symbols)?;
+    let (group, sparse) = match &state.db {
+        Some(db) => (late_interaction_group(db)?, sparse_enabled(db)?),
+        None => (0, false),
+    };
+    let strip = index_strips_boilerplate(state)?;
+    let tokens = if group > 0 {
+        let texts: Vec<String> = symbols.iter().map(|s| s.embedding_text.clone()).collect();
+        let texts = document_texts(state, texts)?;
+        let seqs = token_ids(state, &texts, true)?;
+        embed_token_groups(state, &seqs, group)?
+    } else {
+        Vec::new()
+    };
+    let sparse = if sparse {
+        let texts: Vec<String> = symbols
+            .iter()
+            .map(|s| document_body(strip, &s.embedding_text).into_owned())
+            .collect();
+        token_ids(state, &texts, false)?
+            .iter()
+            .map(|ids| db::tf_weights(ids))
+            .collect()
+    } else {
+        Vec::new()
+    };
+    Ok(ExtraEmbeddings { windows, tokens, sparse })
+}
+
+/// Token ids of each text (identifiers split as for the model), with
+/// [CLS] and [SEP] when `special` is set.
+fn token_ids(state: &State, texts: &[String], special: bool) -> napi::Result<Vec<Vec<u32>>> {
+    let splitting = index_splitting(state)?;
+    texts
+        .iter()
+        .map(|t| {
+            state
+                .tokenizer
+                .encode(split_identifiers(state, splitting, t).as_ref(), special)
+                .map(|e| e.get_ids().to_vec())
+                .map_err(|e| napi::Error::from_reason(format!("Tokenization failed: {}", e)))
+        })
+        .collect()
+}
+
+/// Embed the extra windows of symbols longer than one model input.
+fn embed_windows(state: &mut State, symbols: &[SymbolInput]) -> napi::Result<Vec<Vec<Vec<f32>>>> {
+    let extra = index_max_windows(state)?.saturating_sub(1);
+    if extra == 0 {
+        return Ok(vec![Vec::new(); symbols.len()]);
+    }
+    let splitting = index_splitting(state)?;
+    let texts: Vec<String> = symbols.iter().map(|s| s.embedding_text.clone()).collect();
+    let texts = document_texts(state, texts)?;
+    let mut counts = Vec::with_capacity(symbols.len());
+    let mut seqs = Vec::new();
+    for text in &texts {
+        let encoding = state
+            .tokenizer
+            .encode(split_identifiers(state, splitting, text).as_ref(), true)
+            .map_err(|e| napi::Error::from_reason(format!("Tokenization failed: {}", e)))?;
+        let windows = extra_windows(encoding.get_ids(), MAX_LENGTH, extra);
+        counts.push(windows.len());
+        seqs.extend(windows);
+    }
+    let mut embedded = embed_token_ids(state, &seqs)?.into_iter();
+    Ok(counts
+        .into_iter()
+        .map(|n| embedded.by_ref().take(n).collect())
+        .collect())
+}
+
+/// Replace a symbol's stored extra windows. Callers own the transaction.
+fn replace_windows(
+    conn: &rusqlite::Connection,
+    workspace: &str,
+    file_path: &str,
+    line: i32,
+    windows: &[Vec<f32>],
+) -> napi::Result<()> {
+    conn.prepare_cached(
+        "DELETE FROM symbol_windows WHERE workspace = ? AND file_path = ? AND line = ?",
+    )
+    .and_then(|mut stmt| stmt.execute(rusqlite::params![workspace, file_path, line]))
+    .map_err(|e| napi::Error::from_reason(format!("DB error: {}", e)))?;
+    if windows.is_empty() {
+        return Ok(());
+    }
+    let mut stmt = conn.prepare_cached(
+        "INSERT INTO symbol_windows (workspace, file_path, line, seq, embedding)
+         VALUES (?, ?, ?, ?, ?)",
+    ).map_err(|e| napi::Error::from_reason(format!("DB error: {}", e)))?;
+    for (i, emb) in windows.iter().enumerate() {
+        let bytes: &[u8] = bytemuck::cast_slice(emb.as_slice());
+        stmt.execute(rusqlite::params![workspace, file_path, line, i as i64 + 1, bytes])
+            .map_err(|e| napi::Error::from_reason(format!("DB insert error: {}", e)))?;
+    }
+    Ok(())
+}
+
+/// Replace a symbol's stored token-group vectors; `vectors` empty removes
+/// them. Callers own the transaction.
+fn replace_tokens(
+    conn: &rusqlite::Connection,
+    workspace: &str,
+    file_path: &str,
+    line: i32,
+    vectors: &[f32],
+) -> napi::Result<()> {
+    let result = if vectors.is_empty() {
+        conn.prepare_cached(
+            "DELETE FROM symbol_tokens WHERE workspace = ? AND file_path = ? AND line = ?",
+        )
+        .and_then(|mut stmt| stmt.execute(rusqlite::params![workspace, file_path, line]))
+    } else {
+        let bytes: &[u8] = bytemuck::cast_slice(vectors);
+        conn.prepare_cached(
+            "INSERT OR REPLACE INTO symbol_tokens (workspace, file_path, line, vectors)
+             VALUES (?, ?, ?, ?)",
+        )
+        .and_then(|mut stmt| stmt.execute(rusqlite::params![workspace, file_path, line, bytes]))
+    };
+    result.map_err(|e| napi::Error::from_reason(format!("DB error: {}", e)))?;
+    Ok(())
+}
+
+/// Replace a symbol's sparse vector postings. Callers own the transaction.
+fn replace_sparse(
+    conn: &rusqlite::Connection,
+    workspace: &str,
+    file_path: &str,
+    line: i32,
+    terms: &[(u32, f32)],
+) -> napi::Result<()> {
+    conn.prepare_cached(
+        "DELETE FROM sparse_terms WHERE workspace = ? AND file_path = ? AND line = ?",
+    )
+    .and_then(|mut stmt| stmt.execute(rusqlite::params![workspace, file_path, line]))
+    .map_err(|e| napi::Error::from_reason(format!("DB error: {}", e)))?;
+    if terms.is_empty() {
+        return Ok(());
+    }
+    let mut stmt = conn.prepare_cached(
+        "INSERT INTO sparse_terms (workspace, term, file_path, line, weight) VALUES (?, ?, ?, ?, ?)",
+    ).map_err(|e| napi::Error::from_reason(format!("DB error: {}", e)))?;
+    for (term, weight) in terms {
+        stmt.execute(rusqlite::params![workspace, term, file_path, line, weight])
+            .map_err(|e| napi::Error::from_reason(format!("DB insert error: {}", e)))?;
+    }
+    Ok(())
+}
+
+/// Insert symbols with their precomputed embeddings. Callers own the transaction.
+/// `model` is the name of the model that computed them (`ModelInfo::name`);
+/// `compress_text` is the index's `SearchDB::text_compression`.
+#[allow(clippy::too_many_arguments)]
+fn insert_symbols(
+    conn: &rusqlite::Connection,
+    workspace: &str,
+    model: &str,
+    pq: Option<&pq::Codebook>,
+    compress_text: bool,
+    symbols: &[SymbolInput],
+    embeddings: &[Vec<f32>],
+    doc_embeddings: &[Option<Vec<f32>>],
+    extra: &ExtraEmbeddings,
+) -> napi::Result<()> {
+    let mut stmt = conn.prepare_cached(
+        "INSERT OR REPLACE INTO symbols (workspace, file_path, line, name, kind, language, end_line, signature, embedding_text, doc_comment, symbol_id, metadata, embedding_model, embedding_bits, pq_codes, embedding, doc_embedding)
+         VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)"
+    ).map_err(|e| napi::Error::from_reason(format!("DB error: {}", e)))?;
+
+    for (i, (sym, emb)) in symbols.iter().zip(embeddings.iter()).enumerate() {
+        let embedding_bytes: &[u8] = bytemuck::cast_slice(emb.as_slice());
+        let doc_bytes: Option<&[u8]> = doc_embeddings
+            .get(i)
+            .and_then(|d| d.as_ref())
+            .map(|d| bytemuck::cast_slice(d.as_slice()));
+        stmt.execute(rusqlite::params![
+            workspace,
+            sym.file_path,
+            sym.line,
+            sym.name,
+            sym.kind,
+            sym.language,
+            sym.end_line,
+            sym.signature.as_deref().map(|s| db::pack_text(s, compress_text)),
+            db::pack_text(&sym.embedding_text, compress_text),
+            sym.doc_comment,
+            db::symbol_id(&sym.file_path, &sym.name, &sym.kind, sym.signature.as_deref()),
+            sym.metadata,
+            model,
+            db::binarize(emb),
+            pq.map(|pq| pq.encode(emb)),
+            embedding_bytes,
+            doc_bytes
+        ]).map_err(|e| napi::Error::from_reason(format!("DB insert error: {}", e)))?;
+        db::insert_name_trigrams(conn, &sym.name)
+            .map_err(|e| napi::Error::from_reason(format!("DB insert error: {}", e)))?;
+        let sym_windows = extra.windows.get(i).map_or(&[][..], |w| w.as_slice());
+        replace_windows(conn, workspace, &sym.file_path, sym.line, sym_windows)?;
+        let sym_tokens = extra.tokens.get(i).map_or(&[][..], |t| t.as_slice());
+        replace_tokens(conn, workspace, &sym.file_path, sym.line, sym_tokens)?;
+        let sym_sparse = extra.sparse.get(i).map_or(&[][..], |t| t.as_slice());
+        replace_sparse(conn, workspace, &sym.file_path, sym.line, sym_sparse)?;
+    }
+    Ok(())
+}
+
+/// Embed and insert symbols in a single call.
+/// Embeddings never cross the napi boundary.
+/// Wraps all inserts in a transaction for performance.
+/// Symbols with an empty `embedding_text` get it from the index's template.
+#[napi(catch_unwind)]
+pub fn index_symbols(symbols: Vec<SymbolInput>) -> napi::Result<()> {
+    let workspace = with_state(|state| Ok(get_db(state)?.workspace().to_string()))?;
+    index_symbols_into(&workspace, FileChanges::default(), symbols, false)
+}
+
+/// `index_symbols` taking one Buffer in the layout documented in
+/// `wire.rs`, instead of an array of objects: much less marshalling for
+/// large batches.
+#[napi(catch_unwind)]
+pub fn index_symbols_buffer(symbols: Buffer) -> napi::Result<()> {
+    let symbols = decode_symbols(&symbols)
+        .map_err(|e| napi::Error::from_reason(format!("Invalid symbol buffer: {}", e)))?;
+    let workspace = with_state(|state| Ok(get_db(state)?.workspace().to_string()))?;
+    index_symbols_into(&workspace, FileChanges::default(), symbols, false)
+}
+
+/// Decode a `wire` symbol batch.
+fn decode_symbols(buf: &[u8]) -> Result<Vec<SymbolInput>, String> {
+    // Smallest encoding: five empty strings, two lines, two nulls
+    const MIN_SYMBOL_BYTES: usize = 36;
+    let mut r = wire::Reader::new(buf);
+    let count = r.u32()? as usize;
+    let mut symbols = Vec::with_capacity(count.min(buf.len() / MIN_SYMBOL_BYTES));
+    for _ in 0..count {
+        symbols.push(SymbolInput {
+            embedding_text: r.str()?,
+            file_path: r.str()?,
+            name: r.str()?,
+            kind: r.str()?,
+            language: r.str()?,
+            line: r.i32()?,
+            end_line: Some(r.i32()?).filter(|&l| l >= 0),
+            signature: r.opt_str()?,
+            doc_comment: r.opt_str()?,
+            metadata: None,
+        });
+    }
+    r.finish()?;
+    Ok(symbols)
+}
+
+#[napi(object)]
+pub struct IndexBatch {
+    /// Files to remove with their symbols and chunks, as `delete_files`
+    pub deletes: Option<Vec<String>>,
+    /// File records to write, as `upsert_files`
+    pub file_upserts: Option<Vec<FileInput>>,
+    /// Symbols to embed and insert, as `index_symbols`
+    pub symbols: Option<Vec<SymbolInput>>,
+}
+
+/// Apply `delete_files`, `upsert_files`, and `index_symbols` in one
+/// transaction, in that order. Symbols are embedded before anything is
+/// written, so a failed embedding leaves the index untouched rather than
+/// with files recorded as fresh but missing their symbols.
+#[napi(catch_unwind)]
+pub fn apply_index_batch(batch: IndexBatch) -> napi::Result<()> {
+    let workspace = with_state(|state| Ok(get_db(state)?.workspace().to_string()))?;
+    let files = FileChanges {
+        deletes: batch.deletes.unwrap_or_default(),
+        upserts: batch.file_upserts.unwrap_or_default(),
+    };
+    index_symbols_into(&workspace, files, batch.symbols.unwrap_or_default(), false)
+}
+
+/// File records written in the same transaction as an `index_symbols_into`
+/// batch's symbols.
+#[derive(Default)]
+struct FileChanges {
+    deletes: Vec<String>,
+    upserts: Vec<FileInput>,
+}
+
+/// Prepare, embed, and insert `symbols` into `workspace`, after applying
+/// `files` in the same transaction. The model runs outside the state lock's
+/// first and last holds, through `embed_joint`, so the texts can share
+/// forward passes with a concurrent search. `background` selects the index
+/// queue worker's lock and device.
+fn index_symbols_into(
+    workspace: &str,
+    files: FileChanges,
+    mut symbols: Vec<SymbolInput>,
+    background: bool,
+) -> napi::Result<()> {
+    if symbols.is_empty() && files.deletes.is_empty() && files.upserts.is_empty() {
+        return Ok(());
+    }
+    let locked = |f: &mut dyn FnMut(&mut State) -> napi::Result<()>| {
+        if background {
+            with_state_background(|state| f(state))
+        } else {
+            with_state(|state| f(state))
+        }
+    };
+
+    let start = std::time::Instant::now();
+    let mut texts = Vec::new();
+    let mut epoch = 0;
+    locked(&mut |state| {
+        check_index_scope(get_db(state)?, files.upserts.iter().map(|f| f.path.as_str()))?;
+        prepare_symbols(state, &mut symbols)?;
+        texts = document_texts(state, symbol_texts(&symbols))?;
+        epoch = state.db_epoch;
+        Ok(())
+    })?;
+    let all = embed_joint(texts, background)?;
+    let (embeddings, doc_embeddings) = split_symbol_embeddings(&symbols, all);
+
+    locked(&mut |state| {
+        check_db_epoch(state, epoch)?;
+        let extra = if background {
+            state.on_background_device(|state| embed_extra(state, &symbols))?
+        } else {
+            embed_extra(state, &symbols)?
+        };
+        let model = state.info.name.clone();
+        let db = get_db(state)?;
+        let pq = db.pq_codebook();
+        let compress = db.text_compression();
+        let tx = db.transaction()
+            .map_err(|e| napi::Error::from_reason(format!("DB error: {}", e)))?;
+        for path in &files.deletes {
+            delete_file_rows(&tx, workspace, path)?;
+        }
+        let now = db::now_millis();
+        for f in &files.upserts {
+            upsert_file_row(&tx, workspace, f, now)?;
+        }
+        insert_symbols(&tx, workspace, &model, pq.as_deref(), compress, &symbols, &embeddings, &doc_embeddings, &extra)?;
+        tx.commit()
+            .map_err(|e| napi::Error::from_reason(format!("DB error: {}", e)))?;
+        tracing::debug!(
+            "indexed {} symbols ({} files deleted, {} upserted) in {:.1}ms",
+            symbols.len(),
+            files.deletes.len(),
+            files.upserts.len(),
+            start.elapsed().as_secs_f64() * 1000.0
+        );
+        if symbols.len() >= ANALYZE_MIN_ROWS {
+            db.analyze()
+                .map_err(|e| napi::Error::from_reason(format!("DB error: {}", e)))?;
+        }
+        enforce_budget(db)?;
+        Ok(())
+    })
+}
+
+/// Check `symbols` against the index scope, then `normalize_symbols`.
+fn prepare_symbols(state: &mut State, symbols: &mut [SymbolInput]) -> napi::Result<()> {
+    check_index_scope(get_db(state)?, symbols.iter().map(|s| s.file_path.as_str()))?;
+    normalize_symbols(state, symbols)
+}
+
+/// Normalize kinds and languages, fill empty embedding texts from the
+/// index's template, and append doc comments to supplied ones.
+fn normalize_symbols(state: &mut State, symbols: &mut [SymbolInput]) -> napi::Result<()> {
+    for s in symbols.iter_mut() {
+        s.kind = kind::normalize(&s.kind);
+        s.language = lang::normalize_language(&s.language);
+        if let Some(m) = &s.metadata {
+            if !matches!(serde_json::from_str(m), Ok(serde_json::Value::Object(_))) {
+                return Err(napi::Error::from_reason(format!(
+                    "Invalid metadata for {}:{}: expected a JSON object",
+                    s.file_path, s.line
+                )));
+            }
+        }
+    }
+
+    for s in symbols.iter_mut().filter(|s| !s.embedding_text.is_empty()) {
+        template::append_doc(&mut s.embedding_text, s.doc_comment.as_deref());
+    }
+    if symbols.iter().any(|s| s.embedding_text.is_empty()) {
+        let template = embedding_template(get_db(state)?)?;
+        for s in symbols.iter_mut().filter(|s| s.embedding_text.is_empty()) {
+            s.embedding_text = template::render(
+                &template,
+                &template::TemplateFields {
+                    language: &s.language,
+                    path: &s.file_path,
+                    name: &s.name,
+                    kind: &s.kind,
+                    signature: s.signature.as_deref(),
+                    doc: s.doc_comment.as_deref(),
+                },
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Refresh SQLite's query planner statistics. Runs automatically after
+/// large `index_symbols`/`index_files` batches; call it after many small ones.
+#[napi(catch_unwind)]
+pub fn analyze_index() -> napi::Result<()> {
+    with_state(|state| {
+        get_db(state)?
+            .analyze()
+            .map_err(|e| napi::Error::from_reason(format!("DB error: {}", e)))
+    })
+}
+
+// ── Index sessions ─────────────────────────────────────────────────────
+
+/// Symbols embedded and written per batch while an index session is open.
+const INDEX_SESSION_BATCH_SIZE: usize = 256;
+
+/// An ingestion started by `begin_index_session`. Its rows are written into
+/// the DB's open ingest transaction (see `SearchDB::begin_ingest`).
+struct IndexSession {
+    workspace: String,
+    /// Pushed symbols not yet embedded, fewer than a batch
+    pending: Vec<SymbolInput>,
+    written: u64,
+}
+
+/// Start streaming symbols into the index with `push_symbols`, for repos
+/// too large to pass to `index_symbols` at once. Everything pushed is
+/// written in one transaction: `commit_session` makes it visible,
+/// `abort_session` (or any failed push or commit) discards all of it.
+/// Until then other writes to the index fail, and searches in this process
+/// already see the symbols written so far. Symbols go to the workspace
+/// active now.
+#[napi(catch_unwind)]
+pub fn begin_index_session() -> napi::Result<()> {
+    with_state(|state| {
+        if state.index_session.is_some() {
+            return Err(napi::Error::from_reason(
+                "An index session is already open. Commit or abort it first.",
+            ));
+        }
+        let db = get_db(state)?;
+        db.begin_ingest()
+            .map_err(|e| napi::Error::from_reason(format!("DB error: {}", e)))?;
+        state.index_session = Some(IndexSession {
+            workspace: db.workspace().to_string(),
+            pending: Vec::new(),
+            written: 0,
+        });
+        Ok(())
+    })
+}
+
+/// Add symbols to the open index session. They are embedded and written in
+/// batches of 256 as they fill, so the call returns once every full batch
+/// is written; awaiting it between chunks keeps memory bounded. Returns
+/// the number of symbols written so far.
+#[napi(catch_unwind)]
+pub fn push_symbols(symbols: Vec<SymbolInput>) -> napi::Result<f64> {
+    with_state(|state| {
+        with_index_session(state, |state, session| {
+            session.pending.extend(symbols);
+            while session.pending.len() >= INDEX_SESSION_BATCH_SIZE {
+                let rest = session.pending.split_off(INDEX_SESSION_BATCH_SIZE);
+                let batch = std::mem::replace(&mut session.pending, rest);
+                write_session_batch(state, session, batch)?;
+            }
+            Ok(session.written as f64)
+        })
+    })
+}
+
+/// Write the session's remaining symbols and commit it. Returns the number
+/// of symbols written.
+#[napi(catch_unwind)]
+pub fn commit_session() -> napi::Result<f64> {
+    with_state(|state| {
+        let written = with_index_session(state, |state, session| {
+            let batch = std::mem::take(&mut session.pending);
+            write_session_batch(state, session, batch)?;
+            Ok(session.written)
+        })?;
+        state.index_session = None;
+        let db = get_db(state)?;
+        db.end_ingest(true)
+            .map_err(|e| napi::Error::from_reason(format!("DB error: {}", e)))?;
+        if written as usize >= ANALYZE_MIN_ROWS {
+            db.analyze()
+                .map_err(|e| napi::Error::from_reason(format!("DB error: {}", e)))?;
+        }
+        enforce_budget(db)?;
+        Ok(written as f64)
+    })
+}
+
+/// Roll back the open index session. Returns false if none was open.
+#[napi(catch_unwind)]
+pub fn abort_session() -> napi::Result<bool> {
+    with_state(|state| {
+        if state.index_session.take().is_none() {
+            return Ok(false);
+        }
+        get_db(state)?
+            .end_ingest(false)
+            .map_err(|e| napi::Error::from_reason(format!("DB error: {}", e)))?;
+        Ok(true)
+    })
+}
+
+/// Run `f` on the open index session, rolling the session back if it fails.
+fn with_index_session<T>(
+    state: &mut State,
+    f: impl FnOnce(&mut State, &mut IndexSession) -> napi::Result<T>,
+) -> napi::Result<T> {
+    let mut session = state.index_session.take().ok_or_else(|| {
+        napi::Error::from_reason("No index session is open. Call begin_index_session first.")
+    })?;
+    match f(state, &mut session) {
+        Ok(out) => {
+            state.index_session = Some(session);
+            Ok(out)
+        }
+        Err(e) => {
+            if let Some(db) = state.db.as_mut() {
+                let _ = db.end_ingest(false);
+            }
+            Err(e)
+        }
+    }
+}
+
+/// Embed `batch` and write it into the session's transaction.
+fn write_session_batch(
+    state: &mut State,
+    session: &mut IndexSession,
+    mut batch: Vec<SymbolInput>,
+) -> napi::Result<()> {
+    if batch.is_empty() {
+        return Ok(());
+    }
+    prepare_symbols(state, &mut batch)?;
+    let (embeddings, doc_embeddings, extra) = embed_symbols(state, &batch)?;
+    let model = state.info.name.clone();
+    let db = get_db(state)?;
+    let pq = db.pq_codebook();
+    let compress = db.text_compression();
+    let conn = db
+        .ingest_conn()
+        .ok_or_else(|| napi::Error::from_reason("The index session's transaction is gone"))?;
+    insert_symbols(conn, &session.workspace, &model, pq.as_deref(), compress, &batch, &embeddings, &doc_embeddings, &extra)?;
+    session.written += batch.len() as u64;
+    Ok(())
+}
+
+// ── Background indexing ────────────────────────────────────────────────
+
+/// Symbols per queued batch; the worker holds the index for one batch at a
+/// time.
+const QUEUE_BATCH_SIZE: usize = 64;
+
+/// Symbols queued by `enqueue_index`, for the workspace active then.
+struct IndexJob {
+    workspace: String,
+    symbols: Vec<SymbolInput>,
+}
+
+static INDEX_QUEUE: std::sync::OnceLock<queue::WorkQueue<IndexJob>> = std::sync::OnceLock::new();
+
+fn index_queue() -> &'static queue::WorkQueue<IndexJob> {
+    INDEX_QUEUE.get_or_init(|| {
+        queue::WorkQueue::new(|job: IndexJob| {
+            index_symbols_into(&job.workspace, FileChanges::default(), job.symbols, true).map_err(|e| e.reason)
+        })
+    })
+}
+
+fn clear_index_queue() {
+    if let Some(q) = INDEX_QUEUE.get() {
+        q.clear();
+    }
+}
+
+#[napi(object)]
+pub struct JsIndexQueueStatus {
+    /// Batches waiting, of up to 64 symbols each
+    pub pending_batches: u32,
+    pub paused: bool,
+    /// A batch is being embedded right now
+    pub running: bool,
+    pub completed_batches: f64,
+    pub failed_batches: f64,
+    pub last_error: Option<String>,
+}
+
+/// Queue symbols for indexing on a background thread, like `index_symbols`
+/// but without blocking. Higher `priority` batches (default 0) run first,
+/// FIFO within a priority. The worker runs one batch at a time, only while
+/// no other call is in progress, so searches during a large re-index wait
+/// for at most one batch. Symbols go to the workspace active now.
+#[napi(catch_unwind)]
+pub fn enqueue_index(symbols: Vec<SymbolInput>, priority: Option<i32>) -> napi::Result<()> {
+    let workspace = with_state(|state| Ok(get_db(state)?.workspace().to_string()))?;
+    let queue = index_queue();
+    let mut symbols = symbols.into_iter().peekable();
+    while symbols.peek().is_some() {
+        queue.push(
+            IndexJob {
+                workspace: workspace.clone(),
+                symbols: symbols.by_ref().take(QUEUE_BATCH_SIZE).collect(),
+            },
+            priority.unwrap_or(0),
+        );
+    }
+    Ok(())
+}
+
+/// Stop the background worker after its current batch. Queued batches wait
+/// until `resume_index_queue`.
+#[napi(catch_unwind)]
+pub fn pause_index_queue() {
+    index_queue().set_paused(true);
+}
+
+#[napi(catch_unwind)]
+pub fn resume_index_queue() {
+    index_queue().set_paused(false);
+}
+
+#[napi(catch_unwind)]
+pub fn get_index_queue_status() -> JsIndexQueueStatus {
+    let status = index_queue().status();
+    JsIndexQueueStatus {
+        pending_batches: status.pending as u32,
+        paused: status.paused,
+        running: status.running,
+        completed_batches: status.completed as f64,
+        failed_batches: status.failed as f64,
+        last_error: status.last_error,
+    }
+}
+
+/// Embed and store content chunks for one file, replacing its previous chunks.
+///
+/// For files without extractable symbols (markdown, configs, ...). Chunks are
+/// searched alongside symbols when `filters.include_chunks` is set.
+#[napi(catch_unwind)]
+pub fn index_chunks(
+    file_path: String,
+    chunks: Vec<ChunkInput>,
+    language: Option<String>,
+) -> napi::Result<()> {
+    with_state(|state| {
+        let texts: Vec<String> = chunks.iter().map(|c| c.text.clone()).collect();
+        let embeddings = if texts.is_empty() {
+            Vec::new()
+        } else {
+            embed_internal(state, &texts, false)?
+        };
+
+        let rows: Vec<(&str, i32, i32)> = chunks
+            .iter()
+            .map(|c| (c.text.as_str(), c.start_line, c.end_line))
+            .collect();
+        let db = get_db(state)?;
+        db.replace_chunks(
+            &file_path,
+            &lang::normalize_language(language.as_deref().unwrap_or("text")),
+            &rows,
+            &embeddings,
+        )
+        .map_err(|e| napi::Error::from_reason(format!("DB error: {}", e)))?;
+        enforce_budget(db)?;
+        Ok(())
+    })
+}
+
+/// The index's embedding text template, or the default if never set.
+fn embedding_template(db: &SearchDB) -> napi::Result<String> {
+    Ok(db
+        .get_meta(template::META_KEY)
+        .map_err(|e| napi::Error::from_reason(format!("DB error: {}", e)))?
+        .unwrap_or_else(|| template::DEFAULT_TEMPLATE.to_string()))
+}
+
+/// Set the embedding text template for the open index. Placeholders:
+/// `{language}`, `{path}`, `{name}`, `{kind}`, `{signature}` (name when
+/// absent), `{doc}`. Only affects symbols indexed afterwards — reindex to
+/// bring existing rows in line.
+#[napi(catch_unwind)]
+pub fn set_embedding_template(template: String) -> napi::Result<()> {
+    template::validate(&template).map_err(napi::Error::from_reason)?;
+    with_state(|state| {
+        get_db(state)?
+            .set_meta(template::META_KEY, &template)
+            .map_err(|e| napi::Error::from_reason(format!("DB error: {}", e)))
+    })
+}
+
+/// The open index's embedding text template.
+#[napi(catch_unwind)]
+pub fn get_embedding_template() -> napi::Result<String> {
+    with_state(|state| embedding_template(get_db(state)?))
+}
+
+/// Set which tokens of long texts the open index embeds (the model sees at
+/// most 128): `"head"` (the default), `"tail"`, `"head_tail"` (half from each
+/// end), or `"middle_out"` (the middle). Applies to symbols embedded from
+/// now on; `reembed_all` brings existing rows in line.
+#[napi(catch_unwind)]
+pub fn set_truncation(strategy: String) -> napi::Result<()> {
+    let strategy = Truncation::parse(&strategy)?;
+    with_state(|state| {
+        get_db(state)?
+            .set_meta(TRUNCATION_META, strategy.as_str())
+            .map_err(|e| napi::Error::from_reason(format!("DB error: {}", e)))?;
+        state.query_cache.clear();
+        Ok(())
+    })
+}
+
+/// The open index's truncation strategy.
+#[napi(catch_unwind)]
+pub fn get_truncation() -> napi::Result<String> {
+    with_state(|state| Ok(index_truncation(state)?.as_str().to_string()))
+}
+
+/// Split code identifiers into words before tokenizing embedding texts and
+/// queries, since WordPiece cuts `getHTTPRequestRateLimiter` into pieces
+/// unrelated to its words. `splits` picks any of `"camel"` (camelCase),
+/// `"snake"` (snake_case) and `"path"` (`a/b.rs`, `a::b`); empty turns it
+/// off (the default). Stored texts are unchanged. Applies to symbols
+/// embedded from now on; `reembed_all` brings existing rows in line.
+#[napi(catch_unwind)]
+pub fn set_identifier_splitting(splits: Vec<String>) -> napi::Result<()> {
+    let splitting =
+        identifiers::Splitting::parse(&splits.join(",")).map_err(napi::Error::from_reason)?;
+    with_state(|state| {
+        get_db(state)?
+            .set_meta(IDENTIFIER_SPLIT_META, &splitting.as_str())
+            .map_err(|e| napi::Error::from_reason(format!("DB error: {}", e)))?;
+        state.query_cache.clear();
+        Ok(())
+    })
+}
+
+/// The open index's identifier splits (empty when off).
+#[napi(catch_unwind)]
+pub fn get_identifier_splitting() -> napi::Result<Vec<String>> {
+    with_state(|state| {
+        let splitting = index_splitting(state)?.as_str();
+        Ok(splitting.split(',').filter(|s| !s.is_empty()).map(str::to_string).collect())
+    })
+}
+
+/// Strip license headers, generated-code banners, and import blocks from
+/// embedding texts before embedding, so boilerplate doesn't dominate the
+/// vectors of file-level symbols. Off by default; queries and stored texts
+/// are unchanged. Applies to symbols embedded from now on; `reembed_all`
+/// brings existing rows in line.
+#[napi(catch_unwind)]
+pub fn set_boilerplate_stripping(enabled: bool) -> napi::Result<()> {
+    with_state(|state| {
+        get_db(state)?
+            .set_meta(STRIP_BOILERPLATE_META, if enabled { "1" } else { "0" })
+            .map_err(|e| napi::Error::from_reason(format!("DB error: {}", e)))
+    })
+}
+
+/// Whether the open index strips boilerplate before embedding.
+#[napi(catch_unwind)]
+pub fn get_boilerplate_stripping() -> napi::Result<bool> {
+    with_state(|state| index_strips_boilerplate(state))
+}
+
+/// Embed symbols longer than one model input (128 tokens) as up to
+/// `max_windows` overlapping windows instead of one, so text past the first
+/// window is searchable too. A symbol scores as its best window. 1 (the
+/// default) turns windows off. Applies to symbols embedded from now on;
+/// `reembed_all` brings existing rows in line.
+#[napi(catch_unwind)]
+pub fn set_sliding_windows(max_windows: u32) -> napi::Result<()> {
+    if max_windows == 0 || max_windows > MAX_WINDOWS_LIMIT {
+        return Err(napi::Error::from_reason(format!(
+            "max_windows must be between 1 and {}",
+            MAX_WINDOWS_LIMIT
+        )));
+    }
+    with_state(|state| {
+        get_db(state)?
+            .set_meta(MAX_WINDOWS_META, &max_windows.to_string())
+            .map_err(|e| napi::Error::from_reason(format!("DB error: {}", e)))
+    })
+}
+
+/// The open index's `max_windows` (1 when windows are off).
+#[napi(catch_unwind)]
+pub fn get_sliding_windows() -> napi::Result<u32> {
+    with_state(|state| Ok(index_max_windows(state)? as u32))
+}
+
+/// Experimental: also store each symbol's token vectors, mean-pooled over
+/// runs of `group_size` tokens (1 for ColBERT-style per-token vectors),
+/// and have `search` rerank its dense candidates by MaxSim late
+/// interaction: each query token's best match in the symbol, averaged.
+/// Helps recall on long functions at a large cost in index size, roughly
+/// `128 / group_size` extra embeddings per symbol. 0 (the default) turns it
+/// off. Applies to symbols embedded from now on; `reembed_all` brings
+/// existing rows in line, and symbols without token vectors keep their
+/// dense score.
+#[napi(catch_unwind)]
+pub fn set_late_interaction(group_size: u32) -> napi::Result<()> {
+    if group_size > LATE_INTERACTION_GROUP_LIMIT {
+        return Err(napi::Error::from_reason(format!(
+            "group_size must be between 0 and {}",
+            LATE_INTERACTION_GROUP_LIMIT
+        )));
+    }
+    with_state(|state| {
+        get_db(state)?
+            .set_meta(LATE_INTERACTION_META, &group_size.to_string())
+            .map_err(|e| napi::Error::from_reason(format!("DB error: {}", e)))
+    })
+}
+
+/// The open index's late interaction `group_size` (0 when off).
+#[napi(catch_unwind)]
+pub fn get_late_interaction() -> napi::Result<u32> {
+    with_state(|state| Ok(late_interaction_group(get_db(state)?)? as u32))
+}
+
+/// Also store a sparse lexical vector per symbol: log-scaled term
+/// frequencies over the tokenizer's vocabulary (no learned expansion, so
+/// only terms the symbol contains), kept as postings so searches with
+/// `options.sparse_weight` can fuse exact-term matches (weighted toward
+/// rare terms) with dense similarity. A middle ground between full-text
+/// search and embeddings that helps rare identifiers. Off by default.
+/// Applies to symbols embedded from now on; `reembed_all` brings existing
+/// rows in line.
+#[napi(catch_unwind)]
+pub fn set_sparse_vectors(enabled: bool) -> napi::Result<()> {
+    with_state(|state| {
+        get_db(state)?
+            .set_meta(SPARSE_META, if enabled { "1" } else { "0" })
+            .map_err(|e| napi::Error::from_reason(format!("DB error: {}", e)))
+    })
+}
+
+/// Whether the open index stores sparse vectors.
+#[napi(catch_unwind)]
+pub fn get_sparse_vectors() -> napi::Result<bool> {
+    with_state(|state| sparse_enabled(get_db(state)?))
+}
+
+/// Extract symbols from one file as `SymbolInput`s. None when there is no
+/// grammar for `language` (expected lowercase).
+fn extract_file(
+    path: &str,
+    source: &str,
+    language: &str,
+    template: &str,
+) -> Option<Vec<SymbolInput>> {
+    let symbols = extract::extract_symbols(source, language)?;
+    let language = &lang::normalize_language(language);
+    Some(
+        symbols
+            .into_iter()
+            .map(|s| SymbolInput {
+                embedding_text: template::render(
+                    template,
+                    &template::TemplateFields {
+                        language,
+                        path,
+                        name: &s.name,
+                        kind: s.kind,
+                        signature: s.signature.as_deref(),
+                        doc: s.doc_comment.as_deref(),
+                    },
+                ),
+                file_path: path.to_string(),
+                name: s.name,
+                kind: s.kind.to_string(),
+                language: language.to_string(),
+                line: s.line,
+                end_line: Some(s.end_line),
+                signature: s.signature,
+                doc_comment: s.doc_comment,
+                metadata: None,
+            })
+            .collect(),
+    )
+}
+
+/// Parse `source` with the tree-sitter grammar for `language` and return its
+/// indexable symbols, ready for `index_symbols`.
+///
+/// `path` is the repo-relative path stored with each symbol. The embedding
+/// text follows the open index's template (see `set_embedding_template`),
+/// or the default `"{language} | {path} | {signature}"` when no DB is open.
+#[napi(catch_unwind)]
+pub fn extract_symbols(
+    path: String,
+    source: String,
+    language: String,
+) -> napi::Result<Vec<SymbolInput>> {
+    let language = language.to_lowercase();
+    let template = match lock_state()?.as_ref().and_then(|state| state.db.as_ref()) {
+        Some(db) => embedding_template(db)?,
+        None => template::DEFAULT_TEMPLATE.to_string(),
+    };
+    extract_file(&path, &source, &language, &template).ok_or_else(|| {
+        napi::Error::from_reason(format!(
+            "No grammar for language '{}'. Supported: {}",
+            language,
+            extract::supported_languages().join(", ")
+        ))
+    })
+}
+
+/// Leading slice of `content` for shebang/modeline detection, cut on a char
+/// boundary.
+fn content_sample(content: &str) -> &str {
+    let mut end = content.len().min(512);
+    while !content.is_char_boundary(end) {
+        end -= 1;
+    }
+    &content[..end]
+}
+
+/// Guess a file's language from its extension or name, falling back to a
+/// shebang or vim modeline in `content_sample`. Names match the lowercase
+/// names used for the `language` filter ("go", "typescript", "c#", ...).
+#[napi(catch_unwind)]
+pub fn detect_language(path: String, content_sample: Option<String>) -> Option<String> {
+    lang::detect_language(&path, content_sample.as_deref().unwrap_or("")).map(str::to_string)
+}
+
+#[napi(object)]
+pub struct FileSpec {
+    /// Repo-relative path
+    pub path: String,
+    pub content: String,
+    pub language: Option<String>,
+    pub hash: String,
+}
+
+#[napi(object)]
+pub struct JsIndexFilesResult {
+    pub files_indexed: f64,
+    pub symbols_indexed: f64,
+    /// Files recorded with no symbols because no grammar matched their language
+    pub files_unsupported: f64,
+}
+
+/// Extract, embed, and store a batch of files in one call.
+///
+/// Replaces each file's previous symbols and chunks, then upserts its file
+/// record with the new hash and symbol count, all in one transaction. Files
+/// without a `language` get one from `detect_language`. Files without a
+/// grammar are still recorded (0 symbols) so incremental indexing skips them
+/// until their hash changes. Extraction runs without holding the index;
+/// the batch is then written like `apply_index_batch`'s.
+#[napi(catch_unwind)]
+pub fn index_files(specs: Vec<FileSpec>) -> napi::Result<JsIndexFilesResult> {
+    let (workspace, template) = with_state(|state| {
+        let db = get_db(state)?;
+        Ok((db.workspace().to_string(), embedding_template(db)?))
+    })?;
+    let mut symbols: Vec<SymbolInput> = Vec::new();
+    let mut upserts: Vec<FileInput> = Vec::with_capacity(specs.len());
+    let mut files_unsupported = 0;
+
+    for spec in &specs {
+        let language = match &spec.language {
+            Some(lang) => Some(lang::grammar_language(lang)),
+            None => lang::detect_language(&spec.path, content_sample(&spec.content))
+                .map(str::to_string),
+        };
+        let extracted = language
+            .as_deref()
+            .and_then(|lang| extract_file(&spec.path, &spec.content, lang, &template));
+        let symbol_count = match extracted {
+            Some(syms) => {
+                let n = syms.len() as i32;
+                symbols.extend(syms);
+                n
+            }
+            None => {
+                files_unsupported += 1;
+                0
+            }
+        };
+        upserts.push(FileInput {
+            path: spec.path.clone(),
+            hash: spec.hash.clone(),
+            language,
+            symbol_count,
+        });
+    }
+
+    let result = JsIndexFilesResult {
+        files_indexed: upserts.len() as f64,
+        symbols_indexed: symbols.len() as f64,
+        files_unsupported: files_unsupported as f64,
+    };
+    let files = FileChanges {
+        deletes: specs.into_iter().map(|f| f.path).collect(),
+        upserts,
+    };
+    index_symbols_into(&workspace, files, symbols, false)?;
+    Ok(result)
+}
+
+#[napi(object)]
+pub struct JsPlannedFile {
+    pub path: String,
+    /// Git blob id of the current content; pass as `hash` to `index_files`
+    pub hash: String,
+}
+
+#[napi(object)]
+pub struct JsReindexPlan {
+    pub added: Vec<JsPlannedFile>,
+    pub updated: Vec<JsPlannedFile>,
+    pub deleted: Vec<String>,
+}
+
+#[napi(object)]
+pub struct ScopeOptions {
+    /// Extra exclude patterns (gitignore syntax), applied on top of
+    /// `.gitignore`/`.piignore` files
+    pub exclude: Option<Vec<String>>,
+}
+
+/// Compare the worktree under `repo_root` against the stored file hashes and
+/// return which files need indexing or removal.
+///
+/// Uses git blob ids as hashes, so only dirty or untracked files are read.
+/// Only files `should_index` accepts and the index scope (see
+/// `set_index_scope`) includes are planned; indexed files that fell out of
+/// scope are listed as deleted. Files must be indexed with the returned
+/// hash for the next plan to see them as unchanged. Paths are relative to
+/// `repo_root`.
+#[napi(catch_unwind)]
+pub fn plan_reindex(
+    repo_root: String,
+    options: Option<ScopeOptions>,
+) -> napi::Result<JsReindexPlan> {
+    let root = std::path::Path::new(&repo_root);
+    let exclude = options.and_then(|o| o.exclude).unwrap_or_default();
+    let mut scope = scope::IndexScope::new(root, &exclude).map_err(napi::Error::from_reason)?;
+    let mut current = git::worktree_hashes(root).map_err(napi::Error::from_reason)?;
+    current.retain(|path, _| scope.should_index(path));
+
+    with_state(|state| {
+        let db = get_db(state)?;
+        let includes = index_includes(db)?;
+        current.retain(|path, _| includes.contains(path));
+        let stored: HashMap<String, String> = db
+            .get_all_files()
+            .map_err(|e| napi::Error::from_reason(format!("DB error: {}", e)))?
+            .into_iter()
+            .map(|f| (f.path, f.hash))
+            .collect();
+
+        let mut plan = JsReindexPlan { added: Vec::new(), updated: Vec::new(), deleted: Vec::new() };
+        for (path, hash) in &current {
+            match stored.get(path) {
+                None => plan.added.push(JsPlannedFile { path: path.clone(), hash: hash.clone() }),
+                Some(old) if old != hash => {
+                    plan.updated.push(JsPlannedFile { path: path.clone(), hash: hash.clone() })
+                }
+                Some(_) => {}
+            }
+        }
+        plan.deleted = stored
+            .into_keys()
+            .filter(|path| !current.contains_key(path))
+            .collect();
+
+        plan.added.sort_by(|a, b| a.path.cmp(&b.path));
+        plan.updated.sort_by(|a, b| a.path.cmp(&b.path));
+        plan.deleted.sort();
+        Ok(plan)
+    })
+}
+
+/// Whether `path` (relative to `repo_root`) is in the index scope: not under
+/// a built-in skipped directory (node_modules, target, ...), not a generated
+/// file, and not excluded by `.gitignore`/`.piignore` or `options.exclude`.
+/// `watch` and `plan_reindex` apply the same rules.
+#[napi(catch_unwind)]
+pub fn should_index(
+    repo_root: String,
+    path: String,
+    options: Option<ScopeOptions>,
+) -> napi::Result<bool> {
+    let exclude = options.and_then(|o| o.exclude).unwrap_or_default();
+    let mut scope = scope::IndexScope::new(std::path::Path::new(&repo_root), &exclude)
+        .map_err(napi::Error::from_reason)?;
+    Ok(scope.should_index(&path))
+}
+
+const INDEX_SCOPE_META: &str = "index_scope";
+
+/// The open index's include patterns (see `set_index_scope`); empty when
+/// the whole repo is in scope.
+fn index_scope_patterns(db: &SearchDB) -> napi::Result<Vec<String>> {
+    match db
+        .get_meta(INDEX_SCOPE_META)
+        .map_err(|e| napi::Error::from_reason(format!("DB error: {}", e)))?
+    {
+        Some(json) => serde_json::from_str(&json).map_err(|e| {
+            napi::Error::from_reason(format!("Invalid index scope in meta: {}", e))
+        }),
+        None => Ok(Vec::new()),
+    }
+}
+
+fn index_includes(db: &SearchDB) -> napi::Result<scope::Includes> {
+    scope::Includes::new(&index_scope_patterns(db)?).map_err(napi::Error::from_reason)
+}
+
+/// Fail on the first of `paths` outside the open index's scope.
+fn check_index_scope<'a>(
+    db: &SearchDB,
+    paths: impl IntoIterator<Item = &'a str>,
+) -> napi::Result<()> {
+    let patterns = index_scope_patterns(db)?;
+    if patterns.is_empty() {
+        return Ok(());
+    }
+    let includes = scope::Includes::new(&patterns).map_err(napi::Error::from_reason)?;
+    match paths.into_iter().find(|p| !includes.contains(p)) {
+        Some(path) => Err(napi::Error::from_reason(format!(
+            "{} is outside the index scope ({})",
+            path,
+            patterns.join(", ")
+        ))),
+        None => Ok(()),
+    }
+}
+
+/// Limit the open index to paths matching `include` (gitignore syntax,
+/// relative to the repo root, e.g. `["services/payments/", "libs/auth/"]`):
+/// `index_symbols`, `index_files`, `upsert_files` and index sessions then
+/// reject paths outside it, and `plan_reindex` skips them. An empty list
+/// puts the whole repo back in scope. Already indexed files outside the new
+/// scope stay until deleted; `get_unindexed_scope_report` lists them.
+#[napi(catch_unwind)]
+pub fn set_index_scope(include: Vec<String>) -> napi::Result<()> {
+    scope::Includes::new(&include).map_err(napi::Error::from_reason)?;
+    with_state(|state| {
+        let db = get_db(state)?;
+        if include.is_empty() {
+            return db
+                .delete_meta(INDEX_SCOPE_META)
+                .map_err(|e| napi::Error::from_reason(format!("DB error: {}", e)));
+        }
+        let json = serde_json::to_string(&include)
+            .map_err(|e| napi::Error::from_reason(format!("Failed to encode scope: {}", e)))?;
+        db.set_meta(INDEX_SCOPE_META, &json)
+            .map_err(|e| napi::Error::from_reason(format!("DB error: {}", e)))
+    })
+}
+
+/// The open index's include patterns; empty when the whole repo is in scope.
+#[napi(catch_unwind)]
+pub fn get_index_scope() -> napi::Result<Vec<String>> {
+    with_state(|state| index_scope_patterns(get_db(state)?))
+}
+
+#[napi(object)]
+pub struct JsScopeReport {
+    /// Include patterns; empty when the whole repo is in scope
+    pub include: Vec<String>,
+    /// Indexed files inside the scope
+    pub files_in_scope: f64,
+    /// Indexed files outside it, e.g. from before the scope was narrowed,
+    /// sorted
+    pub out_of_scope_files: Vec<String>,
+    /// Symbols of `out_of_scope_files`
+    pub out_of_scope_symbols: f64,
+    /// With `repo_root`: worktree files `should_index` accepts that the
+    /// scope leaves unindexed
+    pub excluded_files: Option<f64>,
+}
+
+/// How the current workspace's index lines up with its scope (see
+/// `set_index_scope`): indexed files outside it, and with `repo_root`, how
+/// much of the worktree the scope leaves out (`options` as in
+/// `should_index`).
+#[napi(catch_unwind)]
+pub fn get_unindexed_scope_report(
+    repo_root: Option<String>,
+    options: Option<ScopeOptions>,
+) -> napi::Result<JsScopeReport> {
+    let worktree = match &repo_root {
+        Some(root) => {
+            let root = std::path::Path::new(root);
+            let exclude = options.and_then(|o| o.exclude).unwrap_or_default();
+            let mut scope =
+                scope::IndexScope::new(root, &exclude).map_err(napi::Error::from_reason)?;
+            let mut paths: Vec<String> = git::worktree_hashes(root)
+                .map_err(napi::Error::from_reason)?
+                .into_keys()
+                .collect();
+            paths.retain(|path| scope.should_index(path));
+            Some(paths)
+        }
+        None => None,
+    };
+
+    with_state(|state| {
+        let db = get_db(state)?;
+        let include = index_scope_patterns(db)?;
+        let includes = scope::Includes::new(&include).map_err(napi::Error::from_reason)?;
+        let files = db
+            .get_all_files()
+            .map_err(|e| napi::Error::from_reason(format!("DB error: {}", e)))?;
+        let (inside, outside): (Vec<_>, Vec<_>) =
+            files.into_iter().partition(|f| includes.contains(&f.path));
+        let mut out_of_scope_files: Vec<String> = outside.iter().map(|f| f.path.clone()).collect();
+        out_of_scope_files.sort();
+        Ok(JsScopeReport {
+            include,
+            files_in_scope: inside.len() as f64,
+            out_of_scope_files,
+            out_of_scope_symbols: outside
+                .iter()
+                .map(|f| f.symbol_count.unwrap_or(0).max(0) as f64)
+                .sum(),
+            excluded_files: worktree.map(|paths| {
+                paths.iter().filter(|p| !includes.contains(p)).count() as f64
+            }),
+        })
+    })
+}
+
+/// Run each query embedding against the DB and merge the results, keeping the
+/// best score per result identity (see `set_dedup_key`). Returns candidates
+/// at or above their kind's threshold, sorted by score descending.
+fn merge_candidates(
+    db: &SearchDB,
+    query_embeddings: &[Vec<f32>],
+    pool_k: i32,
+    threshold: &KindThresholds,
+    filters: &SearchFilters,
+) -> napi::Result<Vec<db::SearchResult>> {
+    // Fetch more per-query so we have enough after dedup
+    let per_query_k = if query_embeddings.len() > 1 {
+        (pool_k as f64 * 1.5).ceil() as i32
+    } else {
+        pool_k
+    };
+
+    let identity = dedup_key(db)?;
+    let mut best_by_key: HashMap<String, db::SearchResult> = HashMap::new();
+    let cancel = CancelScope::new(db);
+
+    let resolved = ResolvedFilters::new(filters)?;
+    let docs_only = filters.search_docs_only == Some(true);
+    let chunks_only = !docs_only && resolved.chunks_only();
+    let with_chunks = !docs_only
+        && (chunks_only || (filters.include_chunks == Some(true) && resolved.kind.is_none()));
+
+    // Nothing below the loosest threshold survives the post-filter, so let the
+    // scan drop it before it reaches the heap
+    let db_filters = db::Filters {
+        min_score: Some(threshold.min_score(resolved.kind.as_deref())),
+        fast_prefilter: filters.fast_prefilter == Some(true),
+        quantized: filters.quantized == Some(true),
+        ..resolved.db()
+    };
+
+    for emb in query_embeddings {
+        let mut results = if chunks_only {
+            Vec::new()
+        } else if docs_only {
+            db.search_docs(emb, per_query_k, &db_filters)
+                .map_err(|e| napi::Error::from_reason(format!("Search error: {}", e)))?
+        } else {
+            db.search(emb, per_query_k, &db_filters)
+                .map_err(|e| napi::Error::from_reason(format!("Search error: {}", e)))?
+        };
+        if with_chunks {
+            results.extend(
+                db.search_chunks(emb, per_query_k, &db_filters)
+                    .map_err(|e| napi::Error::from_reason(format!("Search error: {}", e)))?,
+            );
+        }
+
+        for r in results {
+            let key = identity.key(&r.file_path, r.line, &r.name, &r.kind, r.signature.as_deref());
+            let existing = best_by_key.get(&key);
+            if existing.is_none_or(|e| r.score > e.score) {
+                best_by_key.insert(key, r);
+            }
+        }
+    }
+
+    let mut merged: Vec<_> = best_by_key
+        .into_values()
+        .filter(|r| r.score >= threshold.for_kind(&r.kind))
+        .collect();
+    let ranker = rank::Ranker::new(&ranking_rules(db)?).map_err(napi::Error::from_reason)?;
+    let opens = if ranker.popularity {
+        let keys: Vec<String> = merged
+            .iter()
+            .map(|r| identity.key(&r.file_path, r.line, &r.name, &r.kind, r.signature.as_deref()))
+            .collect();
+        let keys: Vec<&str> = keys.iter().map(String::as_str).collect();
+        db.open_counts(filters.workspace.as_deref(), &keys)
+            .map_err(|e| napi::Error::from_reason(format!("DB error: {}", e)))?
+    } else {
+        Vec::new()
+    };
+    ranker.apply(&mut merged, &opens);
+    merged.sort_by(|a, b| b.score.total_cmp(&a.score));
+    drop(cancel);
+
+    // Recency for the size budget's eviction order, batched by the
+    // connection. Best effort: read-only indexes can't record it, and it
+    // shouldn't fail the search.
+    let mut paths: Vec<&str> = merged.iter().map(|r| r.file_path.as_str()).collect();
+    paths.sort_unstable();
+    paths.dedup();
+    let _ = db.touch_files(filters.workspace.as_deref(), &paths);
+
+    Ok(merged)
+}
+
+impl From<db::SearchResult> for JsSearchResult {
+    fn from(r: db::SearchResult) -> Self {
+        JsSearchResult {
+            file_path: r.file_path,
+            name: r.name,
+            kind: r.kind,
+            language: r.language,
+            line: r.line,
+            end_line: r.end_line,
+            signature: r.signature,
+            doc_comment: r.doc_comment,
+            symbol_id: r.symbol_id,
+            score: r.score,
+            highlights: None,
+            metadata: r.metadata,
+            repo: None,
+            neighbor_of: None,
+        }
+    }
+}
+
+/// Convert results, attaching query-term match offsets when `highlight` is on.
+fn to_js_results(
+    results: Vec<db::SearchResult>,
+    queries: &[String],
+    highlight: bool,
+) -> Vec<JsSearchResult> {
+    if !highlight {
+        return results.into_iter().map(JsSearchResult::from).collect();
+    }
+    let terms = highlight::query_terms(queries.iter().map(String::as_str));
+    results
+        .into_iter()
+        .map(|r| {
+            let mut fields = vec![("name", r.name.as_str())];
+            if let Some(sig) = &r.signature {
+                fields.push(("signature", sig.as_str()));
+            }
+            if let Some(doc) = &r.doc_comment {
+                fields.push(("doc_comment", doc.as_str()));
+            }
+            let highlights = highlight::find(&terms, &fields)
+                .into_iter()
+                .map(|h| JsHighlight {
+                    field: h.field.to_string(),
+                    term: h.term,
+                    start: h.start as u32,
+                    end: h.end as u32,
+                })
+                .collect();
+            JsSearchResult { highlights: Some(highlights), ..JsSearchResult::from(r) }
+        })
+        .collect()
+}
+
+/// Validate `options.diversify` up front so bad input fails before embedding.
+fn diversify_option(options: Option<SearchOptions>) -> napi::Result<Option<DiversifyOptions>> {
+    let diversify = options.and_then(|o| o.diversify);
+    if let Some(d) = &diversify {
+        if d.by != "file" && d.by != "embedding" {
+            return Err(napi::Error::from_reason(format!(
+                "Unknown diversify mode '{}'. Expected \"file\" or \"embedding\".",
+                d.by
+            )));
+        }
+    }
+    Ok(diversify)
+}
+
+/// Shared tail of every symbol search: merge per-query candidates, then
+/// either truncate to top_k or pick top_k with MMR.
+fn search_embedded(
+    db: &SearchDB,
+    query_embeddings: &[Vec<f32>],
+    top_k: i32,
+    threshold: &KindThresholds,
+    filters: &SearchFilters,
+    diversify: Option<DiversifyOptions>,
+) -> napi::Result<Vec<db::SearchResult>> {
+    // MMR needs a larger pool to choose from
+    let pool_k = if diversify.is_some() { top_k * 3 } else { top_k };
+    let mut merged = merge_candidates(db, query_embeddings, pool_k, threshold, filters)?;
+
+    Ok(match diversify {
+        Some(d) if d.by == "embedding" => {
+            let keys: Vec<(&str, i32)> =
+                merged.iter().map(|r| (r.file_path.as_str(), r.line)).collect();
+            let embeddings = {
+                let _cancel = CancelScope::new(db);
+                db.get_embeddings(filters.workspace.as_deref(), &keys)
+                    .map_err(|e| napi::Error::from_reason(format!("DB error: {}", e)))?
+            };
+            rank::mmr(merged, top_k as usize, d.lambda, |i, j| {
+                match (&embeddings[i], &embeddings[j]) {
+                    (Some(a), Some(b)) => f32::dot(a, b).unwrap_or(0.0).max(0.0),
+                    _ => 0.0,
+                }
+            })
+        }
+        Some(d) => {
+            let files: Vec<String> = merged.iter().map(|r| r.file_path.clone()).collect();
+            rank::mmr(merged, top_k as usize, d.lambda, |i, j| {
+                if files[i] == files[j] { 1.0 } else { 0.0 }
+            })
+        }
+        None => {
+            merged.truncate(top_k as usize);
+            merged
+        }
+    })
+}
+
+/// `search_embedded`, or with `query_tokens` (see `embed_query_tokens`) a
+/// larger dense pool reranked by late interaction against the stored
+/// token vectors. Symbols without token vectors keep their dense score.
+fn search_reranked(
+    db: &SearchDB,
+    query_embeddings: &[Vec<f32>],
+    query_tokens: Option<&[Vec<Vec<f32>>]>,
+    top_k: i32,
+    threshold: &KindThresholds,
+    filters: &SearchFilters,
+    diversify: Option<DiversifyOptions>,
+) -> napi::Result<Vec<db::SearchResult>> {
+    let Some(query_tokens) = query_tokens else {
+        return search_embedded(db, query_embeddings, top_k, threshold, filters, diversify);
+    };
+    let pool_k = top_k * LATE_INTERACTION_POOL;
+    let mut results = search_embedded(db, query_embeddings, pool_k, threshold, filters, diversify)?;
+    let keys: Vec<(&str, i32)> = results.iter().map(|r| (r.file_path.as_str(), r.line)).collect();
+    let tokens = {
+        let _cancel = CancelScope::new(db);
+        db.get_token_vectors(filters.workspace.as_deref(), &keys)
+            .map_err(|e| napi::Error::from_reason(format!("DB error: {}", e)))?
+    };
+    let dims = query_embeddings.first().map_or(0, Vec::len);
+    for (r, t) in results.iter_mut().zip(tokens) {
+        if let Some(t) = t {
+            r.score = query_tokens
+                .iter()
+                .map(|q| db::max_sim(q, &t, dims))
+                .fold(f64::NEG_INFINITY, f64::max);
+        }
+    }
+    Ok(threshold_top_k(results, top_k, threshold))
+}
+
+/// The `top_k` best of rescored `results` that pass `threshold`.
+fn threshold_top_k(
+    mut results: Vec<db::SearchResult>,
+    top_k: i32,
+    threshold: &KindThresholds,
+) -> Vec<db::SearchResult> {
+    results.retain(|r| r.score >= threshold.for_kind(&r.kind));
+    results.sort_by(|a, b| b.score.total_cmp(&a.score));
+    results.truncate(top_k as usize);
+    results
+}
+
+/// Rescore `dense` candidates as `(1 - weight) * dense + weight * sparse`
+/// against the sparse query for `terms`, adding the best sparse matches
+/// the dense search missed (scored against the closest query embedding).
+fn fuse_sparse(
+    db: &SearchDB,
+    query_embeddings: &[Vec<f32>],
+    dense: Vec<db::SearchResult>,
+    terms: &[u32],
+    weight: f64,
+    filters: &SearchFilters,
+) -> napi::Result<Vec<db::SearchResult>> {
+    let _cancel = CancelScope::new(db);
+    let workspace = filters.workspace.as_deref();
+    let query = db
+        .sparse_query(workspace, terms)
+        .map_err(|e| napi::Error::from_reason(format!("DB error: {}", e)))?;
+    let resolved = ResolvedFilters::new(filters)?;
+    // Only symbols have sparse vectors
+    let symbols_searched = filters.search_docs_only != Some(true) && !resolved.chunks_only();
+    let mut sparse = if symbols_searched {
+        db.sparse_search(&query, dense.len().max(1), &resolved.db())
+            .map_err(|e| napi::Error::from_reason(format!("Search error: {}", e)))?
+    } else {
+        Vec::new()
+    };
+    let seen: HashSet<(&str, i32)> = dense.iter().map(|r| (r.file_path.as_str(), r.line)).collect();
+    sparse.retain(|r| !seen.contains(&(r.file_path.as_str(), r.line)));
+
+    let keys: Vec<(&str, i32)> = dense.iter().map(|r| (r.file_path.as_str(), r.line)).collect();
+    let sparse_scores = db
+        .sparse_scores(workspace, &query, &keys)
+        .map_err(|e| napi::Error::from_reason(format!("DB error: {}", e)))?;
+    let keys: Vec<(&str, i32)> = sparse.iter().map(|r| (r.file_path.as_str(), r.line)).collect();
+    let embeddings = db
+        .get_embeddings(workspace, &keys)
+        .map_err(|e| napi::Error::from_reason(format!("DB error: {}", e)))?;
+
+    let fuse = |dense: f64, sparse: f64| (1.0 - weight) * dense + weight * sparse;
+    let mut fused: Vec<db::SearchResult> = dense
+        .into_iter()
+        .zip(sparse_scores)
+        .map(|(r, s)| db::SearchResult {
+            score: fuse(r.score, s),
+            ..r
+        })
+        .collect();
+    fused.extend(sparse.into_iter().zip(embeddings).map(|(r, e)| {
+        let similarity = e.map_or(0.0, |e| {
+            query_embeddings
+                .iter()
+                .map(|q| f32::dot(q, &e).unwrap_or(0.0))
+                .fold(f64::NEG_INFINITY, f64::max)
+        });
+        db::SearchResult {
+            score: fuse(similarity, r.score),
+            ..r
+        }
+    }));
+    Ok(fused)
+}
+
+/// Multi-query search with dedup, all in Rust.
+///
+/// Embeds all queries as a batch, runs each against the DB,
+/// deduplicates by result identity (see `set_dedup_key`) keeping the best
+/// score, and returns top_k results sorted by score descending.
+/// With `options.diversify`, MMR picks the top_k from a larger candidate pool.
+/// `threshold` is either a single score or per-kind thresholds with a default.
+/// Results of the last 64 distinct calls are cached until the index changes.
+#[napi(catch_unwind)]
+pub fn search(
+    queries: Vec<String>,
+    top_k: i32,
+    threshold: Either<f64, KindThresholds>,
+    filters: SearchFilters,
+    options: Option<SearchOptions>,
+) -> napi::Result<Vec<JsSearchResult>> {
+    let threshold = kind_thresholds(threshold);
+    let highlight = options.as_ref().and_then(|o| o.highlight) == Some(true);
+    let instruction = options.as_ref().and_then(|o| o.task_instruction.clone());
+    let snapshot = options.as_ref().and_then(|o| o.snapshot.clone());
+    let sparse_weight = options.as_ref().and_then(|o| o.sparse_weight).filter(|&w| w > 0.0);
+    if sparse_weight.is_some_and(|w| !(0.0..=1.0).contains(&w)) {
+        return Err(napi::Error::from_reason("sparse_weight must be between 0 and 1"));
+    }
+    let kind_limits: HashMap<String, usize> = options
+        .as_ref()
+        .and_then(|o| o.per_kind_limits.as_ref())
+        .into_iter()
+        .flatten()
+        .map(|(k, &n)| (kind::normalize(k), n as usize))
+        .collect();
+    let stale_weight = options.as_ref().and_then(|o| o.stale_weight).filter(|&w| w < 1.0);
+    if stale_weight.is_some_and(|w| w < 0.0) {
+        return Err(napi::Error::from_reason("stale_weight must be between 0 and 1"));
+    }
+    let pool_k = if kind_limits.is_empty() && stale_weight.is_none() {
+        top_k
+    } else {
+        top_k * KIND_LIMIT_POOL
+    };
+    let diversify = diversify_option(options)?;
+    let start = std::time::Instant::now();
+
+    let prepared = with_state(|state| {
+        if queries.is_empty() {
+            return Ok(ControlFlow::Break(Vec::new()));
+        }
+        let db = get_db(state)?;
+        // A snapshot's contents don't move the live index's generation
+        if snapshot.is_some() {
+            let texts = query_texts(state, &queries, instruction.as_deref());
+            let cached = cached_queries(state, &texts);
+            return Ok(ControlFlow::Continue((None, texts, cached, state.db_epoch)));
+        }
+        let key = result_cache_key(
+            db.workspace(),
+            &queries,
+            top_k,
+            &threshold,
+            &filters,
+            diversify.as_ref(),
+            instruction.as_deref(),
+            sparse_weight,
+            &kind_limits,
+            stale_weight,
+        );
+        sync_result_cache(state)?;
+        if let Some(results) = state.result_cache.get(&key) {
+            tracing::debug!("search {:?}: {} cached results", queries, results.len());
+            return Ok(ControlFlow::Break(to_js_results(results, &queries, highlight)));
+        }
+        let texts = query_texts(state, &queries, instruction.as_deref());
+        let cached = cached_queries(state, &texts);
+        Ok(ControlFlow::Continue((Some(key), texts, cached, state.db_epoch)))
+    })?;
+    let (key, texts, cached, epoch) = match prepared {
+        ControlFlow::Break(results) => return Ok(results),
+        ControlFlow::Continue(prepared) => prepared,
+    };
+
+    // Batch-embed the uncached queries at once, jointly with any indexing
+    // waiting on the model
+    let fresh = embed_joint(missing_queries(&texts, &cached), false)?;
+
+    with_state(|state| {
+        check_db_epoch(state, epoch)?;
+        if snapshot.is_none() {
+            restore_on_access(state, &filters);
+        }
+        let query_embeddings = fill_queries(state, &texts, cached, fresh);
+        let model = state.info.name.clone();
+        let snapshot_db = match &snapshot {
+            Some(name) => Some(open_snapshot(get_db(state)?, name)?),
+            None => None,
+        };
+        let group = match &snapshot_db {
+            Some(db) => late_interaction_group(db)?,
+            None => late_interaction_group(get_db(state)?)?,
+        };
+        let query_tokens = if group > 0 {
+            Some(embed_query_tokens(state, &texts)?)
+        } else {
+            None
+        };
+        let sparse_terms = match sparse_weight {
+            Some(_) => Some(token_ids(state, &queries, false)?.concat()),
+            None => None,
+        };
+        let db: &SearchDB = match &snapshot_db {
+            Some(db) => db,
+            None => get_db(state)?,
+        };
+        let results = match (sparse_terms, sparse_weight) {
+            (Some(terms), Some(weight)) if sparse_enabled(db)? => {
+                // Dense matches under the threshold can still pass on their
+                // sparse score
+                let relaxed = KindThresholds {
+                    default: -1.0,
+                    by_kind: HashMap::new(),
+                };
+                let dense = search_reranked(
+                    db,
+                    &query_embeddings,
+                    query_tokens.as_deref(),
+                    pool_k * SPARSE_POOL,
+                    &relaxed,
+                    &filters,
+                    diversify,
+                )?;
+                let fused = fuse_sparse(db, &query_embeddings, dense, &terms, weight, &filters)?;
+                threshold_top_k(fused, pool_k, &threshold)
+            }
+            _ => search_reranked(
+                db,
+                &query_embeddings,
+                query_tokens.as_deref(),
+                pool_k,
+                &threshold,
+                &filters,
+                diversify,
+            )?,
+        };
+        let results = match stale_weight {
+            Some(weight) => {
+                let stale = {
+                    let _cancel = CancelScope::new(db);
+                    db.stale_results(filters.workspace.as_deref(), &model, &results)
+                        .map_err(|e| napi::Error::from_reason(format!("DB error: {}", e)))?
+                };
+                let demoted = rank::demote(results, &stale, weight);
+                threshold_top_k(demoted, pool_k, &threshold)
+            }
+            None => results,
+        };
+        let results = rank::limit_per_kind(results, top_k as usize, &kind_limits);
+        tracing::debug!(
+            "search {:?}: {} results in {:.1}ms",
+            queries,
+            results.len(),
+            start.elapsed().as_secs_f64() * 1000.0
+        );
+        let Some(key) = key else {
+            return Ok(to_js_results(results, &queries, highlight));
+        };
+        // Only cache results of an index that didn't change since the lookup
+        let generation = state.result_cache_generation;
+        sync_result_cache(state)?;
+        if state.result_cache_generation == generation {
+            state.result_cache.insert(key, results.clone());
+        }
+        Ok(to_js_results(results, &queries, highlight))
+    })
+}
+
+pub struct SearchTask {
+    queries: Vec<String>,
+    top_k: i32,
+    threshold: Option<Either<f64, KindThresholds>>,
+    filters: Option<SearchFilters>,
+    options: Option<SearchOptions>,
+}
+
+impl napi::Task for SearchTask {
+    type Output = Vec<JsSearchResult>;
+    type JsValue = Vec<JsSearchResult>;
+
+    fn compute(&mut self) -> napi::Result<Self::Output> {
+        let (Some(threshold), Some(filters)) = (self.threshold.take(), self.filters.take()) else {
+            return Err(napi::Error::from_reason("Search task already ran"));
+        };
+        search(
+            std::mem::take(&mut self.queries),
+            self.top_k,
+            threshold,
+            filters,
+            self.options.take(),
+        )
+    }
+
+    fn resolve(&mut self, _env: napi::Env, output: Self::Output) -> napi::Result<Self::JsValue> {
+        Ok(output)
+    }
+}
+
+/// `search` off the JS thread, so a slow one can be stopped with
+/// `cancel_search`.
+#[napi(catch_unwind)]
+pub fn search_async(
+    queries: Vec<String>,
+    top_k: i32,
+    threshold: Either<f64, KindThresholds>,
+    filters: SearchFilters,
+    options: Option<SearchOptions>,
+) -> AsyncTask<SearchTask> {
+    AsyncTask::new(SearchTask {
+        queries,
+        top_k,
+        threshold: Some(threshold),
+        filters: Some(filters),
+        options,
+    })
+}
+
+/// Interrupt the search statement running right now (via
+/// `sqlite3_interrupt`), e.g. the scan of a slow filtered `search_async`.
+/// The interrupted search rejects with an "interrupted" error, usually
+/// "Search error: interrupted"; the index is unchanged. Doesn't wait for
+/// the state lock, so it works while the search runs. Index writes are
+/// never interrupted. Returns false, doing nothing, if no search is
+/// reading the index, e.g. while one is still embedding its queries.
+#[napi(catch_unwind)]
+pub fn cancel_search() -> bool {
+    match &*SEARCH_INTERRUPT.lock().unwrap_or_else(|e| e.into_inner()) {
+        Some(handle) => {
+            handle.interrupt();
+            true
+        }
+        None => false,
+    }
+}
+
+/// Empty `State::result_cache` if the index changed since it was filled.
+fn sync_result_cache(state: &mut State) -> napi::Result<()> {
+    let generation = get_db(state)?
+        .generation()
+        .map_err(|e| napi::Error::from_reason(format!("DB error: {}", e)))?;
+    if generation != state.result_cache_generation {
+        state.result_cache.clear();
+        state.result_cache_generation = generation;
+    }
+    Ok(())
+}
+
+/// Everything that determines a `search` call's results, short of the
+/// index contents.
+#[allow(clippy::too_many_arguments)]
+fn result_cache_key(
+    workspace: &str,
+    queries: &[String],
+    top_k: i32,
+    threshold: &KindThresholds,
+    filters: &SearchFilters,
+    diversify: Option<&DiversifyOptions>,
+    task_instruction: Option<&str>,
+    sparse_weight: Option<f64>,
+    kind_limits: &HashMap<String, usize>,
+    stale_weight: Option<f64>,
+) -> String {
+    let by_kind: std::collections::BTreeMap<&String, &f64> = threshold.by_kind.iter().collect();
+    let kind_limits: std::collections::BTreeMap<&String, &usize> = kind_limits.iter().collect();
+    serde_json::json!({
+        "workspace": filters.workspace.as_deref().unwrap_or(workspace),
+        "queries": queries,
+        "top_k": top_k,
+        "threshold": [threshold.default, by_kind],
+        "language": filters.language,
+        "kind": filters.kind,
+        "path_prefix": filters.path_prefix,
+        "include_chunks": filters.include_chunks,
+        "search_docs_only": filters.search_docs_only,
+        "fast_prefilter": filters.fast_prefilter,
+        "quantized": filters.quantized,
+        "metadata_filter": format!("{:?}", metadata_filter(filters)),
+        "min_indexed_at": filters.min_indexed_at,
+        "max_indexed_at": filters.max_indexed_at,
+        "path_regex": filters.path_regex,
+        "name_regex": filters.name_regex,
+        "filter_expr": format!("{:?}", filter_expr(filters).ok().flatten()),
+        "diversify": diversify.map(|d| (&d.by, d.lambda)),
+        "task_instruction": task_instruction,
+        "sparse_weight": sparse_weight,
+        "per_kind_limits": kind_limits,
+        "stale_weight": stale_weight,
+    })
+    .to_string()
+}
+
+#[napi(object)]
+pub struct JsSearchStats {
+    /// Rows scored across all queries: vectors, prefilter codes, and windows
+    pub candidates_scanned: f64,
+    pub queries: u32,
+    pub embed_ms: f64,
+    /// Time in the DB scans
+    pub db_ms: f64,
+    /// Dedup, thresholds, ranking rules, and diversification
+    pub merge_ms: f64,
+}
+
+#[napi(object)]
+pub struct JsSearchWithStats {
+    pub results: Vec<JsSearchResult>,
+    pub stats: JsSearchStats,
+}
+
+/// `search`, also reporting the work it took, e.g. to show "searched 412k
+/// symbols in 38ms" or catch performance regressions. Always runs the
+/// search: the result cache is neither read nor filled.
+#[napi(catch_unwind)]
+pub fn search_with_stats(
+    queries: Vec<String>,
+    top_k: i32,
+    threshold: Either<f64, KindThresholds>,
+    filters: SearchFilters,
+    options: Option<SearchOptions>,
+) -> napi::Result<JsSearchWithStats> {
+    let threshold = kind_thresholds(threshold);
+    let highlight = options.as_ref().and_then(|o| o.highlight) == Some(true);
+    let instruction = options.as_ref().and_then(|o| o.task_instruction.clone());
+    let snapshot = options.as_ref().and_then(|o| o.snapshot.clone());
+    let diversify = diversify_option(options)?;
+
+    with_state(|state| {
+        if snapshot.is_none() {
+            restore_on_access(state, &filters);
+        }
+        let embed_start = std::time::Instant::now();
+        let texts = query_texts(state, &queries, instruction.as_deref());
+        let query_embeddings = embed_prefixed_queries(state, &texts)?;
+        let embed_ms = embed_start.elapsed().as_secs_f64() * 1000.0;
+
+        let db = get_db(state)?;
+        let snapshot_db = snapshot.as_deref().map(|name| open_snapshot(db, name)).transpose()?;
+        let db: &SearchDB = snapshot_db.as_ref().unwrap_or(db);
+        db.take_scan_stats();
+        let search_start = std::time::Instant::now();
+        let results = if queries.is_empty() {
+            Vec::new()
+        } else {
+            search_embedded(db, &query_embeddings, top_k, &threshold, &filters, diversify)?
+        };
+        let search_ms = search_start.elapsed().as_secs_f64() * 1000.0;
+        let scan = db.take_scan_stats();
+        let db_ms = scan.elapsed.as_secs_f64() * 1000.0;
+
+        Ok(JsSearchWithStats {
+            results: to_js_results(results, &queries, highlight),
+            stats: JsSearchStats {
+                candidates_scanned: scan.rows as f64,
+                queries: queries.len() as u32,
+                embed_ms,
+                db_ms,
+                merge_ms: (search_ms - db_ms).max(0.0),
+            },
+        })
+    })
+}
+
+#[napi(object)]
+pub struct SearchSessionOptions {
+    /// Candidates kept for paging (default 100)
+    pub max_results: Option<i32>,
+    pub highlight: Option<bool>,
+}
+
+#[napi(object)]
+pub struct JsSearchSession {
+    /// Pass to `search_next`
+    pub cursor: f64,
+    /// Candidates available for paging
+    pub total: f64,
+}
+
+/// Run a search once and keep the merged, ranked candidates for paging with
+/// `search_next`, so later pages don't re-embed the queries.
+///
+/// Pages reflect the index at session creation. Up to 16 sessions are kept
+/// (oldest dropped first); opening, closing, or restoring the DB drops them all.
+#[napi(catch_unwind)]
+pub fn search_session(
+    queries: Vec<String>,
+    threshold: Either<f64, KindThresholds>,
+    filters: SearchFilters,
+    options: Option<SearchSessionOptions>,
+) -> napi::Result<JsSearchSession> {
+    let threshold = kind_thresholds(threshold);
+    let (max_results, highlight) = match options {
+        Some(o) => (o.max_results.unwrap_or(100), o.highlight == Some(true)),
+        None => (100, false),
+    };
+
+    with_state(|state| {
+        let results = if queries.is_empty() {
+            Vec::new()
+        } else {
+            let query_embeddings = embed_internal(state, &queries, true)?;
+            let db = get_db(state)?;
+            let mut merged =
+                merge_candidates(db, &query_embeddings, max_results, &threshold, &filters)?;
+            merged.truncate(max_results.max(0) as usize);
+            merged
+        };
+
+        let id = state.next_session_id;
+        state.next_session_id = state.next_session_id.wrapping_add(1);
+        if state.sessions.len() >= MAX_SEARCH_SESSIONS {
+            state.sessions.remove(0);
+        }
+        let total = results.len();
+        state.sessions.push(SearchSession { id, queries, highlight, results });
+        Ok(JsSearchSession { cursor: id as f64, total: total as f64 })
+    })
+}
+
+/// Fetch `limit` results starting at `offset` from a `search_session`.
+/// Returns an empty list past the end.
+#[napi(catch_unwind)]
+pub fn search_next(cursor: f64, offset: u32, limit: u32) -> napi::Result<Vec<JsSearchResult>> {
+    with_state(|state| {
+        let session = state
+            .sessions
+            .iter()
+            .find(|s| s.id == cursor as u32)
+            .ok_or_else(|| {
+                napi::Error::from_reason(format!(
+                    "Unknown or expired search cursor {}. Start a new search_session().",
+                    cursor
+                ))
+            })?;
+        let page: Vec<db::SearchResult> = session
+            .results
+            .iter()
+            .skip(offset as usize)
+            .take(limit as usize)
+            .cloned()
+            .collect();
+        Ok(to_js_results(page, &session.queries, session.highlight))
+    })
+}
+
+/// Drop a search session. Returns false if the cursor was unknown.
+#[napi(catch_unwind)]
+pub fn close_search(cursor: f64) -> napi::Result<bool> {
+    with_state(|state| {
+        let before = state.sessions.len();
+        state.sessions.retain(|s| s.id != cursor as u32);
+        Ok(state.sessions.len() != before)
+    })
+}
+
+#[napi(object)]
+pub struct JsSearchExplain {
+    /// "indexed" (seek via index/primary key range) or "full_scan"
+    pub plan: String,
+    /// Symbols matching the filters, counted up to `cap + 1`
+    pub matching_rows: f64,
+    pub cap: f64,
+    pub sql: String,
+}
+
+/// Show how `search` would read symbols for `filters`: a filtered search
+/// first counts matching rows (up to the cap), seeking through an index when
+/// the count is under the cap and scanning the table sequentially otherwise.
+#[napi(catch_unwind)]
+pub fn explain_search(filters: SearchFilters) -> napi::Result<JsSearchExplain> {
+    let resolved = ResolvedFilters::new(&filters)?;
+    with_state(|state| {
+        let db = get_db(state)?;
+        let e = db
+            .explain_search(filters.search_docs_only == Some(true), &resolved.db())
+            .map_err(|e| napi::Error::from_reason(format!("DB error: {}", e)))?;
+        Ok(JsSearchExplain {
+            plan: e.plan.as_str().to_string(),
+            matching_rows: e.matching_rows as f64,
+            cap: e.cap as f64,
+            sql: e.sql,
+        })
+    })
+}
+
+/// Set the matching-row count above which filtered searches switch from an
+/// index seek to a full table scan (default 50,000). Applies to the open DB.
+#[napi(catch_unwind)]
+pub fn set_prefilter_cap(cap: f64) -> napi::Result<()> {
+    with_state(|state| {
+        get_db(state)?.set_prefilter_cap(cap.max(0.0) as u64);
+        Ok(())
+    })
+}
+
+fn parse_vector_storage(s: &str) -> napi::Result<db::VectorStorage> {
+    db::VectorStorage::parse(s).ok_or_else(|| {
+        napi::Error::from_reason(format!(
+            "Unknown vector storage '{}'. Expected \"blob\" or \"vec0\".",
+            s
+        ))
+    })
+}
+
+/// Choose how the open index stores vectors for exact symbol searches:
+/// `"blob"` (the default) streams embedding BLOBs through simsimd; `"vec0"`
+/// also keeps them in a sqlite-vec `vec0` table and searches it with its KNN
+/// query, filters included. That table is partitioned by language, so
+/// language-filtered searches read only the matching vectors. Switching
+/// copies (or drops) every vector, so pick it right after creating an index.
+/// Returns the vectors copied.
+#[napi(catch_unwind)]
+pub fn set_vector_storage(storage: String) -> napi::Result<f64> {
+    let storage = parse_vector_storage(&storage)?;
+    with_state(|state| {
+        state.sessions.clear();
+        get_db(state)?
+            .set_vector_storage(storage)
+            .map(|n| n as f64)
+            .map_err(|e| napi::Error::from_reason(format!("DB error: {}", e)))
+    })
+}
+
+/// The open index's vector storage: "blob" or "vec0".
+#[napi(catch_unwind)]
+pub fn get_vector_storage() -> napi::Result<String> {
+    with_state(|state| Ok(get_db(state)?.vector_storage().as_str().to_string()))
+}
+
+#[napi(object)]
+pub struct JsTextCompressionResult {
+    /// Stored size of every `embedding_text` and `signature`, in bytes
+    pub bytes_before: f64,
+    pub bytes_after: f64,
+}
+
+/// Store symbols' embedding text and signature zstd-compressed (`true`) or
+/// plain (`false`), rewriting existing rows across all workspaces to match.
+/// Reads are unaffected either way. Returns the size of those columns
+/// before and after. Freed pages are reused by later writes;
+/// `rebuild_index` returns them to the OS.
+#[napi(catch_unwind)]
+pub fn set_text_compression(enabled: bool) -> napi::Result<JsTextCompressionResult> {
+    with_state(|state| {
+        let (before, after) = get_db(state)?
+            .set_text_compression(enabled)
+            .map_err(|e| napi::Error::from_reason(format!("DB error: {}", e)))?;
+        Ok(JsTextCompressionResult {
+            bytes_before: before as f64,
+            bytes_after: after as f64,
+        })
+    })
+}
+
+#[napi(catch_unwind)]
+pub fn get_text_compression() -> napi::Result<bool> {
+    with_state(|state| Ok(get_db(state)?.text_compression()))
+}
+
+/// Page size `rebuild_index` uses by default: the fastest for streaming
+/// scans in search_bench.rs.
+const REBUILD_PAGE_SIZE: u32 = 16384;
+
+#[napi(object)]
+pub struct RebuildOptions {
+    /// Page size of the rebuilt index (default 16384)
+    pub page_size: Option<u32>,
+    /// Vector storage of the rebuilt index, "blob" or "vec0" (default:
+    /// unchanged)
+    pub vector_storage: Option<String>,
+}
+
+#[napi(object)]
+pub struct JsRebuildResult {
+    pub rows_copied: f64,
+    /// Index file size (including its WAL) before and after
+    pub bytes_before: f64,
+    pub bytes_after: f64,
+    pub page_size: u32,
+}
+
+/// Recreate the open index file with the current schema and layout: the
+/// chosen page size, clustered WITHOUT ROWID tables, a language-partitioned
+/// `vec0` table when using that storage, and fresh planner statistics.
+/// Every row is copied into a new file next to the index, which then
+/// replaces it, so existing indexes pick up layout changes without being
+/// re-embedded. The index is reopened as it was open before (same mode,
+/// settings, and workspace). Fails for `":memory:"` indexes, during an
+/// index session, and if another process has the index open; the file is
+/// swapped under an exclusive lock, so nothing can open it meanwhile.
+#[napi(catch_unwind)]
+pub fn rebuild_index(options: Option<RebuildOptions>) -> napi::Result<JsRebuildResult> {
+    let (page_size, storage) = match options {
+        Some(o) => (o.page_size, o.vector_storage),
+        None => (None, None),
+    };
+    let page_size = page_size.unwrap_or(REBUILD_PAGE_SIZE);
+    let open_options = open_options(Some(OpenDbOptions {
+        page_size: Some(page_size),
+        mmap_size: None,
+        cache_size: None,
+        synchronous: None,
+    }))?;
+    let storage = storage.as_deref().map(parse_vector_storage).transpose()?;
+
+    with_state(|state| {
+        if state.index_session.is_some() {
+            return Err(napi::Error::from_reason(
+                "An index session is open. Commit or abort it before rebuilding.",
+            ));
+        }
+        let db = get_db(state)?;
+        let path = db
+            .path()
+            .map(str::to_string)
+            .ok_or_else(|| napi::Error::from_reason("Can't rebuild an in-memory index"))?;
+        let workspace = db.workspace().to_string();
+        let (reopen_options, readonly) = (db.open_options(), db.is_readonly());
+        let file_size = |p: &str| std::fs::metadata(p).map_or(0, |m| m.len());
+        let with_wal = |p: &str| file_size(p) + file_size(&format!("{}-wal", p));
+        let bytes_before = with_wal(&path);
+
+        let tmp = format!("{}.rebuild", path);
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{}", tmp, suffix));
+        }
+        let rows_copied = (|| {
+            let mut new = SearchDB::open_with(std::path::Path::new(&tmp), &open_options)?;
+            let rows = new.copy_from(std::path::Path::new(&path))?;
+            if let Some(storage) = storage {
+                new.set_vector_storage(storage)?;
+            }
+            Ok(rows)
+        })()
+        .map_err(|e: rusqlite::Error| {
+            let _ = std::fs::remove_file(&tmp);
+            napi::Error::from_reason(format!("Rebuild failed: {}", e))
+        })?;
+        let bytes_after = with_wal(&tmp);
+
+        set_db(state, None);
+        let replaced = match db::lock_index_file(std::path::Path::new(&path)) {
+            Ok(lock) => {
+                let renamed = std::fs::rename(&tmp, &path).map_err(|e| e.to_string());
+                drop(lock);
+                renamed
+            }
+            Err(e) => Err(format!(
+                "can't lock the index, is it open in another process? {}",
+                e
+            )),
+        };
+        if replaced.is_err() {
+            let _ = std::fs::remove_file(&tmp);
+        }
+
+        let reopened = if readonly {
+            SearchDB::open_readonly_with(std::path::Path::new(&path), &reopen_options)
+        } else {
+            SearchDB::open_with(std::path::Path::new(&path), &reopen_options)
+        };
+        let mut db = reopened
+            .map_err(|e| napi::Error::from_reason(format!("Failed to open DB: {}", e)))?;
+        db.set_workspace(&workspace);
+        set_db(state, Some(db));
+        replaced.map_err(|e| napi::Error::from_reason(format!("Rebuild failed: {}", e)))?;
+
+        Ok(JsRebuildResult {
+            rows_copied: rows_copied as f64,
+            bytes_before: bytes_before as f64,
+            bytes_after: bytes_after as f64,
+            page_size,
+        })
+    })
+}
+
+#[napi(object)]
+pub struct JsPathRule {
+    /// gitignore syntax, e.g. `src/`, `generated/`, `*_test.go`
+    pub pattern: String,
+    /// Added to matching results' scores; negative to penalize
+    pub boost: f64,
+}
+
+#[napi(object)]
+pub struct JsRankingRules {
+    pub path: Option<Vec<JsPathRule>>,
+    /// Score multipliers by kind, e.g. `{ function: 1.1, constant: 0.8 }`;
+    /// unlisted kinds keep 1.0. Kind aliases are normalized.
+    pub kind: Option<HashMap<String, f64>>,
+    /// Boost results by how often they were opened (`record_click`), by up
+    /// to 0.03. Default true.
+    pub popularity: Option<bool>,
+}
+
+/// The open index's ranking rules, empty if never set.
+fn ranking_rules(db: &SearchDB) -> napi::Result<rank::RankingRules> {
+    match db
+        .get_meta(rank::RULES_META_KEY)
+        .map_err(|e| napi::Error::from_reason(format!("DB error: {}", e)))?
+    {
+        Some(json) => serde_json::from_str(&json)
+            .map_err(|e| napi::Error::from_reason(format!("Invalid ranking rules: {}", e))),
+        None => Ok(rank::RankingRules::default()),
+    }
+}
+
+/// Set the open index's score adjustments, applied when search candidates
+/// are merged (after thresholds). A result's score is multiplied by its
+/// kind's weight, then every path rule matching its path adds its boost
+/// (e.g. `{ pattern: "generated/", boost: -0.1 }`), and unless `popularity`
+/// is false, often-opened results get a small boost. Replaces the previous
+/// rules.
+#[napi(catch_unwind)]
+pub fn set_ranking_rules(rules: JsRankingRules) -> napi::Result<()> {
+    let rules = rank::RankingRules {
+        path: rules
+            .path
+            .unwrap_or_default()
+            .into_iter()
+            .map(|r| rank::PathRule { pattern: r.pattern, boost: r.boost })
+            .collect(),
+        kind: rules
+            .kind
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(k, w)| (kind::normalize(&k), w))
+            .collect(),
+        popularity: rules.popularity.unwrap_or(true),
+    };
+    rank::Ranker::new(&rules).map_err(napi::Error::from_reason)?;
+    let json = serde_json::to_string(&rules)
+        .map_err(|e| napi::Error::from_reason(format!("Invalid ranking rules: {}", e)))?;
+    with_state(|state| {
+        get_db(state)?
+            .set_meta(rank::RULES_META_KEY, &json)
+            .map_err(|e| napi::Error::from_reason(format!("DB error: {}", e)))
+    })
+}
+
+/// The open index's ranking rules.
+#[napi(catch_unwind)]
+pub fn get_ranking_rules() -> napi::Result<JsRankingRules> {
+    with_state(|state| {
+        let rules = ranking_rules(get_db(state)?)?;
+        Ok(JsRankingRules {
+            path: Some(
+                rules
+                    .path
+                    .into_iter()
+                    .map(|r| JsPathRule { pattern: r.pattern, boost: r.boost })
+                    .collect(),
+            ),
+            kind: Some(rules.kind),
+            popularity: Some(rules.popularity),
+        })
+    })
+}
+
+/// Run several scoped searches in one call.
+///
+/// Every query across all requests is embedded in a single batch (identical
+/// query strings are embedded once), then each request is searched with its
+/// own filters. Results are returned in request order.
+#[napi(catch_unwind)]
+pub fn search_many(requests: Vec<SearchRequest>) -> napi::Result<Vec<Vec<JsSearchResult>>> {
+    let mut parsed = Vec::with_capacity(requests.len());
+    for req in requests {
+        let threshold = kind_thresholds(req.threshold);
+        let highlight = req.options.as_ref().and_then(|o| o.highlight) == Some(true);
+        let instruction = req.options.as_ref().and_then(|o| o.task_instruction.clone());
+        let diversify = diversify_option(req.options)?;
+        parsed.push((
+            req.queries,
+            req.top_k,
+            threshold,
+            req.filters,
+            diversify,
+            highlight,
+            instruction,
+        ));
+    }
+
+    with_state(|state| {
+        // Prefixed query texts per request, and the unique ones across all
+        // requests in first-seen order
+        let texts: Vec<Vec<String>> = parsed
+            .iter()
+            .map(|(queries, .., instruction)| query_texts(state, queries, instruction.as_deref()))
+            .collect();
+        let mut unique: Vec<String> = Vec::new();
+        let mut index_of: HashMap<&str, usize> = HashMap::new();
+        for t in texts.iter().flatten() {
+            if !index_of.contains_key(t.as_str()) {
+                index_of.insert(t.as_str(), unique.len());
+                unique.push(t.clone());
+            }
+        }
+        if unique.is_empty() {
+            return Ok(parsed.iter().map(|_| Vec::new()).collect());
+        }
+        for (.., filters, _, _, _) in &parsed {
+            restore_on_access(state, filters);
+        }
+
+        let embeddings = embed_prefixed_queries(state, &unique)?;
+
+        let db = get_db(state)?;
+        let mut grouped = Vec::with_capacity(parsed.len());
+        for ((queries, top_k, threshold, filters, diversify, highlight, _), texts) in
+            parsed.iter().zip(&texts)
+        {
+            if queries.is_empty() {
+                grouped.push(Vec::new());
+                continue;
+            }
+            let query_embeddings: Vec<Vec<f32>> = texts
+                .iter()
+                .map(|t| embeddings[index_of[t.as_str()]].clone())
+                .collect();
+            let results = search_embedded(
+                db,
+                &query_embeddings,
+                *top_k,
+                threshold,
+                filters,
+                diversify.clone(),
+            )?;
+            grouped.push(to_js_results(results, queries, *highlight));
+        }
+        Ok(grouped)
+    })
+}
+
+/// Search several indexes (e.g. one per repo) and return one ranked list.
+///
+/// Queries are embedded once; each index is opened read-only alongside the
+/// current one, searched with the same threshold and filters, and its results
+/// tagged with its path in `repo`. Every index must have been built with the
+/// loaded model's dimensions.
+#[napi(catch_unwind)]
+pub fn search_federated(
+    db_paths: Vec<String>,
+    queries: Vec<String>,
+    top_k: i32,
+    threshold: Either<f64, KindThresholds>,
+    filters: SearchFilters,
+) -> napi::Result<Vec<JsSearchResult>> {
+    let threshold = kind_thresholds(threshold);
+
+    with_state(|state| {
+        if queries.is_empty() || db_paths.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let query_embeddings = embed_internal(state, &queries, true)?;
+
+        let mut merged: Vec<(usize, db::SearchResult)> = Vec::new();
+        for (i, path) in db_paths.iter().enumerate() {
+            let db = SearchDB::open_readonly(std::path::Path::new(path)).map_err(|e| {
+                napi::Error::from_reason(format!("Failed to open DB {}: {}", path, e))
+            })?;
+            let dims = db
+                .get_meta("dimensions")
+                .map_err(|e| napi::Error::from_reason(format!("DB error: {}", e)))?;
+            if dims.as_deref() != Some(state.dims.to_string().as_str()) {
+                return Err(napi::Error::from_reason(format!(
+                    "{} was indexed with {} dimensions, model produces {}",
+                    path,
+                    dims.as_deref().unwrap_or("unknown"),
+                    state.dims
+                )));
+            }
+            let results = merge_candidates(&db, &query_embeddings, top_k, &threshold, &filters)?;
+            merged.extend(results.into_iter().take(top_k.max(0) as usize).map(|r| (i, r)));
+        }
+
+        merged.sort_by(|a, b| b.1.score.total_cmp(&a.1.score));
+        merged.truncate(top_k.max(0) as usize);
+        Ok(merged
+            .into_iter()
+            .map(|(i, r)| JsSearchResult {
+                repo: Some(db_paths[i].clone()),
+                ..JsSearchResult::from(r)
+            })
+            .collect())
+    })
+}
+
+/// Embed texts and return the vectors. For callers that combine embeddings
+/// (e.g. a centroid of example snippets) before `search_by_vector`.
+#[napi(catch_unwind)]
+pub fn embed(texts: Vec<String>, is_query: bool) -> napi::Result<Vec<Float32Array>> {
+    with_state(|state| {
+        let embeddings = embed_internal(state, &texts, is_query)?;
+        Ok(embeddings.into_iter().map(Float32Array::new).collect())
+    })
+}
+
+#[napi(object)]
+pub struct JsNoveltyScore {
+    pub file_path: String,
+    pub line: i32,
+    pub name: String,
+    /// Similarity to the closest indexed symbol, 0 when the index has none
+    /// matching the filters. Low means logic unlike anything indexed
+    pub max_similarity: f64,
+    /// The closest indexed symbol
+    pub nearest: Option<JsSearchResult>,
+}
+
+/// How novel each of `symbols` (e.g. the new or changed symbols of a diff,
+/// not yet indexed) is: each is embedded as `index_symbols` would, and
+/// scored by its similarity to the closest symbol in the index matching
+/// `filters`. Returns scores in input order; sort by `max_similarity`
+/// ascending to review the most novel code first. A changed symbol that is
+/// still indexed in its old form will mostly match itself, so compare
+/// `nearest` against its location to tell edits from new code.
+#[napi(catch_unwind)]
+pub fn score_novelty(
+    mut symbols: Vec<SymbolInput>,
+    filters: Option<SearchFilters>,
+) -> napi::Result<Vec<JsNoveltyScore>> {
+    let filters = filters.unwrap_or_default();
+    let resolved = ResolvedFilters::new(&filters)?;
+    let db_filters = resolved.db();
+    with_state(|state| {
+        normalize_symbols(state, &mut symbols)?;
+        let texts: Vec<String> = symbols.iter().map(|s| s.embedding_text.clone()).collect();
+        let embeddings = embed_internal(state, &texts, false)?;
+        let db = get_db(state)?;
+        symbols
+            .into_iter()
+            .zip(&embeddings)
+            .map(|(s, emb)| {
+                let nearest = db
+                    .search(emb, 1, &db_filters)
+                    .map_err(|e| napi::Error::from_reason(format!("DB error: {}", e)))?
+                    .into_iter()
+                    .next();
+                Ok(JsNoveltyScore {
+                    file_path: s.file_path,
+                    line: s.line,
+                    name: s.name,
+                    max_similarity: nearest.as_ref().map_or(0.0, |r| r.score),
+                    nearest: nearest.map(JsSearchResult::from),
+                })
+            })
+            .collect()
+    })
+}
+
+/// Search by a caller-supplied vector instead of query text.
+///
+/// The vector is L2-normalized here so that centroids and other combinations
+/// score on the same cosine scale as `search()`.
+#[napi(catch_unwind)]
+pub fn search_by_vector(
+    embedding: Float32Array,
+    top_k: i32,
+    threshold: Either<f64, KindThresholds>,
+    filters: SearchFilters,
+    options: Option<SearchOptions>,
+) -> napi::Result<Vec<JsSearchResult>> {
+    let threshold = kind_thresholds(threshold);
+    let snapshot = options.as_ref().and_then(|o| o.snapshot.clone());
+    let diversify = diversify_option(options)?;
+
+    let mut query: Vec<f32> = embedding.to_vec();
+    let norm = query.iter().map(|x| x * x).sum::<f32>().sqrt();
+    if norm == 0.0 || !norm.is_finite() {
+        return Err(napi::Error::from_reason("Query vector has zero or non-finite norm"));
+    }
+    query.iter_mut().for_each(|x| *x /= norm);
+
+    with_state(|state| {
+        if query.len() != state.dims {
+            return Err(napi::Error::from_reason(format!(
+                "Query vector has {} dimensions, model produces {}",
+                query.len(),
+                state.dims
+            )));
+        }
+        if snapshot.is_none() {
+            restore_on_access(state, &filters);
+        }
+        let db = get_db(state)?;
+        let snapshot_db = snapshot.as_deref().map(|name| open_snapshot(db, name)).transpose()?;
+        let db: &SearchDB = snapshot_db.as_ref().unwrap_or(db);
+        let results = search_embedded(db, &[query], top_k, &threshold, &filters, diversify)?;
+        Ok(results.into_iter().map(JsSearchResult::from).collect())
+    })
+}
+
+/// Code related to the symbol enclosing `file_path:line` (the innermost,
+/// see `get_symbols_in_range`): `search_by_vector` with its stored
+/// embedding, so nothing is embedded unless its file was evicted. The
+/// symbol itself is left out. Empty when no indexed symbol encloses the
+/// line.
+#[napi(catch_unwind)]
+pub fn find_similar_to_range(
+    file_path: String,
+    line: i32,
+    top_k: i32,
+    threshold: Either<f64, KindThresholds>,
+    filters: SearchFilters,
+) -> napi::Result<Vec<JsSearchResult>> {
+    let threshold = kind_thresholds(threshold);
+    with_state(|state| {
+        let Some(symbol) = get_db(state)?
+            .symbols_in_range(&file_path, line, line)
+            .map_err(|e| napi::Error::from_reason(format!("DB error: {}", e)))?
+            .pop()
+        else {
+            return Ok(Vec::new());
+        };
+        let stored_embedding = |db: &SearchDB| {
+            db.get_embeddings(None, &[(symbol.file_path.as_str(), symbol.line)])
+                .map(|mut e| e.pop().flatten())
+                .map_err(|e| napi::Error::from_reason(format!("DB error: {}", e)))
+        };
+        let embedding = match stored_embedding(get_db(state)?)? {
+            Some(embedding) => embedding,
+            None => {
+                restore_rows(state, Some(std::slice::from_ref(&symbol.file_path)))?;
+                stored_embedding(get_db(state)?)?.ok_or_else(|| {
+                    napi::Error::from_reason(format!(
+                        "{}:{} has no stored embedding",
+                        symbol.file_path, symbol.line
+                    ))
+                })?
+            }
+        };
+        restore_on_access(state, &filters);
+        let db = get_db(state)?;
+        // One extra, since the symbol itself is dropped below
+        let pool_k = top_k.saturating_add(1);
+        let mut results = search_embedded(db, &[embedding], pool_k, &threshold, &filters, None)?;
+        let same_workspace = filters.workspace.as_deref().is_none_or(|w| w == db.workspace());
+        results.retain(|r| {
+            !(same_workspace && r.file_path == symbol.file_path && r.line == symbol.line)
+        });
+        results.truncate(top_k.max(0) as usize);
+        Ok(results.into_iter().map(JsSearchResult::from).collect())
+    })
+}
+
+/// File-level search: aggregates symbol scores per file and returns the
+/// top_k files, each with its best-matching symbols nested.
+///
+/// `aggregate` is `"max"` (best symbol score) or `"sum_topk"` (sum of the
+/// file's top `symbols_per_file` scores, favoring files with many hits).
+#[napi(catch_unwind)]
+pub fn search_files(
+    queries: Vec<String>,
+    top_k: i32,
+    threshold: Either<f64, KindThresholds>,
+    filters: SearchFilters,
+    options: Option<FileSearchOptions>,
+) -> napi::Result<Vec<JsFileSearchResult>> {
+    let threshold = kind_thresholds(threshold);
+    let aggregate = options
+        .as_ref()
+        .and_then(|o| o.aggregate.clone())
+        .unwrap_or_else(|| "max".to_string());
+    let aggregate = match aggregate.as_str() {
+        "max" => rank::FileAggregate::Max,
+        "sum_topk" => rank::FileAggregate::SumTopK,
+        other => {
+            return Err(napi::Error::from_reason(format!(
+                "Unknown aggregate '{}'. Expected \"max\" or \"sum_topk\".",
+                other
+            )))
+        }
+    };
+    let symbols_per_file = options
+        .as_ref()
+        .and_then(|o| o.symbols_per_file)
+        .unwrap_or(3)
+        .max(1) as usize;
+
+    with_state(|state| {
+        if queries.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let query_embeddings = embed_internal(state, &queries, true)?;
+
+        let db = get_db(state)?;
+
+        // Symbol pool large enough that top_k files each get a few hits
+        let pool_k = top_k * symbols_per_file as i32 * 2;
+        let merged = merge_candidates(db, &query_embeddings, pool_k, &threshold, &filters)?;
+
+        let mut groups = rank::group_by_file(merged, aggregate, symbols_per_file);
+        groups.truncate(top_k as usize);
+
+        Ok(groups
+            .into_iter()
+            .map(|g| JsFileSearchResult {
+                file_path: g.file_path,
+                score: g.score,
+                symbols: g.symbols.into_iter().map(JsSearchResult::from).collect(),
+            })
+            .collect())
+    })
+}
+
+/// Re-hydrate results by `symbol_id` (e.g. from history) without searching.
+/// Returned in input order with score 0; ids no longer in the index, and
+/// chunk ids (which are derived, not stored), are null.
+#[napi(catch_unwind)]
+pub fn get_symbols_by_id(ids: Vec<String>) -> napi::Result<Vec<Option<JsSearchResult>>> {
+    with_state(|state| {
+        let db = get_db(state)?;
+        let ids: Vec<&str> = ids.iter().map(String::as_str).collect();
+        let rows = db
+            .get_symbols_by_id(&ids)
+            .map_err(|e| napi::Error::from_reason(format!("DB error: {}", e)))?;
+        Ok(rows.into_iter().map(|r| r.map(JsSearchResult::from)).collect())
+    })
+}
+
+/// Indexed symbols of `file_path` overlapping lines `start_line` to
+/// `end_line` (inclusive), by start line, with score 0. Pass a cursor's
+/// line as both to get the symbols enclosing it, innermost last.
+#[napi(catch_unwind)]
+pub fn get_symbols_in_range(
+    file_path: String,
+    start_line: i32,
+    end_line: i32,
+) -> napi::Result<Vec<JsSearchResult>> {
+    with_state(|state| {
+        let rows = get_db(state)?
+            .symbols_in_range(&file_path, start_line, end_line)
+            .map_err(|e| napi::Error::from_reason(format!("DB error: {}", e)))?;
+        Ok(rows.into_iter().map(JsSearchResult::from).collect())
+    })
+}
+
+#[napi(catch_unwind)]
+pub fn db_get_stats() -> napi::Result<JsStats> {
+    with_state(|state| {
+        let db = get_db(state)?;
+        let stats = db
+            .get_stats()
+            .map_err(|e| napi::Error::from_reason(format!("DB error: {}", e)))?;
+        Ok(JsStats {
+            symbol_count: stats.symbol_count as f64,
+            file_count: stats.file_count as f64,
+        })
+    })
+}
+
+#[napi(object)]
+pub struct JsKindCount {
+    pub kind: String,
+    pub count: f64,
+}
+
+/// Distinct symbol kinds in the current workspace with their symbol counts,
+/// most common first. Kinds are stored normalized (see `kind::normalize`),
+/// so filters can use any alias of these.
+#[napi(catch_unwind)]
+pub fn get_kinds() -> napi::Result<Vec<JsKindCount>> {
+    with_state(|state| {
+        let counts = get_db(state)?
+            .kind_counts()
+            .map_err(|e| napi::Error::from_reason(format!("DB error: {}", e)))?;
+        Ok(counts
+            .into_iter()
+            .map(|(kind, count)| JsKindCount {
+                kind,
+                count: count as f64,
+            })
+            .collect())
+    })
+}
+
+#[napi(object)]
+pub struct JsLanguageCount {
+    pub language: String,
+    pub count: f64,
+}
+
+/// Distinct symbol languages in the current workspace with their symbol
+/// counts, most common first, for building filter UIs. Languages are stored
+/// normalized, so filters can also use aliases ("ts", "py", ...).
+#[napi(catch_unwind)]
+pub fn get_languages() -> napi::Result<Vec<JsLanguageCount>> {
+    with_state(|state| {
+        let counts = get_db(state)?
+            .language_counts()
+            .map_err(|e| napi::Error::from_reason(format!("DB error: {}", e)))?;
+        Ok(counts
+            .into_iter()
+            .map(|(language, count)| JsLanguageCount {
+                language,
+                count: count as f64,
+            })
+            .collect())
+    })
+}
+
+/// Symbols sampled by `get_index_profile` to measure token lengths.
+const PROFILE_SAMPLE_SIZE: u32 = 2000;
+
+#[napi(object)]
+pub struct JsHistogramBucket {
+    /// Inclusive bounds
+    pub min: f64,
+    pub max: f64,
+    pub count: f64,
+}
+
+#[napi(object)]
+pub struct JsDistribution {
+    pub count: f64,
+    pub mean: f64,
+    pub p50: f64,
+    pub p90: f64,
+    pub p99: f64,
+    pub max: f64,
+    /// Power-of-two buckets (0, 1, 2-3, 4-7, ...), empty ones omitted
+    pub buckets: Vec<JsHistogramBucket>,
+}
+
+#[napi(object)]
+pub struct JsTableSize {
+    pub table: String,
+    /// Pages of the table and its indexes
+    pub bytes: f64,
+}
+
+#[napi(object)]
+pub struct JsIndexProfile {
+    /// Over the current workspace's files, including those with no symbols
+    pub symbols_per_file: JsDistribution,
+    pub languages: Vec<JsLanguageCount>,
+    /// Tokens per embedding text (with the document prefix and special
+    /// tokens), over a random sample of symbols
+    pub embedding_tokens: JsDistribution,
+    /// Share of sampled symbols longer than one model input (128 tokens),
+    /// whose tails are only searchable with sliding windows
+    pub over_max_length: f64,
+    /// Whole index file, all workspaces
+    pub table_sizes: Vec<JsTableSize>,
+}
+
+/// Summary statistics and a power-of-two histogram of `values`.
+fn distribution(mut values: Vec<u64>) -> JsDistribution {
+    values.sort_unstable();
+    let percentile = |p: f64| match values.len() {
+        0 => 0.0,
+        n => values[((n - 1) as f64 * p).round() as usize] as f64,
+    };
+    let mut buckets: Vec<JsHistogramBucket> = Vec::new();
+    for &v in &values {
+        let (min, max) = match v {
+            0 => (0, 0),
+            v => {
+                let min = 1u64 << (63 - v.leading_zeros());
+                (min, min * 2 - 1)
+            }
+        };
+        match buckets.last_mut() {
+            Some(b) if b.min == min as f64 => b.count += 1.0,
+            _ => buckets.push(JsHistogramBucket {
+                min: min as f64,
+                max: max as f64,
+                count: 1.0,
+            }),
+        }
+    }
+    JsDistribution {
+        count: values.len() as f64,
+        mean: values.iter().sum::<u64>() as f64 / values.len().max(1) as f64,
+        p50: percentile(0.5),
+        p90: percentile(0.9),
+        p99: percentile(0.99),
+        max: values.last().copied().unwrap_or(0) as f64,
+        buckets,
+    }
+}
+
+/// How the index's contents are distributed, to guide chunking and
+/// exclusion settings: symbols per file, symbols per language, embedding
+/// text length in tokens (over `sample_size` random symbols, default 2000),
+/// and disk use per table.
+#[napi(catch_unwind)]
+pub fn get_index_profile(sample_size: Option<u32>) -> napi::Result<JsIndexProfile> {
+    with_state(|state| {
+        let db = get_db(state)?;
+        let to_napi = |e: rusqlite::Error| napi::Error::from_reason(format!("DB error: {}", e));
+        let per_file = db.symbols_per_file().map_err(to_napi)?;
+        let languages = db.language_counts().map_err(to_napi)?;
+        let table_sizes = db.table_sizes().map_err(to_napi)?;
+        let texts = db
+            .sample_embedding_texts(sample_size.unwrap_or(PROFILE_SAMPLE_SIZE) as usize)
+            .map_err(to_napi)?;
+
+        let mut tokens = Vec::with_capacity(texts.len());
+        for text in &texts {
+            let text = format!("{}{}", state.document_prefix, text);
+            let encoding = state
+                .tokenizer
+                .encode(text.as_str(), true)
+                .map_err(|e| napi::Error::from_reason(format!("Tokenization failed: {}", e)))?;
+            tokens.push(encoding.get_ids().len() as u64);
+        }
+        let over = tokens.iter().filter(|&&n| n > MAX_LENGTH as u64).count();
+
+        Ok(JsIndexProfile {
+            symbols_per_file: distribution(per_file),
+            languages: languages
+                .into_iter()
+                .map(|(language, count)| JsLanguageCount {
+                    language,
+                    count: count as f64,
+                })
+                .collect(),
+            over_max_length: over as f64 / tokens.len().max(1) as f64,
+            embedding_tokens: distribution(tokens),
+            table_sizes: table_sizes
+                .into_iter()
+                .map(|(table, bytes)| JsTableSize {
+                    table,
+                    bytes: bytes as f64,
+                })
+                .collect(),
+        })
+    })
+}
+
+// ── Embedding handles ──────────────────────────────────────────────────
+
+/// Embeddings held natively for JS behind an opaque `External`; see
+/// `embed_handle`.
+pub struct EmbeddingBatch {
+    vectors: Vec<Vec<f32>>,
+    is_query: bool,
+}
+
+#[napi(object)]
+pub struct JsEmbeddingHandleInfo {
+    pub count: u32,
+    pub dims: u32,
+    pub is_query: bool,
+}
+
+/// Like `embed`, but the vectors stay in native memory: JS gets an opaque
+/// handle to pass to `embedding_handle_get` or `index_symbols_with_handle`,
+/// so nothing is copied into V8 unless asked for. Freed when the handle is
+/// garbage collected.
+#[napi(catch_unwind)]
+pub fn embed_handle(texts: Vec<String>, is_query: bool) -> napi::Result<External<EmbeddingBatch>> {
+    with_state(|state| {
+        let vectors = embed_internal(state, &texts, is_query)?;
+        let bytes = vectors.len() * state.dims * std::mem::size_of::<f32>();
+        Ok(External::new_with_size_hint(EmbeddingBatch { vectors, is_query }, bytes))
+    })
+}
+
+#[napi(catch_unwind)]
+pub fn embedding_handle_info(handle: External<EmbeddingBatch>) -> JsEmbeddingHandleInfo {
+    JsEmbeddingHandleInfo {
+        count: handle.vectors.len() as u32,
+        dims: handle.vectors.first().map_or(0, |v| v.len() as u32),
+        is_query: handle.is_query,
+    }
+}
+
+/// Copy one vector out of a handle, e.g. to inspect it.
+#[napi(catch_unwind)]
+pub fn embedding_handle_get(
+    handle: External<EmbeddingBatch>,
+    index: u32,
+) -> napi::Result<Float32Array> {
+    handle
+        .vectors
+        .get(index as usize)
+        .map(|v| Float32Array::new(v.clone()))
+        .ok_or_else(|| {
+            napi::Error::from_reason(format!(
+                "Index {} out of range for {} embeddings",
+                index,
+                handle.vectors.len()
+            ))
+        })
+}
+
+/// `index_symbols` with the symbols' embeddings taken from `handle` (one per
+/// symbol, in order, embedded as documents) instead of embedding their
+/// text again. Doc comments and extra windows are still embedded here.
+#[napi(catch_unwind)]
+pub fn index_symbols_with_handle(
+    mut symbols: Vec<SymbolInput>,
+    handle: External<EmbeddingBatch>,
+) -> napi::Result<()> {
+    if handle.is_query {
+        return Err(napi::Error::from_reason(
+            "Handle holds query embeddings; index with document embeddings",
+        ));
+    }
+    if handle.vectors.len() != symbols.len() {
+        return Err(napi::Error::from_reason(format!(
+            "Handle holds {} embeddings for {} symbols",
+            handle.vectors.len(),
+            symbols.len()
+        )));
+    }
+    with_state(|state| {
+        if let Some(v) = handle.vectors.iter().find(|v| v.len() != state.dims) {
+            return Err(napi::Error::from_reason(format!(
+                "Handle embeddings have {} dimensions, model produces {}",
+                v.len(),
+                state.dims
+            )));
+        }
+        prepare_symbols(state, &mut symbols)?;
+        let docs: Vec<String> = symbols
+            .iter()
+            .filter_map(|s| s.doc_comment.clone().filter(|d| !d.is_empty()))
+            .collect();
+        let mut doc_vectors = embed_internal(state, &docs, false)?.into_iter();
+        let doc_embeddings: DocEmbeddings = symbols
+            .iter()
+            .map(|s| match &s.doc_comment {
+                Some(d) if !d.is_empty() => doc_vectors.next(),
+                _ => None,
+            })
+            .collect();
+        let extra = embed_extra(state, &symbols)?;
+
+        let model = state.info.name.clone();
+        let db = get_db(state)?;
+        let ws = db.workspace().to_string();
+        let pq = db.pq_codebook();
+        let compress = db.text_compression();
+        let tx = db.transaction()
+            .map_err(|e| napi::Error::from_reason(format!("DB error: {}", e)))?;
+        insert_symbols(&tx, &ws, &model, pq.as_deref(), compress, &symbols, &handle.vectors, &doc_embeddings, &extra)?;
+        tx.commit()
+            .map_err(|e| napi::Error::from_reason(format!("DB error: {}", e)))?;
+        if symbols.len() >= ANALYZE_MIN_ROWS {
+            db.analyze()
+                .map_err(|e| napi::Error::from_reason(format!("DB error: {}", e)))?;
+        }
+        enforce_budget(db)?;
+        Ok(())
+    })
+}
+
+// ── Call graph ─────────────────────────────────────────────────────────
+
+/// Neighbors added per hit by `search_with_neighbors` unless overridden.
+const DEFAULT_MAX_NEIGHBORS: u32 = 5;
+/// Deepest `expand` accepted by `search_with_neighbors`.
+const MAX_EXPAND: u32 = 3;
+
+#[napi(object)]
+pub struct EdgeInput {
+    /// `symbol_id` of the calling/referencing symbol
+    pub caller: String,
+    /// `symbol_id` of the called/referenced symbol
+    pub callee: String,
+}
+
+/// Record call/reference edges between symbols in the current workspace,
+/// for `search_with_neighbors`. Returns how many were new.
+#[napi(catch_unwind)]
+pub fn insert_edges(edges: Vec<EdgeInput>) -> napi::Result<u32> {
+    with_state(|state| {
+        let pairs: Vec<(&str, &str)> = edges
+            .iter()
+            .map(|e| (e.caller.as_str(), e.callee.as_str()))
+            .collect();
+        get_db(state)?
+            .insert_edges(&pairs)
+            .map(|n| n as u32)
+            .map_err(|e| napi::Error::from_reason(format!("DB error: {}", e)))
+    })
+}
+
+/// Remove the outgoing edges of `callers` (symbol ids), e.g. before
+/// re-recording a changed file's calls. Returns how many were removed.
+#[napi(catch_unwind)]
+pub fn delete_edges(callers: Vec<String>) -> napi::Result<u32> {
+    with_state(|state| {
+        let callers: Vec<&str> = callers.iter().map(String::as_str).collect();
+        get_db(state)?
+            .delete_edges(&callers)
+            .map(|n| n as u32)
+            .map_err(|e| napi::Error::from_reason(format!("DB error: {}", e)))
+    })
+}
+
+#[napi(object)]
+pub struct NeighborOptions {
+    /// Edge hops to follow from each hit (default 1, at most 3)
+    pub expand: Option<u32>,
+    /// Neighbors added per hit (default 5)
+    pub max_neighbors: Option<u32>,
+}
+
+/// `search`, then pull in the symbols connected to each hit through the
+/// edge table (see `insert_edges`), in either direction, so related code
+/// comes along even when it didn't match the query. Each hit is followed by
+/// its neighbors, which have `neighbor_of` set and are scored by their own
+/// similarity to the queries (0 while evicted). Symbols already in the
+/// results aren't repeated.
+#[napi(catch_unwind)]
+pub fn search_with_neighbors(
+    queries: Vec<String>,
+    top_k: i32,
+    threshold: Either<f64, KindThresholds>,
+    filters: SearchFilters,
+    options: Option<NeighborOptions>,
+) -> napi::Result<Vec<JsSearchResult>> {
+    let threshold = kind_thresholds(threshold);
+    let (expand, max_neighbors) = match &options {
+        Some(o) => (o.expand.unwrap_or(1), o.max_neighbors.unwrap_or(DEFAULT_MAX_NEIGHBORS)),
+        None => (1, DEFAULT_MAX_NEIGHBORS),
+    };
+    if expand == 0 || expand > MAX_EXPAND {
+        return Err(napi::Error::from_reason(format!(
+            "expand must be between 1 and {}",
+            MAX_EXPAND
+        )));
+    }
+
+    with_state(|state| {
+        if queries.is_empty() {
+            return Ok(Vec::new());
+        }
+        restore_on_access(state, &filters);
+        let query_embeddings = embed_internal(state, &queries, true)?;
+        let db = get_db(state)?;
+        let hits = search_embedded(db, &query_embeddings, top_k, &threshold, &filters, None)?;
+
+        let workspace = filters.workspace.as_deref();
+        let mut seen: HashSet<(String, i32)> =
+            hits.iter().map(|r| (r.file_path.clone(), r.line)).collect();
+        let mut results = Vec::with_capacity(hits.len());
+        for hit in hits {
+            let hit_id = hit.symbol_id.clone();
+            results.push(JsSearchResult::from(hit));
+
+            let mut added = 0;
+            let mut frontier = vec![hit_id.clone()];
+            for _ in 0..expand {
+                let mut next = Vec::new();
+                for id in &frontier {
+                    let found = db
+                        .neighbors(workspace, id)
+                        .map_err(|e| napi::Error::from_reason(format!("DB error: {}", e)))?;
+                    for (mut r, emb) in found {
+                        if added >= max_neighbors {
+                            break;
+                        }
+                        if !seen.insert((r.file_path.clone(), r.line)) {
+                            continue;
+                        }
+                        r.score = emb.map_or(0.0, |e| {
+                            query_embeddings
+                                .iter()
+                                .map(|q| f32::dot(q, &e).unwrap_or(0.0))
+                                .fold(f64::MIN, f64::max)
+                        });
+                        next.push(r.symbol_id.clone());
+                        results.push(JsSearchResult {
+                            neighbor_of: Some(hit_id.clone()),
+                            ..JsSearchResult::from(r)
+                        });
+                        added += 1;
+                    }
+                }
+                frontier = next;
+            }
+        }
+        Ok(results)
+    })
+}
+
+// ── Quantization ───────────────────────────────────────────────────────
+
+#[napi(object)]
+pub struct JsPqTrainResult {
+    /// Embeddings the codebook was trained on
+    pub samples: f64,
+    /// Symbols re-encoded with the new codebook
+    pub rows_encoded: f64,
+    /// Size of one encoded embedding (`m` bytes)
+    pub bytes_per_row: f64,
+}
+
+pub struct TrainPqTask {
+    sample_size: usize,
+    m: usize,
+    bits: u32,
+}
+
+impl napi::Task for TrainPqTask {
+    type Output = JsPqTrainResult;
+    type JsValue = JsPqTrainResult;
+
+    fn compute(&mut self) -> napi::Result<Self::Output> {
+        with_state(|state| {
+            let db = get_db(state)?;
+            let samples = db
+                .sample_embeddings(self.sample_size)
+                .map_err(|e| napi::Error::from_reason(format!("DB error: {}", e)))?;
+            let codebook =
+                pq::Codebook::train(&samples, self.m, self.bits).map_err(napi::Error::from_reason)?;
+            let bytes_per_row = codebook.m;
+            let rows_encoded = db
+                .set_pq_codebook(codebook)
+                .map_err(|e| napi::Error::from_reason(format!("DB error: {}", e)))?;
+            Ok(JsPqTrainResult {
+                samples: samples.len() as f64,
+                rows_encoded: rows_encoded as f64,
+                bytes_per_row: bytes_per_row as f64,
+            })
+        })
+    }
+
+    fn resolve(&mut self, _env: napi::Env, output: Self::Output) -> napi::Result<Self::JsValue> {
+        Ok(output)
+    }
+}
+
+/// Train a product quantization codebook on up to `sample_size` random
+/// symbol embeddings and re-encode every stored symbol with it. Each
+/// embedding splits into `m` subvectors (`m` must divide the dimensions),
+/// coded with `bits` (1-8) each: `m = 192, bits = 8` codes 768-dim
+/// embeddings in 192 bytes, 16x smaller. Symbols indexed later are encoded
+/// automatically; retrain after large changes to the index.
+///
+/// The codes speed up searches, not storage: they're kept next to the full
+/// embeddings, which `filters.quantized` searches use to rescore the
+/// shortlist the codes pick, so the index grows slightly.
+///
+/// Enables `filters.quantized`. Runs off the JS thread, but holds the index
+/// for the duration, so other calls wait.
+#[napi(catch_unwind)]
+pub fn train_pq(sample_size: u32, m: u32, bits: u32) -> AsyncTask<TrainPqTask> {
+    AsyncTask::new(TrainPqTask {
+        sample_size: sample_size as usize,
+        m: m as usize,
+        bits,
+    })
+}
+
+// ── Clustering ─────────────────────────────────────────────────────────
+
+/// Embeddings `cluster_index` trains on by default.
+const CLUSTER_SAMPLE_SIZE: u32 = 10_000;
+
+#[napi(object)]
+pub struct JsCluster {
+    pub id: u32,
+    /// The cluster's leading top terms, e.g. "http request client"
+    pub label: String,
+    /// Name terms most specific to the cluster, best first
+    pub top_terms: Vec<String>,
+    /// Symbols assigned to it
+    pub size: f64,
+}
+
+impl From<db::Cluster> for JsCluster {
+    fn from(c: db::Cluster) -> Self {
+        JsCluster {
+            id: c.id,
+            label: c.label,
+            top_terms: c.top_terms,
+            size: c.size as f64,
+        }
+    }
+}
+
+#[napi(object)]
+pub struct JsClusterResult {
+    /// Embeddings the centroids were trained on
+    pub samples: f64,
+    /// Symbols assigned a cluster
+    pub rows_assigned: f64,
+    /// Non-empty clusters, largest first
+    pub clusters: Vec<JsCluster>,
+}
+
+pub struct ClusterIndexTask {
+    k: usize,
+    sample_size: usize,
+}
+
+impl napi::Task for ClusterIndexTask {
+    type Output = JsClusterResult;
+    type JsValue = JsClusterResult;
+
+    fn compute(&mut self) -> napi::Result<Self::Output> {
+        with_state(|state| {
+            let db = get_db(state)?;
+            let samples = db
+                .sample_workspace_embeddings(self.sample_size)
+                .map_err(|e| napi::Error::from_reason(format!("DB error: {}", e)))?;
+            let centroids =
+                cluster::Centroids::train(&samples, self.k).map_err(napi::Error::from_reason)?;
+            let rows_assigned = db
+                .set_clusters(&centroids)
+                .map_err(|e| napi::Error::from_reason(format!("DB error: {}", e)))?;
+            let clusters = db
+                .clusters()
+                .map_err(|e| napi::Error::from_reason(format!("DB error: {}", e)))?;
+            Ok(JsClusterResult {
+                samples: samples.len() as f64,
+                rows_assigned: rows_assigned as f64,
+                clusters: clusters.into_iter().map(JsCluster::from).collect(),
+            })
+        })
+    }
+
+    fn resolve(&mut self, _env: napi::Env, output: Self::Output) -> napi::Result<Self::JsValue> {
+        Ok(output)
+    }
+}
+
+/// Group the current workspace's symbols into `k` topical clusters: k-means
+/// on up to `sample_size` (default 10000) random embeddings, then every
+/// embedded symbol is assigned its nearest centroid. Each cluster is
+/// labeled by the name terms most specific to it. Replaces the previous
+/// run's clusters; symbols indexed afterwards have none until the next run.
+///
+/// Runs off the JS thread, but holds the index for the duration, so other
+/// calls wait.
+#[napi(catch_unwind)]
+pub fn cluster_index(k: u32, sample_size: Option<u32>) -> AsyncTask<ClusterIndexTask> {
+    AsyncTask::new(ClusterIndexTask {
+        k: k as usize,
+        sample_size: sample_size.unwrap_or(CLUSTER_SAMPLE_SIZE) as usize,
+    })
+}
+
+/// The current workspace's clusters from the last `cluster_index`, largest
+/// first; empty if it never ran.
+#[napi(catch_unwind)]
+pub fn get_clusters() -> napi::Result<Vec<JsCluster>> {
+    with_state(|state| {
+        let clusters = get_db(state)?
+            .clusters()
+            .map_err(|e| napi::Error::from_reason(format!("DB error: {}", e)))?;
+        Ok(clusters.into_iter().map(JsCluster::from).collect())
+    })
+}
+
+#[napi(object)]
+#[derive(Default)]
+pub struct DuplicateOptions {
+    /// Most pairs to return (default 100)
+    pub limit: Option<u32>,
+    /// Only compare symbols matching these
+    pub filters: Option<SearchFilters>,
+}
+
+#[napi(object)]
+pub struct JsDuplicatePair {
+    pub a: JsSearchResult,
+    pub b: JsSearchResult,
+    pub similarity: f64,
+}
+
+#[napi(object)]
+pub struct JsDuplicateReport {
+    /// Most similar first
+    pub pairs: Vec<JsDuplicatePair>,
+    /// Pairs at or above the threshold, including those past `limit`
+    pub total: f64,
+    /// Clusters compared
+    pub clusters: u32,
+    /// Symbols indexed since the last `cluster_index`, which weren't
+    /// compared
+    pub unclustered: f64,
+}
+
+pub struct FindDuplicatesTask {
+    threshold: f64,
+    options: Option<DuplicateOptions>,
+}
+
+impl napi::Task for FindDuplicatesTask {
+    type Output = JsDuplicateReport;
+    type JsValue = JsDuplicateReport;
+
+    fn compute(&mut self) -> napi::Result<Self::Output> {
+        if !(-1.0..=1.0).contains(&self.threshold) {
+            return Err(napi::Error::from_reason("threshold must be between -1 and 1"));
+        }
+        let options = self.options.take().unwrap_or_default();
+        let filters = options.filters.unwrap_or_default();
+        let resolved = ResolvedFilters::new(&filters)?;
+        let db_filters = resolved.db();
+        let limit = options.limit.unwrap_or(100) as usize;
+        with_state(|state| {
+            let scan = get_db(state)?
+                .near_duplicates(self.threshold as f32, limit, &db_filters)
+                .map_err(|e| napi::Error::from_reason(format!("DB error: {}", e)))?;
+            if scan.clusters == 0 {
+                return Err(napi::Error::from_reason(
+                    "Index has no clusters. Call cluster_index first.",
+                ));
+            }
+            Ok(JsDuplicateReport {
+                pairs: scan
+                    .pairs
+                    .into_iter()
+                    .map(|p| JsDuplicatePair {
+                        a: JsSearchResult::from(p.a),
+                        b: JsSearchResult::from(p.b),
+                        similarity: p.similarity,
+                    })
+                    .collect(),
+                total: scan.total as f64,
+                clusters: scan.clusters as u32,
+                unclustered: scan.unclustered as f64,
+            })
+        })
+    }
+
+    fn resolve(&mut self, _env: napi::Env, output: Self::Output) -> napi::Result<Self::JsValue> {
+        Ok(output)
+    }
+}
+
+/// Report pairs of symbols whose embeddings have a similarity of at least
+/// `threshold` (0.95 and up is usually copy-paste with small edits), as
+/// refactoring candidates. Only symbols in the same cluster are compared,
+/// which keeps the scan far below all pairs, so call `cluster_index`
+/// first; more clusters is faster but misses more pairs that straddle two.
+/// Pairs where one symbol encloses the other are skipped.
+///
+/// Runs off the JS thread, but holds the index for the duration, so other
+/// calls wait.
+#[napi(catch_unwind)]
+pub fn find_near_duplicates(
+    threshold: f64,
+    options: Option<DuplicateOptions>,
+) -> AsyncTask<FindDuplicatesTask> {
+    AsyncTask::new(FindDuplicatesTask { threshold, options })
+}
+
+// ── Re-embedding ───────────────────────────────────────────────────────
+
+#[napi(object)]
+pub struct JsReembedProgress {
+    /// Symbols and chunks re-embedded so far
+    pub done: f64,
+    pub total: f64,
+}
+
+#[napi(object)]
+pub struct JsReembedResult {
+    pub symbols: f64,
+    pub chunks: f64,
+    /// Dimensions recorded in meta, from the loaded model
+    pub dimensions: u32,
+}
+
+pub struct ReembedTask {
+    batch_size: usize,
+    on_progress: Option<ThreadsafeFunction<JsReembedProgress, ErrorStrategy::Fatal>>,
+}
+
+impl ReembedTask {
+    fn report(&self, done: u64, total: u64) {
+        if let Some(cb) = &self.on_progress {
+            cb.call(
+                JsReembedProgress {
+                    done: done as f64,
+                    total: total as f64,
+                },
+                ThreadsafeFunctionCallMode::NonBlocking,
+            );
+        }
+    }
+}
+
+impl napi::Task for ReembedTask {
+    type Output = JsReembedResult;
+    type JsValue = JsReembedResult;
+
+    fn compute(&mut self) -> napi::Result<Self::Output> {
+        let (symbol_total, chunk_total, rebuild_vec0) = with_state(|state| {
+            let dims = state.info.config.n_embd as usize;
+            let db = get_db(state)?;
+            let resized = db
+                .get_meta("dimensions")
+                .map_err(|e| napi::Error::from_reason(format!("DB error: {}", e)))?
+                .and_then(|d| d.parse::<usize>().ok())
+                != Some(dims);
+            // vec_symbols' column has a fixed length, so its triggers would
+            // reject the new vectors: search blobs until the run finishes
+            let rebuild_vec0 = resized && db.vector_storage() == db::VectorStorage::Vec0;
+            if rebuild_vec0 {
+                db.set_vector_storage(db::VectorStorage::Blob)
+                    .map_err(|e| napi::Error::from_reason(format!("DB error: {}", e)))?;
+                state.sessions.clear();
+            }
+            let db = get_db(state)?;
+            let (symbols, chunks) = db
+                .clear_pq_codebook()
+                .and_then(|_| db.embedded_counts())
+                .map_err(|e| napi::Error::from_reason(format!("DB error: {}", e)))?;
+            Ok((symbols, chunks, rebuild_vec0))
+        })?;
+        let total = symbol_total + chunk_total;
+        let mut done = 0u64;
+        self.report(done, total);
+
+        let mut after: Option<db::RowKey> = None;
+        loop {
+            let page = with_state(|state| {
+                let page = get_db(state)?
+                    .embedded_symbols_page(after.as_ref(), self.batch_size)
+                    .map_err(|e| napi::Error::from_reason(format!("DB error: {}", e)))?;
+                let Some((ws, last)) = page.last() else {
+                    return Ok(0);
+                };
+                after = Some((ws.clone(), last.file_path.clone(), last.line));
+                let (workspaces, symbols): (Vec<String>, Vec<SymbolInput>) = page
+                    .into_iter()
+                    .map(|(ws, s)| (ws, SymbolInput::from(s)))
+                    .unzip();
+                let (embeddings, doc_embeddings, extra) =
+                    state.on_background_device(|state| embed_symbols(state, &symbols))?;
+
+                let model = state.info.name.clone();
+                let db = get_db(state)?;
+                let tx = db.transaction()
+                    .map_err(|e| napi::Error::from_reason(format!("DB error: {}", e)))?;
+                {
+                    let mut stmt = tx.prepare_cached(
+                        "UPDATE symbols SET embedding = ?, doc_embedding = ?, embedding_bits = ?, embedding_model = ?
+                         WHERE workspace = ? AND file_path = ? AND line = ?",
+                    ).map_err(|e| napi::Error::from_reason(format!("DB error: {}", e)))?;
+                    for (i, (((ws, sym), emb), doc)) in workspaces
+                        .iter()
+                        .zip(&symbols)
+                        .zip(&embeddings)
+                        .zip(&doc_embeddings)
+                        .enumerate()
+                    {
+                        let embedding_bytes: &[u8] = bytemuck::cast_slice(emb.as_slice());
+                        let doc_bytes: Option<&[u8]> =
+                            doc.as_ref().map(|d| bytemuck::cast_slice(d.as_slice()));
+                        stmt.execute(rusqlite::params![
+                            embedding_bytes,
+                            doc_bytes,
+                            db::binarize(emb),
+                            model,
+                            ws,
+                            sym.file_path,
+                            sym.line
+                        ]).map_err(|e| napi::Error::from_reason(format!("DB error: {}", e)))?;
+                        let sym_windows = extra.windows.get(i).map_or(&[][..], |w| w.as_slice());
+                        replace_windows(&tx, ws, &sym.file_path, sym.line, sym_windows)?;
+                        let sym_tokens = extra.tokens.get(i).map_or(&[][..], |t| t.as_slice());
+                        replace_tokens(&tx, ws, &sym.file_path, sym.line, sym_tokens)?;
+                        let sym_sparse = extra.sparse.get(i).map_or(&[][..], |t| t.as_slice());
+                        replace_sparse(&tx, ws, &sym.file_path, sym.line, sym_sparse)?;
+                    }
+                }
+                tx.commit()
+                    .map_err(|e| napi::Error::from_reason(format!("DB error: {}", e)))?;
+                Ok(symbols.len())
+            })?;
+            if page == 0 {
+                break;
+            }
+            done += page as u64;
+            self.report(done, total);
+        }
+
+        let mut after: Option<db::RowKey> = None;
+        loop {
+            let page = with_state(|state| {
+                let page = get_db(state)?
+                    .embedded_chunks_page(after.as_ref(), self.batch_size)
+                    .map_err(|e| napi::Error::from_reason(format!("DB error: {}", e)))?;
+                let Some((last, _)) = page.last() else {
+                    return Ok(0);
+                };
+                after = Some(last.clone());
+                let texts: Vec<String> = page.iter().map(|(_, text)| text.clone()).collect();
+                let embeddings =
+                    state.on_background_device(|state| embed_internal(state, &texts, false))?;
+
+                let db = get_db(state)?;
+                let tx = db.transaction()
+                    .map_err(|e| napi::Error::from_reason(format!("DB error: {}", e)))?;
+                for (((ws, path, start_line), _), emb) in page.iter().zip(&embeddings) {
+                    let embedding_bytes: &[u8] = bytemuck::cast_slice(emb.as_slice());
+                    tx.execute(
+                        "UPDATE chunks SET embedding = ? WHERE workspace = ? AND file_path = ? AND start_line = ?",
+                        rusqlite::params![embedding_bytes, ws, path, start_line],
+                    ).map_err(|e| napi::Error::from_reason(format!("DB error: {}", e)))?;
+                }
+                tx.commit()
+                    .map_err(|e| napi::Error::from_reason(format!("DB error: {}", e)))?;
+                Ok(page.len())
+            })?;
+            if page == 0 {
+                break;
+            }
+            done += page as u64;
+            self.report(done, total);
+        }
+
+        with_state(|state| {
+            let name = state.info.name.clone();
+            let dimensions = state.info.config.n_embd as u32;
+            let db = get_db(state)?;
+            db.set_meta("model", &name)
+                .and_then(|_| db.set_meta("dimensions", &dimensions.to_string()))
+                .map_err(|e| napi::Error::from_reason(format!("DB error: {}", e)))?;
+            if rebuild_vec0 {
+                db.set_vector_storage(db::VectorStorage::Vec0)
+                    .map_err(|e| napi::Error::from_reason(format!("DB error: {}", e)))?;
+                state.sessions.clear();
+            }
+            Ok(JsReembedResult {
+                symbols: symbol_total as f64,
+                chunks: chunk_total as f64,
+                dimensions,
+            })
+        })
+    }
+
+    fn resolve(&mut self, _env: napi::Env, output: Self::Output) -> napi::Result<Self::JsValue> {
+        Ok(output)
+    }
+}
+
+/// Re-embed every stored symbol and chunk, in all workspaces, from its
+/// stored text with the loaded model, then record the model's name and
+/// dimensions in meta. Use after switching models instead of re-extracting
+/// the repo. Evicted rows are left for `restore_evicted`, which will use the
+/// new model anyway.
+///
+/// Runs off the JS thread, `batch_size` rows (default 256) per transaction,
+/// releasing the index between batches so searches keep working; until it
+/// finishes they see a mix of old and new vectors (see
+/// `SearchOptions.stale_weight`). If the new model's dimensions differ,
+/// old vectors can't be scored against its queries at all, so searches
+/// meanwhile only find rows already re-embedded; a `"vec0"` index searches
+/// blobs until the run finishes and then rebuilds `vec_symbols` at the new
+/// size (after a failed run, call `set_vector_storage("vec0")` again). The
+/// PQ codebook no longer fits the new vectors and is dropped: retrain it
+/// with `train_pq`. `on_progress` receives `{ done, total }` after each
+/// batch.
+#[napi(catch_unwind)]
+pub fn reembed_all(
+    batch_size: Option<u32>,
+    on_progress: Option<ThreadsafeFunction<JsReembedProgress, ErrorStrategy::Fatal>>,
+) -> AsyncTask<ReembedTask> {
+    AsyncTask::new(ReembedTask {
+        batch_size: batch_size.map_or(256, |b| b.max(1) as usize),
+        on_progress,
+    })
+}
+
+#[napi(object)]
+pub struct JsStaleEmbedding {
+    pub file_path: String,
+    pub line: i32,
+    pub name: String,
+    pub kind: String,
+    /// Model that computed the stored embedding, if recorded
+    pub model: Option<String>,
+}
+
+#[napi(object)]
+pub struct JsStaleEmbeddings {
+    /// Stale symbols in the workspace, including those past `limit`
+    pub total: f64,
+    pub symbols: Vec<JsStaleEmbedding>,
+}
+
+/// Symbols in the current workspace whose stored embedding was computed by
+/// a model other than `current_model` (default: the loaded model's name),
+/// e.g. to watch a `reembed_all` progress or re-index just those files.
+/// Returns at most `limit` (default 1000) in path order, plus the total.
+#[napi(catch_unwind)]
+pub fn find_stale_embeddings(
+    current_model: Option<String>,
+    limit: Option<u32>,
+) -> napi::Result<JsStaleEmbeddings> {
+    with_state(|state| {
+        let model = current_model.unwrap_or_else(|| state.info.name.clone());
+        let (total, rows) = get_db(state)?
+            .stale_embeddings(&model, limit.unwrap_or(1000) as usize)
+            .map_err(|e| napi::Error::from_reason(format!("DB error: {}", e)))?;
+        Ok(JsStaleEmbeddings {
+            total: total as f64,
+            symbols: rows
+                .into_iter()
+                .map(|r| JsStaleEmbedding {
+                    file_path: r.file_path,
+                    line: r.line,
+                    name: r.name,
+                    kind: r.kind,
+                    model: r.model,
+                })
+                .collect(),
+        })
+    })
+}
+
+// ── Size budget ────────────────────────────────────────────────────────
+
+const SIZE_BUDGET_META: &str = "size_budget";
+
+#[napi(object)]
+pub struct JsEvictionStats {
+    pub files: f64,
+    pub symbols: f64,
+    pub chunks: f64,
+    /// Vector bytes dropped
+    pub bytes: f64,
+}
+
+impl From<db::EvictionStats> for JsEvictionStats {
+    fn from(s: db::EvictionStats) -> Self {
+        JsEvictionStats {
+            files: s.files as f64,
+            symbols: s.symbols as f64,
+            chunks: s.chunks as f64,
+            bytes: s.bytes as f64,
+        }
+    }
+}
+
+#[napi(object)]
+pub struct JsIndexSize {
+    /// Bytes in use in the DB file (free pages excluded)
+    pub bytes: f64,
+    pub budget: Option<f64>,
+    /// Currently evicted, across all workspaces
+    pub evicted_files: f64,
+    pub evicted_symbols: f64,
+    pub evicted_chunks: f64,
+}
+
+#[napi(object)]
+pub struct JsRestoreResult {
+    pub symbols: f64,
+    pub chunks: f64,
+}
+
+/// Evict least recently searched files until the index fits its budget.
+/// No-op without a budget.
+fn enforce_budget(db: &mut SearchDB) -> napi::Result<db::EvictionStats> {
+    let budget = db
+        .get_meta(SIZE_BUDGET_META)
+        .map_err(|e| napi::Error::from_reason(format!("DB error: {}", e)))?
+        .and_then(|b| b.parse::<u64>().ok());
+    match budget {
+        Some(budget) => db
+            .evict_lru(budget)
+            .map_err(|e| napi::Error::from_reason(format!("DB error: {}", e))),
+        None => Ok(db::EvictionStats::default()),
+    }
+}
+
+/// Cap the open index at `max_bytes` (None removes the cap) and enforce it
+/// now. Whenever indexing pushes the index over the cap, the vectors of the
+/// least recently searched files are dropped; their records and embedding
+/// text stay, so `restore_evicted` can re-embed them without re-extracting.
+/// Searches restore evicted files in their scope before running (see
+/// `restore_on_access`).
+#[napi(catch_unwind)]
+pub fn set_index_budget(max_bytes: Option<f64>) -> napi::Result<JsEvictionStats> {
+    with_state(|state| {
+        let db = get_db(state)?;
+        match max_bytes {
+            Some(b) if b <= 0.0 => {
+                return Err(napi::Error::from_reason("max_bytes must be positive"))
+            }
+            Some(b) => db.set_meta(SIZE_BUDGET_META, &(b as u64).to_string()),
+            None => db.delete_meta(SIZE_BUDGET_META),
+        }
+        .map_err(|e| napi::Error::from_reason(format!("DB error: {}", e)))?;
+        Ok(enforce_budget(db)?.into())
+    })
+}
+
+/// Current index size, budget, and what is evicted.
+#[napi(catch_unwind)]
+pub fn get_index_size() -> napi::Result<JsIndexSize> {
+    with_state(|state| {
+        let db = get_db(state)?;
+        let to_err = |e: rusqlite::Error| napi::Error::from_reason(format!("DB error: {}", e));
+        let bytes = db.index_size().map_err(to_err)?;
+        let budget = db
+            .get_meta(SIZE_BUDGET_META)
+            .map_err(to_err)?
+            .and_then(|b| b.parse::<f64>().ok());
+        let (files, symbols, chunks) = db.eviction_counts().map_err(to_err)?;
+        Ok(JsIndexSize {
+            bytes: bytes as f64,
+            budget,
+            evicted_files: files as f64,
+            evicted_symbols: symbols as f64,
+            evicted_chunks: chunks as f64,
+        })
+    })
+}
+
+/// Re-embed evicted symbols and chunks from their stored text, for `paths`
+/// or every evicted file in the current workspace. Restored files count as
+/// just searched, so the next eviction pass takes them last.
+#[napi(catch_unwind)]
+pub fn restore_evicted(paths: Option<Vec<String>>) -> napi::Result<JsRestoreResult> {
+    with_state(|state| restore_rows(state, paths.as_deref()))
+}
+
+/// Evicted files a search re-embeds before it runs (see `restore_on_access`).
+const LAZY_RESTORE_FILES: usize = 16;
+
+/// Re-embed evicted files in the scope of `filters` so a search can score
+/// them, up to `LAZY_RESTORE_FILES` per call, most recently searched first;
+/// a broad search over a heavily evicted index restores the rest over later
+/// calls. The budget is enforced again by the next indexing pass. Best
+/// effort: on a read-only index, or if the restore fails, the files stay
+/// evicted and the search goes ahead without them.
+fn restore_on_access(state: &mut State, filters: &SearchFilters) {
+    let Ok(db) = get_db(state) else { return };
+    if db.is_readonly() || filters.workspace.as_deref().is_some_and(|w| w != db.workspace()) {
+        return;
+    }
+    let paths = match db.evicted_paths(filters.path_prefix.as_deref(), LAZY_RESTORE_FILES) {
+        Ok(paths) if paths.is_empty() => return,
+        Ok(paths) => paths,
+        Err(e) => {
+            tracing::warn!("can't look up evicted files: {}", e);
+            return;
+        }
+    };
+    if let Err(e) = restore_rows(state, Some(&paths)) {
+        tracing::warn!("can't restore {} evicted files: {}", paths.len(), e);
+    }
+}
+
+/// `restore_evicted` under the state lock.
+fn restore_rows(state: &mut State, paths: Option<&[String]>) -> napi::Result<JsRestoreResult> {
+    let (stored, chunks) = get_db(state)?
+        .evicted_rows(paths)
+        .map_err(|e| napi::Error::from_reason(format!("DB error: {}", e)))?;
+    let symbols: Vec<SymbolInput> = stored.into_iter().map(SymbolInput::from).collect();
+    let (embeddings, doc_embeddings, extra) = embed_symbols(state, &symbols)?;
+    let chunk_texts: Vec<String> = chunks.iter().map(|c| c.2.clone()).collect();
+    let chunk_embeddings = if chunk_texts.is_empty() {
+        Vec::new()
+    } else {
+        embed_internal(state, &chunk_texts, false)?
+    };
+
+    let model = state.info.name.clone();
+    let db = get_db(state)?;
+    let ws = db.workspace().to_string();
+    let pq = db.pq_codebook();
+    let compress = db.text_compression();
+    let tx = db.transaction()
+        .map_err(|e| napi::Error::from_reason(format!("DB error: {}", e)))?;
+    insert_symbols(&tx, &ws, &model, pq.as_deref(), compress, &symbols, &embeddings, &doc_embeddings, &extra)?;
+    for ((path, start_line, _), emb) in chunks.iter().zip(&chunk_embeddings) {
+        let embedding_bytes: &[u8] = bytemuck::cast_slice(emb.as_slice());
+        tx.execute(
+            "UPDATE chunks SET embedding = ? WHERE workspace = ? AND file_path = ? AND start_line = ?",
+            rusqlite::params![embedding_bytes, ws, path, start_line],
+        ).map_err(|e| napi::Error::from_reason(format!("DB error: {}", e)))?;
+    }
+    // Requested files count as restored even without rows to re-embed, so
+    // `restore_on_access` doesn't pick them again
+    let mut restored: Vec<&str> = symbols
+        .iter()
+        .map(|s| s.file_path.as_str())
+        .chain(chunks.iter().map(|c| c.0.as_str()))
+        .chain(paths.into_iter().flatten().map(String::as_str))
+        .collect();
+    restored.sort_unstable();
+    restored.dedup();
+    let now = db::now_millis();
+    for path in &restored {
+        tx.execute(
+            "UPDATE files SET evicted_at = NULL, last_hit_at = ? WHERE workspace = ? AND path = ?",
+            rusqlite::params![now, ws, path],
+        ).map_err(|e| napi::Error::from_reason(format!("DB error: {}", e)))?;
+    }
+    tx.commit()
+        .map_err(|e| napi::Error::from_reason(format!("DB error: {}", e)))?;
+
+    Ok(JsRestoreResult {
+        symbols: symbols.len() as f64,
+        chunks: chunks.len() as f64,
+    })
+}
+
+// ── Query log ──────────────────────────────────────────────────────────
+
+const DEDUP_KEY_META: &str = "dedup_key";
+
+/// How results are identified when merging multi-query candidates and in
+/// the query log.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum DedupKey {
+    /// `file_path:line:name` — the default
+    Location,
+    /// `file_path:name:kind` — survives the symbol moving within its file
+    Symbol,
+    /// The stored symbol id — also distinguishes overloads by signature
+    Id,
+}
+
+impl DedupKey {
+    fn parse(s: &str) -> napi::Result<Self> {
+        match s {
+            "location" => Ok(DedupKey::Location),
+            "symbol" => Ok(DedupKey::Symbol),
+            "id" => Ok(DedupKey::Id),
+            other => Err(napi::Error::from_reason(format!(
+                "Unknown dedup key '{}'. Expected \"location\", \"symbol\", or \"id\".",
+                other
+            ))),
+        }
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            DedupKey::Location => "location",
+            DedupKey::Symbol => "symbol",
+            DedupKey::Id => "id",
+        }
+    }
+
+    fn key(
+        self,
+        file_path: &str,
+        line: i32,
+        name: &str,
+        kind: &str,
+        signature: Option<&str>,
+    ) -> String {
+        match self {
+            DedupKey::Location => format!("{}:{}:{}", file_path, line, name),
+            DedupKey::Symbol => format!("{}:{}:{}", file_path, name, kind),
+            DedupKey::Id => db::symbol_id(file_path, name, kind, signature),
+        }
+    }
+}
+
+/// The index's dedup key strategy, or `location` if never set.
+fn dedup_key(db: &SearchDB) -> napi::Result<DedupKey> {
+    match db
+        .get_meta(DEDUP_KEY_META)
+        .map_err(|e| napi::Error::from_reason(format!("DB error: {}", e)))?
+    {
+        Some(s) => DedupKey::parse(&s),
+        None => Ok(DedupKey::Location),
+    }
+}
+
+/// Set how the open index identifies results for dedup and in the query
+/// log: `"location"` (`file_path:line:name`, the default), `"symbol"`
+/// (`file_path:name:kind`), or `"id"` (the stored symbol id). Keys already
+/// in the query log keep the strategy they were recorded with.
+#[napi(catch_unwind)]
+pub fn set_dedup_key(strategy: String) -> napi::Result<()> {
+    let strategy = DedupKey::parse(&strategy)?;
+    with_state(|state| {
+        get_db(state)?
+            .set_meta(DEDUP_KEY_META, strategy.as_str())
+            .map_err(|e| napi::Error::from_reason(format!("DB error: {}", e)))
+    })
+}
+
+/// The open index's dedup key strategy.
+#[napi(catch_unwind)]
+pub fn get_dedup_key() -> napi::Result<String> {
+    with_state(|state| Ok(dedup_key(get_db(state)?)?.as_str().to_string()))
+}
+
+/// Record a search and its results. Returns the query id for `record_click`.
+#[napi(catch_unwind)]
+pub fn log_search(query: String, results: Vec<JsSearchResult>) -> napi::Result<f64> {
+    with_state(|state| {
+        let db = get_db(state)?;
+        let identity = dedup_key(db)?;
+        let logged: Vec<db::LoggedResult> = results
+            .iter()
+            .map(|r| db::LoggedResult {
+                key: identity.key(&r.file_path, r.line, &r.name, &r.kind, r.signature.as_deref()),
+                score: r.score,
+            })
+            .collect();
+        let id = db
+            .log_search(&query, &logged)
+            .map_err(|e| napi::Error::from_reason(format!("DB error: {}", e)))?;
+        Ok(id as f64)
+    })
+}
+
+/// Record that a result (by the key `log_search` recorded for it) was opened.
+/// Opens feed the popularity boost (see `set_ranking_rules`).
+#[napi(catch_unwind)]
+pub fn record_click(query_id: f64, result_key: String) -> napi::Result<()> {
+    with_state(|state| {
+        let db = get_db(state)?;
+        db.record_click(query_id as i64, &result_key)
+            .map_err(|e| napi::Error::from_reason(format!("DB error: {}", e)))
+    })
+}
+
+/// Most recent logged searches, newest first.
+#[napi(catch_unwind)]
+pub fn get_query_history(limit: i32) -> napi::Result<Vec<JsQueryLogEntry>> {
+    with_state(|state| {
+        let db = get_db(state)?;
+        let entries = db
+            .get_query_history(limit as i64)
+            .map_err(|e| napi::Error::from_reason(format!("DB error: {}", e)))?;
+        Ok(entries
+            .into_iter()
+            .map(|e| JsQueryLogEntry {
+                id: e.id as f64,
+                query: e.query,
+                results: e
+                    .results
+                    .into_iter()
+                    .map(|r| JsLoggedResult { key: r.key, score: r.score })
+                    .collect(),
+                clicks: e.clicks,
+                created_at: e.created_at as f64,
+            })
+            .collect())
+    })
+}
+
+// ── Diagnostics ────────────────────────────────────────────────────────
+
+#[napi(object)]
+pub struct JsModelInfo {
+    /// `_name_or_path` from config.json, else the model directory's name
+    pub name: String,
+    pub hidden_size: u32,
+    pub num_layers: u32,
+    pub num_heads: u32,
+    /// Tokens per text; longer inputs are truncated
+    pub max_length: u32,
+    pub vocab_size: u32,
+    pub rotary_base: f64,
+    pub rotary_fraction: f64,
+    pub rotary_interleaved: bool,
+    pub parameter_count: f64,
+    /// Weight dtype as loaded, e.g. "float32"
+    pub dtype: String,
+    pub device: String,
+    /// Device queued indexing and re-embedding run on
+    pub background_device: String,
+    /// Whether the forward pass runs through a compiled graph
+    pub compiled: bool,
+    /// Whether `InitOptions.deterministic` is on
+    pub deterministic: bool,
+    /// Time `init` spent reading config, weights, and tokenizer
+    pub load_ms: f64,
+    pub query_prefix: String,
+    pub document_prefix: String,
+    /// `dimensions` recorded in the open index, if any. A mismatch with
+    /// `hidden_size` means the index was built with a different model.
+    pub index_dimensions: Option<u32>,
+}
+
+/// Describe the loaded model, and the open index's dimensions for checking
+/// compatibility.
+#[napi(catch_unwind)]
+pub fn get_model_info() -> napi::Result<JsModelInfo> {
+    with_state(|state| {
+        let index_dimensions = match &state.db {
+            Some(db) => db
+                .get_meta("dimensions")
+                .map_err(|e| napi::Error::from_reason(format!("DB error: {}", e)))?
+                .and_then(|d| d.parse().ok()),
+            None => None,
+        };
+        let info = &state.info;
+        Ok(JsModelInfo {
+            name: info.name.clone(),
+            hidden_size: info.config.n_embd as u32,
+            num_layers: info.config.n_layer as u32,
+            num_heads: info.config.n_head as u32,
+            max_length: MAX_LENGTH as u32,
+            vocab_size: info.config.vocab_size as u32,
+            rotary_base: info.config.rotary_emb_base as f64,
+            rotary_fraction: info.config.rotary_emb_fraction as f64,
+            rotary_interleaved: info.config.rotary_emb_interleaved,
+            parameter_count: info.parameter_count as f64,
+            dtype: info.dtype.clone(),
+            device: state.device.to_string(),
+            background_device: state.background_device.to_string(),
+            compiled: state.compile,
+            deterministic: state.deterministic,
+            load_ms: info.load_ms,
+            query_prefix: state.query_prefix.clone(),
+            document_prefix: state.document_prefix.clone(),
+            index_dimensions,
+        })
+    })
+}
+
+#[napi(object)]
+pub struct JsCacheStats {
+    pub hits: f64,
+    pub misses: f64,
+    /// `hits / (hits + misses)`, 0 before the first lookup
+    pub hit_rate: f64,
+    pub entries: u32,
+}
+
+impl<K: std::hash::Hash + Eq + Clone, V: Clone> From<&cache::LruCache<K, V>> for JsCacheStats {
+    fn from(c: &cache::LruCache<K, V>) -> Self {
+        let lookups = c.hits() + c.misses();
+        JsCacheStats {
+            hits: c.hits() as f64,
+            misses: c.misses() as f64,
+            hit_rate: if lookups == 0 { 0.0 } else { c.hits() as f64 / lookups as f64 },
+            entries: c.len() as u32,
+        }
+    }
+}
+
+#[napi(object)]
+pub struct JsMetrics {
+    /// `search` result cache
+    pub result_cache: JsCacheStats,
+    /// Query embedding cache, shared by every search API
+    pub query_cache: JsCacheStats,
+}
+
+/// Counters since `init`.
+#[napi(catch_unwind)]
+pub fn get_metrics() -> napi::Result<JsMetrics> {
+    with_state(|state| {
+        Ok(JsMetrics {
+            result_cache: JsCacheStats::from(&state.result_cache),
+            query_cache: JsCacheStats::from(&state.query_cache),
+        })
+    })
+}
+
+#[napi(object)]
+pub struct JsSelfTestCheck {
+    pub name: String,
+    pub ok: bool,
+    pub detail: String,
+}
+
+#[napi(object)]
+pub struct JsSelfTestReport {
+    /// True when every check passed.
+    pub ok: bool,
+    pub checks: Vec<JsSelfTestCheck>,
+    pub elapsed_ms: f64,
+}
+
+/// End-to-end health check: embedding sanity, dimension agreement with the
+/// index metadata, and an insert+search round trip on a throwaway in-memory DB.
+///
+/// Never throws for a failed check — failures are reported in the result so
+/// users can see *which* stage is broken.
+#[napi(catch_unwind)]
+pub fn self_test() -> napi::Result<JsSelfTestReport> {
+    const SAMPLE: &str = "fn parse_config(path: &Path) -> Result<Config>";
+
+    with_state(|state| {
+        let start = std::time::Instant::now();
+        let mut checks = Vec::new();
+        let mut check = |name: &str, ok: bool, detail: String| {
+            checks.push(JsSelfTestCheck {
+                name: name.to_string(),
+                ok,
+                detail,
+            });
+        };
+
+        check("device", true, format!("running on {}", state.device));
+
+        if state.precision_reference.is_empty() {
+            check("precision", true, format!("running in {}", state.info.dtype));
+        } else {
+            let texts = benchmark::sample_texts(benchmark::SAMPLE_TEXTS.len());
+            match embed_texts(state, &texts) {
+                Ok(embeddings) => {
+                    let min_cosine = embeddings
+                        .iter()
+                        .zip(&state.precision_reference)
+                        .map(|(a, b)| a.iter().zip(b).map(|(x, y)| x * y).sum::<f32>())
+                        .fold(f32::INFINITY, f32::min);
+                    check(
+                        "precision",
+                        min_cosine >= PRECISION_MIN_COSINE,
+                        format!(
+                            "{} vs float32: min cosine {:.4} over {} texts (need {})",
+                            state.info.dtype,
+                            min_cosine,
+                            embeddings.len(),
+                            PRECISION_MIN_COSINE
+                        ),
+                    );
+                }
+                Err(e) => check("precision", false, e.reason),
+            }
+        }
+
+        let embedding = match embed_internal(state, &[SAMPLE.to_string()], false) {
+            Ok(mut v) => {
+                check("embed", true, "embedded sample text".to_string());
+                v.pop()
+            }
+            Err(e) => {
+                check("embed", false, e.reason);
+                None
+            }
+        };
+
+        if let Some(emb) = &embedding {
+            let norm = emb.iter().map(|x| x * x).sum::<f32>().sqrt();
+            let finite = emb.iter().all(|x| x.is_finite());
+            check(
+                "l2_norm",
+                finite && (norm - 1.0).abs() < 1e-3,
+                format!("norm = {:.6}{}", norm, if finite { "" } else { " (non-finite values)" }),
+            );
 
-fn tokenize_batch(
-    tokenizer: &Tokenizer,
-    texts: &[String],
-    max_len: usize,
-) -> (mlx_rs::Array, mlx_rs::Array) {
-    let encodings: Vec<_> = texts
-        .iter()
-        .map(|t| tokenizer.encode(t.as_str(), true).unwrap())
-        .collect();
+            // Compare against the open index if there is one, else a fresh schema
+            let meta_dims = match state.db.as_ref() {
+                Some(db) => db.get_meta("dimensions"),
+                None => SearchDB::open(std::path::Path::new(":memory:"))
+                    .and_then(|db| db.get_meta("dimensions")),
+            };
+            match meta_dims {
+                Ok(Some(d)) => check(
+                    "dimensions",
+                    d.parse::<usize>().ok() == Some(emb.len()),
+                    format!("model = {}, index meta = {}", emb.len(), d),
+                ),
+                Ok(None) => check("dimensions", false, "index meta has no dimensions".to_string()),
+                Err(e) => check("dimensions", false, format!("DB error: {}", e)),
+            }
 
-    let batch_size = encodings.len();
-    let mut input_ids = vec![0i32; batch_size * max_len];
-    let mut attention_mask = vec![0i32; batch_size * max_len];
+            let model = state.info.name.clone();
+            let round_trip = (|| -> napi::Result<String> {
+                let mut db = SearchDB::open(std::path::Path::new(":memory:"))
+                    .map_err(|e| napi::Error::from_reason(format!("DB error: {}", e)))?;
+                let symbol = SymbolInput {
+                    embedding_text: SAMPLE.to_string(),
+                    file_path: "self_test.rs".to_string(),
+                    name: "parse_config".to_string(),
+                    kind: "function".to_string(),
+                    language: "rust".to_string(),
+                    line: 1,
+                    end_line: None,
+                    signature: None,
+                    doc_comment: None,
+                    metadata: None,
+                };
+                let tx = db
+                    .transaction()
+                    .map_err(|e| napi::Error::from_reason(format!("DB error: {}", e)))?;
+                insert_symbols(&tx, "", &model, None, false, std::slice::from_ref(&symbol), std::slice::from_ref(emb), &[], &ExtraEmbeddings::default())?;
+                tx.commit()
+                    .map_err(|e| napi::Error::from_reason(format!("DB error: {}", e)))?;
 
-    for (i, enc) in encodings.iter().enumerate() {
-        let ids = enc.get_ids();
-        let len = ids.len().min(max_len);
-        for j in 0..len {
-            input_ids[i * max_len + j] = ids[j] as i32;
-            attention_mask[i * max_len + j] = 1;
+                let results = db
+                    .search(emb, 1, &db::Filters::default())
+                    .map_err(|e| napi::Error::from_reason(format!("Search error: {}", e)))?;
+                match results.first() {
+                    Some(r) if r.name == symbol.name && r.score > 0.99 => {
+                        Ok(format!("found inserted symbol, score = {:.4}", r.score))
+                    }
+                    Some(r) => Err(napi::Error::from_reason(format!(
+                        "top hit was {} with score {:.4}",
+                        r.name, r.score
+                    ))),
+                    None => Err(napi::Error::from_reason("search returned no results")),
+                }
+            })();
+            match round_trip {
+                Ok(detail) => check("round_trip", true, detail),
+                Err(e) => check("round_trip", false, e.reason),
+            }
         }
-    }
 
-    let ids = mlx_rs::Array::from_slice(&input_ids, &[batch_size as i32, max_len as i32]);
-    let mask = mlx_rs::Array::from_slice(&attention_mask, &[batch_size as i32, max_len as i32]);
-    (ids, mask)
+        let ok = checks.iter().all(|c| c.ok);
+        Ok(JsSelfTestReport {
+            ok,
+            checks,
+            elapsed_ms: start.elapsed().as_secs_f64() * 1000.0,
+        })
+    })
 }
 
-fn embed_internal(
-    model: &mut NomicBertModel,
-    tokenizer: &Tokenizer,
-    texts: &[String],
-    is_query: bool,
-) -> napi::Result<Vec<Vec<f32>>> {
-    let prefixed: Vec<String> = if is_query {
-        texts
-            .iter()
-            .map(|t| format!("{}{}", QUERY_PREFIX, t))
-            .collect()
-    } else {
-        texts.to_vec()
-    };
-
-    let mut results = Vec::new();
-    let batch_size = 32;
+#[napi(object)]
+pub struct BenchmarkOptions {
+    /// Timed iterations (default 10)
+    pub iterations: Option<u32>,
+    /// Untimed iterations first (default 2)
+    pub warmup: Option<u32>,
+    /// Texts or symbols per iteration for "embed" and "index" (default 32)
+    pub batch_size: Option<u32>,
+    /// Results per search for "search" (default 25)
+    pub top_k: Option<i32>,
+    /// Query for "search" (default "rate limiting middleware")
+    pub query: Option<String>,
+    /// Vector storage of the throwaway index for "index" (default "blob");
+    /// see `set_vector_storage`
+    pub vector_storage: Option<String>,
+}
 
-    for chunk in prefixed.chunks(batch_size) {
-        let chunk_vec: Vec<String> = chunk.to_vec();
-        let (input_ids, attention_mask) = tokenize_batch(tokenizer, &chunk_vec, MAX_LENGTH);
+#[napi(object)]
+pub struct JsBenchmarkResult {
+    pub kind: String,
+    pub device: String,
+    pub iterations: u32,
+    /// Texts, symbols, or searches per iteration
+    pub items_per_iteration: u32,
+    pub mean_ms: f64,
+    pub p50_ms: f64,
+    pub p95_ms: f64,
+    pub min_ms: f64,
+    pub max_ms: f64,
+    pub items_per_sec: f64,
+    /// Symbols scanned per search ("search" only)
+    pub index_rows: Option<f64>,
+    /// Vector storage of the index searched or written ("search" and
+    /// "index" only)
+    pub vector_storage: Option<String>,
+}
 
-        let hidden = model
-            .forward(&input_ids, Some(&attention_mask))
-            .map_err(|e| napi::Error::from_reason(format!("Forward pass failed: {}", e)))?;
-        let result = mean_pool_normalize(&hidden, &attention_mask)
-            .map_err(|e| napi::Error::from_reason(format!("Pooling failed: {}", e)))?;
-        result
-            .eval()
-            .map_err(|e| napi::Error::from_reason(format!("Eval failed: {}", e)))?;
+/// Time one part of the pipeline with the loaded model:
+/// - "embed": embedding `batch_size` texts per iteration, bypassing caches
+/// - "search": one symbol search of the open index per iteration
+/// - "index": embedding and inserting `batch_size` symbols per iteration,
+///   into a throwaway in-memory index
+#[napi(catch_unwind)]
+pub fn run_benchmark(
+    kind: String,
+    options: Option<BenchmarkOptions>,
+) -> napi::Result<JsBenchmarkResult> {
+    let options = options.unwrap_or(BenchmarkOptions {
+        iterations: None,
+        warmup: None,
+        batch_size: None,
+        top_k: None,
+        query: None,
+        vector_storage: None,
+    });
+    let iterations = options.iterations.unwrap_or(10).max(1) as usize;
+    let warmup = options.warmup.unwrap_or(2) as usize;
+    let batch_size = options.batch_size.unwrap_or(32).max(1) as usize;
 
-        let data = result.as_slice::<f32>();
-        let dims = result.shape();
-        let n = dims[0] as usize;
-        let d = dims[1] as usize;
-        for i in 0..n {
-            results.push(data[i * d..(i + 1) * d].to_vec());
-        }
-    }
+    with_state(|state| {
+        let (timing, items, index_rows, storage) = match kind.as_str() {
+            "embed" => {
+                let texts = benchmark::sample_texts(batch_size);
+                let timing = benchmark::measure(warmup, iterations, || {
+                    embed_uncached(state, &texts, false).map(|_| ())
+                })?;
+                (timing, batch_size, None, None)
+            }
+            "search" => {
+                let query = options
+                    .query
+                    .unwrap_or_else(|| "rate limiting middleware".to_string());
+                let emb = embed_uncached(state, &[query], true)?
+                    .pop()
+                    .unwrap_or_default();
+                let top_k = options.top_k.unwrap_or(25);
+                let db = get_db(state)?;
+                let rows = db
+                    .get_stats()
+                    .map_err(|e| napi::Error::from_reason(format!("DB error: {}", e)))?
+                    .symbol_count;
+                let filters = db::Filters::default();
+                let timing = benchmark::measure(warmup, iterations, || {
+                    db.search(&emb, top_k, &filters)
+                        .map(|_| ())
+                        .map_err(|e| napi::Error::from_reason(format!("Search error: {}", e)))
+                })?;
+                (timing, 1, Some(rows as f64), Some(db.vector_storage()))
+            }
+            "index" => {
+                let storage =
+                    parse_vector_storage(options.vector_storage.as_deref().unwrap_or("blob"))?;
+                let mut db = SearchDB::open(std::path::Path::new(":memory:"))
+                    .map_err(|e| napi::Error::from_reason(format!("DB error: {}", e)))?;
+                db.set_vector_storage(storage)
+                    .map_err(|e| napi::Error::from_reason(format!("DB error: {}", e)))?;
+                let texts = benchmark::sample_texts(batch_size);
+                let mut next_line = 0;
+                let timing = benchmark::measure(warmup, iterations, || {
+                    let symbols: Vec<SymbolInput> = texts
+                        .iter()
+                        .map(|text| {
+                            next_line += 1;
+                            SymbolInput {
+                                embedding_text: text.clone(),
+                                file_path: "benchmark.rs".to_string(),
+                                name: format!("symbol_{}", next_line),
+                                kind: "function".to_string(),
+                                language: "rust".to_string(),
+                                line: next_line,
+                                end_line: None,
+                                signature: None,
+                                doc_comment: None,
+                                metadata: None,
+                            }
+                        })
+                        .collect();
+                    let (embeddings, doc_embeddings, extra) = embed_symbols(state, &symbols)?;
+                    let tx = db
+                        .transaction()
+                        .map_err(|e| napi::Error::from_reason(format!("DB error: {}", e)))?;
+                    insert_symbols(&tx, "", &state.info.name, None, false, &symbols, &embeddings, &doc_embeddings, &extra)?;
+                    tx.commit()
+                        .map_err(|e| napi::Error::from_reason(format!("DB error: {}", e)))
+                })?;
+                (timing, batch_size, None, Some(storage))
+            }
+            other => {
+                return Err(napi::Error::from_reason(format!(
+                    "Unknown benchmark '{}'. Expected \"embed\", \"search\", or \"index\".",
+                    other
+                )))
+            }
+        };
 
-    Ok(results)
+        Ok(JsBenchmarkResult {
+            kind: kind.clone(),
+            device: state.device.to_string(),
+            iterations: timing.iterations as u32,
+            items_per_iteration: items as u32,
+            mean_ms: timing.mean_ms,
+            p50_ms: timing.p50_ms,
+            p95_ms: timing.p95_ms,
+            min_ms: timing.min_ms,
+            max_ms: timing.max_ms,
+            items_per_sec: if timing.mean_ms > 0.0 {
+                items as f64 * 1000.0 / timing.mean_ms
+            } else {
+                0.0
+            },
+            index_rows,
+            vector_storage: storage.map(|s| s.as_str().to_string()),
+        })
+    })
 }
 
-// ── Batch DB helpers ───────────────────────────────────────────────────
+#[napi(object)]
+pub struct JsCaseResult {
+    pub query: String,
+    /// Fraction of expected symbols in the top k
+    pub recall: f64,
+    pub reciprocal_rank: f64,
+    /// 1-based rank of the first expected symbol, if any was found
+    pub first_hit_rank: Option<u32>,
+    /// Query embedding plus search
+    pub latency_ms: f64,
+}
 
-fn get_db(state: &mut State) -> napi::Result<&mut SearchDB> {
-    state
-        .db
-        .as_mut()
-        .ok_or_else(|| napi::Error::from_reason("DB not opened. Call open_db() first."))
+#[napi(object)]
+pub struct JsEvaluation {
+    pub top_k: i32,
+    /// Mean recall@k over cases
+    pub recall_at_k: f64,
+    /// Mean reciprocal rank over cases
+    pub mrr: f64,
+    pub mean_latency_ms: f64,
+    pub p50_latency_ms: f64,
+    pub p95_latency_ms: f64,
+    pub cases: Vec<JsCaseResult>,
 }
 
-// ── napi types ─────────────────────────────────────────────────────────
+/// Measure retrieval quality of the open index with the current model and
+/// settings. `cases_json` is an array of
+/// `{ "query", "expected": [{ "symbol_id"?, "file_path"?, "name"? }] }`;
+/// a result matches an expected symbol when every field given agrees. Each
+/// query runs through the same path as `search` (caches bypassed), so the
+/// numbers reflect `threshold`, `filters`, and the index's dedup and kind
+/// settings.
+#[napi(catch_unwind)]
+pub fn evaluate_search(
+    cases_json: String,
+    top_k: i32,
+    threshold: Either<f64, KindThresholds>,
+    filters: SearchFilters,
+) -> napi::Result<JsEvaluation> {
+    let cases = recall::parse_cases(&cases_json).map_err(napi::Error::from_reason)?;
+    let threshold = kind_thresholds(threshold);
 
-#[napi(object)]
-pub struct JsFileRow {
-    pub path: String,
-    pub hash: String,
-    pub language: Option<String>,
-    pub symbol_count: Option<i32>,
-    pub indexed_at: f64,
+    with_state(|state| {
+        restore_on_access(state, &filters);
+        let mut results = Vec::with_capacity(cases.len());
+        let mut latencies = Vec::with_capacity(cases.len());
+        for case in cases {
+            let start = std::time::Instant::now();
+            let embeddings = embed_uncached(state, std::slice::from_ref(&case.query), true)?;
+            let found =
+                search_embedded(get_db(state)?, &embeddings, top_k, &threshold, &filters, None)?;
+            let latency_ms = start.elapsed().as_secs_f64() * 1000.0;
+
+            let hits: Vec<recall::Hit> = found
+                .iter()
+                .map(|r| recall::Hit {
+                    symbol_id: &r.symbol_id,
+                    file_path: &r.file_path,
+                    name: &r.name,
+                })
+                .collect();
+            let score = recall::score(&case.expected, &hits);
+            latencies.push(latency_ms);
+            results.push(JsCaseResult {
+                query: case.query,
+                recall: score.recall,
+                reciprocal_rank: score.reciprocal_rank,
+                first_hit_rank: score.first_hit_rank.map(|r| r as u32),
+                latency_ms,
+            });
+        }
+
+        let n = results.len().max(1) as f64;
+        let timing = benchmark::Timing::from_samples(latencies);
+        Ok(JsEvaluation {
+            top_k,
+            recall_at_k: results.iter().map(|r| r.recall).sum::<f64>() / n,
+            mrr: results.iter().map(|r| r.reciprocal_rank).sum::<f64>() / n,
+            mean_latency_ms: timing.mean_ms,
+            p50_latency_ms: timing.p50_ms,
+            p95_latency_ms: timing.p95_ms,
+            cases: results,
+        })
+    })
 }
 
 #[napi(object)]
-pub struct JsSearchResult {
+pub struct JsCorruptRow {
+    /// "symbols" or "chunks"
+    pub table: String,
+    pub workspace: String,
     pub file_path: String,
-    pub name: String,
-    pub kind: String,
-    pub language: String,
+    /// Symbol line, or chunk start line
     pub line: i32,
-    pub end_line: Option<i32>,
-    pub signature: Option<String>,
-    pub score: f64,
+    /// "embedding" or "doc_embedding"
+    pub column: String,
+    pub problem: String,
 }
 
 #[napi(object)]
-pub struct JsStats {
-    pub symbol_count: f64,
-    pub file_count: f64,
+pub struct JsIntegrityReport {
+    /// True when no stored vector is corrupt.
+    pub ok: bool,
+    pub rows_checked: f64,
+    /// Rows that need re-embedding (or were deleted, with `repair`)
+    pub corrupt: Vec<JsCorruptRow>,
+    pub deleted: f64,
 }
 
-#[napi(object)]
-pub struct SymbolInput {
-    pub embedding_text: String,
-    pub file_path: String,
-    pub name: String,
-    pub kind: String,
-    pub language: String,
-    pub line: i32,
-    pub end_line: Option<i32>,
-    pub signature: Option<String>,
+/// Check the stored vectors of every workspace for wrong lengths and
+/// NaN/infinite values. With `repair`, delete corrupt rows and mark their
+/// files for re-extraction on the next incremental reindex.
+#[napi(catch_unwind)]
+pub fn verify_index(repair: Option<bool>) -> napi::Result<JsIntegrityReport> {
+    with_state(|state| {
+        let db = get_db(state)?;
+        let report = db
+            .verify_embeddings(repair.unwrap_or(false))
+            .map_err(|e| napi::Error::from_reason(format!("DB error: {}", e)))?;
+        Ok(JsIntegrityReport {
+            ok: report.corrupt.is_empty(),
+            rows_checked: report.rows_checked as f64,
+            corrupt: report
+                .corrupt
+                .into_iter()
+                .map(|r| JsCorruptRow {
+                    table: r.table.to_string(),
+                    workspace: r.workspace,
+                    file_path: r.file_path,
+                    line: r.line,
+                    column: r.column.to_string(),
+                    problem: r.problem,
+                })
+                .collect(),
+            deleted: report.deleted as f64,
+        })
+    })
 }
 
 #[napi(object)]
-pub struct FileInput {
+pub struct JsFileDrift {
+    pub workspace: String,
     pub path: String,
-    pub hash: String,
-    pub language: Option<String>,
-    pub symbol_count: i32,
+    /// Orphaned rows, or the file record's expected symbol count
+    pub rows: f64,
 }
 
 #[napi(object)]
-pub struct SearchFilters {
-    pub language: Option<String>,
-    pub kind: Option<String>,
-    pub path_prefix: Option<String>,
+pub struct JsConsistencyReport {
+    /// True when files and their symbols and chunks agree.
+    pub ok: bool,
+    /// Files with symbols but no file record
+    pub orphan_symbols: Vec<JsFileDrift>,
+    /// Files with chunks but no file record
+    pub orphan_chunks: Vec<JsFileDrift>,
+    /// File records whose symbols are missing
+    pub missing_symbols: Vec<JsFileDrift>,
+    pub deleted: f64,
 }
 
-// ── Batch APIs ─────────────────────────────────────────────────────────
+impl From<db::FileDrift> for JsFileDrift {
+    fn from(d: db::FileDrift) -> Self {
+        JsFileDrift {
+            workspace: d.workspace,
+            path: d.path,
+            rows: d.rows as f64,
+        }
+    }
+}
 
-/// Get all indexed files. Single FFI call returns everything.
-#[napi]
-pub fn db_get_all_files() -> napi::Result<Vec<JsFileRow>> {
+/// Check every workspace for symbols and chunks without a file record and
+/// file records without their symbols, as an interrupted `delete_files`
+/// can leave. With `repair`, delete the orphaned rows and mark files
+/// missing symbols for re-extraction on the next incremental reindex.
+#[napi(catch_unwind)]
+pub fn check_consistency(repair: Option<bool>) -> napi::Result<JsConsistencyReport> {
     with_state(|state| {
-        let db = get_db(state)?;
-        let rows = db
-            .get_all_files()
+        let report = get_db(state)?
+            .check_consistency(repair.unwrap_or(false))
             .map_err(|e| napi::Error::from_reason(format!("DB error: {}", e)))?;
-        Ok(rows
-            .into_iter()
-            .map(|r| JsFileRow {
-                path: r.path,
-                hash: r.hash,
-                language: r.language,
-                symbol_count: r.symbol_count,
-                indexed_at: r.indexed_at as f64,
-            })
-            .collect())
+        let convert = |v: Vec<db::FileDrift>| v.into_iter().map(JsFileDrift::from).collect();
+        Ok(JsConsistencyReport {
+            ok: report.orphan_symbols.is_empty()
+                && report.orphan_chunks.is_empty()
+                && report.missing_symbols.is_empty(),
+            orphan_symbols: convert(report.orphan_symbols),
+            orphan_chunks: convert(report.orphan_chunks),
+            missing_symbols: convert(report.missing_symbols),
+            deleted: report.deleted as f64,
+        })
     })
 }
 
-/// Delete multiple files and their symbols in a single transaction.
-#[napi]
-pub fn delete_files(paths: Vec<String>) -> napi::Result<()> {
-    with_state(|state| {
-        let db = get_db(state)?;
-        let tx = db.transaction()
-            .map_err(|e| napi::Error::from_reason(format!("DB error: {}", e)))?;
-        for path in &paths {
-            tx.execute("DELETE FROM symbols WHERE file_path = ?", rusqlite::params![path])
-                .map_err(|e| napi::Error::from_reason(format!("DB error: {}", e)))?;
-            tx.execute("DELETE FROM files WHERE path = ?", rusqlite::params![path])
-                .map_err(|e| napi::Error::from_reason(format!("DB error: {}", e)))?;
-        }
-        tx.commit()
-            .map_err(|e| napi::Error::from_reason(format!("DB error: {}", e)))?;
-        Ok(())
-    })
+#[napi(object)]
+pub struct JsDebugEmbedding {
+    pub token_ids: Vec<u32>,
+    pub hidden_size: u32,
+    /// Final hidden states for the real (unpadded) tokens, row-major
+    /// `[token_ids.length, hidden_size]`, from the padded+masked forward pass.
+    pub hidden_states: Float32Array,
+    /// Pooled, normalized embedding as `search()` and `index_symbols()` compute it.
+    pub pooled: Float32Array,
+    /// Same text run with no padding at all (sequence trimmed to its tokens).
+    pub trimmed_pooled: Float32Array,
+    /// Max |pooled - trimmed_pooled|. Near zero when the additive SDPA mask
+    /// fully hides padding; larger values mean padding leaks into attention.
+    pub mask_vs_trim_max_diff: f64,
 }
 
-/// Upsert multiple file records in a single transaction.
-#[napi]
-pub fn upsert_files(files: Vec<FileInput>) -> napi::Result<()> {
-    with_state(|state| {
-        let db = get_db(state)?;
-        let tx = db.transaction()
-            .map_err(|e| napi::Error::from_reason(format!("DB error: {}", e)))?;
-        let now = std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)
-            .unwrap()
-            .as_millis() as i64;
-        for f in &files {
-            tx.execute(
-                "INSERT OR REPLACE INTO files (path, hash, language, symbol_count, indexed_at) VALUES (?, ?, ?, ?, ?)",
-                rusqlite::params![f.path, f.hash, f.language, f.symbol_count, now],
-            ).map_err(|e| napi::Error::from_reason(format!("DB error: {}", e)))?;
-        }
-        tx.commit()
-            .map_err(|e| napi::Error::from_reason(format!("DB error: {}", e)))?;
-        Ok(())
-    })
+/// Padded-vs-trimmed forward for one text. Returns (hidden states of real
+/// tokens, pooled padded, pooled trimmed).
+fn debug_forward(
+    state: &mut State,
+    text: &str,
+    token_count: usize,
+) -> napi::Result<(Vec<f32>, Vec<f32>, Vec<f32>)> {
+    let texts = [text.to_string()];
+    let truncation = index_truncation(state)?;
+    let rows = batch_rows(state, 1);
+    let mut run = |max_len: usize| -> napi::Result<(mlx_rs::Array, mlx_rs::Array)> {
+        let (input_ids, attention_mask) =
+            tokenize_batch(&state.tokenizer, &texts, rows, max_len, truncation);
+        let hidden = state
+            .model
+            .forward(&input_ids, Some(&attention_mask))
+            .map_err(|e| napi::Error::from_reason(format!("Forward pass failed: {}", e)))?;
+        let pooled = mean_pool_normalize(&hidden, &attention_mask)
+            .map_err(|e| napi::Error::from_reason(format!("Pooling failed: {}", e)))?;
+        hidden
+            .eval()
+            .and_then(|_| pooled.eval())
+            .map_err(|e| napi::Error::from_reason(format!("Eval failed: {}", e)))?;
+        Ok((hidden, pooled))
+    };
+
+    let (hidden, pooled) = run(MAX_LENGTH)?;
+    let (_, trimmed) = run(token_count.clamp(1, MAX_LENGTH))?;
+
+    let real = token_count.min(MAX_LENGTH) * state.dims;
+    Ok((
+        hidden.as_slice::<f32>()[..real].to_vec(),
+        pooled.as_slice::<f32>()[..state.dims].to_vec(),
+        trimmed.as_slice::<f32>()[..state.dims].to_vec(),
+    ))
 }
 
-/// Embed and insert symbols in a single call.
-/// Embeddings never cross the napi boundary.
-/// Wraps all inserts in a transaction for performance.
-#[napi]
-pub fn index_symbols(symbols: Vec<SymbolInput>) -> napi::Result<()> {
+fn max_abs_diff(a: &[f32], b: &[f32]) -> f64 {
+    a.iter()
+        .zip(b)
+        .map(|(x, y)| (x - y).abs() as f64)
+        .fold(0.0, f64::max)
+}
+
+/// Expose the embedding pipeline's intermediates for one text, for parity
+/// checks against the Python reference implementation.
+#[napi(catch_unwind)]
+pub fn debug_embed(text: String, is_query: Option<bool>) -> napi::Result<JsDebugEmbedding> {
     with_state(|state| {
-        if symbols.is_empty() {
-            return Ok(());
-        }
+        let text = format!("{}{}", state.prefix(is_query.unwrap_or(false)), text);
+        let encoding = state
+            .tokenizer
+            .encode(text.as_str(), true)
+            .map_err(|e| napi::Error::from_reason(format!("Tokenization failed: {}", e)))?;
+        let token_ids: Vec<u32> = encoding.get_ids().iter().take(MAX_LENGTH).copied().collect();
 
-        let texts: Vec<String> = symbols.iter().map(|s| s.embedding_text.clone()).collect();
-        let embeddings = embed_internal(&mut state.model, &state.tokenizer, &texts, false)?;
+        let (hidden, pooled, trimmed) = debug_forward(state, &text, token_ids.len())?;
+        let diff = max_abs_diff(&pooled, &trimmed);
 
-        let db = get_db(state)?;
-        let tx = db.transaction()
-            .map_err(|e| napi::Error::from_reason(format!("DB error: {}", e)))?;
-        {
-            let mut stmt = tx.prepare_cached(
-                "INSERT OR REPLACE INTO symbols (file_path, line, name, kind, language, end_line, signature, embedding_text, embedding)
-                 VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)"
-            ).map_err(|e| napi::Error::from_reason(format!("DB error: {}", e)))?;
-
-            for (sym, emb) in symbols.iter().zip(embeddings.iter()) {
-                let embedding_bytes: &[u8] = bytemuck::cast_slice(emb.as_slice());
-                stmt.execute(rusqlite::params![
-                    sym.file_path,
-                    sym.line,
-                    sym.name,
-                    sym.kind,
-                    sym.language,
-                    sym.end_line,
-                    sym.signature,
-                    sym.embedding_text,
-                    embedding_bytes
-                ]).map_err(|e| napi::Error::from_reason(format!("DB insert error: {}", e)))?;
-            }
-        }
-        tx.commit()
-            .map_err(|e| napi::Error::from_reason(format!("DB error: {}", e)))?;
-        Ok(())
+        Ok(JsDebugEmbedding {
+            token_ids,
+            hidden_size: state.dims as u32,
+            hidden_states: Float32Array::new(hidden),
+            pooled: Float32Array::new(pooled),
+            trimmed_pooled: Float32Array::new(trimmed),
+            mask_vs_trim_max_diff: diff,
+        })
     })
 }
 
-/// Multi-query search with dedup, all in Rust.
+/// One reference case, as dumped from the Python implementation.
+#[derive(serde::Deserialize)]
+struct ReferenceCase {
+    text: String,
+    #[serde(default)]
+    is_query: bool,
+    token_ids: Option<Vec<u32>>,
+    embedding: Vec<f32>,
+}
+
+#[napi(object)]
+pub struct JsParityResult {
+    pub text: String,
+    /// None when the reference didn't include token ids.
+    pub token_ids_match: Option<bool>,
+    pub max_abs_diff: f64,
+    pub cosine: f64,
+    pub ok: bool,
+}
+
+/// Compare embeddings against reference output.
 ///
-/// Embeds all queries as a batch, runs each against the DB,
-/// deduplicates by (file_path, line, name) keeping the best score,
-/// and returns top_k results sorted by score descending.
-#[napi]
-pub fn search(
-    queries: Vec<String>,
-    top_k: i32,
-    threshold: f64,
-    filters: SearchFilters,
-) -> napi::Result<Vec<JsSearchResult>> {
+/// `reference_json` is a case object or an array of them:
+/// `{ "text", "is_query"?, "token_ids"?, "embedding" }`. A case passes when
+/// token ids match (if given) and every component is within `tolerance`
+/// (default 1e-3).
+#[napi(catch_unwind)]
+pub fn verify_against(
+    reference_json: String,
+    tolerance: Option<f64>,
+) -> napi::Result<Vec<JsParityResult>> {
+    let tolerance = tolerance.unwrap_or(1e-3);
+    let value: serde_json::Value = serde_json::from_str(&reference_json)
+        .map_err(|e| napi::Error::from_reason(format!("Invalid reference JSON: {}", e)))?;
+    let cases: Vec<ReferenceCase> = if value.is_array() {
+        serde_json::from_value(value)
+    } else {
+        serde_json::from_value(value).map(|c| vec![c])
+    }
+    .map_err(|e| napi::Error::from_reason(format!("Invalid reference case: {}", e)))?;
+
     with_state(|state| {
-        if queries.is_empty() {
-            return Ok(Vec::new());
+        let mut results = Vec::with_capacity(cases.len());
+        for case in cases {
+            let text = format!("{}{}", state.prefix(case.is_query), case.text);
+            let token_ids_match = match &case.token_ids {
+                Some(expected) => {
+                    let encoding = state
+                        .tokenizer
+                        .encode(text.as_str(), true)
+                        .map_err(|e| napi::Error::from_reason(format!("Tokenization failed: {}", e)))?;
+                    Some(encoding.get_ids().iter().take(MAX_LENGTH).eq(expected.iter()))
+                }
+                None => None,
+            };
+
+            let actual = embed_internal(state, std::slice::from_ref(&case.text), case.is_query)?
+                .pop()
+                .unwrap_or_default();
+            let (max_diff, cosine) = if actual.len() == case.embedding.len() {
+                (
+                    max_abs_diff(&actual, &case.embedding),
+                    // simsimd returns cosine distance
+                    1.0 - <f32 as SpatialSimilarity>::cos(&actual, &case.embedding).unwrap_or(1.0),
+                )
+            } else {
+                (f64::INFINITY, 0.0)
+            };
+
+            results.push(JsParityResult {
+                text: case.text,
+                ok: token_ids_match != Some(false) && max_diff <= tolerance,
+                token_ids_match,
+                max_abs_diff: max_diff,
+                cosine,
+            });
         }
+        Ok(results)
+    })
+}
 
-        // Batch-embed all queries at once
-        let query_embeddings =
-            embed_internal(&mut state.model, &state.tokenizer, &queries, true)?;
+// ── Logging ────────────────────────────────────────────────────────────
 
-        let db = get_db(state)?;
+#[napi(object)]
+pub struct JsLogRecord {
+    /// "error", "warn", "info", "debug", or "trace"
+    pub level: String,
+    /// Module the record came from, e.g. "semantic_search_native::db"
+    pub target: String,
+    pub message: String,
+}
 
-        // Fetch more per-query so we have enough after dedup
-        let per_query_k = if queries.len() > 1 {
-            (top_k as f64 * 1.5).ceil() as i32
-        } else {
-            top_k
+/// Where `JsLogLayer` sends records; None until `set_log_callback`.
+static LOG_SINK: Mutex<Option<ThreadsafeFunction<JsLogRecord, ErrorStrategy::Fatal>>> =
+    Mutex::new(None);
+
+/// The installed subscriber's level, set by `set_log_callback`.
+static LOG_FILTER: std::sync::OnceLock<LogFilterHandle> = std::sync::OnceLock::new();
+
+type LogFilterHandle = tracing_subscriber::reload::Handle<LevelFilter, Registry>;
+
+/// Forwards `tracing` events, ours and the `log` records of dependencies
+/// (ureq, notify, tokenizers) bridged by `LogTracer`, to `LOG_SINK`.
+struct JsLogLayer;
+
+impl<S: tracing::Subscriber> tracing_subscriber::Layer<S> for JsLogLayer {
+    fn on_event(
+        &self,
+        event: &tracing::Event<'_>,
+        _ctx: tracing_subscriber::layer::Context<'_, S>,
+    ) {
+        use tracing_log::NormalizeEvent;
+        let Some(sink) = &*LOG_SINK.lock().unwrap_or_else(|e| e.into_inner()) else {
+            return;
         };
+        let normalized = event.normalized_metadata();
+        let metadata = normalized.as_ref().unwrap_or_else(|| event.metadata());
+        let mut message = LogMessage(String::new());
+        event.record(&mut message);
+        sink.call(
+            JsLogRecord {
+                level: metadata.level().as_str().to_lowercase(),
+                target: metadata.target().to_string(),
+                message: message.0,
+            },
+            ThreadsafeFunctionCallMode::NonBlocking,
+        );
+    }
+}
 
-        // Run each query and merge results, keeping best score per symbol
-        let mut best_by_key: HashMap<String, db::SearchResult> = HashMap::new();
-
-        for emb in &query_embeddings {
-            let results = db
-                .search(
-                    emb,
-                    per_query_k,
-                    filters.language.as_deref(),
-                    filters.kind.as_deref(),
-                    filters.path_prefix.as_deref(),
-                )
-                .map_err(|e| napi::Error::from_reason(format!("Search error: {}", e)))?;
+/// An event's message followed by its other fields as ` name=value`.
+/// Fields `LogTracer` adds for the source location are left out.
+struct LogMessage(String);
 
-            for r in results {
-                let key = format!("{}:{}:{}", r.file_path, r.line, r.name);
-                let existing = best_by_key.get(&key);
-                if existing.map_or(true, |e| r.score > e.score) {
-                    best_by_key.insert(key, r);
-                }
+impl tracing::field::Visit for LogMessage {
+    fn record_debug(&mut self, field: &tracing::field::Field, value: &dyn std::fmt::Debug) {
+        use std::fmt::Write;
+        match field.name() {
+            "message" => {
+                let _ = write!(self.0, "{:?}", value);
+            }
+            name if name.starts_with("log.") => {}
+            name => {
+                let _ = write!(self.0, " {}={:?}", name, value);
             }
         }
+    }
+}
 
-        // Sort by best score, filter by threshold, take top_k
-        let mut merged: Vec<_> = best_by_key
-            .into_values()
-            .filter(|r| r.score >= threshold)
-            .collect();
-        merged.sort_by(|a, b| b.score.partial_cmp(&a.score).unwrap());
-        merged.truncate(top_k as usize);
-
-        Ok(merged
-            .into_iter()
-            .map(|r| JsSearchResult {
-                file_path: r.file_path,
-                name: r.name,
-                kind: r.kind,
-                language: r.language,
-                line: r.line,
-                end_line: r.end_line,
-                signature: r.signature,
-                score: r.score,
-            })
-            .collect())
+/// Install the subscriber and the `log` bridge on first use. Logging starts
+/// off; `set_log_level` turns it on.
+fn log_filter() -> &'static LogFilterHandle {
+    use tracing_subscriber::layer::SubscriberExt;
+    LOG_FILTER.get_or_init(|| {
+        let (filter, handle) =
+            tracing_subscriber::reload::Layer::new(LevelFilter::OFF);
+        let subscriber = tracing_subscriber::registry().with(filter).with(JsLogLayer);
+        // Fails only if the host process installed its own; then ours is unused
+        let _ = tracing::subscriber::set_global_default(subscriber);
+        let _ = tracing_log::LogTracer::init();
+        handle
     })
 }
 
-#[napi]
-pub fn db_get_stats() -> napi::Result<JsStats> {
-    with_state(|state| {
-        let db = get_db(state)?;
-        let stats = db
-            .get_stats()
-            .map_err(|e| napi::Error::from_reason(format!("DB error: {}", e)))?;
-        Ok(JsStats {
-            symbol_count: stats.symbol_count as f64,
-            file_count: stats.file_count as f64,
-        })
-    })
+/// Apply `level` to our events and to the `log` records of dependencies.
+fn set_log_level(level: LevelFilter) {
+    use tracing_log::AsLog;
+    let _ = log_filter().modify(|filter| *filter = level);
+    tracing_log::log::set_max_level(level.as_log());
+}
+
+/// Send native log records at `level` (`"error"`, `"warn"`, `"info"`,
+/// `"debug"` or `"trace"`) and above to `callback`: model loading, index
+/// and search timings, scan plans, forward passes (trace), and records of
+/// dependencies such as model downloads and file watching. Records arrive
+/// asynchronously. `"off"` or no callback stops logging. The callback
+/// doesn't keep Node's event loop alive.
+#[napi(catch_unwind)]
+pub fn set_log_callback(
+    env: Env,
+    level: String,
+    callback: Option<ThreadsafeFunction<JsLogRecord, ErrorStrategy::Fatal>>,
+) -> napi::Result<()> {
+    let filter: LevelFilter = level.parse().map_err(|_| {
+        napi::Error::from_reason(format!(
+            "Unknown log level '{}'. Expected \"off\", \"error\", \"warn\", \"info\", \"debug\" or \"trace\".",
+            level
+        ))
+    })?;
+    let mut callback = callback;
+    if let Some(callback) = &mut callback {
+        callback.unref(&env)?;
+    }
+    let filter = if callback.is_some() {
+        filter
+    } else {
+        LevelFilter::OFF
+    };
+    *LOG_SINK.lock().unwrap_or_else(|e| e.into_inner()) = callback;
+    set_log_level(filter);
+    Ok(())
+}
+
+// ── Watch mode ─────────────────────────────────────────────────────────
+
+/// Active watchers by id. Separate from `STATE` so watching doesn't need
+/// `init()` and callbacks never contend with indexing for the state lock.
+static WATCHERS: std::sync::OnceLock<Mutex<HashMap<u32, watch::RepoWatcher>>> =
+    std::sync::OnceLock::new();
+static NEXT_WATCHER_ID: std::sync::atomic::AtomicU32 = std::sync::atomic::AtomicU32::new(1);
+
+fn watchers() -> napi::Result<std::sync::MutexGuard<'static, HashMap<u32, watch::RepoWatcher>>> {
+    WATCHERS
+        .get_or_init(|| Mutex::new(HashMap::new()))
+        .lock()
+        .map_err(|e| napi::Error::from_reason(format!("Lock poisoned: {}", e)))
+}
+
+#[napi(object)]
+pub struct WatchOptions {
+    /// Quiet period before a batch of changes is reported (default 500ms)
+    pub debounce_ms: Option<u32>,
+    /// Extra exclude patterns (gitignore syntax), as in `should_index`
+    pub exclude: Option<Vec<String>>,
+}
+
+/// Watch `repo_root` for file changes. `on_change` receives the changed
+/// paths (relative to `repo_root`) once per debounce window, limited to paths
+/// `should_index` accepts; deleted files are included, so check existence
+/// before reindexing. Returns a watcher id for `unwatch`.
+#[napi(catch_unwind)]
+pub fn watch(
+    repo_root: String,
+    on_change: ThreadsafeFunction<Vec<String>, ErrorStrategy::Fatal>,
+    options: Option<WatchOptions>,
+) -> napi::Result<f64> {
+    let (debounce_ms, exclude) = match options {
+        Some(o) => (o.debounce_ms, o.exclude),
+        None => (None, None),
+    };
+    let root = std::path::Path::new(&repo_root);
+    let scope = scope::IndexScope::new(root, &exclude.unwrap_or_default())
+        .map_err(napi::Error::from_reason)?;
+    let watcher = watch::watch(
+        root,
+        std::time::Duration::from_millis(debounce_ms.unwrap_or(500) as u64),
+        scope,
+        move |paths| {
+            on_change.call(paths, ThreadsafeFunctionCallMode::NonBlocking);
+        },
+    )
+    .map_err(napi::Error::from_reason)?;
+
+    let id = NEXT_WATCHER_ID.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+    watchers()?.insert(id, watcher);
+    Ok(id as f64)
+}
+
+/// Stop a watcher started with `watch`. Returns false if the id is unknown.
+#[napi(catch_unwind)]
+pub fn unwatch(id: f64) -> napi::Result<bool> {
+    Ok(watchers()?.remove(&(id as u32)).is_some())
 }
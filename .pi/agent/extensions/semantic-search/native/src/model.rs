@@ -9,7 +9,7 @@ use mlx_rs::{
     module::Module,
     nn::{self, Embedding, LayerNorm, Linear, Rope, RopeInput},
     ops,
-    Array,
+    Array, Dtype,
 };
 
 use mlx_macros::ModuleParameters;
@@ -39,6 +39,9 @@ pub struct NomicBertConfig {
     pub mlp_fc2_bias: bool,
     #[serde(default)]
     pub prenorm: bool,
+    /// Hub id the weights were exported from, when config.json records it.
+    #[serde(rename = "_name_or_path", default)]
+    pub name_or_path: Option<String>,
 }
 
 fn default_n_inner() -> Option<i32> { None }
@@ -141,6 +144,16 @@ impl NomicBertModel {
             encoder: NomicEncoder { layers },
         })
     }
+
+    /// Cast every weight to `dtype` (after `load_safetensors`). Activations
+    /// follow the weights; pooling and normalization still run in f32.
+    pub fn cast_parameters(&mut self, dtype: Dtype) -> Result<(), Exception> {
+        let params = mlx_rs::module::ModuleParameters::parameters_mut(self).flatten();
+        for param in params.into_values() {
+            *param = param.as_dtype(dtype)?;
+        }
+        Ok(())
+    }
 }
 
 impl NomicBlock {
@@ -212,12 +225,37 @@ impl NomicBertModel {
         &mut self,
         input_ids: &Array,
         attention_mask: Option<&Array>,
+    ) -> Result<Array, Exception> {
+        self.forward_inner(input_ids, attention_mask, None)
+    }
+
+    /// `forward`, also returning the hidden states after the embedding layer
+    /// norm and after each encoder layer (`n_layer + 1` arrays), for
+    /// comparing against a reference implementation layer by layer.
+    pub fn forward_with_layers(
+        &mut self,
+        input_ids: &Array,
+        attention_mask: Option<&Array>,
+    ) -> Result<(Array, Vec<Array>), Exception> {
+        let mut layers = Vec::with_capacity(self.encoder.layers.len() + 1);
+        let x = self.forward_inner(input_ids, attention_mask, Some(&mut layers))?;
+        Ok((x, layers))
+    }
+
+    fn forward_inner(
+        &mut self,
+        input_ids: &Array,
+        attention_mask: Option<&Array>,
+        mut layer_outputs: Option<&mut Vec<Array>>,
     ) -> Result<Array, Exception> {
         // Embed tokens
         let mut x = self.embeddings.word_embeddings.forward(input_ids)?;
 
         // Embedding layer norm
         x = self.emb_ln.forward(&x)?;
+        if let Some(out) = layer_outputs.as_deref_mut() {
+            out.push(x.clone());
+        }
 
         // Build attention mask: [batch, 1, 1, seq_len]
         // 0 for real tokens, -10000 for padding
@@ -226,7 +264,8 @@ impl NomicBertModel {
             let ones = Array::from_f32(1.0);
             let neg = Array::from_f32(-10000.0);
             // (1 - mask) * -10000, then reshape to [B, 1, 1, L]
-            let m = ones.subtract(&am_f)?.multiply(&neg)?;
+            // Cast to the activation dtype; attention rejects a wider mask.
+            let m = ones.subtract(&am_f)?.multiply(&neg)?.as_dtype(x.dtype())?;
             let shape = am.shape();
             Some(m.reshape(&[shape[0], 1, 1, shape[1]])?)
         } else {
@@ -236,6 +275,9 @@ impl NomicBertModel {
         // Encoder layers
         for layer in &mut self.encoder.layers {
             x = layer.forward(&x, mask.as_ref())?;
+            if let Some(out) = layer_outputs.as_deref_mut() {
+                out.push(x.clone());
+            }
         }
 
         Ok(x)
@@ -329,3 +371,13 @@ pub fn mean_pool_normalize(
     let norm = ops::maximum(&norm, &norm_eps)?;
     pooled.divide(&norm)
 }
+
+/// `forward` then `mean_pool_normalize`, as one function of the model and
+/// its inputs so it can be traced by `mlx_rs::transforms::compile`.
+pub fn embed_pooled(
+    model: &mut NomicBertModel,
+    (input_ids, attention_mask): (&Array, &Array),
+) -> Result<Array, Exception> {
+    let hidden = model.forward(input_ids, Some(attention_mask))?;
+    mean_pool_normalize(&hidden, attention_mask)
+}
@@ -0,0 +1,224 @@
+//! Product quantization: code embeddings as one byte per subvector.
+//!
+//! An embedding is split into `m` subvectors of `dims / m` floats; each is
+//! coded as the index of its nearest centroid in that subspace's codebook
+//! (k-means, `2^bits` centroids). Searching compares a query against codes
+//! with asymmetric distance computation (ADC): per-subspace distances from
+//! the query to every centroid are tabulated once, then each row's distance
+//! is `m` table lookups.
+//!
+//! The index keeps the full embeddings next to the codes: codes make the
+//! shortlist scan read far less, and the shortlist is rescored exactly. They
+//! don't shrink the index.
+
+/// Lloyd iterations per subspace when training.
+const KMEANS_ITERS: usize = 15;
+
+#[derive(Debug, Clone)]
+pub struct Codebook {
+    /// Subvectors per embedding
+    pub m: usize,
+    /// Bits per code; `2^bits` centroids per subspace
+    pub bits: u32,
+    /// Floats per subvector
+    pub dsub: usize,
+    /// `m * ksub * dsub` floats, subspace-major
+    centroids: Vec<f32>,
+}
+
+impl Codebook {
+    fn ksub(&self) -> usize {
+        1 << self.bits
+    }
+
+    fn centroid(&self, sub: usize, k: usize) -> &[f32] {
+        let start = (sub * self.ksub() + k) * self.dsub;
+        &self.centroids[start..start + self.dsub]
+    }
+
+    /// Train per-subspace codebooks on `samples` (all `dims` long).
+    pub fn train(samples: &[Vec<f32>], m: usize, bits: u32) -> Result<Self, String> {
+        if !(1..=8).contains(&bits) {
+            return Err(format!("bits must be between 1 and 8, got {}", bits));
+        }
+        let ksub = 1usize << bits;
+        let dims = samples.first().map_or(0, |s| s.len());
+        if let Some(s) = samples.iter().find(|s| s.len() != dims) {
+            return Err(format!(
+                "Samples must all have {} dimensions, got one with {}",
+                dims,
+                s.len()
+            ));
+        }
+        if m == 0 || !dims.is_multiple_of(m) {
+            return Err(format!(
+                "m ({}) must divide the embedding dimensions ({})",
+                m, dims
+            ));
+        }
+        if samples.len() < ksub {
+            return Err(format!(
+                "Need at least {} samples to train {}-bit codes, got {}",
+                ksub,
+                bits,
+                samples.len()
+            ));
+        }
+        let dsub = dims / m;
+
+        let mut centroids = Vec::with_capacity(m * ksub * dsub);
+        let mut sub_samples: Vec<&[f32]> = Vec::with_capacity(samples.len());
+        for sub in 0..m {
+            sub_samples.clear();
+            sub_samples.extend(samples.iter().map(|s| &s[sub * dsub..(sub + 1) * dsub]));
+            centroids.extend(kmeans(&sub_samples, ksub, dsub));
+        }
+        Ok(Codebook {
+            m,
+            bits,
+            dsub,
+            centroids,
+        })
+    }
+
+    /// Nearest centroid per subspace.
+    pub fn encode(&self, v: &[f32]) -> Vec<u8> {
+        (0..self.m)
+            .map(|sub| {
+                let x = &v[sub * self.dsub..(sub + 1) * self.dsub];
+                nearest(x, (0..self.ksub()).map(|k| self.centroid(sub, k))) as u8
+            })
+            .collect()
+    }
+
+    /// Squared distances from each query subvector to every centroid in its
+    /// subspace: `m * ksub` entries, for `adc_distance`.
+    pub fn distance_table(&self, query: &[f32]) -> Vec<f32> {
+        let mut table = Vec::with_capacity(self.m * self.ksub());
+        for sub in 0..self.m {
+            let q = &query[sub * self.dsub..(sub + 1) * self.dsub];
+            for k in 0..self.ksub() {
+                table.push(l2sq(q, self.centroid(sub, k)));
+            }
+        }
+        table
+    }
+
+    /// Approximate L2² between the query behind `table` and a coded row.
+    pub fn adc_distance(&self, table: &[f32], codes: &[u8]) -> f32 {
+        let ksub = self.ksub();
+        codes
+            .iter()
+            .enumerate()
+            .map(|(sub, &c)| table[sub * ksub + c as usize])
+            .sum()
+    }
+
+    /// Serialize as little-endian `m`, `bits`, `dsub` (u32 each), then the
+    /// centroids as f32s.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(12 + self.centroids.len() * 4);
+        for v in [self.m as u32, self.bits, self.dsub as u32] {
+            out.extend_from_slice(&v.to_le_bytes());
+        }
+        for c in &self.centroids {
+            out.extend_from_slice(&c.to_le_bytes());
+        }
+        out
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, String> {
+        let header = |i: usize| -> Result<u32, String> {
+            bytes
+                .get(i * 4..i * 4 + 4)
+                .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+                .ok_or_else(|| "PQ codebook is truncated".to_string())
+        };
+        let (m, bits, dsub) = (header(0)? as usize, header(1)?, header(2)? as usize);
+        if !(1..=8).contains(&bits) {
+            return Err(format!("PQ codebook has invalid bits {}", bits));
+        }
+        let body = &bytes[12..];
+        let expected = m
+            .checked_mul(1 << bits)
+            .and_then(|n| n.checked_mul(dsub))
+            .and_then(|n| n.checked_mul(4));
+        if m == 0 || dsub == 0 || expected != Some(body.len()) {
+            return Err("PQ codebook size does not match its header".to_string());
+        }
+        let centroids = body
+            .chunks_exact(4)
+            .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .collect();
+        Ok(Codebook {
+            m,
+            bits,
+            dsub,
+            centroids,
+        })
+    }
+}
+
+fn l2sq(a: &[f32], b: &[f32]) -> f32 {
+    a.iter().zip(b).map(|(x, y)| (x - y) * (x - y)).sum()
+}
+
+pub(crate) fn nearest<'a>(x: &[f32], centroids: impl Iterator<Item = &'a [f32]>) -> usize {
+    let mut best = (0, f32::INFINITY);
+    for (k, c) in centroids.enumerate() {
+        let d = l2sq(x, c);
+        if d < best.1 {
+            best = (k, d);
+        }
+    }
+    best.0
+}
+
+/// Lloyd's k-means, seeded with evenly spaced samples so training is
+/// deterministic for a given sample. Returns `k * dsub` floats.
+pub(crate) fn kmeans(points: &[&[f32]], k: usize, dsub: usize) -> Vec<f32> {
+    let stride = points.len() / k;
+    let mut centroids: Vec<f32> = (0..k).flat_map(|i| points[i * stride].to_vec()).collect();
+    let mut assignment = vec![0usize; points.len()];
+
+    for _ in 0..KMEANS_ITERS {
+        for (p, a) in points.iter().zip(assignment.iter_mut()) {
+            *a = nearest(p, centroids.chunks_exact(dsub));
+        }
+
+        let mut sums = vec![0f32; k * dsub];
+        let mut counts = vec![0usize; k];
+        for (p, &a) in points.iter().zip(&assignment) {
+            counts[a] += 1;
+            for (s, x) in sums[a * dsub..(a + 1) * dsub].iter_mut().zip(p.iter()) {
+                *s += x;
+            }
+        }
+        // Empty clusters restart on the points farthest from their
+        // centroids, one each, splitting the clusters that fit worst
+        let mut farthest = Vec::new();
+        if counts.contains(&0) {
+            farthest = points
+                .iter()
+                .zip(&assignment)
+                .enumerate()
+                .map(|(i, (p, &a))| (l2sq(p, &centroids[a * dsub..(a + 1) * dsub]), i))
+                .collect();
+            farthest.sort_unstable_by(|a, b| b.0.total_cmp(&a.0));
+        }
+        let mut farthest = farthest.into_iter();
+        for c in 0..k {
+            let centroid = &mut centroids[c * dsub..(c + 1) * dsub];
+            if counts[c] == 0 {
+                if let Some((_, i)) = farthest.next() {
+                    centroid.copy_from_slice(points[i]);
+                }
+                continue;
+            }
+            for (dst, s) in centroid.iter_mut().zip(&sums[c * dsub..(c + 1) * dsub]) {
+                *dst = s / counts[c] as f32;
+            }
+        }
+    }
+    centroids
+}
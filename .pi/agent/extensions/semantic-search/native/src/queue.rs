@@ -0,0 +1,190 @@
+//! Background work queue for low-priority indexing.
+//!
+//! Jobs wait in a priority queue (highest priority first, FIFO within a
+//! priority) and a single worker thread runs them one at a time. Before each
+//! job the worker waits until no foreground call is in flight and the queue
+//! isn't paused, so interactive calls are delayed by at most the job already
+//! running.
+
+use std::collections::BinaryHeap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+
+pub struct WorkQueue<T> {
+    inner: Arc<Inner<T>>,
+}
+
+struct Inner<T> {
+    pending: Mutex<Pending<T>>,
+    wake: Condvar,
+    foreground: AtomicUsize,
+}
+
+struct Pending<T> {
+    jobs: BinaryHeap<Job<T>>,
+    next_seq: u64,
+    paused: bool,
+    running: bool,
+    completed: u64,
+    failed: u64,
+    last_error: Option<String>,
+}
+
+struct Job<T> {
+    priority: i32,
+    seq: u64,
+    item: T,
+}
+
+impl<T> PartialEq for Job<T> {
+    fn eq(&self, other: &Self) -> bool {
+        (self.priority, self.seq) == (other.priority, other.seq)
+    }
+}
+
+impl<T> Eq for Job<T> {}
+
+impl<T> PartialOrd for Job<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<T> Ord for Job<T> {
+    /// Max-heap: higher priority first, then lower sequence number (older).
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.priority
+            .cmp(&other.priority)
+            .then_with(|| other.seq.cmp(&self.seq))
+    }
+}
+
+/// Snapshot of a queue for status reporting.
+#[derive(Debug, Clone)]
+pub struct QueueStatus {
+    pub pending: usize,
+    pub paused: bool,
+    /// A job is running right now
+    pub running: bool,
+    pub completed: u64,
+    pub failed: u64,
+    pub last_error: Option<String>,
+}
+
+/// Marks a foreground call in flight while alive; see `WorkQueue::foreground`.
+pub struct ForegroundGuard<'a, T> {
+    inner: &'a Inner<T>,
+}
+
+impl<T> Drop for ForegroundGuard<'_, T> {
+    fn drop(&mut self) {
+        if self.inner.foreground.fetch_sub(1, Ordering::SeqCst) == 1 {
+            // Take the lock so the worker can't miss the wakeup between
+            // checking the counter and waiting
+            drop(self.inner.lock());
+            self.inner.wake.notify_all();
+        }
+    }
+}
+
+impl<T> Inner<T> {
+    fn lock(&self) -> MutexGuard<'_, Pending<T>> {
+        self.pending.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl<T: Send + 'static> WorkQueue<T> {
+    /// Start the worker thread. `run` executes one job; an `Err` is counted
+    /// and kept as `last_error`.
+    pub fn new(run: impl Fn(T) -> Result<(), String> + Send + 'static) -> Self {
+        let inner = Arc::new(Inner {
+            pending: Mutex::new(Pending {
+                jobs: BinaryHeap::new(),
+                next_seq: 0,
+                paused: false,
+                running: false,
+                completed: 0,
+                failed: 0,
+                last_error: None,
+            }),
+            wake: Condvar::new(),
+            foreground: AtomicUsize::new(0),
+        });
+
+        let worker = Arc::clone(&inner);
+        std::thread::Builder::new()
+            .name("index-queue".to_string())
+            .spawn(move || loop {
+                let item = {
+                    let mut pending = worker.lock();
+                    loop {
+                        if !pending.paused && worker.foreground.load(Ordering::SeqCst) == 0 {
+                            if let Some(job) = pending.jobs.pop() {
+                                pending.running = true;
+                                break job.item;
+                            }
+                        }
+                        pending = worker.wake.wait(pending).unwrap_or_else(|e| e.into_inner());
+                    }
+                };
+                let result = run(item);
+                let mut pending = worker.lock();
+                pending.running = false;
+                match result {
+                    Ok(()) => pending.completed += 1,
+                    Err(e) => {
+                        pending.failed += 1;
+                        pending.last_error = Some(e);
+                    }
+                }
+            })
+            .expect("failed to spawn index queue worker");
+
+        WorkQueue { inner }
+    }
+
+    pub fn push(&self, item: T, priority: i32) {
+        let mut pending = self.inner.lock();
+        let seq = pending.next_seq;
+        pending.next_seq += 1;
+        pending.jobs.push(Job {
+            priority,
+            seq,
+            item,
+        });
+        drop(pending);
+        self.inner.wake.notify_all();
+    }
+
+    /// Hold the worker back until the returned guard drops. Jobs already
+    /// running finish first.
+    pub fn foreground(&self) -> ForegroundGuard<'_, T> {
+        self.inner.foreground.fetch_add(1, Ordering::SeqCst);
+        ForegroundGuard { inner: &self.inner }
+    }
+
+    pub fn set_paused(&self, paused: bool) {
+        self.inner.lock().paused = paused;
+        self.inner.wake.notify_all();
+    }
+
+    /// Drop every job not yet started. Returns how many were dropped.
+    pub fn clear(&self) -> usize {
+        let mut pending = self.inner.lock();
+        let dropped = pending.jobs.len();
+        pending.jobs.clear();
+        dropped
+    }
+
+    pub fn status(&self) -> QueueStatus {
+        let pending = self.inner.lock();
+        QueueStatus {
+            pending: pending.jobs.len(),
+            paused: pending.paused,
+            running: pending.running,
+            completed: pending.completed,
+            failed: pending.failed,
+            last_error: pending.last_error.clone(),
+        }
+    }
+}
@@ -0,0 +1,252 @@
+//! Post-retrieval reranking over the merged candidate pool.
+//!
+//! Everything here runs after the DB scan on a few dozen candidates, so plain
+//! loops are fine — the hot path is the distance computation in `db.rs`.
+
+use crate::db::SearchResult;
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+
+/// Maximal marginal relevance: greedily pick the candidate that maximizes
+/// `lambda * score - (1 - lambda) * max_sim(candidate, already_picked)`.
+///
+/// `candidates` must be sorted by score descending. `sim(i, j)` returns the
+/// similarity between candidates `i` and `j` in [0, 1].
+pub fn mmr(
+    candidates: Vec<SearchResult>,
+    top_k: usize,
+    lambda: f64,
+    sim: impl Fn(usize, usize) -> f64,
+) -> Vec<SearchResult> {
+    let n = candidates.len();
+    let mut picked: Vec<usize> = Vec::with_capacity(top_k.min(n));
+    // Running max similarity of each candidate to the picked set
+    let mut max_sim = vec![0.0f64; n];
+    let mut used = vec![false; n];
+
+    while picked.len() < top_k.min(n) {
+        let mut best: Option<(usize, f64)> = None;
+        for i in 0..n {
+            if used[i] {
+                continue;
+            }
+            let value = lambda * candidates[i].score - (1.0 - lambda) * max_sim[i];
+            if best.is_none_or(|(_, b)| value > b) {
+                best = Some((i, value));
+            }
+        }
+        let Some((chosen, _)) = best else { break };
+        used[chosen] = true;
+        picked.push(chosen);
+        for i in 0..n {
+            if !used[i] {
+                max_sim[i] = max_sim[i].max(sim(i, chosen));
+            }
+        }
+    }
+
+    let mut slots: Vec<Option<SearchResult>> = candidates.into_iter().map(Some).collect();
+    picked
+        .into_iter()
+        .filter_map(|i| slots[i].take())
+        .collect()
+}
+
+/// The first `top_k` of `candidates`, skipping those whose kind already
+/// has its `limits` share. Kinds without a limit are unlimited.
+///
+/// `candidates` must be sorted by score descending.
+pub fn limit_per_kind(
+    candidates: Vec<SearchResult>,
+    top_k: usize,
+    limits: &HashMap<String, usize>,
+) -> Vec<SearchResult> {
+    let mut taken: HashMap<String, usize> = HashMap::new();
+    candidates
+        .into_iter()
+        .filter(|r| match limits.get(&r.kind) {
+            Some(&limit) => {
+                let n = taken.entry(r.kind.clone()).or_default();
+                *n += 1;
+                *n <= limit
+            }
+            None => true,
+        })
+        .take(top_k)
+        .collect()
+}
+
+/// `candidates` with the scores of those in `stale` (by `(file_path,
+/// line)`) scaled by `weight`, dropping them at 0. Re-sort afterwards.
+pub fn demote(
+    candidates: Vec<SearchResult>,
+    stale: &HashSet<(String, i32)>,
+    weight: f64,
+) -> Vec<SearchResult> {
+    candidates
+        .into_iter()
+        .filter_map(|mut r| {
+            if stale.contains(&(r.file_path.clone(), r.line)) {
+                if weight <= 0.0 {
+                    return None;
+                }
+                r.score *= weight;
+            }
+            Some(r)
+        })
+        .collect()
+}
+
+/// How symbol scores combine into a file score.
+#[derive(Debug, Clone, Copy)]
+pub enum FileAggregate {
+    /// Best single symbol score.
+    Max,
+    /// Sum of the file's top-N symbol scores.
+    SumTopK,
+}
+
+pub struct FileGroup {
+    pub file_path: String,
+    pub score: f64,
+    pub symbols: Vec<SearchResult>,
+}
+
+/// Group candidates by file, keeping each file's top `per_file` symbols,
+/// and rank files by the aggregated score (descending).
+///
+/// `candidates` must be sorted by score descending.
+pub fn group_by_file(
+    candidates: Vec<SearchResult>,
+    aggregate: FileAggregate,
+    per_file: usize,
+) -> Vec<FileGroup> {
+    let mut by_file: HashMap<String, Vec<SearchResult>> = HashMap::new();
+    for r in candidates {
+        let symbols = by_file.entry(r.file_path.clone()).or_default();
+        if symbols.len() < per_file {
+            symbols.push(r);
+        }
+    }
+
+    let mut groups: Vec<FileGroup> = by_file
+        .into_iter()
+        .map(|(file_path, symbols)| {
+            let score = match aggregate {
+                FileAggregate::Max => symbols[0].score,
+                FileAggregate::SumTopK => symbols.iter().map(|s| s.score).sum(),
+            };
+            FileGroup { file_path, score, symbols }
+        })
+        .collect();
+    groups.sort_by(|a, b| b.score.total_cmp(&a.score));
+    groups
+}
+
+/// `meta` key holding the index's ranking rules as JSON.
+pub const RULES_META_KEY: &str = "ranking_rules";
+
+/// Largest score boost popularity can give, reached at `POPULARITY_SATURATION`
+/// opens.
+const POPULARITY_WEIGHT: f64 = 0.03;
+const POPULARITY_SATURATION: f64 = 50.0;
+
+/// Score adjustments stored per index (see `set_ranking_rules`).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RankingRules {
+    #[serde(default)]
+    pub path: Vec<PathRule>,
+    /// Score multiplier per (normalized) kind; kinds not listed keep 1.0
+    #[serde(default)]
+    pub kind: HashMap<String, f64>,
+    /// Blend in how often each result was opened (see `record_click`)
+    #[serde(default = "enabled")]
+    pub popularity: bool,
+}
+
+impl Default for RankingRules {
+    fn default() -> Self {
+        RankingRules { path: Vec::new(), kind: HashMap::new(), popularity: true }
+    }
+}
+
+fn enabled() -> bool {
+    true
+}
+
+/// Add `boost` (negative to penalize) to results whose path matches
+/// `pattern`, in gitignore syntax: `src/` matches anything under a `src`
+/// directory, `*_test.go` any file of that name.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PathRule {
+    pub pattern: String,
+    pub boost: f64,
+}
+
+/// `RankingRules` with patterns compiled.
+pub struct Ranker {
+    path: Vec<(Gitignore, f64)>,
+    kind: HashMap<String, f64>,
+    pub popularity: bool,
+}
+
+impl Ranker {
+    pub fn new(rules: &RankingRules) -> Result<Self, String> {
+        if let Some((kind, w)) = rules.kind.iter().find(|(_, w)| !w.is_finite() || **w < 0.0) {
+            return Err(format!(
+                "Kind weight for '{}' must be a non-negative number, got {}",
+                kind, w
+            ));
+        }
+        if let Some(rule) = rules.path.iter().find(|r| !r.boost.is_finite()) {
+            return Err(format!(
+                "Boost for path pattern '{}' must be a finite number, got {}",
+                rule.pattern, rule.boost
+            ));
+        }
+        let path = rules
+            .path
+            .iter()
+            .map(|rule| {
+                let mut builder = GitignoreBuilder::new("");
+                builder
+                    .add_line(None, &rule.pattern)
+                    .map_err(|e| format!("Invalid path pattern '{}': {}", rule.pattern, e))?;
+                let matcher = builder
+                    .build()
+                    .map_err(|e| format!("Invalid path pattern '{}': {}", rule.pattern, e))?;
+                Ok((matcher, rule.boost))
+            })
+            .collect::<Result<_, String>>()?;
+        Ok(Ranker { path, kind: rules.kind.clone(), popularity: rules.popularity })
+    }
+
+    /// Sum of the boosts of every path rule matching `file_path`.
+    fn path_boost(&self, file_path: &str) -> f64 {
+        let relative = file_path.trim_start_matches('/');
+        self.path
+            .iter()
+            .filter(|(m, _)| m.matched_path_or_any_parents(relative, false).is_ignore())
+            .map(|(_, boost)| boost)
+            .sum()
+    }
+
+    /// Adjust the scores of `candidates` in place. `opens[i]` is how often
+    /// candidate `i` was opened; empty when popularity is off. Re-sort
+    /// afterwards.
+    pub fn apply(&self, candidates: &mut [SearchResult], opens: &[u64]) {
+        for (i, r) in candidates.iter_mut().enumerate() {
+            r.score *= self.kind.get(&r.kind).copied().unwrap_or(1.0);
+            r.score += self.path_boost(&r.file_path);
+            r.score += opens.get(i).map_or(0.0, |&n| popularity_prior(n));
+        }
+    }
+}
+
+/// Log-scaled boost for a result opened `opens` times: small enough to
+/// reorder near-ties, not to lift an unrelated result over a good match.
+fn popularity_prior(opens: u64) -> f64 {
+    let scaled = (opens as f64).ln_1p() / POPULARITY_SATURATION.ln_1p();
+    POPULARITY_WEIGHT * scaled.min(1.0)
+}
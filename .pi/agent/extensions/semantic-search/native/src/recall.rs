@@ -0,0 +1,79 @@
+//! Retrieval quality metrics over labeled queries.
+//!
+//! A case is a query and the symbols it should find. Each expected symbol
+//! names any of `symbol_id`, `file_path`, `name`; a result matches when every
+//! field given agrees. Recall@k and reciprocal rank are computed per case and
+//! averaged by `evaluate_search`.
+
+use serde::Deserialize;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct LabeledQuery {
+    pub query: String,
+    pub expected: Vec<ExpectedSymbol>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct ExpectedSymbol {
+    #[serde(default)]
+    pub symbol_id: Option<String>,
+    #[serde(default)]
+    pub file_path: Option<String>,
+    #[serde(default)]
+    pub name: Option<String>,
+}
+
+/// The fields of a search result that expectations can name.
+pub struct Hit<'a> {
+    pub symbol_id: &'a str,
+    pub file_path: &'a str,
+    pub name: &'a str,
+}
+
+impl ExpectedSymbol {
+    pub fn matches(&self, hit: &Hit) -> bool {
+        let agrees = |want: &Option<String>, got: &str| want.as_deref().is_none_or(|w| w == got);
+        (self.symbol_id.is_some() || self.file_path.is_some() || self.name.is_some())
+            && agrees(&self.symbol_id, hit.symbol_id)
+            && agrees(&self.file_path, hit.file_path)
+            && agrees(&self.name, hit.name)
+    }
+}
+
+/// Parse a JSON array of `{ query, expected: [...] }` cases.
+pub fn parse_cases(json: &str) -> Result<Vec<LabeledQuery>, String> {
+    let cases: Vec<LabeledQuery> =
+        serde_json::from_str(json).map_err(|e| format!("Invalid cases JSON: {}", e))?;
+    if let Some(case) = cases.iter().find(|c| c.expected.is_empty()) {
+        return Err(format!("Case '{}' has no expected symbols", case.query));
+    }
+    Ok(cases)
+}
+
+/// How one case scored against its ranked results.
+#[derive(Debug, Clone)]
+pub struct CaseScore {
+    /// Fraction of expected symbols among the results
+    pub recall: f64,
+    /// 1 / rank of the first expected symbol, 0 when none was found
+    pub reciprocal_rank: f64,
+    /// 1-based rank of the first expected symbol
+    pub first_hit_rank: Option<usize>,
+}
+
+/// Score `hits` (best first, already cut to k) against `expected`.
+pub fn score(expected: &[ExpectedSymbol], hits: &[Hit]) -> CaseScore {
+    let found = expected
+        .iter()
+        .filter(|e| hits.iter().any(|h| e.matches(h)))
+        .count();
+    let first_hit_rank = hits
+        .iter()
+        .position(|h| expected.iter().any(|e| e.matches(h)))
+        .map(|i| i + 1);
+    CaseScore {
+        recall: found as f64 / expected.len().max(1) as f64,
+        reciprocal_rank: first_hit_rank.map_or(0.0, |r| 1.0 / r as f64),
+        first_hit_rank,
+    }
+}
@@ -0,0 +1,165 @@
+//! Index scope: which paths under a repo root are eligible for indexing.
+//!
+//! Mirrors the TS walker in `indexer.ts`: built-in skipped directories and
+//! generated-file patterns, `.gitignore` files at every directory level, plus
+//! `.piignore` files and caller-supplied exclude patterns (gitignore syntax).
+//! A deeper ignore file overrides a shallower one; custom excludes override all.
+//! An index can further limit itself to include patterns (see `Includes`).
+
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use ignore::Match;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+/// Directories never indexed, regardless of ignore files.
+const SKIP_DIRS: &[&str] = &[
+    "node_modules",
+    "vendor",
+    "__pycache__",
+    "target",
+    "build",
+    "dist",
+    ".git",
+    ".jj",
+    ".code-search-cache",
+    ".next",
+    ".nuxt",
+];
+
+/// Generated/minified file suffixes never indexed.
+const SKIP_SUFFIXES: &[&str] = &[
+    ".pb.go",
+    ".pb.gw.go",
+    "_generated.go",
+    ".gen.go",
+    ".d.ts",
+    ".min.js",
+    ".bundle.js",
+];
+
+const IGNORE_FILES: &[&str] = &[".gitignore", ".piignore"];
+
+pub struct IndexScope {
+    root: PathBuf,
+    excludes: Gitignore,
+    /// Per-directory matchers, loaded lazily. None when the directory has no
+    /// ignore files.
+    dir_matchers: HashMap<PathBuf, Option<Gitignore>>,
+}
+
+impl IndexScope {
+    pub fn new(root: &Path, excludes: &[String]) -> Result<Self, String> {
+        let mut builder = GitignoreBuilder::new(root);
+        for pattern in excludes {
+            builder
+                .add_line(None, pattern)
+                .map_err(|e| format!("Invalid exclude pattern '{}': {}", pattern, e))?;
+        }
+        let excludes = builder
+            .build()
+            .map_err(|e| format!("Invalid exclude patterns: {}", e))?;
+        Ok(IndexScope {
+            root: root.to_path_buf(),
+            excludes,
+            dir_matchers: HashMap::new(),
+        })
+    }
+
+    /// Whether `rel_path` (relative to the root) would be indexed. Only ignore
+    /// rules are checked; the file need not exist.
+    pub fn should_index(&mut self, rel_path: &str) -> bool {
+        let rel = Path::new(rel_path);
+        let Some(file_name) = rel.file_name().and_then(|n| n.to_str()) else {
+            return false;
+        };
+        if SKIP_SUFFIXES.iter().any(|s| file_name.ends_with(s)) {
+            return false;
+        }
+        let parent = rel.parent().unwrap_or(Path::new(""));
+        if parent
+            .components()
+            .any(|c| SKIP_DIRS.iter().any(|d| c.as_os_str() == *d))
+        {
+            return false;
+        }
+
+        let abs = self.root.join(rel);
+        match self.excludes.matched_path_or_any_parents(&abs, false) {
+            Match::Ignore(_) => return false,
+            Match::Whitelist(_) => return true,
+            Match::None => {}
+        }
+
+        // Deepest directory first: the nearest ignore file decides
+        let dirs: Vec<PathBuf> = parent.ancestors().map(|d| self.root.join(d)).collect();
+        for dir in dirs {
+            if let Some(matcher) = self.matcher_for(&dir) {
+                match matcher.matched_path_or_any_parents(&abs, false) {
+                    Match::Ignore(_) => return false,
+                    Match::Whitelist(_) => return true,
+                    Match::None => {}
+                }
+            }
+        }
+        true
+    }
+
+    fn matcher_for(&mut self, dir: &Path) -> Option<&Gitignore> {
+        self.dir_matchers
+            .entry(dir.to_path_buf())
+            .or_insert_with(|| {
+                let mut builder = GitignoreBuilder::new(dir);
+                let mut found = false;
+                for name in IGNORE_FILES {
+                    let path = dir.join(name);
+                    if path.is_file() {
+                        // Unparseable lines are skipped, like git does
+                        builder.add(path);
+                        found = true;
+                    }
+                }
+                if found {
+                    builder.build().ok()
+                } else {
+                    None
+                }
+            })
+            .as_ref()
+    }
+}
+
+/// Include patterns (gitignore syntax) that limit an index to part of a
+/// repo, e.g. `services/payments/` or `*.go`. A path is included when a
+/// pattern matches it or one of its directories; `!` patterns carve
+/// exceptions out of earlier ones. No patterns include everything.
+pub struct Includes {
+    matcher: Option<Gitignore>,
+}
+
+impl Includes {
+    pub fn new(patterns: &[String]) -> Result<Self, String> {
+        if patterns.is_empty() {
+            return Ok(Includes { matcher: None });
+        }
+        let mut builder = GitignoreBuilder::new("");
+        for pattern in patterns {
+            builder
+                .add_line(None, pattern)
+                .map_err(|e| format!("Invalid include pattern '{}': {}", pattern, e))?;
+        }
+        let matcher = builder
+            .build()
+            .map_err(|e| format!("Invalid include patterns: {}", e))?;
+        Ok(Includes { matcher: Some(matcher) })
+    }
+
+    /// Whether `rel_path` (relative to the root) is included.
+    pub fn contains(&self, rel_path: &str) -> bool {
+        match &self.matcher {
+            Some(m) => m
+                .matched_path_or_any_parents(rel_path.trim_start_matches('/'), false)
+                .is_ignore(),
+            None => true,
+        }
+    }
+}
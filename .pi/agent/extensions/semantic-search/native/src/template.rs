@@ -0,0 +1,103 @@
+//! Embedding text templates.
+//!
+//! Each index stores its template in `meta` so every writer formats symbol
+//! text the same way. Placeholders: `{language}`, `{path}`, `{name}`,
+//! `{kind}`, `{signature}` (falls back to the name), and `{doc}` (empty when
+//! the symbol has no doc comment). Templates without `{doc}`, and embedding
+//! texts supplied by the caller, get the doc comment appended after
+//! `DOC_SEPARATOR`.
+
+/// Matches the format the TS chunker has always produced.
+pub const DEFAULT_TEMPLATE: &str = "{language} | {path} | {signature}";
+
+/// Joins the rendered template and a doc comment the template didn't place.
+pub const DOC_SEPARATOR: &str = "\n";
+
+/// `meta` key holding the index's template.
+pub const META_KEY: &str = "embedding_template";
+
+const PLACEHOLDERS: &[&str] = &["language", "path", "name", "kind", "signature", "doc"];
+
+pub struct TemplateFields<'a> {
+    pub language: &'a str,
+    pub path: &'a str,
+    pub name: &'a str,
+    pub kind: &'a str,
+    pub signature: Option<&'a str>,
+    pub doc: Option<&'a str>,
+}
+
+/// Check that every `{...}` in `template` is a known placeholder.
+pub fn validate(template: &str) -> Result<(), String> {
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        let after = &rest[start + 1..];
+        let end = after
+            .find('}')
+            .ok_or_else(|| format!("Unclosed '{{' in template '{}'", template))?;
+        let name = &after[..end];
+        if !PLACEHOLDERS.contains(&name) {
+            return Err(format!(
+                "Unknown placeholder '{{{}}}'. Expected one of: {}",
+                name,
+                PLACEHOLDERS
+                    .iter()
+                    .map(|p| format!("{{{}}}", p))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ));
+        }
+        rest = &after[end + 1..];
+    }
+    Ok(())
+}
+
+/// Fill in `template`. Assumes it passed `validate`; unknown placeholders are
+/// left as-is.
+pub fn render(template: &str, fields: &TemplateFields) -> String {
+    let mut out = String::with_capacity(template.len() + 64);
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        out.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        let Some(end) = after.find('}') else {
+            rest = &rest[start..];
+            break;
+        };
+        match &after[..end] {
+            "language" => out.push_str(fields.language),
+            "path" => out.push_str(fields.path),
+            "name" => out.push_str(fields.name),
+            "kind" => out.push_str(fields.kind),
+            "signature" => out.push_str(fields.signature.unwrap_or(fields.name)),
+            "doc" => out.push_str(fields.doc.unwrap_or("")),
+            other => {
+                out.push('{');
+                out.push_str(other);
+                out.push('}');
+            }
+        }
+        rest = &after[end + 1..];
+    }
+    out.push_str(rest);
+
+    if let Some(doc) = fields.doc.filter(|d| !d.is_empty()) {
+        if !template.contains("{doc}") {
+            out.push_str(DOC_SEPARATOR);
+            out.push_str(doc);
+        }
+    }
+    out
+}
+
+/// Append `doc` to caller-supplied embedding text the way `render` appends it
+/// to a template without `{doc}`. Text that already contains the doc comment
+/// is left as is.
+pub fn append_doc(text: &mut String, doc: Option<&str>) {
+    if let Some(doc) = doc.filter(|d| !d.is_empty()) {
+        if !text.contains(doc) {
+            text.push_str(DOC_SEPARATOR);
+            text.push_str(doc);
+        }
+    }
+}
@@ -0,0 +1,68 @@
+//! Filesystem change subscription.
+//!
+//! Wraps a debounced `notify` watcher (FSEvents on macOS) over a repo root and
+//! reports changed paths relative to that root, one batch per debounce window.
+//! Paths outside the index scope (ignored, generated, `.git`) are dropped.
+
+use crate::scope::IndexScope;
+use notify::RecursiveMode;
+use notify_debouncer_mini::{new_debouncer, DebounceEventResult, Debouncer};
+use std::collections::BTreeSet;
+use std::path::{Component, Path, PathBuf};
+use std::time::Duration;
+
+/// An active watch. Dropping it stops the watcher thread.
+pub struct RepoWatcher {
+    _debouncer: Debouncer<notify::RecommendedWatcher>,
+}
+
+/// Watch `repo_root` recursively. `on_change` gets the sorted, deduplicated
+/// repo-relative paths changed in each debounce window (created, modified,
+/// or removed — callers check existence) that fall inside `scope`.
+pub fn watch(
+    repo_root: &Path,
+    debounce: Duration,
+    mut scope: IndexScope,
+    on_change: impl Fn(Vec<String>) + Send + 'static,
+) -> Result<RepoWatcher, String> {
+    // FSEvents reports canonical paths (e.g. /private/var/...), so relativize
+    // against the canonical root
+    let root: PathBuf = repo_root
+        .canonicalize()
+        .map_err(|e| format!("Cannot watch {}: {}", repo_root.display(), e))?;
+
+    let handler_root = root.clone();
+    let mut debouncer = new_debouncer(debounce, move |res: DebounceEventResult| {
+        let Ok(events) = res else { return };
+        let paths: BTreeSet<String> = events
+            .into_iter()
+            .filter_map(|e| relative_path(&handler_root, &e.path))
+            .filter(|p| scope.should_index(p))
+            .collect();
+        if !paths.is_empty() {
+            on_change(paths.into_iter().collect());
+        }
+    })
+    .map_err(|e| format!("Failed to create watcher: {}", e))?;
+
+    debouncer
+        .watcher()
+        .watch(&root, RecursiveMode::Recursive)
+        .map_err(|e| format!("Cannot watch {}: {}", root.display(), e))?;
+
+    Ok(RepoWatcher {
+        _debouncer: debouncer,
+    })
+}
+
+fn relative_path(root: &Path, path: &Path) -> Option<String> {
+    let rel = path.strip_prefix(root).ok()?;
+    if rel.as_os_str().is_empty()
+        || rel
+            .components()
+            .any(|c| c == Component::Normal(".git".as_ref()))
+    {
+        return None;
+    }
+    Some(rel.to_string_lossy().into_owned())
+}
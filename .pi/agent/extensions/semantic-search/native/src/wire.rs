@@ -0,0 +1,81 @@
+//! Binary symbol batches, for `index_symbols_buffer`.
+//!
+//! One Buffer in place of thousands of JS objects: napi hands over the bytes
+//! without copying, and decoding is a linear walk instead of a property
+//! lookup per field. All integers are little-endian.
+//!
+//! ```text
+//! batch  := u32 count, symbol * count
+//! symbol := str embedding_text, str file_path, str name, str kind,
+//!           str language, i32 line, i32 end_line (-1 for none),
+//!           opt signature, opt doc_comment
+//! str    := u32 byte length, UTF-8 bytes
+//! opt    := u32 byte length (0xFFFFFFFF for none), UTF-8 bytes
+//! ```
+
+/// Length prefix of an absent optional string.
+pub const NONE: u32 = u32::MAX;
+
+/// Cursor over a batch, failing on truncation or invalid UTF-8.
+pub struct Reader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(buf: &'a [u8]) -> Self {
+        Reader { buf, pos: 0 }
+    }
+
+    fn take(&mut self, n: usize) -> Result<&'a [u8], String> {
+        let end = self
+            .pos
+            .checked_add(n)
+            .filter(|&end| end <= self.buf.len())
+            .ok_or_else(|| format!("truncated at byte {}", self.pos))?;
+        let bytes = &self.buf[self.pos..end];
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    pub fn u32(&mut self) -> Result<u32, String> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    pub fn i32(&mut self) -> Result<i32, String> {
+        self.u32().map(|v| v as i32)
+    }
+
+    pub fn str(&mut self) -> Result<String, String> {
+        let len = self.u32()?;
+        if len == NONE {
+            return Err(format!("unexpected null string at byte {}", self.pos - 4));
+        }
+        self.utf8(len as usize)
+    }
+
+    pub fn opt_str(&mut self) -> Result<Option<String>, String> {
+        match self.u32()? {
+            NONE => Ok(None),
+            len => self.utf8(len as usize).map(Some),
+        }
+    }
+
+    fn utf8(&mut self, len: usize) -> Result<String, String> {
+        let start = self.pos;
+        let bytes = self.take(len)?;
+        std::str::from_utf8(bytes)
+            .map(str::to_string)
+            .map_err(|e| format!("invalid UTF-8 at byte {}: {}", start, e))
+    }
+
+    /// Fail if bytes are left over.
+    pub fn finish(&self) -> Result<(), String> {
+        if self.pos == self.buf.len() {
+            Ok(())
+        } else {
+            Err(format!("{} trailing bytes", self.buf.len() - self.pos))
+        }
+    }
+}
@@ -0,0 +1,214 @@
+"""Export the golden fixture for tests/model_parity.rs.
+
+Usage: python3 export_model_parity.py
+
+Builds a tiny NomicBert (2 layers, 16 hidden, 2 heads) with seeded random
+weights, writes it to tiny_nomic_bert/{config.json,model.safetensors}, runs
+it on a few fixed token sequences, and writes model_parity.json: per case,
+the token ids, every hidden state (embedding LN output, then each layer,
+flattened [tokens * hidden]) and the mean-pooled, L2-normalized embedding.
+
+The forward pass is a plain-Python port of the reference PyTorch
+implementation (nomic-ai's modeling_hf_nomic_bert.py: rotary attention,
+SwiGLU MLP, post-norm), in float64 over the float32 weights, so it needs no
+third-party packages and the fixture doesn't depend on a BLAS or GPU.
+"""
+
+import json
+import math
+import os
+import random
+import struct
+
+HERE = os.path.dirname(os.path.abspath(__file__))
+MODEL_DIR = os.path.join(HERE, "tiny_nomic_bert")
+
+CONFIG = {
+    "model_type": "nomic_bert",
+    "vocab_size": 32,
+    "n_embd": 16,
+    "n_head": 2,
+    "n_layer": 2,
+    "n_inner": 32,
+    "layer_norm_epsilon": 1e-12,
+    "rotary_emb_base": 1000,
+    "rotary_emb_fraction": 1.0,
+    "rotary_emb_interleaved": False,
+    "qkv_proj_bias": False,
+    "mlp_fc1_bias": False,
+    "mlp_fc2_bias": False,
+    "prenorm": False,
+}
+
+TOKEN_IDS = [
+    [1, 7, 3, 19, 2],
+    [1, 30, 30, 4, 11, 25, 8, 2],
+    [1, 2],
+]
+
+
+def f32(x):
+    return struct.unpack("<f", struct.pack("<f", x))[0]
+
+
+def init_weights(seed=0):
+    """Weights keyed like CodeRankEmbed's safetensors, as (shape, values)."""
+    rng = random.Random(seed)
+    hidden, inner = CONFIG["n_embd"], CONFIG["n_inner"]
+
+    def tensor(*shape, mean=0.0, std=0.3):
+        n = math.prod(shape)
+        return shape, [f32(rng.gauss(mean, std)) for _ in range(n)]
+
+    weights = {
+        "embeddings.word_embeddings.weight": tensor(CONFIG["vocab_size"], hidden, std=1.0),
+        "emb_ln.weight": tensor(hidden, mean=1.0, std=0.1),
+        "emb_ln.bias": tensor(hidden, std=0.1),
+    }
+    for i in range(CONFIG["n_layer"]):
+        p = f"encoder.layers.{i}."
+        weights[p + "attn.Wqkv.weight"] = tensor(3 * hidden, hidden)
+        weights[p + "attn.out_proj.weight"] = tensor(hidden, hidden)
+        weights[p + "mlp.fc11.weight"] = tensor(inner, hidden)
+        weights[p + "mlp.fc12.weight"] = tensor(inner, hidden)
+        weights[p + "mlp.fc2.weight"] = tensor(hidden, inner)
+        for norm in ("norm1", "norm2"):
+            weights[p + norm + ".weight"] = tensor(hidden, mean=1.0, std=0.1)
+            weights[p + norm + ".bias"] = tensor(hidden, std=0.1)
+    return weights
+
+
+def write_safetensors(path, weights):
+    header, data, offset = {}, bytearray(), 0
+    for name, (shape, values) in weights.items():
+        raw = struct.pack(f"<{len(values)}f", *values)
+        header[name] = {
+            "dtype": "F32",
+            "shape": list(shape),
+            "data_offsets": [offset, offset + len(raw)],
+        }
+        data += raw
+        offset += len(raw)
+    header_bytes = json.dumps(header, separators=(",", ":")).encode()
+    header_bytes += b" " * (-len(header_bytes) % 8)
+    with open(path, "wb") as f:
+        f.write(struct.pack("<Q", len(header_bytes)))
+        f.write(header_bytes)
+        f.write(data)
+
+
+def rows(tensor):
+    shape, values = tensor
+    cols = shape[-1]
+    return [values[r * cols:(r + 1) * cols] for r in range(len(values) // cols)]
+
+
+def linear(x, weight):
+    """x @ weight.T for a [out, in] weight, as torch.nn.Linear."""
+    w = rows(weight)
+    return [[sum(a * b for a, b in zip(row, wr)) for wr in w] for row in x]
+
+
+def layer_norm(x, weight, bias):
+    eps = CONFIG["layer_norm_epsilon"]
+    out = []
+    for row in x:
+        mean = sum(row) / len(row)
+        var = sum((v - mean) ** 2 for v in row) / len(row)
+        inv = 1.0 / math.sqrt(var + eps)
+        out.append([(v - mean) * inv * w + b for v, w, b in zip(row, weight[1], bias[1])])
+    return out
+
+
+def rotary(x, dim):
+    """Non-interleaved RoPE over the first `dim` features of each head row:
+    the two halves rotate as pairs (rotate_half)."""
+    half = dim // 2
+    base = CONFIG["rotary_emb_base"]
+    out = []
+    for pos, row in enumerate(x):
+        row = list(row)
+        for i in range(half):
+            theta = pos * base ** (-2 * i / dim)
+            c, s = math.cos(theta), math.sin(theta)
+            a, b = row[i], row[i + half]
+            row[i], row[i + half] = a * c - b * s, a * s + b * c
+        out.append(row)
+    return out
+
+
+def attention(x, p, weights):
+    hidden, heads = CONFIG["n_embd"], CONFIG["n_head"]
+    head_dim = hidden // heads
+    rotary_dim = int(head_dim * CONFIG["rotary_emb_fraction"])
+    qkv = linear(x, weights[p + "attn.Wqkv.weight"])
+    out = [[0.0] * hidden for _ in x]
+    for h in range(heads):
+        lo = h * head_dim
+
+        def head(part):
+            start = part * hidden + lo
+            return [row[start:start + head_dim] for row in qkv]
+
+        q, k, v = rotary(head(0), rotary_dim), rotary(head(1), rotary_dim), head(2)
+        scale = 1.0 / math.sqrt(head_dim)
+        for i, qi in enumerate(q):
+            scores = [scale * sum(a * b for a, b in zip(qi, kj)) for kj in k]
+            top = max(scores)
+            exps = [math.exp(s - top) for s in scores]
+            total = sum(exps)
+            for d in range(head_dim):
+                out[i][lo + d] = sum(e * vj[d] for e, vj in zip(exps, v)) / total
+    return linear(out, weights[p + "attn.out_proj.weight"])
+
+
+def mlp(x, p, weights):
+    up = linear(x, weights[p + "mlp.fc11.weight"])
+    gate = linear(x, weights[p + "mlp.fc12.weight"])
+    act = [[u * g / (1.0 + math.exp(-g)) for u, g in zip(ur, gr)] for ur, gr in zip(up, gate)]
+    return linear(act, weights[p + "mlp.fc2.weight"])
+
+
+def add(a, b):
+    return [[x + y for x, y in zip(ra, rb)] for ra, rb in zip(a, b)]
+
+
+def forward(token_ids, weights):
+    table = rows(weights["embeddings.word_embeddings.weight"])
+    x = layer_norm([table[t] for t in token_ids], weights["emb_ln.weight"], weights["emb_ln.bias"])
+    hidden_states = [x]
+    for i in range(CONFIG["n_layer"]):
+        p = f"encoder.layers.{i}."
+        x = layer_norm(add(x, attention(x, p, weights)), weights[p + "norm1.weight"], weights[p + "norm1.bias"])
+        x = layer_norm(add(x, mlp(x, p, weights)), weights[p + "norm2.weight"], weights[p + "norm2.bias"])
+        hidden_states.append(x)
+    pooled = [sum(col) / len(x) for col in zip(*x)]
+    norm = max(math.sqrt(sum(v * v for v in pooled)), 1e-12)
+    return hidden_states, [v / norm for v in pooled]
+
+
+def main():
+    weights = init_weights()
+    os.makedirs(MODEL_DIR, exist_ok=True)
+    with open(os.path.join(MODEL_DIR, "config.json"), "w") as f:
+        json.dump(CONFIG, f, indent=2)
+        f.write("\n")
+    write_safetensors(os.path.join(MODEL_DIR, "model.safetensors"), weights)
+
+    cases = []
+    for ids in TOKEN_IDS:
+        hidden_states, embedding = forward(ids, weights)
+        cases.append(
+            {
+                "token_ids": ids,
+                "hidden_states": [[round(v, 7) for row in h for v in row] for h in hidden_states],
+                "embedding": [round(v, 7) for v in embedding],
+            }
+        )
+    with open(os.path.join(HERE, "model_parity.json"), "w") as f:
+        json.dump(cases, f)
+        f.write("\n")
+
+
+if __name__ == "__main__":
+    main()
@@ -0,0 +1,123 @@
+//! Golden-fixture parity test: load the tiny NomicBert in
+//! `tests/fixtures/tiny_nomic_bert`, run it on the reference token ids in
+//! `tests/fixtures/model_parity.json`, and compare every layer's hidden
+//! states and the final embedding against output exported from the Python
+//! implementation, so refactors to model.rs (attention, RoPE, norms) are
+//! caught before they reach an index.
+//!
+//! The tiny model has CodeRankEmbed's architecture and weight names with
+//! seeded random weights, small enough to commit. Regenerate it and the
+//! fixture with `python3 tests/fixtures/export_model_parity.py`.
+
+#[allow(dead_code)] // shared with the addon; this test uses only part of it
+#[path = "../src/model.rs"]
+mod model;
+
+use mlx_rs::{module::ModuleParametersExt, Array};
+use model::{mean_pool_normalize, NomicBertConfig, NomicBertModel};
+use std::path::Path;
+
+/// Largest allowed absolute difference in any hidden state or embedding value.
+const MAX_ABS_TOLERANCE: f32 = 1e-3;
+/// Smallest allowed cosine similarity between the final embeddings.
+const MIN_COSINE: f32 = 0.9999;
+
+const FIXTURES: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/model_parity.json");
+const MODEL_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/tiny_nomic_bert");
+
+#[derive(serde::Deserialize)]
+struct Fixture {
+    token_ids: Vec<i32>,
+    hidden_states: Vec<Vec<f32>>,
+    embedding: Vec<f32>,
+}
+
+/// Read a committed fixture file, failing the test with how to regenerate
+/// it when it's missing.
+fn read_fixture(path: &Path) -> String {
+    match std::fs::read_to_string(path) {
+        Ok(s) => s,
+        Err(e) => panic!(
+            "can't read fixture {} ({}); regenerate it with tests/fixtures/export_model_parity.py",
+            path.display(),
+            e
+        ),
+    }
+}
+
+fn max_abs_diff(a: &[f32], b: &[f32]) -> f32 {
+    if a.len() != b.len() {
+        return f32::INFINITY;
+    }
+    a.iter().zip(b).map(|(x, y)| (x - y).abs()).fold(0.0, f32::max)
+}
+
+fn cosine(a: &[f32], b: &[f32]) -> f32 {
+    let dot: f32 = a.iter().zip(b).map(|(x, y)| x * y).sum();
+    let norm = |v: &[f32]| v.iter().map(|x| x * x).sum::<f32>().sqrt();
+    dot / (norm(a) * norm(b))
+}
+
+#[test]
+fn model_matches_reference_outputs() {
+    let dir = Path::new(MODEL_DIR);
+    let weights = dir.join("model.safetensors");
+    assert!(
+        weights.exists(),
+        "missing fixture {}; regenerate it with tests/fixtures/export_model_parity.py",
+        weights.display()
+    );
+    let fixtures: Vec<Fixture> = serde_json::from_str(&read_fixture(Path::new(FIXTURES))).unwrap();
+    assert!(!fixtures.is_empty(), "{} has no cases", FIXTURES);
+
+    let config: NomicBertConfig =
+        serde_json::from_str(&read_fixture(&dir.join("config.json"))).unwrap();
+    let mut model = NomicBertModel::new(&config).unwrap();
+    model.load_safetensors(weights).unwrap();
+
+    let mut failures = Vec::new();
+    for (i, case) in fixtures.iter().enumerate() {
+        let len = case.token_ids.len() as i32;
+        let ids = Array::from_slice(&case.token_ids, &[1, len]);
+        let mask = Array::from_slice(&vec![1i32; len as usize], &[1, len]);
+
+        let (hidden, layers) = model.forward_with_layers(&ids, Some(&mask)).unwrap();
+        let pooled = mean_pool_normalize(&hidden, &mask).unwrap();
+        pooled.eval().unwrap();
+
+        let label = format!("case {}", i);
+
+        if case.hidden_states.len() != layers.len() {
+            failures.push(format!(
+                "{}: fixture has {} hidden states, model produced {}",
+                label,
+                case.hidden_states.len(),
+                layers.len()
+            ));
+        }
+        for (layer, (actual, expected)) in layers.iter().zip(&case.hidden_states).enumerate() {
+            actual.eval().unwrap();
+            let diff = max_abs_diff(actual.as_slice::<f32>(), expected);
+            if diff > MAX_ABS_TOLERANCE {
+                let name = if layer == 0 {
+                    "embeddings".to_string()
+                } else {
+                    format!("layer {}", layer - 1)
+                };
+                failures.push(format!("{}: {} max abs diff {:.2e}", label, name, diff));
+            }
+        }
+
+        let actual = pooled.as_slice::<f32>();
+        let diff = max_abs_diff(actual, &case.embedding);
+        if diff > MAX_ABS_TOLERANCE {
+            failures.push(format!("{}: embedding max abs diff {:.2e}", label, diff));
+        }
+        let cos = cosine(actual, &case.embedding);
+        if cos.is_nan() || cos < MIN_COSINE {
+            failures.push(format!("{}: embedding cosine {:.6}", label, cos));
+        }
+    }
+
+    assert!(failures.is_empty(), "parity drift:\n{}", failures.join("\n"));
+}